@@ -166,11 +166,13 @@ impl<'a> ServBehaviour for DemoServer<'a> {
     }
 
     fn auth_password(&mut self, user: TextString, password: TextString) -> bool {
-        user.as_str().unwrap_or("") == "matt" && password.as_str().unwrap_or("") == "pw"
+        // Invalid UTF-8 is rejected
+        matches!((user.as_str_checked(), password.as_str_checked()),
+            (Ok("matt"), Ok("pw")))
     }
 
     fn auth_pubkey(&mut self, user: TextString, pubkey: &PubKey) -> bool {
-        if user.as_str().unwrap_or("") != "matt" {
+        if user.as_str_checked() != Ok("matt") {
             return false
         }
 
@@ -71,7 +71,7 @@ async fn run(args: Args) -> Result<i32> {
     // sunsetc example here uses the normal threaded scheduler in order to test the
     // "multi-thread" feature (and as a more "default" example).
     let ssh_task = tokio::task::spawn(async move {
-        let mut rxbuf = Zeroizing::new(vec![0; 3000]);
+        let mut rxbuf = Zeroizing::new(vec![0; sunset::config::RFC_MIN_BUFFER]);
         let mut txbuf = Zeroizing::new(vec![0; 3000]);
         let ssh = SSHClient::new(&mut rxbuf, &mut txbuf)?;
 
@@ -96,6 +96,13 @@ async fn run(args: Args) -> Result<i32> {
             app.add_authkey(read_key(&i).with_context(|| format!("loading key {i}"))?);
         }
 
+        if args.forward_agent {
+            match std::env::var("SSH_AUTH_SOCK") {
+                Ok(p) => { app.forward_agent(p); }
+                Err(_) => warn!("No SSH_AUTH_SOCK for agent forwarding"),
+            }
+        }
+
         let agent = load_agent_keys(&mut app).await;
         if let Some(agent) = agent {
             app.agent(agent);
@@ -183,6 +190,10 @@ struct Args {
     /// no agent forwarding
     no_agent: bool,
 
+    #[argh(switch, short='A')]
+    /// forward the agent from $SSH_AUTH_SOCK
+    forward_agent: bool,
+
     #[allow(unused)]
     #[argh(switch, short='2', hidden_help)]
     /// ssh version 2
@@ -9,6 +9,7 @@ use std::path::Path;
 use pretty_hex::PrettyHex;
 use tokio::net::UnixStream;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use embedded_io_async::{Read as _, Write as _};
 
 use sunset_sshwire_derive::*;
 
@@ -18,6 +19,7 @@ use sunset::{PubKey, AuthSigMsg, Signature,OwnedSig, SignKey};
 use sshwire::{WireError, WireResult, BinString, TextString, Blob, SSHSink, SSHSource, SSHDecode, SSHEncode};
 use sshwire::{SSHEncodeEnum, SSHDecodeEnum};
 use sunset::sshnames::*;
+use sunset_embassy::ChanInOut;
 
 // Must be sufficient for the list of all public keys
 const MAX_RESPONSE: usize = 200_000;
@@ -145,17 +147,15 @@ impl AgentClient {
         Ok(r)
     }
 
-    pub async fn keys(&mut self) -> Result<Vec<SignKey>> {
+    /// Returns the agent's public keys with their comments.
+    ///
+    /// Keys are in the agent's order.
+    pub async fn request_identities(&mut self) -> Result<Vec<(PubKey<'_>, String)>> {
         match self.request(AgentRequest::RequestIdentities).await? {
             AgentResponse::IdentitiesAnswer(i) => {
-                let mut keys = vec![];
-                for (pk, comment) in i.keys.iter() {
-                    match SignKey::from_agent_pubkey(pk) {
-                        Ok(k) => keys.push(k),
-                        Err(e) => debug!("skipping agent key {comment:?}: {e}")
-                    }
-                }
-                Ok(keys)
+                Ok(i.keys.into_iter()
+                    .map(|(pk, comment)| (pk, comment.as_str_lossy().into()))
+                    .collect())
             }
             resp => {
                 debug!("response: {resp:?}");
@@ -164,12 +164,78 @@ impl AgentClient {
         }
     }
 
-    pub async fn sign_auth(&mut self, key: &SignKey, msg: &AuthSigMsg<'_>) -> Result<OwnedSig> {
-        let flags = match key {
-            #[cfg(feature = "rsa")]
-            SignKey::AgentRSA(_) => SSH_AGENT_FLAG_RSA_SHA2_256,
-            _ => 0,
+    /// Returns the agent's keys usable for signing.
+    ///
+    /// Unsupported key types are skipped.
+    pub async fn keys(&mut self) -> Result<Vec<SignKey>> {
+        let mut keys = vec![];
+        for (pk, comment) in self.request_identities().await?.iter() {
+            match SignKey::from_agent_pubkey(pk) {
+                Ok(k) => keys.push(k),
+                Err(e) => debug!("skipping agent key {comment:?}: {e}")
+            }
+        }
+        Ok(keys)
+    }
+
+    /// Forwards an agent channel opened by a server to this agent.
+    ///
+    /// Runs until either side closes. A separate `AgentClient` should
+    /// be used for each forwarded channel.
+    pub async fn forward(self, chan: ChanInOut<'_, '_>) -> Result<()> {
+        let (mut ar, mut aw) = self.conn.into_split();
+
+        let to_agent = async {
+            let mut chan = chan.clone();
+            let mut buf = [0u8; 1000];
+            loop {
+                let l = chan.read(&mut buf).await?;
+                if l == 0 {
+                    break
+                }
+                aw.write_all(&buf[..l]).await?;
+            }
+            Ok::<_, Error>(())
+        };
+
+        let from_agent = async {
+            let mut chan = chan.clone();
+            let mut buf = [0u8; 1000];
+            loop {
+                let l = ar.read(&mut buf).await?;
+                if l == 0 {
+                    break
+                }
+                chan.write_all(&buf[..l]).await?;
+            }
+            Ok::<_, Error>(())
         };
+
+        match embassy_futures::select::select(to_agent, from_agent).await {
+            embassy_futures::select::Either::First(r) => r,
+            embassy_futures::select::Either::Second(r) => r,
+        }
+    }
+
+    /// Returns `SSH_AGENTC_SIGN_REQUEST` flags for the signature algorithm
+    /// in `msg`. RSA keys would otherwise get a legacy SHA1 `ssh-rsa` signature.
+    #[cfg_attr(not(feature = "rsa"), allow(unused_variables))]
+    fn sign_flags(key: &SignKey, msg: &AuthSigMsg<'_>) -> Result<u32> {
+        match key {
+            #[cfg(feature = "rsa")]
+            SignKey::AgentRSA(_) => match msg.sig_algorithm() {
+                Some(SSH_NAME_RSA_SHA256) => Ok(SSH_AGENT_FLAG_RSA_SHA2_256),
+                a => {
+                    debug!("Unsupported RSA signature algorithm {a:?}");
+                    Err(Error::msg("Unsupported RSA signature algorithm"))
+                }
+            },
+            _ => Ok(0),
+        }
+    }
+
+    pub async fn sign_auth(&mut self, key: &SignKey, msg: &AuthSigMsg<'_>) -> Result<OwnedSig> {
+        let flags = Self::sign_flags(key, msg)?;
         trace!("flags {flags:?}");
         let r = AgentRequest::SignRequest(AgentSignRequest {
             key_blob: Blob(key.pubkey()),
@@ -11,6 +11,7 @@ use sunset::{Error, Result, Runner, SessionCommand};
 use sunset_embassy::*;
 
 use std::collections::VecDeque;
+use std::path::PathBuf;
 use embassy_sync::channel::{Channel, Sender, Receiver};
 use embassy_sync::signal::Signal;
 use embedded_io_async::{Read as _, Write as _};
@@ -40,6 +41,8 @@ pub struct CmdlineClient {
     host: String,
     port: u16,
     agent: Option<AgentClient>,
+    /// Agent socket for forwarding
+    forward_agent: Option<PathBuf>,
 
     pty_guard: Option<RawPtyGuard>,
 
@@ -52,6 +55,7 @@ impl CmdlineClient {
             cmd: SessionCommand::Shell,
             want_pty: false,
             agent: None,
+            forward_agent: None,
 
             username: username.as_ref().into(),
             host: host.as_ref().into(),
@@ -96,6 +100,12 @@ impl CmdlineClient {
         self.agent = Some(agent)
     }
 
+    /// Forwards the agent at `path` to the server, such as `$SSH_AUTH_SOCK`
+    pub fn forward_agent(&mut self, path: impl Into<PathBuf>) -> &mut Self {
+        self.forward_agent = Some(path.into());
+        self
+    }
+
     async fn chan_run(io: ChanInOut<'_, '_>,
         io_err: Option<ChanIn<'_, '_>>,
         pty_guard: Option<RawPtyGuard>) -> Result<()> {
@@ -245,6 +255,9 @@ impl CmdlineClient {
         let launch_chan: Channel::<SunsetRawMutex, (ChanInOut, Option<ChanIn>, Option<RawPtyGuard>), 1>
             = Channel::new();
 
+        let agent_chan: Channel::<SunsetRawMutex, ChanInOut, 1> = Channel::new();
+        let forward_agent = self.forward_agent.clone();
+
         let mut exit_code = 1i32;
 
         let prog_loop = async {
@@ -281,6 +294,29 @@ impl CmdlineClient {
                             p.skip()
                         }?;
                     }
+                    CliEvent::KbdInteractive(k) => {
+                        for t in [k.name()?, k.instruction()?] {
+                            if !t.as_ref().is_empty() {
+                                println!("{}", t.as_str()?);
+                            }
+                        }
+                        let mut answers = vec![];
+                        for p in k.prompts()?.as_slice() {
+                            let q = p.prompt.as_str()?;
+                            let a = if p.echo {
+                                print!("{q}");
+                                std::io::Write::flush(&mut std::io::stdout())?;
+                                let mut a = String::new();
+                                std::io::stdin().read_line(&mut a)?;
+                                a.trim_end_matches(['\r', '\n']).to_string()
+                            } else {
+                                rpassword::prompt_password(q)?
+                            };
+                            answers.push(a);
+                        }
+                        let answers: Vec<&str> = answers.iter().map(|a| a.as_str()).collect();
+                        k.responses(&answers)?;
+                    }
                     CliEvent::AgentSign(k) => {
                         let agent = self.agent.as_mut().expect("agent keys without agent?");
                         let key = k.key()?;
@@ -300,6 +336,9 @@ impl CmdlineClient {
                         if let Some(p) = self.pty.take() {
                             opener.pty(p)?;
                         }
+                        if forward_agent.is_some() {
+                            opener.agent_forward()?;
+                        }
                         opener.cmd(&self.cmd)?;
                         // Start the IO loop
                         // TODO is there a better way
@@ -315,9 +354,29 @@ impl CmdlineClient {
                             }
                         }
                     }
+                    CliEvent::ChanRequestResult { .. } => (),
+                    CliEvent::OpenAgent(a) => {
+                        if forward_agent.is_some() {
+                            let ch = a.accept()?;
+                            drop(ph);
+                            let io = cli.chan_io(ch).await?;
+                            // Waiting here would block progress. If an earlier
+                            // channel is still queued, drop this one to close it.
+                            if agent_chan.try_send(io).is_err() {
+                                debug!("Agent forwarding busy, closing channel");
+                            }
+                        }
+                        // otherwise rejected on drop
+                    }
                     CliEvent::Banner(b) => {
                         println!("Banner from server:\n{}", b.banner()?)
                     }
+                    CliEvent::HostkeysUpdate(_) => {
+                        trace!("Ignoring host keys update");
+                    }
+                    CliEvent::DebugMessage(m) => {
+                        println!("Debug message from server: {}", m.message()?)
+                    }
                     CliEvent::Defunct => {
                         trace!("break defunct");
                         break Ok::<_, Error>(())
@@ -331,7 +390,24 @@ impl CmdlineClient {
             Self::chan_run(io, extin, pty).await
         };
 
-        embassy_futures::select::select(prog_loop, chanio).await;
+        // Forwarded agent channels are handled one at a time
+        let agent_loop = async {
+            loop {
+                let io = agent_chan.receive().await;
+                let Some(path) = forward_agent.as_ref() else {
+                    continue
+                };
+                let r = match AgentClient::new(path).await {
+                    Ok(a) => a.forward(io).await,
+                    Err(e) => Err(e.into()),
+                };
+                if let Err(e) = r {
+                    debug!("Agent forwarding ended: {e}");
+                }
+            }
+        };
+
+        embassy_futures::select::select3(prog_loop, chanio, agent_loop).await;
 
         Ok(exit_code)
     }
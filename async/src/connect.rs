@@ -0,0 +1,207 @@
+//! A simple client connection helper.
+//!
+//! [`connect()`] handles key exchange and authentication, returning a
+//! [`Session`] that can open channels. [`SSHClient::run()`] must be
+//! polled concurrently to perform network IO.
+#[allow(unused_imports)]
+use {
+    sunset::{Error, Result},
+    log::{debug, error, info, log, trace, warn},
+};
+
+use std::collections::VecDeque;
+
+use sunset::{CliEvent, Pty, SessionCommand, SignKey};
+use sunset_embassy::{ChanIn, ChanInOut, ProgressHolder, SSHClient};
+
+use crate::knownhosts;
+
+/// Settings for [`connect()`]
+pub struct ConnectConfig {
+    pub username: String,
+    /// Used for known hosts checking
+    pub host: String,
+    pub port: u16,
+    /// Keys to try in order, before any password
+    pub keys: Vec<SignKey>,
+    pub password: Option<String>,
+    /// Check the host key against `~/.ssh/known_hosts`.
+    ///
+    /// If disabled any host key is accepted, which is insecure.
+    pub check_known_hosts: bool,
+}
+
+impl ConnectConfig {
+    pub fn new(username: &str, host: &str) -> Self {
+        Self {
+            username: username.into(),
+            host: host.into(),
+            port: sunset::sshnames::SSH_PORT,
+            keys: vec![],
+            password: None,
+            check_known_hosts: true,
+        }
+    }
+}
+
+/// Performs key exchange and authentication.
+///
+/// `cli.run()` must be running concurrently. Fails if authentication
+/// fails or the connection ends.
+pub async fn connect<'c, 'a>(cli: &'c SSHClient<'a>, config: ConnectConfig)
+    -> Result<Session<'c, 'a>> {
+    let ConnectConfig { username, host, port, keys, mut password, check_known_hosts } = config;
+    let mut keys = VecDeque::from(keys);
+
+    loop {
+        let mut ph = ProgressHolder::new();
+        let ev = cli.progress(&mut ph).await?;
+        match ev {
+            CliEvent::Hostkey(h) => {
+                if !check_known_hosts {
+                    h.accept()?;
+                    continue;
+                }
+                let key = h.hostkey()?;
+                match knownhosts::check_known_hosts(&host, port, &key) {
+                    Ok(()) => h.accept(),
+                    Err(e) => {
+                        debug!("Known hosts check failed: {e:?}");
+                        h.reject()
+                    }
+                }?;
+            }
+            CliEvent::Username(u) => u.username(&username)?,
+            CliEvent::Pubkey(p) => {
+                if let Some(k) = keys.pop_front() {
+                    p.pubkey(k)
+                } else {
+                    p.skip()
+                }?
+            }
+            CliEvent::Password(p) => {
+                if let Some(pw) = password.take() {
+                    p.password(pw)
+                } else {
+                    p.skip()
+                }?
+            }
+            CliEvent::Authenticated => {
+                debug!("Authentication succeeded");
+                return Ok(Session { cli })
+            }
+            CliEvent::Defunct => {
+                return Err(Error::msg("Connection ended before authentication"))
+            }
+            ev => Session::other_event(ev)?,
+        }
+    }
+}
+
+/// An authenticated client session, returned by [`connect()`].
+///
+/// Methods should not be called concurrently with other
+/// `SSHClient::progress()` callers.
+pub struct Session<'c, 'a> {
+    cli: &'c SSHClient<'a>,
+}
+
+impl<'c, 'a> Session<'c, 'a> {
+    /// Opens a session channel without a PTY, running `cmd` or a shell.
+    ///
+    /// Returns stdin/stdout and stderr.
+    pub async fn open_session(&mut self, cmd: Option<&str>)
+        -> Result<(ChanInOut<'c, 'a>, ChanIn<'c, 'a>)> {
+        let (io, ext) = self.cli.open_session_nopty().await?;
+        let num = io.num();
+
+        let cmd = match cmd {
+            Some(c) => SessionCommand::Exec(c),
+            None => SessionCommand::Shell,
+        };
+
+        loop {
+            let mut ph = ProgressHolder::new();
+            let ev = self.cli.progress(&mut ph).await?;
+            match ev {
+                CliEvent::SessionOpened(mut opener) if opener.channel() == num => {
+                    opener.cmd(&cmd)?;
+                    return Ok((io, ext))
+                }
+                CliEvent::Defunct => {
+                    return Err(Error::msg("Connection ended"))
+                }
+                ev => Session::other_event(ev)?,
+            }
+        }
+    }
+
+    /// Opens a session channel running a shell, with a PTY if `pty` is given.
+    ///
+    /// Returns once the server has confirmed the shell request. Returns
+    /// stdin/stdout, and stderr when there is no PTY. A PTY session has
+    /// all output on stdout.
+    pub async fn open_shell(&mut self, pty: Option<Pty>)
+        -> Result<(ChanInOut<'c, 'a>, Option<ChanIn<'c, 'a>>)> {
+        let (io, ext) = self.cli.open_shell(pty).await?;
+        let num = io.num();
+
+        loop {
+            let mut ph = ProgressHolder::new();
+            let ev = self.cli.progress(&mut ph).await?;
+            match ev {
+                CliEvent::ChanRequestResult { ch, success } if ch == num => {
+                    if !success {
+                        return Err(Error::msg("Shell request failed"))
+                    }
+                    return Ok((io, ext))
+                }
+                CliEvent::Defunct => {
+                    return Err(Error::msg("Connection ended"))
+                }
+                ev => Session::other_event(ev)?,
+            }
+        }
+    }
+
+    /// Opens a `direct-tcpip` forwarded connection to `host:port` from the server.
+    pub async fn open_direct_tcpip(&mut self, host: &str, port: u16)
+        -> Result<ChanInOut<'c, 'a>> {
+        self.cli.open_direct_tcpip(host, port, "localhost", 0).await
+    }
+
+    /// Handles events until the connection ends.
+    ///
+    /// This must be polled while channels are in use, to handle
+    /// session exit and other events.
+    pub async fn run_events(&mut self) -> Result<()> {
+        loop {
+            let mut ph = ProgressHolder::new();
+            let ev = self.cli.progress(&mut ph).await?;
+            match ev {
+                CliEvent::Defunct => return Ok(()),
+                ev => Session::other_event(ev)?,
+            }
+        }
+    }
+
+    /// Default handling for events not otherwise expected
+    fn other_event(ev: CliEvent) -> Result<()> {
+        match ev {
+            CliEvent::Banner(b) => {
+                info!("Banner from server:\n{}", b.banner()?);
+            }
+            CliEvent::DebugMessage(m) => {
+                info!("Debug message from server: {}", m.message()?);
+            }
+            CliEvent::SessionExit(ex) => {
+                debug!("Session exit {ex:?}");
+            }
+            ev => {
+                // Dropping an event performs a default reject
+                trace!("Ignoring event {ev:?}");
+            }
+        }
+        Ok(())
+    }
+}
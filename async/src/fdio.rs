@@ -149,16 +149,20 @@ impl<F: Write+Unpin> AsyncWrite for OutFd<F> {
         self: Pin<&mut Self>,
         _cx: &mut Context<'_>,
     ) -> Poll<std::io::Result<()>> {
-        Poll::Ready(Ok(()))
+        match self.get_mut() {
+            // Std handles may buffer internally
+            Self::Sync(f) => Poll::Ready(f.flush()),
+            // Writes go directly to the fd
+            Self::Async(_) => Poll::Ready(Ok(())),
         }
+    }
 
+    /// Flushes output. The underlying fd isn't closed, since it is shared
+    /// with the process's standard streams.
     fn poll_shutdown(
         self: Pin<&mut Self>,
-        _cx: &mut Context<'_>,
+        cx: &mut Context<'_>,
     ) -> Poll<std::io::Result<()>> {
-        warn!("shutdown on fd not implemented");
-        Poll::Ready(Ok(()))
-        // nix::sys::socket::shutdown(*self.f.get_ref(), nix::sys::socket::Shutdown::Write)?;
-        // Poll::Ready(Ok(()))
+        self.poll_flush(cx)
     }
 }
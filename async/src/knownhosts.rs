@@ -6,6 +6,9 @@ use std::path::{Path, PathBuf};
 use std::io::{BufRead, Write, Read};
 use std::io;
 
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+
 use crate::*;
 use sunset::packets::PubKey;
 
@@ -61,6 +64,47 @@ fn line_entry(line: &str) -> Option<(String, String)> {
     line.split_once(' ').map(|(h, k)| (h.into(), k.into()))
 }
 
+/// Returns whether a known_hosts host field matches `host` (from [`host_part()`]).
+///
+/// The field may be a comma separated list of names, or an OpenSSH
+/// hashed `|1|salt|hash` entry. Malformed hashed entries don't match.
+fn host_matches(field: &str, host: &str) -> bool {
+    if let Some(hashed) = field.strip_prefix(HASHED_PREFIX) {
+        match hashed_host_matches(hashed, host) {
+            Some(m) => m,
+            None => {
+                debug!("Malformed hashed host entry");
+                false
+            }
+        }
+    } else {
+        field.split(',').any(|h| h.eq_ignore_ascii_case(host))
+    }
+}
+
+const HASHED_PREFIX: &str = "|1|";
+
+/// Returns `None` if the `salt|hash` entry is malformed.
+fn hashed_host_matches(hashed: &str, host: &str) -> Option<bool> {
+    use base64ct::{Base64, Encoding};
+
+    let (salt, hash) = hashed.split_once('|')?;
+    let mut salt_buf = [0u8; SHA1_LEN];
+    let salt = Base64::decode(salt, &mut salt_buf).ok()?;
+    let mut hash_buf = [0u8; SHA1_LEN];
+    let hash = Base64::decode(hash, &mut hash_buf).ok()?;
+    if hash.len() != SHA1_LEN {
+        return None
+    }
+    let mut h = HmacSha1::new_from_slice(salt).ok()?;
+    h.update(host.as_bytes());
+    Some(h.verify_slice(hash).is_ok())
+}
+
+/// OpenSSH `HashKnownHosts` entries use HMAC-SHA1
+type HmacSha1 = Hmac<Sha1>;
+const SHA1_LEN: usize = 20;
+
 /// Returns the host string. Non-22 ports are appended.
 fn host_part(host: &str, port: u16) -> String {
     let mut host = host.to_lowercase();
@@ -93,7 +137,7 @@ pub fn check_known_hosts_file(
         }) {
         let line = line + 1;
 
-        if lh != match_host {
+        if !host_matches(&lh, &match_host) {
             continue;
         }
 
@@ -185,3 +229,18 @@ fn add_key(
     Ok(())
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashed_host() {
+        // "foo", hashed as by ssh-keygen -H
+        let field = "|1|JfKTdBh7rNbXkVAQCRp4OQoPfmI=|Eo2fwGrtIR9otLFOtc05D8I6MWs=";
+        assert!(host_matches(field, "foo"));
+        assert!(!host_matches(field, "bar"));
+        assert!(!host_matches("|1|garbage", "foo"));
+        assert!(host_matches("other,[foo]:2222", &host_part("foo", 2222)));
+    }
+}
@@ -7,6 +7,7 @@ mod pty;
 mod cmdline_client;
 pub mod knownhosts;
 mod agent;
+mod connect;
 
 #[cfg(unix)]
 mod fdio;
@@ -18,6 +19,7 @@ use pty::{raw_pty, RawPtyGuard};
 pub use cmdline_client::CmdlineClient;
 
 pub use agent::AgentClient;
+pub use connect::{connect, ConnectConfig, Session};
 
 // for sshwire derive
 use sunset::sshwire;
@@ -84,7 +84,7 @@ async fn session<S: DemoServer>(socket: &mut TcpSocket<'_>, config: &SunsetMutex
     }
 }
 
-/// Provides `ServBehaviour` for the server
+/// Handles `ServEvent`s for the server
 ///
 /// Further customisations are provided by `DemoServer` generic
 pub struct ServerApp {
@@ -127,9 +127,44 @@ impl ServerApp {
             ServEvent::SessionPty(a) => {
                 a.succeed()
             }
+            ServEvent::SessionWinch(a) => {
+                // The demo menu has no terminal size to update
+                a.succeed()
+            }
+            ServEvent::SessionSubsystem(a) => {
+                // No subsystems in the demo
+                a.fail()
+            }
+            ServEvent::SessionAgentReq(a) => {
+                // No agent forwarding in the demo
+                a.fail()
+            }
+            ServEvent::SessionEnv(a) => {
+                // The demo has no environment
+                a.fail()
+            }
+            ServEvent::SessionBreak(a) => {
+                // No serial console in the demo
+                a.fail()
+            }
             ServEvent::SessionExec(a) => {
+                // Only the interactive menu is provided
+                if let Ok(cmd) = a.command() {
+                    info!("Rejecting exec of {cmd:?}");
+                }
                 a.fail()
             }
+            // No remote forwarding in the demo
+            ServEvent::TcpipForward(a)
+            | ServEvent::CancelTcpipForward(a) => {
+                a.reject()
+            }
+            ServEvent::DebugMessage(m) => {
+                if let Ok(m) = m.message() {
+                    info!("Debug message from client: {m}");
+                }
+                Ok(())
+            }
             | ServEvent::Defunct
             | ServEvent::SessionShell(_) => {
                 error!("Expected caller to handle {event:?}");
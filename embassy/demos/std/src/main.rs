@@ -16,6 +16,8 @@ use embassy_sync::channel::Channel;
 use embassy_futures::select::select;
 use embassy_net_tuntap::TunTapDevice;
 
+use heapless::String;
+
 use sunset::*;
 use sunset_embassy::{SSHServer, SunsetMutex, SunsetRawMutex, ProgressHolder};
 
@@ -85,7 +87,8 @@ impl DemoServer for StdDemo {
 
     async fn run(&self, serv: &SSHServer<'_>, mut common: ServerApp) -> Result<()>
     {
-        let chan_pipe = Channel::<SunsetRawMutex, ChanHandle, 1>::new();
+        // An optional exec command is sent along with the channel
+        let chan_pipe = Channel::<SunsetRawMutex, (ChanHandle, Option<String<100>>), 1>::new();
 
         let prog_loop = async {
             loop {
@@ -98,7 +101,20 @@ impl DemoServer for StdDemo {
                         if let Some(ch) = common.sess.take() {
                             debug_assert!(ch.num() == a.channel()?);
                             a.succeed()?;
-                            let _ = chan_pipe.try_send(ch);
+                            let _ = chan_pipe.try_send((ch, None));
+                        } else {
+                            a.fail()?;
+                        }
+                    }
+                    ServEvent::SessionExec(a) =>
+                    {
+                        // Runs a single menu command
+                        let cmd = a.command()?.as_str().ok()
+                            .and_then(|c| String::try_from(c).ok());
+                        if let (Some(ch), Some(cmd)) = (common.sess.take(), cmd) {
+                            debug_assert!(ch.num() == a.channel()?);
+                            a.succeed()?;
+                            let _ = chan_pipe.try_send((ch, Some(cmd)));
                         } else {
                             a.fail()?;
                         }
@@ -112,7 +128,7 @@ impl DemoServer for StdDemo {
 
         let shell_loop = async {
 
-            let ch = chan_pipe.receive().await;
+            let (ch, exec) = chan_pipe.receive().await;
 
             debug!("got handle");
 
@@ -124,6 +140,15 @@ impl DemoServer for StdDemo {
 
             let mut menu = MenuRunner::new(&setupmenu::SETUP_MENU, &mut menu_buf, true, menu_out);
 
+            if let Some(cmd) = exec {
+                for c in cmd.bytes().chain(*b"\r\n") {
+                    menu.input_byte(c);
+                }
+                menu.context.flush(&mut stdio).await?;
+                stdio.send_exit_status(0).await?;
+                return Ok(())
+            }
+
             // bodge
             for c in "help\r\n".bytes() {
                 menu.input_byte(c);
@@ -22,6 +22,9 @@ pub struct SSHClient<'a> {
 }
 
 impl<'a> SSHClient<'a> {
+    /// Creates a new client.
+    ///
+    /// Buffer sizes are chosen by the application, see [`Runner::new()`].
     pub fn new(inbuf: &'a mut [u8], outbuf: &'a mut [u8],
         ) -> Result<Self> {
         let runner = Runner::new_client(inbuf, outbuf)?;
@@ -36,6 +39,26 @@ impl<'a> SSHClient<'a> {
         self.sunset.run(rsock, wsock).await
     }
 
+    /// Informs the session of the current time, in seconds.
+    ///
+    /// Should be called periodically from an application timer to drive
+    /// rekeying and keepalives, see [`Runner::set_time()`].
+    /// Returns [`Error::Timeout`] if the peer has stopped responding,
+    /// the application should then close the connection.
+    pub async fn set_time(&self, now: u64) -> Result<()> {
+        self.sunset.set_time(now).await
+    }
+
+    /// Sends keepalives when the connection is idle, see [`Runner::set_keepalive()`].
+    pub async fn set_keepalive(&self, interval: u64, max_missed: u32) {
+        self.sunset.set_keepalive(interval, max_missed).await
+    }
+
+    /// Sends a debug message to the peer, see [`Runner::send_debug()`].
+    pub async fn send_debug(&self, message: &str, always_display: bool) -> Result<()> {
+        self.sunset.send_debug(message, always_display).await
+    }
+
     /// Returns an event from the SSH Session
     ///
     /// Note that the returned `ProgressHolder` holds a mutex over the session,
@@ -62,7 +85,7 @@ impl<'a> SSHClient<'a> {
         Ok((cstd, cerr))
     }
 
-    pub async fn open_session_pty(&'a self) -> Result<ChanInOut<'_, 'a>> {
+    pub async fn open_session_pty(&self) -> Result<ChanInOut<'_, 'a>> {
         let chan = self.sunset.with_runner(|runner| {
             runner.open_client_session()
         }).await?;
@@ -72,4 +95,59 @@ impl<'a> SSHClient<'a> {
         let cstd = ChanInOut::new(num, ChanData::Normal, &self.sunset);
         Ok(cstd)
     }
+
+    /// Opens a session running a shell, with a PTY if `pty` is given.
+    ///
+    /// See [`Runner::open_shell()`]. The server's reply is returned from
+    /// [`progress()`](Self::progress) as a [`CliEvent::ChanRequestResult`].
+    /// Returns stdin/stdout, and stderr when there is no PTY.
+    pub async fn open_shell(&self, pty: Option<Pty>)
+    -> Result<(ChanInOut<'_, 'a>, Option<ChanIn<'_, 'a>>)> {
+        let is_pty = pty.is_some();
+        let chan = self.sunset.with_runner(|runner| {
+            runner.open_shell(pty)
+        }).await?;
+
+        let num = chan.num();
+        if is_pty {
+            self.sunset.add_channel(chan, 1).await?;
+            Ok((ChanInOut::new(num, ChanData::Normal, &self.sunset), None))
+        } else {
+            self.sunset.add_channel(chan, 2).await?;
+            let cstd = ChanInOut::new(num, ChanData::Normal, &self.sunset);
+            let cerr = ChanIn::new(num, ChanData::Stderr, &self.sunset);
+            Ok((cstd, Some(cerr)))
+        }
+    }
+
+    /// Returns a [`ChanInOut`] for a channel opened by the server.
+    ///
+    /// `ch` is the [`ChanHandle`] from accepting an incoming channel, such as
+    /// [`CliEvent::OpenAgent`](sunset::event::CliEvent::OpenAgent).
+    pub async fn chan_io(&self, ch: ChanHandle) -> Result<ChanInOut<'_, 'a>> {
+        let num = ch.num();
+        self.sunset.add_channel(ch, 1).await?;
+        Ok(ChanInOut::new(num, ChanData::Normal, &self.sunset))
+    }
+
+    /// Reads stderr data (`SSH_MSG_CHANNEL_EXTENDED_DATA`) from a session channel.
+    ///
+    /// `ch` is a stream from [`open_session_pty()`][Self::open_session_pty] etc,
+    /// regular reads from `ch` only return stdout. Returns 0 on EOF.
+    pub async fn read_stderr(&self, ch: &ChanInOut<'_, 'a>, buf: &mut [u8]) -> Result<usize> {
+        self.sunset.read_channel(ch.num(), ChanData::Stderr, buf).await
+    }
+
+    /// Opens a `direct-tcpip` forwarded connection to `host:port` from the server.
+    pub async fn open_direct_tcpip(&self, host: &str, port: u16,
+        orig_host: &str, orig_port: u16) -> Result<ChanInOut<'_, 'a>> {
+        let chan = self.sunset.with_runner(|runner| {
+            runner.open_direct_tcpip(host, port, orig_host, orig_port)
+        }).await?;
+
+        let num = chan.num();
+        self.sunset.add_channel(chan, 1).await?;
+        let cstd = ChanInOut::new(num, ChanData::Normal, &self.sunset);
+        Ok(cstd)
+    }
 }
@@ -6,7 +6,9 @@ use embedded_io_async::{Read, Write, ErrorType};
 
 use crate::*;
 use embassy_sunset::EmbassySunset;
-use sunset::{Result, ChanData, ChanNum};
+use heapless::String;
+
+use sunset::{config, Result, ChanData, ChanNum};
 
 /// Common implementation
 struct ChanIO<'g, 'a> {
@@ -89,6 +91,11 @@ impl<'g, 'a> ChanInOut<'g, 'a> {
         })
     }
 
+    /// Returns the local channel number
+    pub fn num(&self) -> ChanNum {
+        self.0.num
+    }
+
     /// A future that waits until the channel closes
     pub async fn until_closed(&self) -> Result<()> {
         self.0.until_closed().await
@@ -100,6 +107,42 @@ impl<'g, 'a> ChanInOut<'g, 'a> {
     pub async fn term_window_change(&self, winch: sunset::packets::WinChange) -> Result<()> {
         self.0.sunset.term_window_change(self.0.num, winch).await
     }
+
+    /// Send a break, see [`Runner::term_break()`](sunset::Runner::term_break)
+    ///
+    /// Only applicable to client session channels
+    pub async fn term_break(&self, length: u32) -> Result<()> {
+        self.0.sunset.term_break(self.0.num, length).await
+    }
+
+    /// Send a signal, see [`Runner::send_signal()`](sunset::Runner::send_signal)
+    ///
+    /// Only applicable to client session channels
+    pub async fn send_signal(&self, signal: &str) -> Result<()> {
+        self.0.sunset.send_signal(self.0.num, signal).await
+    }
+
+    /// Send the exit status of a command
+    ///
+    /// Only applicable to server session channels.
+    pub async fn send_exit_status(&self, status: u32) -> Result<()> {
+        self.0.sunset.send_exit_status(self.0.num, status).await
+    }
+
+    /// The exit status of the remote command
+    ///
+    /// Only applicable to client session channels. Returns `None`
+    /// if the server hasn't sent an exit status.
+    pub async fn exit_status(&self) -> Result<Option<u32>> {
+        self.0.sunset.exit_status(self.0.num).await
+    }
+
+    /// The name of the signal that terminated the remote command, such as "TERM"
+    ///
+    /// Only applicable to client session channels.
+    pub async fn exit_signal(&self) -> Result<Option<String<{ config::MAX_SIGNAL_NAME }>>> {
+        self.0.sunset.exit_signal(self.0.num).await
+    }
 }
 
 impl<'g, 'a> ChanIn<'g, 'a> {
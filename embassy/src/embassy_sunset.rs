@@ -20,8 +20,10 @@ use atomic_polyfill::AtomicUsize;
 
 use pin_utils::pin_mut;
 
+use heapless::String;
+
 use sunset::{error, ChanData, ChanHandle, ChanNum, Error, Result, Runner};
-use sunset::config::MAX_CHANNELS;
+use sunset::config::{self, MAX_CHANNELS};
 use sunset::event::Event;
 
 #[cfg(feature = "multi-thread")]
@@ -485,12 +487,59 @@ impl<'a> EmbassySunset<'a> {
         }).await
     }
 
+    /// See [`Runner::set_time()`]
+    pub async fn set_time(&self, now: u64) -> Result<()> {
+        self.with_runner(|r| r.set_time(now)).await
+    }
+
+    /// See [`Runner::set_keepalive()`]
+    pub async fn set_keepalive(&self, interval: u64, max_missed: u32) {
+        self.with_runner(|r| r.set_keepalive(interval, max_missed)).await
+    }
+
+    /// See [`Runner::send_debug()`]
+    pub async fn send_debug(&self, message: &str, always_display: bool) -> Result<()> {
+        self.with_runner(|r| r.send_debug(message, always_display)).await
+    }
+
     pub async fn term_window_change(&self, num: ChanNum, winch: sunset::packets::WinChange) -> Result<()> {
         let mut inner = self.inner.lock().await;
         let (runner, h, _) = inner.fetch(num)?;
         runner.term_window_change(h, winch)
     }
 
+    pub async fn term_break(&self, num: ChanNum, length: u32) -> Result<()> {
+        let mut inner = self.inner.lock().await;
+        let (runner, h, _) = inner.fetch(num)?;
+        runner.term_break(h, length)
+    }
+
+    pub async fn send_signal(&self, num: ChanNum, signal: &str) -> Result<()> {
+        let mut inner = self.inner.lock().await;
+        let (runner, h, _) = inner.fetch(num)?;
+        runner.send_signal(h, signal)
+    }
+
+    pub async fn send_exit_status(&self, num: ChanNum, status: u32) -> Result<()> {
+        let mut inner = self.inner.lock().await;
+        let (runner, h, _) = inner.fetch(num)?;
+        runner.send_exit_status(h, status)
+    }
+
+    pub async fn exit_status(&self, num: ChanNum) -> Result<Option<u32>> {
+        let mut inner = self.inner.lock().await;
+        let (runner, h, _) = inner.fetch(num)?;
+        Ok(runner.channel_exit_status(h))
+    }
+
+    pub async fn exit_signal(&self, num: ChanNum)
+        -> Result<Option<String<{ config::MAX_SIGNAL_NAME }>>> {
+        let mut inner = self.inner.lock().await;
+        let (runner, h, _) = inner.fetch(num)?;
+        // OK unwrap: same capacity as the source
+        Ok(runner.channel_exit_signal(h).map(|s| String::try_from(s).unwrap()))
+    }
+
     /// Adds a new channel handle provided by sunset core.
     ///
     /// EmbassySunset will take ownership of the handle. An initial refcount
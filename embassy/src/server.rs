@@ -19,6 +19,9 @@ pub struct SSHServer<'a> {
 }
 
 impl<'a> SSHServer<'a> {
+    /// Creates a new server.
+    ///
+    /// Buffer sizes are chosen by the application, see [`Runner::new()`].
     // May return an error if RNG fails
     pub fn new(inbuf: &'a mut [u8], outbuf: &'a mut [u8],
         ) -> Result<Self> {
@@ -34,6 +37,26 @@ impl<'a> SSHServer<'a> {
         self.sunset.run(rsock, wsock).await
     }
 
+    /// Informs the session of the current time, in seconds.
+    ///
+    /// Should be called periodically from an application timer to drive
+    /// rekeying and keepalives, see [`Runner::set_time()`].
+    /// Returns [`Error::Timeout`] if the peer has stopped responding,
+    /// the application should then close the connection.
+    pub async fn set_time(&self, now: u64) -> Result<()> {
+        self.sunset.set_time(now).await
+    }
+
+    /// Sends keepalives when the connection is idle, see [`Runner::set_keepalive()`].
+    pub async fn set_keepalive(&self, interval: u64, max_missed: u32) {
+        self.sunset.set_keepalive(interval, max_missed).await
+    }
+
+    /// Sends a debug message to the peer, see [`Runner::send_debug()`].
+    pub async fn send_debug(&self, message: &str, always_display: bool) -> Result<()> {
+        self.sunset.send_debug(message, always_display).await
+    }
+
     /// Returns an event from the SSH Session
     ///
     /// Note that the returned `ProgressHolder` holds a mutex over the session,
@@ -54,6 +77,10 @@ impl<'a> SSHServer<'a> {
     /// data type.
     /// `ch` is the [`ChanHandle`] provided from accepting a channel open [`ServEvent`].
     /// methods.
+    ///
+    /// Once the channel is closed reads return EOF and writes fail. The channel
+    /// number isn't reused until all returned streams are dropped, so a stale
+    /// stream can't access a later channel.
     pub async fn stdio(&self, ch: ChanHandle) -> Result<ChanInOut<'_, 'a>> {
         let num = ch.num();
         self.sunset.add_channel(ch, 1).await?;
@@ -75,5 +102,11 @@ impl<'a> SSHServer<'a> {
         Ok((i, e))
     }
 
-    // TODO: add stdio_stderr()
+    /// Writes stderr data (`SSH_MSG_CHANNEL_EXTENDED_DATA`) to a channel.
+    ///
+    /// `ch` is a stream from [`stdio()`][Self::stdio], this is an alternative
+    /// to the separate stderr stream from [`stdio_stderr()`][Self::stdio_stderr].
+    pub async fn write_stderr(&self, ch: &ChanInOut<'_, 'a>, buf: &[u8]) -> Result<usize> {
+        self.sunset.write_channel(ch.num(), ChanData::Stderr, buf).await
+    }
 }
@@ -43,11 +43,22 @@ impl<'a> AuthSigMsg<'a> {
             u,
         }
     }
+
+    /// Returns the signature algorithm name for a public key request.
+    ///
+    /// This is needed by agents to select RSA signature hash flags.
+    pub fn sig_algorithm(&self) -> Option<&str> {
+        match &self.u.method {
+            packets::AuthMethod::PubKey(m) => Some(m.sig_algo),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
 pub enum AuthType {
     Password,
     PubKey,
+    KeyboardInteractive,
 }
 
@@ -0,0 +1,211 @@
+//! OpenSSH certificates.
+//!
+//! Certificates are a public key signed by a certificate authority (CA) key,
+//! restricted to a type (user or host), a list of principals and a validity
+//! period. See [PROTOCOL.certkeys](https://cvsweb.openbsd.org/src/usr.bin/ssh/PROTOCOL.certkeys?annotate=HEAD).
+
+#[allow(unused_imports)]
+use {
+    crate::error::{Error, Result, TrapBug},
+    log::{debug, error, info, log, trace, warn},
+};
+
+use crate::*;
+use packets::{Ed25519Cert, PubKey, Signature};
+use sshnames::*;
+use sshwire::{SSHEncode, SSHSink, WireResult};
+
+/// The signed portion of a certificate, everything prior to the signature.
+struct CertSignedData<'a>(&'a Ed25519Cert<'a>);
+
+impl SSHEncode for CertSignedData<'_> {
+    fn enc(&self, s: &mut dyn SSHSink) -> WireResult<()> {
+        let c = self.0;
+        SSH_NAME_ED25519_CERT.enc(s)?;
+        c.nonce.enc(s)?;
+        c.key.enc(s)?;
+        c.serial.enc(s)?;
+        c.cert_type.enc(s)?;
+        c.key_id.enc(s)?;
+        c.principals.enc(s)?;
+        c.valid_after.enc(s)?;
+        c.valid_before.enc(s)?;
+        c.critical_options.enc(s)?;
+        c.extensions.enc(s)?;
+        c.reserved.enc(s)?;
+        c.signature_key.enc(s)
+    }
+}
+
+/// Iterates over a packed list of SSH strings.
+fn packed_strings(mut b: &[u8]) -> impl Iterator<Item = Result<&[u8]>> {
+    core::iter::from_fn(move || {
+        if b.is_empty() {
+            return None
+        }
+        let l = b.get(..4).map(|l| u32::from_be_bytes(l.try_into().unwrap()) as usize);
+        let v = l.and_then(|l| b.get(4..).and_then(|r| r.get(..l)));
+        let Some(v) = v else {
+            // stop after a truncated string
+            b = &[];
+            return Some(Err(Error::BadCert { reason: "Bad encoding" }))
+        };
+        b = &b[4 + v.len()..];
+        Some(Ok(v))
+    })
+}
+
+impl<'a> Ed25519Cert<'a> {
+    /// Checks that the certificate is valid and signed by `ca`.
+    ///
+    /// `cert_type` is [`SSH_CERT_TYPE_HOST`] or [`SSH_CERT_TYPE_USER`].
+    /// `principal` is the hostname or username, it must be listed in the
+    /// certificate unless the list is empty. `now` is seconds since
+    /// the Unix epoch.
+    ///
+    /// Certificates with critical options are rejected.
+    /// Returns [`Error::BadCert`] on failure.
+    pub fn check(&self, ca: &PubKey, cert_type: u32, principal: &str, now: u64) -> Result<()> {
+        if self.cert_type != cert_type {
+            return Err(Error::BadCert { reason: "Wrong certificate type" })
+        }
+
+        let signer: PubKey = sshwire::read_ssh(self.signature_key.0, None)
+            .map_err(|_| Error::BadCert { reason: "Bad CA key" })?;
+        if matches!(signer, PubKey::Ed25519Cert(_)) {
+            return Err(Error::BadCert { reason: "CA key is a certificate" })
+        }
+        if &signer != ca {
+            return Err(Error::BadCert { reason: "Untrusted CA" })
+        }
+
+        let sig: Signature = sshwire::read_ssh(self.signature.0, None)
+            .map_err(|_| Error::BadCert { reason: "Bad signature" })?;
+        let sig_type = sig.sig_type()
+            .map_err(|_| Error::BadCert { reason: "Unknown signature type" })?;
+        sig_type.verify(&signer, &CertSignedData(self), &sig)
+            .map_err(|_| Error::BadCert { reason: "Bad signature" })?;
+
+        if now < self.valid_after || now >= self.valid_before {
+            debug!("Certificate valid {}..{}, now {now}", self.valid_after, self.valid_before);
+            return Err(Error::BadCert { reason: "Expired or not yet valid" })
+        }
+
+        if !self.critical_options.0.is_empty() {
+            return Err(Error::BadCert { reason: "Unsupported critical option" })
+        }
+
+        if !self.principals.0.is_empty() {
+            let mut found = false;
+            for p in packed_strings(self.principals.0) {
+                found |= p? == principal.as_bytes();
+            }
+            if !found {
+                return Err(Error::BadCert { reason: "Principal not listed" })
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the certificate's principals.
+    ///
+    /// An empty list means any principal is allowed.
+    pub fn principals(&self) -> impl Iterator<Item = Result<&'a [u8]>> {
+        packed_strings(self.principals.0)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use crate::cert::*;
+    use crate::sunsetlog::*;
+    use crate::{KeyType, SignKey};
+    use crate::sign::SigType;
+    use packets::Ed25519PubKey;
+    use sshwire::{BinString, Blob, TextString};
+
+    /// Creates a certificate for `key` signed by `ca`.
+    ///
+    /// The returned encoded `PubKey` is written to `buf`.
+    pub(crate) fn make_cert<'b>(buf: &'b mut [u8], key: &SignKey, ca: &SignKey,
+        cert_type: u32, principals: &[&str], valid: (u64, u64)) -> &'b [u8] {
+        let PubKey::Ed25519(k) = key.pubkey() else { panic!() };
+        let mut pbuf = [0u8; 200];
+        let mut l = 0;
+        for p in principals {
+            l += sshwire::write_ssh(&mut pbuf[l..], p).unwrap();
+        }
+        let mut cabuf = [0u8; 200];
+        let cal = sshwire::write_ssh(&mut cabuf, &ca.pubkey()).unwrap();
+
+        let mut cert = Ed25519Cert {
+            nonce: BinString(&[7; 32]),
+            key: Ed25519PubKey { key: Blob(k.key.0) },
+            serial: 1,
+            cert_type,
+            key_id: TextString(b"test"),
+            principals: BinString(&pbuf[..l]),
+            valid_after: valid.0,
+            valid_before: valid.1,
+            critical_options: BinString(&[]),
+            extensions: BinString(&[]),
+            reserved: BinString(&[]),
+            signature_key: BinString(&cabuf[..cal]),
+            signature: BinString(&[]),
+        };
+        let sig = ca.sign(&CertSignedData(&cert)).unwrap();
+        let mut sigbuf = vec![0u8; 600];
+        let sl = sshwire::write_ssh(&mut sigbuf, &Signature::from(&sig)).unwrap();
+        cert.signature = BinString(&sigbuf[..sl]);
+
+        let l = sshwire::write_ssh(buf, &PubKey::Ed25519Cert(cert)).unwrap();
+        &buf[..l]
+    }
+
+    #[test]
+    fn check_cert() {
+        init_test_log();
+        let key = SignKey::generate(KeyType::Ed25519, None).unwrap();
+        let ca = SignKey::generate(KeyType::Ed25519, None).unwrap();
+        let other = SignKey::generate(KeyType::Ed25519, None).unwrap();
+        let mut buf = vec![0u8; 1000];
+        let b = make_cert(&mut buf, &key, &ca, SSH_CERT_TYPE_HOST,
+            &["host1", "host2"], (100, 200));
+
+        let pk: PubKey = sshwire::read_ssh(b, None).unwrap();
+        assert_eq!(pk.algorithm_name(), Ok(SSH_NAME_ED25519_CERT));
+        let PubKey::Ed25519Cert(c) = &pk else { panic!() };
+        let PubKey::Ed25519(k) = key.pubkey() else { panic!() };
+        assert_eq!(c.key, k);
+        assert_eq!(c.principals().collect::<Result<Vec<_>>>().unwrap(),
+            [b"host1".as_slice(), b"host2"]);
+
+        let capk = ca.pubkey();
+        c.check(&capk, SSH_CERT_TYPE_HOST, "host2", 150).unwrap();
+        let bad = |r: Result<()>| matches!(r, Err(Error::BadCert { .. }));
+        assert!(bad(c.check(&capk, SSH_CERT_TYPE_USER, "host2", 150)));
+        assert!(bad(c.check(&capk, SSH_CERT_TYPE_HOST, "host3", 150)));
+        assert!(bad(c.check(&capk, SSH_CERT_TYPE_HOST, "host2", 99)));
+        assert!(bad(c.check(&capk, SSH_CERT_TYPE_HOST, "host2", 200)));
+        assert!(bad(c.check(&other.pubkey(), SSH_CERT_TYPE_HOST, "host2", 150)));
+
+        // host key signatures are made by the certified key
+        let msg = packets::Ignore { data: BinString(b"msg") };
+        let sig = key.sign(&msg).unwrap();
+        let sig = Signature::from(&sig);
+        SigType::Ed25519Cert.verify(&pk, &msg, &sig).unwrap();
+        assert!(SigType::Ed25519Cert.verify(&key.pubkey(), &msg, &sig).is_err());
+
+        // modified certificate
+        let mut c2 = c.clone();
+        c2.serial = 2;
+        assert!(bad(c2.check(&capk, SSH_CERT_TYPE_HOST, "host2", 150)));
+
+        // no principals allows any
+        let mut buf = vec![0u8; 1000];
+        let b = make_cert(&mut buf, &key, &ca, SSH_CERT_TYPE_HOST, &[], (0, u64::MAX));
+        let PubKey::Ed25519Cert(c) = sshwire::read_ssh(b, None).unwrap() else { panic!() };
+        c.check(&capk, SSH_CERT_TYPE_HOST, "anyhost", 150).unwrap();
+    }
+}
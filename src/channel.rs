@@ -29,6 +29,12 @@ use snafu::ErrorCompat;
 pub(crate) struct Channels {
     ch: [Option<Channel>; config::MAX_CHANNELS],
     is_client: bool,
+    /// Advertised to the peer for new channels
+    recv_max_packet: usize,
+    /// Limit for the peer's `max_packet`, our output buffer capacity
+    send_max_packet: usize,
+    /// A session from `open_shell()` awaiting confirmation, with its PTY
+    pending_shell: Option<(ChanNum, Option<Pty>)>,
 }
 
 impl Channels {
@@ -36,16 +42,43 @@ impl Channels {
         Channels {
             ch: Default::default(),
             is_client,
+            recv_max_packet: config::DEFAULT_MAX_PACKET,
+            send_max_packet: usize::MAX,
+            pending_shell: None,
         }
     }
 
+    /// Sets the `max_packet` advertised to the peer for new channels.
+    pub(crate) fn set_recv_max_packet(&mut self, max_packet: usize) {
+        self.recv_max_packet = max_packet
+    }
+
+    /// Sets the largest packet we will send, regardless of the peer's `max_packet`.
+    pub(crate) fn set_send_max_packet(&mut self, max_packet: usize) {
+        self.send_max_packet = max_packet
+    }
+
+    /// Returns the send direction for a peer's channel parameters,
+    /// or `None` if they are unusable.
+    fn send_dir(&self, num: u32, max_packet: u32, window: u32) -> Option<ChanDir> {
+        if max_packet == 0 {
+            debug!("Peer channel max_packet is 0");
+            return None
+        }
+        Some(ChanDir {
+            num,
+            max_packet: (max_packet as usize).min(self.send_max_packet),
+            window: window as usize,
+        })
+    }
+
     pub fn open<'b>(
         &mut self,
         ty: packets::ChannelOpenType<'b>,
     ) -> Result<(ChanNum, Packet<'b>)> {
         let num = self.unused_chan()?;
 
-        let chan = Channel::new(num, (&ty).into());
+        let chan = Channel::new(num, (&ty).try_into()?, self.recv_max_packet);
         let p = packets::ChannelOpen {
             sender_num: num.0,
             initial_window: chan.recv.window as u32,
@@ -58,6 +91,19 @@ impl Channels {
         Ok((ch.num(), p))
     }
 
+    /// Opens a session that requests a shell once confirmed by the peer,
+    /// with a PTY if given.
+    ///
+    /// Only one can be pending at a time, otherwise fails with `BadUsage`.
+    pub fn open_shell(&mut self, pty: Option<Pty>) -> Result<(ChanNum, Packet<'static>)> {
+        if self.pending_shell.is_some() {
+            return error::BadUsage.fail()
+        }
+        let (num, p) = self.open(packets::ChannelOpenType::Session)?;
+        self.pending_shell = Some((num, pty));
+        Ok((num, p))
+    }
+
     /// Returns a `Channel` for a local number, any state including `InOpen`.
     fn get_any(&self, num: ChanNum) -> Result<&Channel> {
         self.ch
@@ -91,7 +137,7 @@ impl Channels {
             .ok_or(error::BadChannel { num }.build())
     }
 
-    fn get_mut(&mut self, num: ChanNum) -> Result<&mut Channel> {
+    pub(crate) fn get_mut(&mut self, num: ChanNum) -> Result<&mut Channel> {
         let ch = self.get_any_mut(num)?;
 
         match ch.state {
@@ -105,8 +151,14 @@ impl Channels {
     /// Must be called when an application has finished with a channel.
     pub fn done(&mut self, num: ChanNum) -> Result<()> {
         let ch = self.get_mut(num)?;
-        debug_assert!(!ch.app_done);
+        if ch.app_done {
+            return error::BadChannel { num }.fail()
+        }
         ch.app_done = true;
+        if matches!(ch.state, ChanState::PendingDone) {
+            // Peer has already closed
+            self.remove(num)?;
+        }
         Ok(())
     }
 
@@ -143,12 +195,9 @@ impl Channels {
     /// Creates a new channel in InOpen state.
     fn reserve_chan(&mut self, co: &ChannelOpen) -> Result<&mut Channel> {
         let num = self.unused_chan()?;
-        let mut chan = Channel::new(num, (&co.ty).into());
-        chan.send = Some(ChanDir {
-            num: co.sender_num,
-            max_packet: co.max_packet as usize,
-            window: co.initial_window as usize,
-        });
+        let mut chan = Channel::new(num, (&co.ty).try_into()?, self.recv_max_packet);
+        chan.send = self.send_dir(co.sender_num, co.max_packet, co.initial_window);
+        debug_assert!(chan.send.is_some());
         chan.state = ChanState::InOpen;
 
         let ch = &mut self.ch[num.0 as usize];
@@ -156,66 +205,109 @@ impl Channels {
         Ok(ch.as_mut().unwrap())
     }
 
-    /// Returns the channel data packet to send.
+    /// Accounts for `len` bytes of data to be sent on a channel, reducing
+    /// the send window.
     ///
-    /// Caller has already checked valid length with send_allowed(), and
-    /// validated `dt`.
-    /// Don't call with zero length data.
-    pub(crate) fn send_data<'b>(
+    /// Caller has already checked `len` against the window and max_packet
+    /// with `send_allowed()`, and validated `dt`. `len` must be non-zero.
+    ///
+    /// Returns the remote channel number to send to.
+    pub(crate) fn send_data(
         &mut self,
         num: ChanNum,
-        dt: ChanData,
-        data: &'b [u8],
-    ) -> Result<Packet<'b>> {
-        debug_assert!(!data.is_empty());
+        len: usize,
+    ) -> Result<u32> {
+        debug_assert!(len > 0);
 
         let ch = self.get_mut(num)?;
         let send = ch.send.as_mut().trap()?;
-        if data.len() > send.max_packet || data.len() > send.window {
+        if len > send.max_packet || len > send.window {
             trace!("data len {}, max {}, window {}",
-                data.len(), send.max_packet, send.window);
+                len, send.max_packet, send.window);
             return Err(Error::bug());
         }
-        send.window -= data.len();
+        send.window -= len;
         trace!("send_data: new window {}", send.window);
 
-        let data = BinString(data);
-        let p = match dt {
-            ChanData::Normal => packets::ChannelData { num: send.num, data }.into(),
-            ChanData::Stderr => packets::ChannelDataExt {
-                num: send.num, code: sshnames::SSH_EXTENDED_DATA_STDERR, data }.into(),
-        };
-
-        Ok(p)
+        Ok(send.num)
     }
 
     /// Informs the channel layer that an incoming packet has been read out,
     /// so a window adjustment can be sent.
-    pub(crate) fn finished_input(&mut self, num: ChanNum, len: usize,
+    ///
+    /// With `hold` set (during a key exchange) the adjustment is deferred
+    /// until [`send_window_adjusts()`](Self::send_window_adjusts).
+    pub(crate) fn finished_input(&mut self, num: ChanNum, len: usize, hold: bool,
         s: &mut TrafSend) -> Result<()> {
         let ch = self.get_mut(num)?;
         ch.finished_input(len);
-        if let Some(w) = ch.check_window_adjust()? {
-            s.send(w)?;
+        if !hold {
+            if let Some(w) = ch.check_window_adjust()? {
+                s.send(w)?;
+            }
         }
         Ok(())
     }
 
+    /// Sends any window adjustments held back by `finished_input()`,
+    /// once a key exchange completes.
+    pub(crate) fn send_window_adjusts(&mut self, s: &mut TrafSend) -> Result<()> {
+        for ch in self.ch.iter_mut().flatten() {
+            if ch.send.is_none() || ch.sent_close {
+                continue
+            }
+            if let Some(w) = ch.check_window_adjust()? {
+                s.send(w)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns whether `num` is an open channel that the application
+    /// has not yet released with `done()`.
+    pub(crate) fn is_valid(&self, num: ChanNum) -> bool {
+        self.get(num).is_ok_and(|c| !c.app_done)
+    }
+
     pub(crate) fn have_recv_eof(&self, num: ChanNum) -> bool {
-        self.get(num).map_or(false, |c| c.have_recv_eof())
+        self.get(num).is_ok_and(|c| c.have_recv_eof())
     }
 
     pub(crate) fn is_closed(&self, num: ChanNum) -> bool {
-        self.get(num).map_or(false, |c| c.is_closed())
+        self.get(num).is_ok_and(|c| c.is_closed())
+    }
+
+
+    pub(crate) fn exit_status(&self, num: ChanNum) -> Option<u32> {
+        self.get(num).ok().and_then(|c| c.exit_status)
+    }
+
+    pub(crate) fn exit_signal(&self, num: ChanNum) -> Option<&str> {
+        self.get(num).ok().and_then(|c| c.exit_signal.as_deref())
     }
 
+    /// Returns the reason and description if the peer refused the channel.
+    pub(crate) fn open_failure(&self, num: ChanNum) -> Option<(u32, &str)> {
+        let (reason, desc) = self.get(num).ok()?.open_failure.as_ref()?;
+        Some((*reason, desc.as_str()))
+    }
 
     pub(crate) fn send_allowed(&self, num: ChanNum) -> Option<usize> {
         self.get(num).map_or(Some(0), |c| c.send_allowed())
     }
 
+    /// Returns whether an open session has agent forwarding, requested
+    /// by a client or accepted by a server.
+    pub(crate) fn agent_forwarding(&self) -> bool {
+        self.ch.iter().flatten().any(|c| c.agent_forward && !c.is_closed())
+    }
+
     pub(crate) fn valid_send(&self, num: ChanNum, dt: ChanData) -> bool {
-        self.get(num).map_or(false, |c| c.valid_send(dt))
+        self.get(num).is_ok_and(|c| c.valid_send(dt))
+    }
+
+    pub(crate) fn max_packet(&self, num: ChanNum) -> Result<MaxPacket> {
+        self.get(num)?.max_packet()
     }
 
     pub(crate) fn term_window_change(&self, num: ChanNum, winch: packets::WinChange,
@@ -238,6 +330,96 @@ impl Channels {
         }
     }
 
+    pub(crate) fn send_env(&self, num: ChanNum, name: &str, value: &str,
+        s: &mut TrafSend) -> Result<()> {
+        let ch = self.get(num)?;
+        match ch.ty {
+            ChanType::Session => Req::Env { name, value }.send(ch, s),
+            _ => error::BadChannelData.fail(),
+        }
+    }
+
+    /// Sends a signal to a client session's remote process
+    pub(crate) fn send_signal(&self, num: ChanNum, signal: &str,
+        s: &mut TrafSend) -> Result<()> {
+        if !sshnames::SSH_SIGNALS.contains(&signal) {
+            debug!("Unknown signal {signal:?}");
+            return Err(Error::BadName)
+        }
+        let ch = self.get(num)?;
+        match ch.ty {
+            ChanType::Session => Req::Signal(signal).send(ch, s),
+            _ => error::BadChannelData.fail(),
+        }
+    }
+
+    /// Sends the exit status of a server session's command
+    pub(crate) fn send_exit_status(&self, num: ChanNum, status: u32,
+        s: &mut TrafSend) -> Result<()> {
+        let ch = self.get(num)?;
+        match ch.ty {
+            ChanType::Session => Req::ExitStatus(status).send(ch, s),
+            _ => error::BadChannelData.fail(),
+        }
+    }
+
+    /// Sends the signal that terminated a server session's command
+    pub(crate) fn send_exit_signal(&self, num: ChanNum, signal: &str, core_dumped: bool,
+        msg: &str, s: &mut TrafSend) -> Result<()> {
+        let ch = self.get(num)?;
+        match ch.ty {
+            ChanType::Session => Req::ExitSignal { signal, core_dumped, msg }.send(ch, s),
+            _ => error::BadChannelData.fail(),
+        }
+    }
+
+    /// Sends EOF, no further data will be sent on the channel.
+    pub(crate) fn send_eof(&mut self, num: ChanNum, s: &mut TrafSend) -> Result<()> {
+        self.get_mut(num)?.send_eof(s)
+    }
+
+    /// Sends a channel close. The channel is freed once the peer's
+    /// close has also been received.
+    pub(crate) fn send_close(&mut self, num: ChanNum, s: &mut TrafSend) -> Result<()> {
+        self.get_mut(num)?.send_close(s)
+    }
+
+    /// Sends a subsystem request, the result is returned as a
+    /// [`CliEventId::ChanRequestResult`] event.
+    pub(crate) fn send_subsystem(&mut self, num: ChanNum, name: &str,
+        s: &mut TrafSend) -> Result<()> {
+        let ch = self.get_mut(num)?;
+        if !matches!(ch.ty, ChanType::Session) {
+            return error::BadChannelData.fail()
+        }
+        Req::Subsystem(name).send_want(ch, true, s)?;
+        ch.pending_replies += 1;
+        Ok(())
+    }
+
+    /// Returns the PTY request for `num` if it was opened with `open_shell()`.
+    fn take_pending_shell(&mut self, num: ChanNum) -> Option<Option<Pty>> {
+        match &self.pending_shell {
+            Some((n, _)) if *n == num => self.pending_shell.take().map(|(_, pty)| pty),
+            _ => None,
+        }
+    }
+
+    fn dispatch_reply(&mut self, num: ChanNum, success: bool) -> Result<DispatchEvent> {
+        let is_client = self.is_client;
+        let ch = self.get_mut(num)?;
+        if ch.pending_replies == 0 {
+            debug!("Ignoring unexpected channel reply");
+            return Ok(DispatchEvent::None)
+        }
+        ch.pending_replies -= 1;
+        if is_client {
+            Ok(DispatchEvent::CliEvent(CliEventId::ChanRequestResult { ch: num, success }))
+        } else {
+            Ok(DispatchEvent::None)
+        }
+    }
+
     fn dispatch_open(
         &mut self,
         p: &ChannelOpen<'_>,
@@ -248,7 +430,7 @@ impl Channels {
                 s.send(packets::ChannelOpenFailure {
                     num: p.sender_num,
                     reason: f as u32,
-                    desc: "".into(),
+                    desc: f.description().into(),
                     lang: "",
                 })?;
                 Ok(DispatchEvent::None)
@@ -272,9 +454,27 @@ impl Channels {
                 trace!("dispatch not server");
                 return Err(error::SSHProto.build().into());
             }
+            ChannelOpenType::AuthAgent if !self.is_client => {
+                trace!("dispatch not client");
+                return Err(error::SSHProto.build().into());
+            }
+            ChannelOpenType::AuthAgent if !self.agent_forwarding() => {
+                debug!("Rejecting agent channel, forwarding wasn't requested");
+                return Err(ChanFail::SSH_OPEN_ADMINISTRATIVELY_PROHIBITED.into());
+            }
+            // Incoming TCP forwarding isn't implemented
+            ChannelOpenType::DirectTcpip(_) | ChannelOpenType::ForwardedTcpip(_) => {
+                debug!("Rejecting tcpip channel");
+                return Err(ChanFail::SSH_OPEN_ADMINISTRATIVELY_PROHIBITED.into());
+            }
             _ => (),
        }
 
+        if p.max_packet == 0 {
+            debug!("Rejecting channel with max_packet 0");
+            return Err(ChanFail::SSH_OPEN_ADMINISTRATIVELY_PROHIBITED.into());
+        }
+
         // Reserve a channel
         let ch = self.reserve_chan(p)?;
 
@@ -284,12 +484,13 @@ impl Channels {
             ChannelOpenType::Session => {
                 Ok(DispatchEvent::ServEvent(ServEventId::OpenSession { ch: ch.num() } ))
             }
+            ChannelOpenType::AuthAgent => {
+                Ok(DispatchEvent::CliEvent(CliEventId::OpenAgent { ch: ch.num() } ))
+            }
             // ChannelOpenType::ForwardedTcpip(t) => b.open_tcp_forwarded(handle, t),
             // ChannelOpenType::DirectTcpip(t) => b.open_tcp_direct(handle, t),
-            ChannelOpenType::Unknown(_) => {
-                unreachable!()
-            }
-            _ => todo!(),
+            // Rejected above
+            _ => Err(Error::bug().into()),
         }
     }
 
@@ -302,7 +503,7 @@ impl Channels {
             s.send(packets::ChannelOpenFailure {
                 num: sender_num,
                 reason: failure as u32,
-                desc: "".into(),
+                desc: failure.description().into(),
                 lang: "",
             })?;
             Ok(())
@@ -322,23 +523,34 @@ impl Channels {
             }
 
             Packet::ChannelOpenConfirmation(p) => {
+                let send = self.send_dir(p.sender_num, p.max_packet, p.initial_window);
+                let shell = self.take_pending_shell(ChanNum(p.num));
                 let ch = self.get_any_mut(ChanNum(p.num))?;
                 match ch.state {
                     ChanState::Opening => {
                         debug_assert!(ch.send.is_none());
-                        ch.send = Some(ChanDir {
-                            num: p.sender_num,
-                            max_packet: p.max_packet as usize,
-                            window: p.initial_window as usize,
-                        });
+                        if send.is_none() {
+                            return error::SSHProto.fail()
+                        }
+                        ch.send = send;
 
                         match ch.ty {
                             ChanType::Session => {
-                                ev = DispatchEvent::CliEvent(CliEventId::SessionOpened(ch.num()));
+                                if let Some(pty) = shell {
+                                    // Result is returned as ChanRequestResult
+                                    if let Some(pty) = pty {
+                                        Req::Pty(pty).send(ch, s)?;
+                                    }
+                                    Req::Shell.send_want(ch, true, s)?;
+                                    ch.pending_replies += 1;
+                                } else {
+                                    ev = DispatchEvent::CliEvent(CliEventId::SessionOpened(ch.num()));
+                                }
                             },
                             ChanType::Tcp => {
                                 trace!("TODO tcp channel")
                             }
+                            ChanType::Agent => (),
                         }
 
                         ch.state = ChanState::Normal;
@@ -357,6 +569,18 @@ impl Channels {
                     trace!("open failure late?");
                     return error::SSHProto.fail();
                 } else {
+                    match ChanFail::from_u32(p.reason) {
+                        Some(f) => debug!("Channel {} open failed: {f:?}", p.num),
+                        None => debug!("Channel {} open failed: reason {}", p.num, p.reason),
+                    }
+                    let mut desc = String::new();
+                    for c in p.desc.as_str().unwrap_or("").chars() {
+                        if desc.push(c).is_err() {
+                            break
+                        }
+                    }
+                    self.get_any_mut(ChanNum(p.num))?.open_failure = Some((p.reason, desc));
+                    self.take_pending_shell(ChanNum(p.num));
                     self.remove(ChanNum(p.num))?;
                     // TODO event
                 }
@@ -367,7 +591,7 @@ impl Channels {
                 trace!("new window {}", send.window);
             }
             Packet::ChannelData(p) => {
-                self.get(ChanNum(p.num))?;
+                self.get_mut(ChanNum(p.num))?.recv_data(p.data.0.len())?;
                 // TODO check we are expecting input
                 let di = DataIn {
                     num: ChanNum(p.num),
@@ -377,6 +601,7 @@ impl Channels {
                 ev = DispatchEvent::Data(di);
             }
             Packet::ChannelDataExt(p) => {
+                self.get_mut(ChanNum(p.num))?.recv_data(p.data.0.len())?;
                 if !self.is_client || p.code != sshnames::SSH_EXTENDED_DATA_STDERR {
                     // Discard the data, sunset can't handle this
                     debug!("Ignoring unexpected dt data, code {}", p.code);
@@ -394,11 +619,14 @@ impl Channels {
             }
             Packet::ChannelEof(p) => {
                 let ch = self.get_mut(ChanNum(p.num))?;
-                ch.handle_eof(s)?;
+                ch.handle_eof()?;
             }
             Packet::ChannelClose(p) => {
                 let ch = self.get_mut(ChanNum(p.num))?;
                 ch.handle_close(s)?;
+                // Both sides have closed, the number can be reused
+                // once the application is done.
+                self.remove(ChanNum(p.num))?;
             }
             Packet::ChannelRequest(p) => {
                 let is_client = self.is_client;
@@ -409,11 +637,11 @@ impl Channels {
                     Err(_) => debug!("Ignoring request to unknown channel: {p:#?}"),
                 }
             }
-            Packet::ChannelSuccess(_p) => {
-                trace!("channel success, TODO");
+            Packet::ChannelSuccess(p) => {
+                ev = self.dispatch_reply(ChanNum(p.num), true)?;
             }
-            Packet::ChannelFailure(_p) => {
-                trace!("channel failure, TODO");
+            Packet::ChannelFailure(p) => {
+                ev = self.dispatch_reply(ChanNum(p.num), false)?;
             }
             _ => Error::bug_msg("unreachable")?,
         };
@@ -439,9 +667,12 @@ impl Channels {
         }
     }
 
-    pub fn resume_chanreq(&self, p: &Packet, success: bool, s: &mut TrafSend) -> Result<()> {
+    pub fn resume_chanreq(&mut self, p: &Packet, success: bool, s: &mut TrafSend) -> Result<()> {
         if let Packet::ChannelRequest(r) = p {
-            let ch = self.get(ChanNum(r.num))?;
+            let ch = self.get_mut(ChanNum(r.num))?;
+            if success && matches!(r.req, ChannelReqType::AuthAgentReq) {
+                ch.agent_forward = true;
+            }
             if r.want_reply {
                 let num = ch.send_num()?;
                 if success {
@@ -470,15 +701,19 @@ impl Channels {
 pub enum ChanType {
     Session,
     Tcp,
+    Agent,
 }
 
-impl From<&ChannelOpenType<'_>> for ChanType {
-    fn from(c: &ChannelOpenType) -> Self {
+impl TryFrom<&ChannelOpenType<'_>> for ChanType {
+    type Error = Error;
+    fn try_from(c: &ChannelOpenType) -> Result<Self> {
         match c {
-            ChannelOpenType::Session => ChanType::Session,
-            ChannelOpenType::DirectTcpip(_) => ChanType::Tcp,
-            ChannelOpenType::ForwardedTcpip(_) => ChanType::Tcp,
-            ChannelOpenType::Unknown(_) => unreachable!(),
+            ChannelOpenType::Session => Ok(ChanType::Session),
+            ChannelOpenType::DirectTcpip(_) => Ok(ChanType::Tcp),
+            ChannelOpenType::ForwardedTcpip(_) => Ok(ChanType::Tcp),
+            ChannelOpenType::AuthAgent => Ok(ChanType::Agent),
+            // Unknown types are rejected before reaching here
+            ChannelOpenType::Unknown(_) => Err(Error::bug()),
         }
     }
 }
@@ -499,21 +734,76 @@ pub struct Pty {
     pub modes: Vec<ModePair, { termmodes::NUM_MODES }>,
 }
 
+impl Pty {
+    /// Creates a `Pty` for a client session request.
+    ///
+    /// `modes` are `(opcode, value)` pairs, as listed in [`termmodes`].
+    /// Fails with [`Error::BadUsage`] for an invalid opcode or too many modes,
+    /// or [`Error::BadString`] if `term` is too long.
+    pub fn new(term: &str, cols: u32, rows: u32, width: u32, height: u32,
+        modes: impl IntoIterator<Item = (u8, u32)>) -> Result<Self> {
+        let term = term.try_into().map_err(|_| Error::BadString)?;
+        let mut m = Vec::new();
+        for (opcode, arg) in modes {
+            if opcode == termmodes::TTY_OP_END || opcode >= termmodes::TTY_OP_UNDEFINED {
+                return error::BadUsage.fail()
+            }
+            m.push(ModePair { opcode, arg }).map_err(|_| error::BadUsage.build())?;
+        }
+        Ok(Pty { term, cols, rows, width, height, modes: m })
+    }
+
+    /// Returns the wire `PtyReq`, with modes encoded into `buf`.
+    ///
+    /// A `buf` of [`termmodes::MAX_MODES_LEN`] is always sufficient.
+    pub fn pty_req<'b>(&'b self, buf: &'b mut [u8]) -> Result<packets::PtyReq<'b>> {
+        let len = self.modes.len() * 5 + 1;
+        let b = buf.get_mut(..len).ok_or(error::NoRoom.build())?;
+        for (m, c) in self.modes.iter().zip(b.chunks_exact_mut(5)) {
+            c[0] = m.opcode;
+            c[1..].copy_from_slice(&m.arg.to_be_bytes());
+        }
+        b[len - 1] = termmodes::TTY_OP_END;
+
+        Ok(packets::PtyReq {
+            term: TextString(self.term.as_bytes()),
+            cols: self.cols,
+            rows: self.rows,
+            width: self.width,
+            height: self.height,
+            modes: BinString(b),
+        })
+    }
+}
+
 impl TryFrom<&packets::PtyReq<'_>> for Pty {
     type Error = Error;
     fn try_from(p: &packets::PtyReq) -> Result<Self, Self::Error> {
-        debug!("TODO implement pty modes");
         let term = p.term.as_ascii()?.try_into().map_err(|_| Error::BadString)?;
+        let mut modes = Vec::new();
+        for c in p.modes.0.chunks(5) {
+            let opcode = c[0];
+            if opcode == termmodes::TTY_OP_END || opcode >= termmodes::TTY_OP_UNDEFINED {
+                break;
+            }
+            let arg = c.get(1..5).ok_or(error::SSHProto.build())?;
+            let arg = u32::from_be_bytes(arg.try_into().trap()?);
+            if modes.push(ModePair { opcode, arg }).is_err() {
+                debug!("Ignoring excess pty modes");
+                break;
+            }
+        }
         Ok(Pty {
             term,
             cols: p.cols,
             rows: p.rows,
             width: p.width,
             height: p.height,
-            modes: Vec::new(),
+            modes,
         })
     }
 }
+
 /// Like a `packets::ChannelReqType` but with storage.
 /// Lifetime-free variants have the packet part directly.
 #[derive(Debug)]
@@ -525,27 +815,28 @@ pub enum Req<'a> {
     Pty(Pty),
     WinChange(packets::WinChange),
     Break(packets::Break),
-    // Signal,
-    // ExitStatus,
-    // ExitSignal,
+    Env { name: &'a str, value: &'a str },
+    AuthAgent,
+    Signal(&'a str),
+    ExitStatus(u32),
+    ExitSignal { signal: &'a str, core_dumped: bool, msg: &'a str },
 }
 
 impl Req<'_> {
     pub(crate) fn send(self, ch: &Channel, s: &mut TrafSend) -> Result<()> {
-        let t;
+        self.send_want(ch, false, s)
+    }
+
+    /// Sends the request, with `want_reply` set as requested. The caller is responsible
+    /// for tracking the expected reply.
+    pub(crate) fn send_want(self, ch: &Channel, want_reply: bool, s: &mut TrafSend) -> Result<()> {
+        let pty;
+        let mut modes = [0u8; termmodes::MAX_MODES_LEN];
         let req = match self {
             Req::Shell => ChannelReqType::Shell,
-            Req::Pty(pty) => {
-                debug!("TODO implement pty modes");
-                t = pty.term;
-                ChannelReqType::Pty(packets::PtyReq {
-                    term: TextString(t.as_bytes()),
-                    cols: pty.cols,
-                    rows: pty.rows,
-                    width: pty.width,
-                    height: pty.height,
-                    modes: BinString(&[]),
-                })
+            Req::Pty(p) => {
+                pty = p;
+                ChannelReqType::Pty(pty.pty_req(&mut modes)?)
             }
             Req::Exec(cmd) => {
                 ChannelReqType::Exec(packets::Exec { command: cmd.into() })
@@ -555,12 +846,27 @@ impl Req<'_> {
             }
             Req::WinChange(rt) => ChannelReqType::WinChange(rt),
             Req::Break(rt) => ChannelReqType::Break(rt),
+            Req::AuthAgent => ChannelReqType::AuthAgentReq,
+            Req::Signal(sig) => ChannelReqType::Signal(packets::Signal { sig }),
+            Req::Env { name, value } => {
+                ChannelReqType::Env(packets::Env { name: name.into(), value: value.into() })
+            }
+            Req::ExitStatus(status) => {
+                ChannelReqType::ExitStatus(packets::ExitStatus { status })
+            }
+            Req::ExitSignal { signal, core_dumped, msg } => {
+                ChannelReqType::ExitSignal(packets::ExitSignal {
+                    signal,
+                    core: core_dumped,
+                    error: msg.into(),
+                    lang: "",
+                })
+            }
         };
 
         let p = ChannelRequest {
             num: ch.send_num()?,
-            // we aren't handling responses for anything
-            want_reply: false,
+            want_reply,
             req,
         };
         let p: Packet = p.into();
@@ -585,6 +891,17 @@ impl<'a, S: AsRef<str> + 'a> From<&'a SessionCommand<S>> for Req<'a> {
     }
 }
 
+/// Maximum data packet sizes for a channel, as negotiated at open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaxPacket {
+    /// Advertised to the peer, the largest channel data we will receive
+    /// in a single packet.
+    pub local: usize,
+    /// Advertised by the peer, the largest channel data we may send
+    /// in a single packet.
+    pub remote: usize,
+}
+
 /// Per-direction channel variables
 #[derive(Debug)]
 struct ChanDir {
@@ -628,6 +945,21 @@ pub(crate) struct Channel {
 
     full_window: usize,
 
+    /// Number of sent requests awaiting a success or failure reply
+    pending_replies: u32,
+
+    /// Exit status received from a server, client sessions only
+    exit_status: Option<u32>,
+    /// Exit signal name received from a server, client sessions only
+    exit_signal: Option<String<{ config::MAX_SIGNAL_NAME }>>,
+
+    /// Reason and description if the peer refused our channel open
+    open_failure: Option<(u32, String<{ config::MAX_OPEN_FAIL_DESC }>)>,
+
+    /// Agent forwarding has been requested by a client session, or
+    /// accepted by a server.
+    agent_forward: bool,
+
     /// Set once application has called `done()`. The channel
     /// will only be removed from the list
     /// (allowing channel number re-use) if `app_done` is set
@@ -635,7 +967,9 @@ pub(crate) struct Channel {
 }
 
 impl Channel {
-    fn new(num: ChanNum, ty: ChanType) -> Self {
+    fn new(num: ChanNum, ty: ChanType, max_packet: usize) -> Self {
+        // Allow at least one full packet in flight
+        let window = config::DEFAULT_WINDOW.max(max_packet);
         Channel {
             ty,
             state: ChanState::Opening,
@@ -643,13 +977,17 @@ impl Channel {
             sent_eof: false,
             recv: ChanDir {
                 num: num.0,
-                // TODO these should depend on SSH rx buffer size minus overhead
-                max_packet: config::DEFAULT_MAX_PACKET,
-                window: config::DEFAULT_WINDOW,
+                max_packet,
+                window,
             },
             send: None,
             pending_adjust: 0,
-            full_window: config::DEFAULT_WINDOW,
+            full_window: window,
+            pending_replies: 0,
+            exit_status: None,
+            exit_signal: None,
+            open_failure: None,
+            agent_forward: false,
             app_done: false,
         }
     }
@@ -667,6 +1005,14 @@ impl Channel {
         Ok(self.send.as_ref().trap()?.num)
     }
 
+    /// Negotiated maximum packet sizes, fails if channel is in progress opening
+    pub(crate) fn max_packet(&self) -> Result<MaxPacket> {
+        Ok(MaxPacket {
+            local: self.recv.max_packet,
+            remote: self.send.as_ref().trap()?.max_packet,
+        })
+    }
+
     /// Returns an open confirmation reply packet to send.
     /// Must be called with state of `InOpen`.
     fn open_done<'p>(&mut self) -> Result<Packet<'p>> {
@@ -716,6 +1062,11 @@ impl Channel {
             ChannelReqType::Shell => Ok(DispatchEvent::ServEvent(ServEventId::SessionShell)),
             ChannelReqType::Exec(_) => Ok(DispatchEvent::ServEvent(ServEventId::SessionExec)),
             ChannelReqType::Pty(_) => Ok(DispatchEvent::ServEvent(ServEventId::SessionPty)),
+            ChannelReqType::WinChange(_) => Ok(DispatchEvent::ServEvent(ServEventId::SessionWinch)),
+            ChannelReqType::Subsystem(_) => Ok(DispatchEvent::ServEvent(ServEventId::SessionSubsystem)),
+            ChannelReqType::AuthAgentReq => Ok(DispatchEvent::ServEvent(ServEventId::SessionAgentReq)),
+            ChannelReqType::Env(_) => Ok(DispatchEvent::ServEvent(ServEventId::SessionEnv)),
+            ChannelReqType::Break(_) => Ok(DispatchEvent::ServEvent(ServEventId::SessionBreak)),
             _ => {
                 if let ChannelReqType::Unknown(u) = &p.req {
                     warn!("Unknown channel req type \"{}\"", u)
@@ -742,10 +1093,15 @@ impl Channel {
         }
 
         match &p.req {
-            ChannelReqType::ExitStatus(_) => {
+            ChannelReqType::ExitStatus(e) => {
+                self.exit_status = Some(e.status);
                 Ok(DispatchEvent::CliEvent(CliEventId::SessionExit))
             }
-            ChannelReqType::ExitSignal(_sig) => {
+            ChannelReqType::ExitSignal(sig) => {
+                self.exit_signal = String::try_from(sig.signal).ok();
+                if self.exit_signal.is_none() {
+                    warn!("Long exit signal name discarded");
+                }
                 Ok(DispatchEvent::CliEvent(CliEventId::SessionExit))
             }
             _ => {
@@ -763,42 +1119,70 @@ impl Channel {
         }
     }
 
-    fn handle_eof(&mut self, s: &mut TrafSend) -> Result<()> {
-        //TODO: check existing state?
-        if !self.sent_eof {
+    fn send_eof(&mut self, s: &mut TrafSend) -> Result<()> {
+        if !self.sent_eof && !self.sent_close {
             s.send(packets::ChannelEof { num: self.send_num()? })?;
             self.sent_eof = true;
         }
-
-        self.state = ChanState::RecvEof;
-        // todo!();
         Ok(())
     }
 
-    fn handle_close(&mut self, s: &mut TrafSend) -> Result<()> {
-        //TODO: check existing state?
+    fn send_close(&mut self, s: &mut TrafSend) -> Result<()> {
         if !self.sent_close {
             s.send(packets::ChannelClose { num: self.send_num()? })?;
             self.sent_close = true;
         }
+        Ok(())
+    }
+
+    fn handle_eof(&mut self) -> Result<()> {
+        // The channel is half-closed. Our side can continue sending
+        // until the application sends its own EOF or close.
+        if !matches!(self.state, ChanState::Normal) {
+            debug!("Ignoring EOF in state {:?}", self.state);
+            return Ok(())
+        }
+        self.state = ChanState::RecvEof;
+        Ok(())
+    }
+
+    fn handle_close(&mut self, s: &mut TrafSend) -> Result<()> {
+        //TODO: check existing state?
+        self.send_close(s)?;
         self.state = ChanState::RecvClose;
         Ok(())
     }
 
+    /// Accounts for received data against our advertised window.
+    fn recv_data(&mut self, len: usize) -> Result<()> {
+        if len > self.recv.window || len > self.recv.max_packet {
+            debug!("Channel data len {len} exceeds window {} or max packet {}",
+                self.recv.window, self.recv.max_packet);
+            return error::SSHProto.fail()
+        }
+        self.recv.window -= len;
+        Ok(())
+    }
+
     fn finished_input(&mut self, len: usize) {
         self.pending_adjust = self.pending_adjust.saturating_add(len)
     }
 
     fn have_recv_eof(&self) -> bool {
-        matches!(self.state, ChanState::RecvEof | ChanState::RecvClose)
+        matches!(self.state,
+            ChanState::RecvEof | ChanState::RecvClose | ChanState::PendingDone)
     }
 
     fn is_closed(&self) -> bool {
-        matches!(self.state, ChanState::RecvClose)
+        matches!(self.state, ChanState::RecvClose | ChanState::PendingDone)
     }
 
     // None on close
     fn send_allowed(&self) -> Option<usize> {
+        if self.is_closed() || self.sent_eof || self.sent_close {
+            // Data can't be sent after the peer's close or our own EOF
+            return None
+        }
         let r = self.send.as_ref().map(|s| usize::min(s.window, s.max_packet));
         trace!("send_allowed {r:?}");
         r
@@ -815,6 +1199,7 @@ impl Channel {
         let num = self.send.as_mut().trap()?.num;
         if self.pending_adjust > self.full_window / 2 {
             let adjust = self.pending_adjust as u32;
+            self.recv.window = self.recv.window.saturating_add(self.pending_adjust);
             self.pending_adjust = 0;
             let p = packets::ChannelWindowAdjust { num, adjust }.into();
             Ok(Some(p))
@@ -840,11 +1225,28 @@ pub enum ChanOpened {
     Failure((ChanFail, ChanHandle))
 }
 
+impl ChanOpened {
+    /// Returns the failure reason, or `None` for success or deferral.
+    pub fn failure(&self) -> Option<ChanFail> {
+        match self {
+            Self::Failure((f, _)) => Some(*f),
+            _ => None,
+        }
+    }
+}
+
 /// A SSH protocol local channel number
 ///
 /// The number will always be in the range `0 <= num < MAX_CHANNELS`
 /// and can be used as an index by applications.
-/// Most external application API methods take a `ChanHandle` instead.
+/// Most external application API methods take a `ChanHandle` instead,
+/// which is obtained when a channel is opened.
+/// [`ChanHandle::num()`](crate::ChanHandle::num) gives the number for a handle.
+///
+/// A `ChanNum` may be reused for a new channel once the handle is released
+/// with [`Runner::channel_done()`](crate::Runner::channel_done).
+/// [`Runner::is_channel_valid()`](crate::Runner::is_channel_valid) checks
+/// whether a number still refers to an open channel.
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub struct ChanNum(pub u32);
 
@@ -917,7 +1319,7 @@ impl From<ChanFail> for DispatchOpenError {
 // constructed from runner::cli_session_opener()
 /// Sends shell, command, or other requests to a newly opened session channel
 pub struct CliSessionOpener<'g, 'a> {
-    pub(crate) ch: &'g Channel,
+    pub(crate) ch: &'g mut Channel,
     pub(crate) s: TrafSend<'g, 'a>,
 }
 
@@ -959,6 +1361,17 @@ impl<'g, 'a> CliSessionOpener<'g, 'a> {
         self.send(Req::Subsystem(cmd))
     }
 
+    /// Requests forwarding of an agent.
+    ///
+    /// The server may later open agent channels, received as
+    /// [`CliEvent::OpenAgent`](crate::event::CliEvent::OpenAgent).
+    /// Must be sent prior to requesting a shell or command.
+    pub fn agent_forward(&mut self) -> Result<()> {
+        self.send(Req::AuthAgent)?;
+        self.ch.agent_forward = true;
+        Ok(())
+    }
+
     fn send(&mut self, req: Req) -> Result<()> {
         req.send(self.ch, &mut self.s)
     }
@@ -991,3 +1404,674 @@ impl<'g> CliSessionExit<'g> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::channel::*;
+    use crate::encrypt::{KeyState, TestSend};
+    use crate::sunsetlog::*;
+    use crate::traffic::TrafOut;
+
+    /// Opens a client channel and confirms it with the given parameters
+    fn cli_open(cli: &mut Channels, s: &mut TrafSend, window: u32, max_packet: u32)
+        -> ChanNum {
+        let (num, _) = cli.open(ChannelOpenType::Session).unwrap();
+        let conf = packets::ChannelOpenConfirmation {
+            num: num.0,
+            sender_num: 100 + num.0,
+            initial_window: window,
+            max_packet,
+        };
+        let _ = cli.dispatch_inner(conf.into(), s).unwrap();
+        num
+    }
+
+    /// Opens a server session channel from the peer
+    fn serv_open(serv: &mut Channels, s: &mut TrafSend) -> ChanNum {
+        let open = packets::ChannelOpen {
+            sender_num: 7,
+            initial_window: 1000,
+            max_packet: 500,
+            ty: ChannelOpenType::Session,
+        };
+        let ev = serv.dispatch_inner(open.into(), s).unwrap();
+        let DispatchEvent::ServEvent(ServEventId::OpenSession { ch }) = ev else {
+            panic!("unexpected {ev:?}")
+        };
+        serv.resume_open(ch, None, s).unwrap();
+        ch
+    }
+
+    #[test]
+    fn server_requests() {
+        init_test_log();
+        let mut buf = [0u8; 1000];
+        let mut ts = TestSend::with_buf(&mut buf);
+        let mut s = ts.sender();
+
+        let mut serv = Channels::new(false);
+        let ch = serv_open(&mut serv, &mut s);
+
+        let req = |req| packets::ChannelRequest { num: ch.0, want_reply: false, req };
+        let winch = packets::WinChange { cols: 80, rows: 24, width: 0, height: 0 };
+        let ev = serv.dispatch_inner(req(ChannelReqType::WinChange(winch)).into(), &mut s)
+            .unwrap();
+        assert!(matches!(ev, DispatchEvent::ServEvent(ServEventId::SessionWinch)));
+
+        let exec = packets::Exec { command: "git-upload-pack 'repo'".into() };
+        let ev = serv.dispatch_inner(req(ChannelReqType::Exec(exec)).into(), &mut s)
+            .unwrap();
+        assert!(matches!(ev, DispatchEvent::ServEvent(ServEventId::SessionExec)));
+
+        let env = packets::Env { name: "LANG".into(), value: "C.UTF-8".into() };
+        let ev = serv.dispatch_inner(req(ChannelReqType::Env(env)).into(), &mut s)
+            .unwrap();
+        assert!(matches!(ev, DispatchEvent::ServEvent(ServEventId::SessionEnv)));
+
+        let sub = packets::Subsystem { subsystem: "sftp".into() };
+        let ev = serv.dispatch_inner(req(ChannelReqType::Subsystem(sub)).into(), &mut s)
+            .unwrap();
+        assert!(matches!(ev, DispatchEvent::ServEvent(ServEventId::SessionSubsystem)));
+
+        let ev = serv.dispatch_inner(req(ChannelReqType::AuthAgentReq).into(), &mut s)
+            .unwrap();
+        assert!(matches!(ev, DispatchEvent::ServEvent(ServEventId::SessionAgentReq)));
+
+        let br = packets::Break { length: 1000 };
+        let ev = serv.dispatch_inner(req(ChannelReqType::Break(br)).into(), &mut s)
+            .unwrap();
+        assert!(matches!(ev, DispatchEvent::ServEvent(ServEventId::SessionBreak)));
+    }
+
+    #[test]
+    fn channel_valid() {
+        init_test_log();
+        let mut buf = [0u8; 1000];
+        let mut ts = TestSend::with_buf(&mut buf);
+        let mut s = ts.sender();
+
+        let mut serv = Channels::new(false);
+        let ch = serv_open(&mut serv, &mut s);
+        assert!(serv.is_valid(ch));
+        assert!(!serv.is_valid(ChanNum(ch.0 + 1)));
+        assert!(!serv.is_valid(ChanNum(config::MAX_CHANNELS as u32)));
+
+        // released, but the peer hasn't closed yet
+        serv.done(ch).unwrap();
+        assert!(!serv.is_valid(ch));
+        assert!(matches!(serv.done(ch), Err(Error::BadChannel { .. })));
+
+        let close = packets::ChannelClose { num: ch.0 };
+        let _ = serv.dispatch_inner(close.into(), &mut s).unwrap();
+        assert!(!serv.is_valid(ch));
+        assert!(matches!(serv.done(ch), Err(Error::BadChannel { .. })));
+    }
+
+    #[test]
+    fn subsystem_reply() {
+        init_test_log();
+        let mut buf = [0u8; 1000];
+        let mut ts = TestSend::with_buf(&mut buf);
+        let mut s = ts.sender();
+
+        let mut cli = Channels::new(true);
+        let a = cli_open(&mut cli, &mut s, 1000, 500);
+
+        // not expecting a reply
+        let ev = cli.dispatch_inner(packets::ChannelSuccess { num: a.0 }.into(), &mut s)
+            .unwrap();
+        assert!(matches!(ev, DispatchEvent::None));
+
+        cli.send_subsystem(a, "sftp", &mut s).unwrap();
+        cli.send_subsystem(a, "other", &mut s).unwrap();
+        let ev = cli.dispatch_inner(packets::ChannelSuccess { num: a.0 }.into(), &mut s)
+            .unwrap();
+        assert!(matches!(ev, DispatchEvent::CliEvent(
+            CliEventId::ChanRequestResult { ch, success: true }) if ch == a));
+        let ev = cli.dispatch_inner(packets::ChannelFailure { num: a.0 }.into(), &mut s)
+            .unwrap();
+        assert!(matches!(ev, DispatchEvent::CliEvent(
+            CliEventId::ChanRequestResult { success: false, .. })));
+    }
+
+    /// `open_shell()` sends its requests once the session is confirmed
+    #[test]
+    fn open_shell() {
+        init_test_log();
+        let mut buf = [0u8; 1000];
+        let mut ts = TestSend::with_buf(&mut buf);
+        let mut o = [0u8; 1000];
+        let conf = |num: ChanNum| packets::ChannelOpenConfirmation {
+            num: num.0,
+            sender_num: 100 + num.0,
+            initial_window: 1000,
+            max_packet: 500,
+        };
+        let req = |p: &[u8]| match sshwire::packet_from_bytes(p, &Default::default()).unwrap() {
+            Packet::ChannelRequest(ChannelRequest { want_reply, req: ChannelReqType::Pty(_), .. })
+            => (want_reply, "pty-req"),
+            Packet::ChannelRequest(ChannelRequest { want_reply, req: ChannelReqType::Shell, .. })
+            => (want_reply, "shell"),
+            p => panic!("unexpected {p:?}"),
+        };
+
+        let mut cli = Channels::new(true);
+        let (a, _) = cli.open_shell(None).unwrap();
+        assert!(matches!(cli.open_shell(None), Err(Error::BadUsage { .. })));
+        let ev = cli.dispatch_inner(conf(a).into(), &mut ts.sender()).unwrap();
+        assert!(ev.is_none());
+        assert_eq!(req(ts.recv_payload(&mut o)), (true, "shell"));
+        let ev = cli.dispatch_inner(packets::ChannelSuccess { num: a.0 }.into(),
+            &mut ts.sender()).unwrap();
+        assert!(matches!(ev, DispatchEvent::CliEvent(
+            CliEventId::ChanRequestResult { ch, success: true }) if ch == a));
+
+        // a refused open is no longer pending, so another can follow
+        let (c, _) = cli.open_shell(None).unwrap();
+        let fail = packets::ChannelOpenFailure {
+            num: c.0,
+            reason: ChanFail::SSH_OPEN_ADMINISTRATIVELY_PROHIBITED as u32,
+            desc: "no".into(),
+            lang: "",
+        };
+        let _ = cli.dispatch_inner(fail.into(), &mut ts.sender()).unwrap();
+
+        // a plain session is unaffected
+        let (d, _) = cli.open(ChannelOpenType::Session).unwrap();
+        let ev = cli.dispatch_inner(conf(d).into(), &mut ts.sender()).unwrap();
+        assert!(matches!(ev, DispatchEvent::CliEvent(CliEventId::SessionOpened(ch)) if ch == d));
+        assert!(!ts.out.is_output_pending());
+
+        // with a pty, requested prior to the shell
+        let pty = Pty::new("vt100", 80, 24, 0, 0, []).unwrap();
+        let (e, _) = cli.open_shell(Some(pty)).unwrap();
+        let ev = cli.dispatch_inner(conf(e).into(), &mut ts.sender()).unwrap();
+        assert!(ev.is_none());
+        assert_eq!(req(ts.recv_payload(&mut o)), (false, "pty-req"));
+    }
+
+    #[test]
+    /// Agent channels are only opened by the server, once forwarding
+    /// has been requested
+    fn agent_open() {
+        init_test_log();
+        let mut buf = [0u8; 1000];
+        let mut ts = TestSend::with_buf(&mut buf);
+
+        let open = || packets::ChannelOpen {
+            sender_num: 3,
+            initial_window: 1000,
+            max_packet: 500,
+            ty: ChannelOpenType::AuthAgent,
+        };
+
+        // forwarding wasn't requested
+        let mut cli = Channels::new(true);
+        let ev = cli.dispatch_inner(open().into(), &mut ts.sender()).unwrap();
+        assert!(ev.is_none());
+        let mut b = [0u8; 1000];
+        let p = sshwire::packet_from_bytes(ts.recv_payload(&mut b), &Default::default())
+            .unwrap();
+        let Packet::ChannelOpenFailure(f) = p else {
+            panic!("unexpected {p:?}")
+        };
+        assert_eq!(f.reason, ChanFail::SSH_OPEN_ADMINISTRATIVELY_PROHIBITED as u32);
+
+        let sess = cli_open(&mut cli, &mut ts.sender(), 1000, 500);
+        let mut opener = CliSessionOpener { ch: cli.get_mut(sess).unwrap(), s: ts.sender() };
+        opener.agent_forward().unwrap();
+        let ev = cli.dispatch_inner(open().into(), &mut ts.sender()).unwrap();
+        let DispatchEvent::CliEvent(CliEventId::OpenAgent { ch }) = ev else {
+            panic!("unexpected {ev:?}")
+        };
+        cli.resume_open(ch, None, &mut ts.sender()).unwrap();
+        assert_eq!(cli.send_allowed(ch), Some(500));
+
+        let mut serv = Channels::new(false);
+        assert!(serv.dispatch_inner(open().into(), &mut ts.sender()).is_err());
+
+        // a server may open agent channels once it accepts the request
+        let sess = serv_open(&mut serv, &mut ts.sender());
+        assert!(!serv.agent_forwarding());
+        let req = packets::ChannelRequest {
+            num: sess.0,
+            want_reply: false,
+            req: ChannelReqType::AuthAgentReq,
+        };
+        serv.resume_chanreq(&req.into(), true, &mut ts.sender()).unwrap();
+        assert!(serv.agent_forwarding());
+    }
+
+    #[test]
+    fn exit_status() {
+        init_test_log();
+        let mut buf = [0u8; 1000];
+        let mut ts = TestSend::with_buf(&mut buf);
+        let mut s = ts.sender();
+
+        let mut serv = Channels::new(false);
+        let ch = serv_open(&mut serv, &mut s);
+        serv.send_exit_status(ch, 3, &mut s).unwrap();
+        serv.send_exit_signal(ch, "TERM", false, "", &mut s).unwrap();
+        assert!(serv.send_allowed(ch).is_some());
+        serv.send_eof(ch, &mut s).unwrap();
+        assert_eq!(serv.send_allowed(ch), None);
+        serv.send_close(ch, &mut s).unwrap();
+        // freed once the peer closes too
+        serv.done(ch).unwrap();
+        assert!(serv.get(ch).is_ok());
+        let close = packets::ChannelClose { num: ch.0 };
+        let _ = serv.dispatch_inner(close.into(), &mut s).unwrap();
+        assert!(serv.get_any(ch).is_err());
+
+        let mut cli = Channels::new(true);
+        let a = cli_open(&mut cli, &mut s, 1000, 500);
+        let req = packets::ChannelRequest {
+            num: a.0,
+            want_reply: false,
+            req: ChannelReqType::ExitStatus(packets::ExitStatus { status: 3 }),
+        };
+        assert_eq!(cli.exit_status(a), None);
+        let ev = cli.dispatch_inner(req.into(), &mut s).unwrap();
+        assert!(matches!(ev, DispatchEvent::CliEvent(CliEventId::SessionExit)));
+        assert_eq!(cli.exit_status(a), Some(3));
+
+        let sig = packets::ExitSignal {
+            signal: "KILL",
+            core: false,
+            error: "".into(),
+            lang: "",
+        };
+        let req = packets::ChannelRequest {
+            num: a.0,
+            want_reply: false,
+            req: ChannelReqType::ExitSignal(sig),
+        };
+        let _ = cli.dispatch_inner(req.into(), &mut s).unwrap();
+        assert_eq!(cli.exit_signal(a), Some("KILL"));
+    }
+
+    #[test]
+    fn send_signal() {
+        init_test_log();
+        let mut buf = [0u8; 1000];
+        let mut ts = TestSend::with_buf(&mut buf);
+        let mut s = ts.sender();
+
+        let mut cli = Channels::new(true);
+        let a = cli_open(&mut cli, &mut s, 1000, 500);
+        assert!(matches!(cli.send_signal(a, "SIGINT", &mut s), Err(Error::BadName)));
+        assert!(matches!(cli.send_signal(a, "WINCH", &mut s), Err(Error::BadName)));
+        assert!(!ts.out.is_output_pending());
+        let mut s = ts.sender();
+        cli.send_signal(a, "INT", &mut s).unwrap();
+        assert!(ts.out.is_output_pending());
+    }
+
+    /// Client window-change requests are only sent on sessions
+    #[test]
+    fn window_change() {
+        init_test_log();
+        let mut buf = [0u8; 1000];
+        let mut ts = TestSend::with_buf(&mut buf);
+        let mut s = ts.sender();
+
+        let mut cli = Channels::new(true);
+        let a = cli_open(&mut cli, &mut s, 1000, 500);
+        let winch = packets::WinChange { cols: 100, rows: 30, width: 0, height: 0 };
+        cli.term_window_change(a, winch.clone(), &mut s).unwrap();
+        assert!(ts.out.is_output_pending());
+
+        let mut s = ts.sender();
+        let (b, _) = cli.open(ChannelOpenType::DirectTcpip(packets::DirectTcpip {
+            address: "localhost".into(),
+            port: 80,
+            origin: "127.0.0.1".into(),
+            origin_port: 5000,
+        })).unwrap();
+        let conf = packets::ChannelOpenConfirmation {
+            num: b.0,
+            sender_num: 9,
+            initial_window: 1000,
+            max_packet: 500,
+        };
+        let _ = cli.dispatch_inner(conf.into(), &mut s).unwrap();
+        assert!(matches!(cli.term_window_change(b, winch, &mut s),
+            Err(Error::BadChannelData)));
+    }
+
+    /// Peer EOF half-closes a channel, we can still send
+    #[test]
+    fn half_close() {
+        init_test_log();
+        let mut buf = [0u8; 1000];
+        let mut ts = TestSend::with_buf(&mut buf);
+        let mut s = ts.sender();
+
+        let mut serv = Channels::new(false);
+        let ch = serv_open(&mut serv, &mut s);
+        let eof = packets::ChannelEof { num: ch.0 };
+        let _ = serv.dispatch_inner(eof.into(), &mut s).unwrap();
+        assert!(serv.have_recv_eof(ch));
+        assert!(!serv.is_closed(ch));
+        assert!(!serv.get(ch).unwrap().sent_eof);
+        assert_eq!(serv.send_allowed(ch), Some(500));
+        serv.send_data(ch, 100).unwrap();
+
+        // our close waits for the peer's close before freeing
+        serv.send_close(ch, &mut s).unwrap();
+        serv.done(ch).unwrap();
+        assert!(serv.get(ch).is_ok());
+        let close = packets::ChannelClose { num: ch.0 };
+        let _ = serv.dispatch_inner(close.into(), &mut s).unwrap();
+        assert!(serv.get_any(ch).is_err());
+    }
+
+    #[test]
+    /// Window adjustments are sent as the application consumes input
+    fn window_adjust_on_drain() {
+        init_test_log();
+        let mut buf = [0u8; 1000];
+        let mut ts = TestSend::with_buf(&mut buf);
+        let mut s = ts.sender();
+
+        let mut cli = Channels::new(true);
+        let a = cli_open(&mut cli, &mut s, 1000, 500);
+        let adjust = config::DEFAULT_WINDOW / 2;
+
+        cli.finished_input(a, adjust, false, &mut s).unwrap();
+        // not yet past half the window
+        assert_eq!(cli.get(a).unwrap().pending_adjust, adjust);
+        cli.finished_input(a, 1, false, &mut s).unwrap();
+        assert_eq!(cli.get(a).unwrap().pending_adjust, 0);
+
+        let mut o = [0u8; 1000];
+        assert!(ts.out.output(&mut o) > 0);
+        assert_eq!(ts.out.output(&mut o), 0);
+    }
+
+    #[test]
+    /// Received data is limited by our window, which is replenished
+    /// as the application consumes input
+    fn window_recv() {
+        init_test_log();
+        let mut buf = [0u8; 1000];
+        let mut ts = TestSend::with_buf(&mut buf);
+        let mut s = ts.sender();
+
+        let mut cli = Channels::new(true);
+        let a = cli_open(&mut cli, &mut s, 1000, 500);
+        let window = cli.get(a).unwrap().recv.window;
+        let data = [3u8; 100];
+        let pkt = || Packet::from(packets::ChannelData { num: a.0, data: BinString(&data) });
+
+        // transfer several windows
+        let mut o = [0u8; 1000];
+        let mut adjusts = 0;
+        for _ in 0..(window * 3 / data.len()) {
+            let mut s = ts.sender();
+            let ev = cli.dispatch_inner(pkt(), &mut s).unwrap();
+            assert!(matches!(ev, DispatchEvent::Data(_)));
+            cli.finished_input(a, data.len(), false, &mut s).unwrap();
+            if ts.out.output(&mut o) > 0 {
+                adjusts += 1;
+            }
+            assert!(cli.get(a).unwrap().recv.window <= window);
+        }
+        assert!(adjusts >= 5);
+
+        // data isn't consumed, so the window runs out
+        let mut s = ts.sender();
+        let avail = cli.get(a).unwrap().recv.window;
+        for _ in 0..(avail / data.len()) {
+            let _ = cli.dispatch_inner(pkt(), &mut s).unwrap();
+        }
+        let r = cli.dispatch_inner(pkt(), &mut s);
+        assert!(matches!(r, Err(Error::SSHProto { .. })));
+    }
+
+    #[test]
+    fn direct_tcpip_refused() {
+        init_test_log();
+        let mut buf = [0u8; 1000];
+        let mut out = TrafOut::new(&mut buf);
+        let mut keys = KeyState::new_cleartext();
+        let mut s = out.sender(&mut keys);
+
+        let mut cli = Channels::new(true);
+        let ty = ChannelOpenType::DirectTcpip(packets::DirectTcpip {
+            address: "localhost".into(),
+            port: 80,
+            origin: "127.0.0.1".into(),
+            origin_port: 5000,
+        });
+        let (num, _) = cli.open(ty).unwrap();
+        // not yet confirmed
+        assert_eq!(cli.send_allowed(num), Some(0));
+
+        let fail = packets::ChannelOpenFailure {
+            num: num.0,
+            reason: ChanFail::SSH_OPEN_CONNECT_FAILED as u32,
+            desc: "no".into(),
+            lang: "",
+        };
+        let _ = cli.dispatch_inner(fail.into(), &mut s).unwrap();
+        assert_eq!(cli.send_allowed(num), None);
+        assert!(cli.have_recv_eof(num));
+        assert!(cli.is_closed(num));
+        let (reason, desc) = cli.open_failure(num).unwrap();
+        assert_eq!(reason, ChanFail::SSH_OPEN_CONNECT_FAILED as u32);
+        assert_eq!(desc, "no");
+
+        cli.done(num).unwrap();
+        assert!(cli.get_any(num).is_err());
+    }
+
+    /// Incoming tcpip channels are refused without reserving a channel
+    #[test]
+    fn tcpip_open_refused() {
+        init_test_log();
+        let ty = ChannelOpenType::DirectTcpip(packets::DirectTcpip {
+            address: "localhost".into(),
+            port: 80,
+            origin: "127.0.0.1".into(),
+            origin_port: 5000,
+        });
+        let open = packets::ChannelOpen {
+            sender_num: 3,
+            initial_window: 1000,
+            max_packet: 500,
+            ty,
+        };
+
+        let mut buf = [0u8; 1000];
+        let mut ts = TestSend::with_buf(&mut buf);
+        let mut serv = Channels::new(false);
+        let ev = serv.dispatch_inner(open.into(), &mut ts.sender()).unwrap();
+        assert!(ev.is_none());
+        assert!(serv.get_any(ChanNum(0)).is_err());
+
+        let mut b = [0u8; 1000];
+        let payload = ts.recv_payload(&mut b);
+        let p = sshwire::packet_from_bytes(payload, &Default::default()).unwrap();
+        let Packet::ChannelOpenFailure(f) = p else {
+            panic!("unexpected {p:?}")
+        };
+        assert_eq!(f.num, 3);
+        assert_eq!(f.reason, ChanFail::SSH_OPEN_ADMINISTRATIVELY_PROHIBITED as u32);
+    }
+
+    #[test]
+    fn concurrent_channels() {
+        init_test_log();
+        let mut buf = [0u8; 1000];
+        let mut out = TrafOut::new(&mut buf);
+        let mut keys = KeyState::new_cleartext();
+        let mut s = out.sender(&mut keys);
+
+        let mut cli = Channels::new(true);
+        let nums: std::vec::Vec<ChanNum> = (0..MAX_CHANNELS)
+            .map(|i| cli_open(&mut cli, &mut s, 1000 * (i as u32 + 1), 500))
+            .collect();
+        // no more
+        assert!(matches!(cli.open(ChannelOpenType::Session), Err(Error::NoChannels)));
+
+        // windows are independent
+        let (a, b) = (nums[0], nums[1]);
+        assert_eq!(cli.send_data(a, 400).unwrap(), 100 + a.0);
+        assert_eq!(cli.send_allowed(a), Some(500));
+        assert_eq!(cli.send_allowed(b), Some(500));
+        let _ = cli.dispatch_inner(packets::ChannelWindowAdjust { num: b.0, adjust: 5 }.into(),
+            &mut s).unwrap();
+        assert_eq!(cli.get(b).unwrap().send.as_ref().unwrap().window, 2005);
+        assert_eq!(cli.get(a).unwrap().send.as_ref().unwrap().window, 600);
+
+        // a closed channel's slot is reused. Pretend our close was
+        // already sent, cleartext keys can't send session packets.
+        cli.get_mut(a).unwrap().sent_close = true;
+        let _ = cli.dispatch_inner(packets::ChannelClose { num: a.0 }.into(), &mut s)
+            .unwrap();
+        assert_eq!(cli.send_allowed(a), None);
+        // still held by the application
+        assert!(matches!(cli.open(ChannelOpenType::Session), Err(Error::NoChannels)));
+        cli.done(a).unwrap();
+        let c = cli_open(&mut cli, &mut s, 3000, 500);
+        assert_eq!(c, a);
+        assert_eq!(cli.get(c).unwrap().send.as_ref().unwrap().window, 3000);
+    }
+
+    #[test]
+    fn max_packet_negotiated() {
+        init_test_log();
+        let mut buf = [0u8; 1000];
+        let mut out = TrafOut::new(&mut buf);
+        let mut keys = KeyState::new_cleartext();
+        let mut s = out.sender(&mut keys);
+
+        // client opening a session
+        let mut cli = Channels::new(true);
+        let (num, p) = cli.open(ChannelOpenType::Session).unwrap();
+        let Packet::ChannelOpen(open) = p else { panic!() };
+        assert_eq!(open.max_packet as usize, DEFAULT_MAX_PACKET);
+        // not yet negotiated
+        assert!(cli.max_packet(num).is_err());
+
+        let conf = packets::ChannelOpenConfirmation {
+            num: num.0,
+            sender_num: 7,
+            initial_window: 5000,
+            max_packet: 1234,
+        };
+        let _ = cli.dispatch_inner(conf.into(), &mut s).unwrap();
+        assert_eq!(cli.max_packet(num).unwrap(),
+            MaxPacket { local: DEFAULT_MAX_PACKET, remote: 1234 });
+
+        // server receiving the same open
+        let mut serv = Channels::new(false);
+        let open = ChannelOpen { sender_num: 3, max_packet: 4321, ..open };
+        let _ = serv.dispatch_inner(open.into(), &mut s).unwrap();
+        let snum = ChanNum(0);
+        // as for resume_open(), without sending
+        let p = serv.get_any_mut(snum).unwrap().open_done().unwrap();
+        let Packet::ChannelOpenConfirmation(conf) = p else { panic!() };
+        assert_eq!(conf.max_packet as usize, DEFAULT_MAX_PACKET);
+        assert_eq!(serv.max_packet(snum).unwrap(),
+            MaxPacket { local: DEFAULT_MAX_PACKET, remote: 4321 });
+    }
+
+    #[test]
+    /// Peer max_packet is validated and limited to our buffer
+    fn peer_max_packet_checked() {
+        init_test_log();
+        let mut cli = Channels::new(true);
+        let (num, p) = cli.open(ChannelOpenType::Session).unwrap();
+        let Packet::ChannelOpen(open) = p else { panic!() };
+
+        let mut serv = Channels::new(false);
+        serv.set_send_max_packet(2000);
+
+        // max_packet 0 is rejected with a failure message
+        let mut buf = [0u8; 1000];
+        let mut ts = TestSend::with_buf(&mut buf);
+        let bad = ChannelOpen { max_packet: 0, ty: ChannelOpenType::Session, ..open };
+        let ev = serv.dispatch_inner(bad.into(), &mut ts.sender()).unwrap();
+        assert!(ev.is_none());
+        assert!(ts.out.is_output_pending());
+        assert!(serv.get_any(ChanNum(0)).is_err());
+
+        let mut buf = [0u8; 1000];
+        let mut ts = TestSend::with_buf(&mut buf);
+        let mut s = ts.sender();
+        let big = ChannelOpen { max_packet: 100_000, ..open };
+        let _ = serv.dispatch_inner(big.into(), &mut s).unwrap();
+        assert_eq!(serv.get_any(ChanNum(0)).unwrap().send.as_ref().unwrap().max_packet, 2000);
+
+        let conf = packets::ChannelOpenConfirmation {
+            num: num.0,
+            sender_num: 7,
+            initial_window: 5000,
+            max_packet: 0,
+        };
+        let r = cli.dispatch_inner(conf.into(), &mut s);
+        assert!(matches!(r, Err(Error::SSHProto { .. })));
+    }
+
+    /// Peer opens past the channel limit are refused
+    #[test]
+    fn serv_open_limit() {
+        init_test_log();
+        let mut buf = [0u8; 4000];
+        let mut ts = TestSend::with_buf(&mut buf);
+        let mut s = ts.sender();
+
+        let mut serv = Channels::new(false);
+        for _ in 0..MAX_CHANNELS {
+            serv_open(&mut serv, &mut s);
+        }
+
+        let open = packets::ChannelOpen {
+            sender_num: 8,
+            initial_window: 1000,
+            max_packet: 500,
+            ty: ChannelOpenType::Session,
+        };
+        assert!(matches!(serv.dispatch_open_inner(&open),
+            Err(DispatchOpenError::Failure(ChanFail::SSH_OPEN_RESOURCE_SHORTAGE))));
+        // a failure response is sent, the connection continues
+        let ev = serv.dispatch_inner(open.into(), &mut s).unwrap();
+        assert!(ev.is_none());
+    }
+
+    #[test]
+    fn pty_modes() {
+        init_test_log();
+        let pty = Pty::new("xterm", 80, 24, 0, 0, [(53, 0), (128, 38400)]).unwrap();
+        let mut buf = [0u8; termmodes::MAX_MODES_LEN];
+        let req = pty.pty_req(&mut buf).unwrap();
+        assert_eq!(req.modes.0, [53, 0, 0, 0, 0, 128, 0, 0, 0x96, 0, 0]);
+
+        let p2 = Pty::try_from(&req).unwrap();
+        assert_eq!(p2.term.as_str(), "xterm");
+        assert_eq!(p2.modes.len(), 2);
+        assert_eq!((p2.modes[1].opcode, p2.modes[1].arg), (128, 38400));
+
+        assert!(Pty::new("xterm", 80, 24, 0, 0, [(0, 1)]).is_err());
+        assert!(Pty::new("xterm", 80, 24, 0, 0, [(160, 1)]).is_err());
+    }
+
+    #[test]
+    fn chan_fail() {
+        for f in [ChanFail::SSH_OPEN_ADMINISTRATIVELY_PROHIBITED,
+            ChanFail::SSH_OPEN_CONNECT_FAILED,
+            ChanFail::SSH_OPEN_UNKNOWN_CHANNEL_TYPE,
+            ChanFail::SSH_OPEN_RESOURCE_SHORTAGE] {
+            assert_eq!(ChanFail::from_u32(f as u32), Some(f));
+        }
+        assert_eq!(ChanFail::from_u32(0), None);
+
+        let o = ChanOpened::Failure((ChanFail::SSH_OPEN_CONNECT_FAILED, ChanHandle(ChanNum(1))));
+        assert_eq!(o.failure(), Some(ChanFail::SSH_OPEN_CONNECT_FAILED));
+        assert_eq!(ChanOpened::Success.failure(), None);
+    }
+}
@@ -16,7 +16,7 @@ use traffic::TrafSend;
 use client::*;
 use packets::{MessageNumber, AuthMethod, MethodPubKey, ParseContext, UserauthRequest};
 use packets::{Packet, Signature, Userauth60};
-use sign::{SignKey, OwnedSig};
+use sign::{SignKey, OwnedSig, SigType};
 use sshnames::*;
 use sshwire::{BinString, Blob};
 use kex::SessId;
@@ -25,9 +25,14 @@ use auth::AuthType;
 enum AuthState {
     Unstarted,
     MethodQuery,
-    // other request types
+    /// Password request has been sent
     Request,
-    RequestKey { key: SignKey },
+    /// A public key query has been sent without a signature.
+    ///
+    /// `signed` is set once the signed request follows a `PkOk`.
+    RequestKey { key: SignKey, signed: bool },
+    /// Keyboard-interactive request has been sent
+    KbdInt,
     Idle,
 }
 
@@ -43,8 +48,17 @@ pub(crate) struct CliAuth {
     /// Set to false if hook.next_authkey() returns None.
     try_pubkey: bool,
 
+    /// Set to false if the application skips keyboard-interactive prompts.
+    try_kbdint: bool,
+
+    /// Whether the server most recently listed keyboard-interactive
+    kbdint_offered: bool,
+
     /// Set once we are OKed from MSG_EXT_INFO
     allow_rsa_sha2: bool,
+
+    /// Encoded certificate for the current `RequestKey`, empty if none.
+    cert: Vec<u8, {config::MAX_USER_CERT}>,
 }
 
 impl CliAuth {
@@ -54,7 +68,10 @@ impl CliAuth {
             username: String::new(),
             try_password: true,
             try_pubkey: true,
+            try_kbdint: true,
+            kbdint_offered: false,
             allow_rsa_sha2: false,
+            cert: Vec::new(),
         }
     }
 
@@ -100,6 +117,15 @@ impl CliAuth {
                 parse_ctx.cli_auth_type = None;
                 Ok(DispatchEvent::None)
             }
+            Userauth60::InfoRequest(_req) => {
+                if !matches!(self.state, AuthState::KbdInt) {
+                    trace!("Unexpected info request");
+                    return error::SSHProto.fail()
+                }
+                // parse_ctx.cli_auth_type is left as KeyboardInteractive,
+                // the server may send further info requests.
+                Ok(DispatchEvent::CliEvent(CliEventId::KbdInteractive))
+            }
         }
     }
 
@@ -109,10 +135,20 @@ impl CliAuth {
         sess_id: &'b SessId,
     ) -> Result<AuthSigMsg<'b>> {
 
-        let p = req_packet_pubkey(&self.username, &key, None, true)?;
+        let p = req_packet_pubkey(&self.username, self.request_pubkey(key)?, None, true)?;
         Ok(auth::AuthSigMsg::new(p, sess_id))
     }
 
+    /// Returns the public key to send for `key`, a certificate if one was provided
+    fn request_pubkey<'b>(&'b self, key: &'b SignKey) -> Result<PubKey<'b>> {
+        if self.cert.is_empty() {
+            Ok(key.pubkey())
+        } else {
+            // OK trap: checked in resume_pubkey()
+            sshwire::read_ssh(&self.cert, None).trap()
+        }
+    }
+
     fn auth_pkok(
         &mut self,
         pkok: &UserauthPkOk,
@@ -120,12 +156,13 @@ impl CliAuth {
         parse_ctx: &mut ParseContext,
         s: &mut TrafSend,
     ) -> Result<DispatchEvent> {
-        let AuthState::RequestKey { key } = &self.state else {
+        let AuthState::RequestKey { key, signed: false } = &self.state else {
             trace!("Unexpected userauth60");
             return error::SSHProto.fail()
         };
 
-        if key.pubkey() != pkok.key.0 {
+        let pubkey = self.request_pubkey(key)?;
+        if pubkey != pkok.key.0 {
             trace!("Received pkok for a different key");
             return error::SSHProto.fail()
         }
@@ -136,20 +173,23 @@ impl CliAuth {
 
         // Sign the packet without the signature
         let msg = self.auth_sig_msg(key, sess_id)?;
-        let sig = key.sign(&msg)?;
-        let p = req_packet_pubkey(&self.username, &key, Some(&sig), true)?;
+        // Sign with the same algorithm named in the request, rsa-sha2-256 for RSA
+        let sig_type = SigType::from_name(Signature::sig_name_for_pubkey(&pubkey)?)?;
+        let sig = key.sign_with(sig_type.signature_type(), &msg)?;
+        let p = req_packet_pubkey(&self.username, pubkey, Some(&sig), true)?;
 
         s.send(p)?;
         parse_ctx.cli_auth_type = None;
+        self.set_signed();
         Ok(DispatchEvent::None)
     }
 
 
-    pub fn resume_agentsign(&self, sig: Option<&OwnedSig>,
+    pub fn resume_agentsign(&mut self, sig: Option<&OwnedSig>,
         parse_ctx: &mut ParseContext,
         s: &mut TrafSend,
         ) -> Result<DispatchEvent> {
-        let AuthState::RequestKey { key } = &self.state else {
+        let AuthState::RequestKey { key, signed: false } = &self.state else {
             return Err(Error::bug())
         };
 
@@ -160,11 +200,18 @@ impl CliAuth {
             return Ok(DispatchEvent::CliEvent(CliEventId::Pubkey));
         };
 
-        let p = req_packet_pubkey(&self.username, &key, Some(&sig), true)?;
+        let p = req_packet_pubkey(&self.username, self.request_pubkey(key)?, Some(sig), true)?;
         s.send(p)?;
+        self.set_signed();
         Ok(DispatchEvent::None)
     }
 
+    fn set_signed(&mut self) {
+        if let AuthState::RequestKey { signed, .. } = &mut self.state {
+            *signed = true;
+        }
+    }
+
     fn change_password(&self) -> Result<()> {
         // Doesn't seem to be widely implemented, we'll just fail.
         Err(Error::msg("Password has expired"))
@@ -176,24 +223,98 @@ impl CliAuth {
         &mut self,
         failure: &packets::UserauthFailure,
         parse_ctx: &mut ParseContext,
+        s: &mut TrafSend,
     ) -> Result<DispatchEvent> {
         parse_ctx.cli_auth_type = None;
-        // TODO: look at existing self.state, handle the failure.
-        self.state = AuthState::Idle;
+        let prev = core::mem::replace(&mut self.state, AuthState::Idle);
+        if failure.partial {
+            // The previous method was accepted, but the server requires
+            // another. Continue with a different method from the updated list.
+            match prev {
+                AuthState::Request => {
+                    debug!("Password accepted, further auth required");
+                    self.try_password = false;
+                }
+                AuthState::KbdInt => {
+                    debug!("Keyboard-interactive accepted, further auth required");
+                    self.try_kbdint = false;
+                }
+                AuthState::RequestKey { .. } => {
+                    // A different key may still be requested
+                    debug!("Public key accepted, further auth required");
+                }
+                _ => debug!("Unexpected partial success"),
+            }
+        }
+        self.kbdint_offered = failure.methods.has_algo(SSH_AUTHMETHOD_INTERACTIVE)?;
 
         if self.try_pubkey
             && failure.methods.has_algo(SSH_AUTHMETHOD_PUBLICKEY)? {
             return Ok(DispatchEvent::CliEvent(event::CliEventId::Pubkey))
         }
 
-        if matches!(self.state, AuthState::Idle)
-            && self.try_password
+        if self.try_password
             && failure.methods.has_algo(SSH_AUTHMETHOD_PASSWORD)?
         {
             return Ok(DispatchEvent::CliEvent(event::CliEventId::Password))
         }
 
-        error::NoAuthMethods.fail()
+        self.start_kbdint(s, parse_ctx)?;
+        Ok(DispatchEvent::None)
+    }
+
+    /// Sends a keyboard-interactive request if the server allows it,
+    /// otherwise fails with `NoAuthMethods`.
+    fn start_kbdint(&mut self, s: &mut TrafSend, parse_ctx: &mut ParseContext)
+        -> Result<()> {
+        if !(self.try_kbdint && self.kbdint_offered) {
+            return error::NoAuthMethods.fail()
+        }
+
+        s.send(packets::UserauthRequest {
+            username: self.username.as_str().into(),
+            service: SSH_SERVICE_CONNECTION,
+            method: AuthMethod::KeyboardInteractive(
+                packets::MethodKeyboardInteractive {
+                    lang: "".into(),
+                    submethods: "".into(),
+                },
+            ),
+        })?;
+        parse_ctx.cli_auth_type = Some(AuthType::KeyboardInteractive);
+        self.state = AuthState::KbdInt;
+        Ok(())
+    }
+
+    /// Responds to a keyboard-interactive info request.
+    ///
+    /// `responses` is `None` if the application declined to answer, in which case
+    /// `num_prompts` empty responses are sent and keyboard-interactive
+    /// won't be attempted again.
+    pub fn resume_kbdint(&mut self, s: &mut TrafSend, responses: Option<&[&str]>,
+        num_prompts: usize) -> Result<()> {
+        if !matches!(self.state, AuthState::KbdInt) {
+            return Err(Error::bug())
+        }
+
+        let mut r = packets::InfoResponses::default();
+        if let Some(responses) = responses {
+            if responses.len() != num_prompts {
+                debug!("{} keyboard-interactive responses for {} prompts",
+                    responses.len(), num_prompts);
+                return error::BadUsage.fail()
+            }
+            for x in responses {
+                r.push((*x).into())?;
+            }
+        } else {
+            self.try_kbdint = false;
+            for _ in 0..num_prompts {
+                r.push("".into())?;
+            }
+        }
+
+        s.send(packets::UserauthInfoResponse { responses: r })
     }
 
     pub fn resume_password(&mut self, s: &mut TrafSend, password: Option<&str>,
@@ -201,7 +322,8 @@ impl CliAuth {
 
         let Some(password) = password else {
             self.try_password = false;
-            return error::NoAuthMethods.fail()
+            // fall back to keyboard-interactive, or fail
+            return self.start_kbdint(s, parse_ctx)
         };
 
         let p = req_packet_password(&self.username, password);
@@ -213,6 +335,7 @@ impl CliAuth {
 
     // May return another event to try, such as password auth
     pub fn resume_pubkey(&mut self, s: &mut TrafSend, key: Option<SignKey>,
+        cert: Option<&[u8]>,
         parse_ctx: &mut ParseContext) -> Result<DispatchEvent> {
 
         let Some(key) = key else {
@@ -220,7 +343,8 @@ impl CliAuth {
             if self.try_password {
                 return Ok(DispatchEvent::CliEvent(CliEventId::Password))
             }
-            return error::NoAuthMethods.fail()
+            self.start_kbdint(s, parse_ctx)?;
+            return Ok(DispatchEvent::None)
         };
 
         #[cfg(feature = "rsa")]
@@ -233,16 +357,35 @@ impl CliAuth {
             return Ok(DispatchEvent::CliEvent(CliEventId::Pubkey))
         }
 
-        let p = req_packet_pubkey(&self.username, &key, None, false)?;
+        self.cert.clear();
+        if let Some(cert) = cert {
+            Self::check_cert(cert, &key)?;
+            self.cert.extend_from_slice(cert).map_err(|_| error::BadUsage.build())?;
+        }
+
+        let p = req_packet_pubkey(&self.username, self.request_pubkey(&key)?, None, false)?;
         s.send(p)?;
         parse_ctx.cli_auth_type = Some(AuthType::PubKey);
         trace!("authtype {:?}", parse_ctx.cli_auth_type);
-        self.state = AuthState::RequestKey { key };
+        self.state = AuthState::RequestKey { key, signed: false };
         Ok(DispatchEvent::None)
     }
 
+    /// Checks that `cert` is an encoded certificate for `key`
+    fn check_cert(cert: &[u8], key: &SignKey) -> Result<()> {
+        let c: PubKey = sshwire::read_ssh(cert, None)
+            .map_err(|_| error::BadUsage.build())?;
+        match (c, key.pubkey()) {
+            (PubKey::Ed25519Cert(c), PubKey::Ed25519(k)) if c.key == k => Ok(()),
+            _ => {
+                debug!("Certificate doesn't match key");
+                error::BadUsage.fail()
+            }
+        }
+    }
+
     pub fn fetch_agentsign_key(&self) -> Result<&SignKey> {
-        let AuthState::RequestKey { key } = &self.state else {
+        let AuthState::RequestKey { key, signed: false } = &self.state else {
             return Err(Error::bug())
         };
         debug_assert!(key.is_agent());
@@ -250,7 +393,7 @@ impl CliAuth {
     }
 
     pub fn fetch_agentsign_msg<'b>(&'b self, sess_id: &'b SessId) -> Result<AuthSigMsg<'b>> {
-        let AuthState::RequestKey { key } = &self.state else {
+        let AuthState::RequestKey { key, signed: false } = &self.state else {
             return Err(Error::bug())
         };
 
@@ -287,10 +430,10 @@ fn req_packet_password<'b>(username: &'b str, password: &'b str)
     }.into()
 }
 
-fn req_packet_pubkey<'b>(username: &'b str, key: &'b SignKey,
+fn req_packet_pubkey<'b>(username: &'b str, pubkey: PubKey<'b>,
     sig: Option<&'b OwnedSig>, force_sig: bool) -> Result<packets::UserauthRequest<'b>> {
-    let mut mp = MethodPubKey::new(key.pubkey(), sig)?;
-    mp.force_sig = force_sig;
+    let mut mp = MethodPubKey::new(pubkey, sig)?;
+    mp.sig_present |= force_sig;
     let method = AuthMethod::PubKey(mp);
     Ok(packets::UserauthRequest {
         username: username.into(),
@@ -298,3 +441,134 @@ fn req_packet_pubkey<'b>(username: &'b str, key: &'b SignKey,
         method,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::cliauth::*;
+    use crate::encrypt::TestSend;
+    use crate::sunsetlog::*;
+
+    #[test]
+    fn partial_success() {
+        init_test_log();
+        let mut buf = [0u8; 3000];
+        let mut ts = TestSend::with_buf(&mut buf);
+        let mut s = ts.sender();
+        let mut ctx = ParseContext::new();
+        let sess_id = SessId::from_slice(&[1u8; 32]).unwrap();
+        let fail = |methods, partial| packets::UserauthFailure {
+            methods: namelist::NameList::try_from(methods).unwrap(),
+            partial,
+        };
+
+        let mut auth = CliAuth::new();
+        let _ = auth.progress();
+        auth.resume_username(&mut s, "matt").unwrap();
+        let ev = auth.failure(&fail("publickey,password", false), &mut ctx, &mut s).unwrap();
+        assert!(matches!(ev, DispatchEvent::CliEvent(CliEventId::Pubkey)));
+
+        let key = SignKey::generate(KeyType::Ed25519, None).unwrap();
+        let pk = key.pubkey();
+        let pkok = packets::UserauthPkOk { algo: SSH_NAME_ED25519, key: Blob(pk) };
+        let _ = auth.resume_pubkey(&mut s, Some(key.clone()), None, &mut ctx).unwrap();
+        let _ = auth.auth60(&Userauth60::PkOk(pkok), &sess_id, &mut ctx, &mut s).unwrap();
+
+        // key accepted, password is required too
+        let ev = auth.failure(&fail("password", true), &mut ctx, &mut s).unwrap();
+        assert!(matches!(ev, DispatchEvent::CliEvent(CliEventId::Password)));
+        auth.resume_password(&mut s, Some("pw"), &mut ctx).unwrap();
+
+        // password accepted, it isn't retried
+        let r = auth.failure(&fail("password", true), &mut ctx, &mut s);
+        assert!(matches!(r, Err(Error::NoAuthMethods)));
+    }
+
+    /// A public key is queried without a signature, and signed once after `PkOk`
+    #[test]
+    fn pubkey_probe() {
+        init_test_log();
+        let mut buf = [0u8; 3000];
+        let mut ts = TestSend::with_buf(&mut buf);
+        let mut s = ts.sender();
+        let mut ctx = ParseContext::new();
+        let sess_id = SessId::from_slice(&[1u8; 32]).unwrap();
+
+        let mut auth = CliAuth::new();
+        let _ = auth.progress();
+        auth.resume_username(&mut s, "matt").unwrap();
+        let fail = packets::UserauthFailure {
+            methods: namelist::NameList::try_from("publickey").unwrap(),
+            partial: false,
+        };
+        let _ = auth.failure(&fail, &mut ctx, &mut s).unwrap();
+
+        let key = SignKey::generate(KeyType::Ed25519, None).unwrap();
+        let pk = key.pubkey();
+        let agent_key = SignKey::from_agent_pubkey(&pk).unwrap();
+        let mut out1 = vec![0u8; 1000];
+        let mut buf = [0u8; 1000];
+        let mut ts1 = TestSend::with_buf(&mut buf);
+        let _ = auth.resume_pubkey(&mut ts1.sender(), Some(agent_key), None, &mut ctx).unwrap();
+        let p1 = ts1.recv_payload(&mut out1);
+
+        // the query has no signature
+        let mut out2 = vec![0u8; 1000];
+        let mut buf = [0u8; 1000];
+        let mut ts2 = TestSend::with_buf(&mut buf);
+        let p = req_packet_pubkey("matt", key.pubkey(), None, false).unwrap();
+        ts2.sender().send(p).unwrap();
+        let p2 = ts2.recv_payload(&mut out2);
+        assert_eq!(p1, p2);
+
+        let pkok = packets::UserauthPkOk { algo: SSH_NAME_ED25519, key: Blob(pk.clone()) };
+        let ev = auth.auth60(&Userauth60::PkOk(pkok), &sess_id, &mut ctx, &mut s).unwrap();
+        assert!(matches!(ev, DispatchEvent::CliEvent(CliEventId::AgentSign)));
+        let msg = auth.fetch_agentsign_msg(&sess_id).unwrap();
+        let sig = key.sign(&msg).unwrap();
+        let _ = auth.resume_agentsign(Some(&sig), &mut ctx, &mut s).unwrap();
+
+        // a repeated PkOk doesn't request another signature
+        let pkok = packets::UserauthPkOk { algo: SSH_NAME_ED25519, key: Blob(pk) };
+        let r = auth.auth60(&Userauth60::PkOk(pkok), &sess_id, &mut ctx, &mut s);
+        assert!(matches!(r, Err(Error::SSHProto { .. })));
+    }
+
+    /// A certificate is sent in place of the public key
+    #[test]
+    #[cfg(feature = "user-cert")]
+    fn pubkey_cert() {
+        init_test_log();
+        let mut buf = [0u8; 3000];
+        let mut ts = TestSend::with_buf(&mut buf);
+        let mut s = ts.sender();
+        let mut ctx = ParseContext::new();
+        let sess_id = SessId::from_slice(&[1u8; 32]).unwrap();
+
+        let mut auth = CliAuth::new();
+        let _ = auth.progress();
+        auth.resume_username(&mut s, "matt").unwrap();
+
+        let key = SignKey::generate(KeyType::Ed25519, None).unwrap();
+        let other = SignKey::generate(KeyType::Ed25519, None).unwrap();
+        let ca = SignKey::generate(KeyType::Ed25519, None).unwrap();
+        let mut certbuf = vec![0u8; 1000];
+        let cert = crate::cert::tests::make_cert(&mut certbuf, &key, &ca,
+            SSH_CERT_TYPE_USER, &["matt"], (0, u64::MAX));
+
+        // not a certificate for this key
+        let r = auth.resume_pubkey(&mut s, Some(other), Some(cert), &mut ctx);
+        assert!(matches!(r, Err(Error::BadUsage { .. })));
+
+        let _ = auth.resume_pubkey(&mut s, Some(key.clone()), Some(cert), &mut ctx).unwrap();
+        let certkey: PubKey = sshwire::read_ssh(cert, None).unwrap();
+        let msg = auth.fetch_agentsign_msg(&sess_id).unwrap();
+        assert_eq!(msg.sig_algorithm(), Some(SSH_NAME_ED25519_CERT));
+
+        // PkOk must be for the certificate
+        let pkok = packets::UserauthPkOk { algo: SSH_NAME_ED25519, key: Blob(key.pubkey()) };
+        let r = auth.auth60(&Userauth60::PkOk(pkok), &sess_id, &mut ctx, &mut s);
+        assert!(matches!(r, Err(Error::SSHProto { .. })));
+        let pkok = packets::UserauthPkOk { algo: SSH_NAME_ED25519_CERT, key: Blob(certkey) };
+        let _ = auth.auth60(&Userauth60::PkOk(pkok), &sess_id, &mut ctx, &mut s).unwrap();
+    }
+}
@@ -0,0 +1,322 @@
+//! Payload compression, `zlib` and `zlib@openssh.com`.
+//!
+//! Only available with the `zlib` feature, since the compression stream
+//! state requires `alloc`. Without the feature only `none` is negotiated.
+
+#[allow(unused_imports)]
+use {
+    crate::error::{Error, Result, TrapBug},
+    log::{debug, error, info, log, trace, warn},
+};
+
+use core::fmt;
+use core::fmt::Debug;
+
+#[cfg(feature = "zlib")]
+use {
+    alloc::boxed::Box,
+    alloc::vec::Vec,
+    miniz_oxide::deflate::core::{create_comp_flags_from_zip_params, CompressorOxide},
+    miniz_oxide::inflate::stream::InflateState,
+    miniz_oxide::{DataFormat, MZFlush},
+    zeroize::Zeroize,
+};
+
+use crate::*;
+use sshnames::*;
+
+/// Compression level, matches OpenSSH
+#[cfg(feature = "zlib")]
+const ZLIB_LEVEL: i32 = 6;
+
+/// Maximum growth of a compressed payload over its input.
+///
+/// Deflate stored blocks have 5 bytes overhead per 64kB, plus the
+/// zlib header and sync flush marker. This is rounded up generously.
+#[cfg(feature = "zlib")]
+const ZLIB_OVERHEAD: usize = 64;
+
+/// Placeholder for a compression method prior to creating a
+/// [`Compressor`] or [`Decompressor`], for use during key setup in [`kex`]
+#[derive(Debug, Clone)]
+pub(crate) enum Compress {
+    NoComp,
+    #[cfg(feature = "zlib")]
+    Zlib,
+    /// Delayed until after authentication
+    #[cfg(feature = "zlib")]
+    ZlibOpenSSH,
+}
+
+impl fmt::Display for Compress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl Compress {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::NoComp => SSH_NAME_NONE,
+            #[cfg(feature = "zlib")]
+            Self::Zlib => SSH_NAME_ZLIB,
+            #[cfg(feature = "zlib")]
+            Self::ZlibOpenSSH => SSH_NAME_ZLIB_OPENSSH,
+        }
+    }
+
+    /// Creates a compression method by algorithm name. Must be passed a known name.
+    pub fn from_name(name: &'static str) -> Result<Self> {
+        match name {
+            SSH_NAME_NONE => Ok(Compress::NoComp),
+            #[cfg(feature = "zlib")]
+            SSH_NAME_ZLIB => Ok(Compress::Zlib),
+            #[cfg(feature = "zlib")]
+            SSH_NAME_ZLIB_OPENSSH => Ok(Compress::ZlibOpenSSH),
+            _ => Err(Error::bug()),
+        }
+    }
+
+    /// Returns whether compression should be in effect, given
+    /// the authentication state.
+    #[cfg_attr(not(feature = "zlib"), allow(unused_variables))]
+    fn active(&self, authed: bool) -> bool {
+        match self {
+            Compress::NoComp => false,
+            #[cfg(feature = "zlib")]
+            Compress::Zlib => true,
+            #[cfg(feature = "zlib")]
+            Compress::ZlibOpenSSH => authed,
+        }
+    }
+}
+
+/// Outgoing compression stream state
+pub(crate) enum Compressor {
+    NoComp,
+    #[cfg(feature = "zlib")]
+    Zlib {
+        c: Box<CompressorOxide>,
+        /// Output buffer, reused between packets
+        scratch: Vec<u8>,
+    },
+}
+
+impl Debug for Compressor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let n = match self {
+            Self::NoComp => "NoComp",
+            #[cfg(feature = "zlib")]
+            Self::Zlib { .. } => "Zlib",
+        };
+        f.write_fmt(format_args!("Compressor::{n}"))
+    }
+}
+
+impl Compressor {
+    /// Creates a new compression stream.
+    ///
+    /// `authed` is whether authentication has completed, for `zlib@openssh.com`.
+    pub fn new(comp: &Compress, authed: bool) -> Self {
+        if !comp.active(authed) {
+            return Compressor::NoComp
+        }
+
+        #[cfg(feature = "zlib")]
+        {
+            let flags = create_comp_flags_from_zip_params(ZLIB_LEVEL, 15, 0);
+            Compressor::Zlib {
+                c: Box::new(CompressorOxide::new(flags)),
+                scratch: Vec::new(),
+            }
+        }
+        #[cfg(not(feature = "zlib"))]
+        Compressor::NoComp
+    }
+
+    pub fn is_active(&self) -> bool {
+        !matches!(self, Compressor::NoComp)
+    }
+
+    /// Maximum expansion of a payload, used when calculating available space.
+    pub fn overhead(&self) -> usize {
+        match self {
+            Compressor::NoComp => 0,
+            #[cfg(feature = "zlib")]
+            Compressor::Zlib { .. } => ZLIB_OVERHEAD,
+        }
+    }
+
+    /// Compresses `buf[..len]` in place, returning the compressed length.
+    ///
+    /// The remainder of `buf` is available for output.
+    #[cfg_attr(not(feature = "zlib"), allow(unused_variables))]
+    pub fn compress(&mut self, buf: &mut [u8], len: usize) -> Result<usize> {
+        match self {
+            Compressor::NoComp => Ok(len),
+            #[cfg(feature = "zlib")]
+            Compressor::Zlib { c, scratch } => {
+                let input = buf.get(..len).trap()?;
+                scratch.clear();
+                scratch.resize(len + ZLIB_OVERHEAD, 0);
+                let r = miniz_oxide::deflate::stream::deflate(c, input, scratch,
+                    MZFlush::Sync);
+                if r.status.is_err() {
+                    return Error::bug_msg("deflate failed")
+                }
+                if r.bytes_consumed != len || r.bytes_written == scratch.len() {
+                    // Shouldn't happen given ZLIB_OVERHEAD
+                    return Error::bug_msg("deflate overflow")
+                }
+                let out = &scratch[..r.bytes_written];
+                let Some(b) = buf.get_mut(..out.len()) else {
+                    return error::NoRoom.fail()
+                };
+                b.copy_from_slice(out);
+                Ok(out.len())
+            }
+        }
+    }
+}
+
+/// Incoming decompression stream state
+pub(crate) enum Decompressor {
+    NoComp,
+    #[cfg(feature = "zlib")]
+    Zlib {
+        d: Box<InflateState>,
+        /// Output buffer, reused between packets
+        scratch: Vec<u8>,
+    },
+}
+
+impl Debug for Decompressor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let n = match self {
+            Self::NoComp => "NoComp",
+            #[cfg(feature = "zlib")]
+            Self::Zlib { .. } => "Zlib",
+        };
+        f.write_fmt(format_args!("Decompressor::{n}"))
+    }
+}
+
+impl Decompressor {
+    /// Creates a new decompression stream.
+    ///
+    /// `authed` is whether authentication has completed, for `zlib@openssh.com`.
+    pub fn new(comp: &Compress, authed: bool) -> Self {
+        if !comp.active(authed) {
+            return Decompressor::NoComp
+        }
+
+        #[cfg(feature = "zlib")]
+        {
+            Decompressor::Zlib {
+                d: InflateState::new_boxed(DataFormat::Zlib),
+                scratch: Vec::new(),
+            }
+        }
+        #[cfg(not(feature = "zlib"))]
+        Decompressor::NoComp
+    }
+
+    pub fn is_active(&self) -> bool {
+        !matches!(self, Decompressor::NoComp)
+    }
+
+    /// Decompresses `buf[..len]` in place, returning the decompressed length.
+    ///
+    /// The remainder of `buf` is available for output, a payload larger than
+    /// `buf` will fail with [`Error::BigPacket`].
+    /// With `zeroize` set the plaintext isn't kept in the scratch buffer.
+    #[cfg_attr(not(feature = "zlib"), allow(unused_variables))]
+    pub fn decompress(&mut self, buf: &mut [u8], len: usize, zeroize: bool)
+        -> Result<usize> {
+        match self {
+            Decompressor::NoComp => Ok(len),
+            #[cfg(feature = "zlib")]
+            Decompressor::Zlib { d, scratch } => {
+                let input = buf.get(..len).trap()?;
+                scratch.clear();
+                scratch.resize(buf.len(), 0);
+                let r = miniz_oxide::inflate::stream::inflate(d, input, scratch,
+                    MZFlush::Sync);
+                let w = r.bytes_written;
+                let res = if r.status.is_err() {
+                    debug!("Bad compressed payload");
+                    error::SSHProto.fail()
+                } else if r.bytes_consumed != len || w == scratch.len() {
+                    Err(Error::BigPacket { size: w })
+                } else {
+                    buf[..w].copy_from_slice(&scratch[..w]);
+                    Ok(w)
+                };
+                if zeroize {
+                    scratch[..w].zeroize();
+                }
+                res
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "zlib"))]
+mod tests {
+    use crate::compress::*;
+    use crate::sunsetlog::*;
+
+    #[test]
+    /// Payloads are compressed as a continuing stream
+    fn roundtrip_zlib() {
+        init_test_log();
+        let mut c = Compressor::new(&Compress::Zlib, false);
+        let mut d = Decompressor::new(&Compress::Zlib, false);
+        assert!(c.is_active() && d.is_active());
+
+        let mut buf = vec![0u8; 2000];
+        let payloads: [&[u8]; 4] = [
+            b"",
+            b"hello hello hello hello hello",
+            b"hello hello hello hello hello",
+            &[0x99; 1500],
+        ];
+        for p in payloads {
+            buf[..p.len()].copy_from_slice(p);
+            let clen = c.compress(&mut buf, p.len()).unwrap();
+            let dlen = d.decompress(&mut buf, clen, false).unwrap();
+            assert_eq!(&buf[..dlen], p);
+        }
+
+        // too large for the decompress buffer
+        let p = [0x12; 1500];
+        buf[..p.len()].copy_from_slice(&p);
+        let clen = c.compress(&mut buf, p.len()).unwrap();
+        assert!(matches!(d.decompress(&mut buf[..1000], clen, false),
+            Err(Error::BigPacket { .. })));
+    }
+
+    #[test]
+    /// Decompressed plaintext isn't left in scratch space
+    fn zeroize_scratch() {
+        init_test_log();
+        let mut c = Compressor::new(&Compress::Zlib, false);
+        let mut d = Decompressor::new(&Compress::Zlib, false);
+        let mut buf = vec![0u8; 2000];
+        let p = b"password password password";
+        buf[..p.len()].copy_from_slice(p);
+        let clen = c.compress(&mut buf, p.len()).unwrap();
+        let dlen = d.decompress(&mut buf, clen, true).unwrap();
+        assert_eq!(&buf[..dlen], p);
+        let Decompressor::Zlib { scratch, .. } = &d else { panic!() };
+        assert!(scratch.iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn delayed_zlib() {
+        assert!(!Compressor::new(&Compress::ZlibOpenSSH, false).is_active());
+        assert!(!Decompressor::new(&Compress::ZlibOpenSSH, false).is_active());
+        assert!(Compressor::new(&Compress::ZlibOpenSSH, true).is_active());
+        assert!(!Compressor::new(&Compress::NoComp, true).is_active());
+    }
+}
@@ -1,20 +1,108 @@
 // TODO
 pub const DEFAULT_WINDOW: usize = 1000;
+/// Channel maximum packet size advertised to the peer, prior to
+/// adjusting for the input buffer size.
 pub const DEFAULT_MAX_PACKET: usize = 1000;
 
+/// Input buffer size required for RFC compliance.
+///
+/// [RFC4253](https://tools.ietf.org/html/rfc4253#section-6.1) requires that
+/// implementations handle packets of 35000 bytes. Smaller buffers work
+/// with most peers, since the channel `max_packet` advertised to the peer is
+/// derived from the buffer size. Some servers send other large packets
+/// though (such as a long list of algorithms or host keys).
+pub const RFC_MIN_BUFFER: usize = 35000;
+
+/// Space in the input buffer reserved for packet framing around channel data.
+///
+/// Length, padding (up to 255 bytes), MAC and the channel data header.
+pub const RECV_PACKET_OVERHEAD: usize = 4 + 1 + 255 + 64 + 13;
+
 // TODO: Perhaps instead of MAX_CHANNELS we could have a type alias
 // of either heapless::Vec<> or std::vec::Vec<>
 //
 // This size is arbitrary and may be increased, though note that some code paths assume
 // a linear scan of channels can happen quickly, so may need reworking for performance.
+
+/// Maximum number of concurrent channels (sessions, forwards) per connection.
+///
+/// Each channel has independent windows. A larger limit can be set
+/// with `larger` crate feature.
+#[cfg(not(feature="larger"))]
 pub const MAX_CHANNELS: usize = 4;
 
+/// Maximum number of concurrent channels (sessions, forwards) per connection.
+#[cfg(feature="larger")]
+pub const MAX_CHANNELS: usize = 16;
+
 // Enough for longest 23 of "screen.konsole-256color" on my system
 // Unsure if this is specified somewhere
 pub const MAX_TERM: usize = 32;
 
 pub const DEFAULT_TERM: &str = "xterm";
 
+/// Maximum length of a remote exit-signal name that is retained.
+///
+/// RFC4254 names are short ("TERM", "USR1"), longer names are discarded.
+pub const MAX_SIGNAL_NAME: usize = 20;
+
+/// Maximum length of a channel open failure description that is retained.
+///
+/// Longer descriptions are truncated.
+pub const MAX_OPEN_FAIL_DESC: usize = 64;
+
+/// Maximum number of host keys a server can send with
+/// [`Runner::send_hostkeys()`](crate::Runner::send_hostkeys).
+pub const MAX_HOSTKEYS: usize = 4;
+
+/// Maximum number of prompts in a keyboard-interactive request
+///
+/// Servers usually send one or two (password, OTP code).
+pub const MAX_KBDINT_PROMPTS: usize = 5;
+
+/// Default data volume after which a new key exchange is started, 1GiB.
+///
+/// Counts bytes in both directions. Can be changed with
+/// [`Runner::set_rekey_limits()`](crate::Runner::set_rekey_limits).
+pub const DEFAULT_REKEY_BYTES: u64 = 1 << 30;
+
+/// Default limit of failed authentication attempts before a server disconnects,
+/// the same as OpenSSH `MaxAuthTries`.
+///
+/// Can be changed with [`Runner::set_max_auth_tries()`](crate::Runner::set_max_auth_tries).
+pub const DEFAULT_MAX_AUTH_TRIES: u32 = 6;
+
+/// Default time after which a new key exchange is started, one hour.
+///
+/// Only applies if the application calls [`Runner::set_time()`](crate::Runner::set_time).
+pub const DEFAULT_REKEY_SECS: u64 = 60 * 60;
+
+/// Packets in either direction after which a new key exchange is started.
+///
+/// Avoids sequence numbers wrapping, [RFC4344](https://tools.ietf.org/html/rfc4344) Section 3.1.
+pub const REKEY_PACKETS: u32 = 1 << 31;
+
+/// Space for session packets (such as request replies) sent during a
+/// key exchange, held until it completes.
+pub const MAX_KEX_HELD: usize = 256;
+
+/// Maximum length of a certificate's signed data that can be verified.
+///
+/// Enough for OpenSSH certificates with a few principals and the
+/// default extensions.
+pub const MAX_CERT_SIGNED: usize = 800;
+
+/// Maximum encoded length of a user certificate used for authentication.
+///
+/// Only enabled with the `user-cert` crate feature, since signature
+/// buffers grow by this amount.
+#[cfg(not(feature="user-cert"))]
+pub const MAX_USER_CERT: usize = 0;
+
+/// Maximum encoded length of a user certificate used for authentication.
+#[cfg(feature="user-cert")]
+pub const MAX_USER_CERT: usize = 800;
+
 pub const RSA_DEFAULT_KEYSIZE: usize = 2048;
 pub const RSA_MIN_KEYSIZE: usize = 1024;
 
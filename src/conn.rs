@@ -1,6 +1,6 @@
 //! Represents the state of a SSH connection.
 
-use self::{cliauth::CliAuth, event::Banner, packets::{AuthMethod, UserauthRequest}};
+use self::{cliauth::CliAuth, event::{Banner, DebugMessage, HostkeysUpdate}, packets::{AuthMethod, UserauthRequest}};
 
 #[allow(unused_imports)]
 use {
@@ -22,7 +22,7 @@ use server::Server;
 use traffic::TrafSend;
 use channel::{Channels, CliSessionExit};
 use config::MAX_CHANNELS;
-use kex::{Kex, SessId, AlgoConfig};
+use kex::{Kex, SessId, AlgoConfig, AlgoNames, AlgoRanks};
 use event::{CliEvent, ServEvent};
 
 /// The core state of a SSH instance.
@@ -34,6 +34,12 @@ pub(crate) struct Conn {
 
     sess_id: Option<SessId>,
 
+    /// Algorithms negotiated in the first key exchange, to detect downgrades
+    first_algos: Option<AlgoRanks>,
+
+    /// Algorithms currently in use
+    algo_names: Option<AlgoNames>,
+
     cliserv: ClientServer,
 
     algo_conf: AlgoConfig,
@@ -45,7 +51,25 @@ pub(crate) struct Conn {
     // 256 bytes -> 112 bytes
     pub(crate) remote_version: ident::RemoteVersion,
 
+    our_version: ident::OurVersion,
+
     pub(crate) channels: Channels,
+
+    keepalive: KeepAlive,
+}
+
+/// Keepalive state, see [`Runner::set_keepalive()`](crate::Runner::set_keepalive)
+#[derive(Debug, Default)]
+struct KeepAlive {
+    /// Idle interval in seconds, 0 is disabled
+    interval: u64,
+    max_missed: u32,
+    /// Keepalive requests sent without any packet received since
+    missed: u32,
+    /// Time of the last packet received or keepalive sent
+    last: Option<u64>,
+    /// A packet has been received since the last check
+    rx: bool,
 }
 
 // TODO: what tricks can we do to optimise away client or server code if we only
@@ -147,13 +171,17 @@ impl Conn {
 
         Ok(Conn {
             sess_id: None,
+            first_algos: None,
+            algo_names: None,
             kex: Kex::new(),
             remote_version: ident::RemoteVersion::new(cliserv.is_client()),
+            our_version: Default::default(),
             state: ConnState::SendIdent,
             algo_conf,
             channels: Channels::new(cliserv.is_client()),
             parse_ctx: ParseContext::new(),
             cliserv,
+            keepalive: KeepAlive::default(),
         })
     }
 
@@ -192,9 +220,9 @@ impl Conn {
         let mut disp = Dispatched::default();
         match self.state {
             ConnState::SendIdent => {
-                s.send_version()?;
+                s.send_version(self.our_version.as_bytes())?;
                 // send early to avoid round trip latency
-                // TODO: first_follows would have a second packet here
+                // A guessed KexDHInit may follow, see AlgoConfig::with_first_kex_follows()
                 self.kex.send_kexinit(&self.algo_conf, s)?;
                 disp.event = DispatchEvent::Progressed;
                 self.state = ConnState::ReceiveIdent
@@ -228,15 +256,90 @@ impl Conn {
         }
         trace!("-> {:?}, {disp:?}", self.state);
 
-        // TODO: if keys.seq > MAX_REKEY then we must rekey for security.
+        if s.rekey_due() && !self.is_first_kex() && !self.kex_in_progress() {
+            debug!("Rekey limit reached");
+            self.kex.send_kexinit(&self.algo_conf, s)?;
+        }
 
         Ok(disp)
     }
 
+    /// Starts a new key exchange, unless one is already in progress.
+    pub(crate) fn initiate_rekey(&mut self, s: &mut TrafSend) -> Result<()> {
+        if self.is_first_kex() || self.kex_in_progress() {
+            trace!("Kex already in progress");
+            return Ok(())
+        }
+        self.kex.send_kexinit(&self.algo_conf, s)
+    }
+
+    /// Returns `true` during a key exchange, when only transport
+    /// packets may be sent.
+    pub(crate) fn kex_in_progress(&self) -> bool {
+        !matches!(self.kex, Kex::Idle)
+    }
+
+    pub(crate) fn set_keepalive(&mut self, interval: u64, max_missed: u32) {
+        self.keepalive = KeepAlive { interval, max_missed, ..Default::default() };
+    }
+
+    /// Sends a keepalive request if nothing has been received for the
+    /// keepalive interval.
+    ///
+    /// Returns [`Error::Timeout`] once too many have gone unanswered.
+    pub(crate) fn keepalive(&mut self, now: u64, s: &mut TrafSend) -> Result<()> {
+        if self.keepalive.interval == 0
+            || !matches!(self.state, ConnState::Authed) {
+            return Ok(())
+        }
+        let kex = self.kex_in_progress();
+        let k = &mut self.keepalive;
+
+        if core::mem::take(&mut k.rx) {
+            k.missed = 0;
+            k.last = Some(now);
+            return Ok(())
+        }
+
+        let last = *k.last.get_or_insert(now);
+        if now.saturating_sub(last) < k.interval || kex {
+            return Ok(())
+        }
+
+        if k.missed >= k.max_missed {
+            info!("No response to {} keepalives", k.missed);
+            return Err(Error::Timeout)
+        }
+
+        trace!("Sending keepalive");
+        s.send(packets::GlobalRequest {
+            want_reply: true,
+            req: packets::GlobalRequestMethod::KeepAlive,
+        })?;
+        k.missed += 1;
+        k.last = Some(now);
+        Ok(())
+    }
+
+    /// Sets our identification string, before it has been sent.
+    pub(crate) fn set_ident(&mut self, ident: &'static str) -> Result<()> {
+        if self.initial_sent() {
+            return error::BadUsage.fail()
+        }
+        self.our_version = ident::OurVersion::new(ident)?;
+        Ok(())
+    }
+
     pub(crate) fn initial_sent(&self) -> bool {
         !matches!(self.state, ConnState::SendIdent)
     }
 
+    /// Returns `true` once both sides' version strings have been exchanged,
+    /// so binary packets can be sent.
+    pub(crate) fn version_exchanged(&self) -> bool {
+        self.initial_sent() && self.remote_version.version().is_some()
+    }
+
     pub(crate) fn packet<'p>(&self, payload: &'p[u8]) -> Result<Packet<'p>> {
         sshwire::packet_from_bytes(payload, &self.parse_ctx)
     }
@@ -251,6 +354,8 @@ impl Conn {
 
         match self.packet(payload) {
             Ok(p) => {
+                trace!("Received {} len {}", p.message_name(), payload.len());
+                self.keepalive.rx = true;
                 let num = p.message_num() as u8;
                 let a = self.dispatch_packet(p, s);
                 match a {
@@ -274,28 +379,42 @@ impl Conn {
     }
 
     /// Check that a packet is received in the correct state
+    ///
+    /// Packets of the wrong [`Category`](packets::Category) for the
+    /// connection phase fail with [`Error::PacketWrong`].
     fn check_packet(&self, p: &Packet) -> Result<()> {
+        if matches!(self.kex, Kex::NewKeys { .. })
+            && matches!(p.category(), packets::Category::Kex)
+            && !matches!(p, Packet::NewKeys(_)) {
+            // Our NewKeys has been sent, the peer's NewKeys must be next
+            debug!("Expected NewKeys");
+            return error::PacketWrong.fail()
+        }
+
         let r = if self.is_first_kex() && self.kex.is_strict() {
             // Strict Kex doesn't allow even packets like Ignore or Debug
             match p.category() {
                 packets::Category::Kex => Ok(()),
                 _ => {
                     debug!("Non-kex packet during strict kex");
-                    error::SSHProto.fail()
+                    error::PacketWrong.fail()
                 },
             }
-        } else if !matches!(self.kex, Kex::Idle) {
-            // Normal KEX only allows certain packets
+        } else if !matches!(self.kex, Kex::Idle | Kex::KexInit { .. }) {
+            // Once the peer's KexInit has arrived, normal KEX only allows
+            // certain packets. Before that (we started a rekey) the peer
+            // may still send anything, RFC4253 section 9.
+            // Our own outgoing session packets are held during either.
             match p.category() {
                 packets::Category::All => Ok(()),
                 packets::Category::Kex => Ok(()),
                 _ => {
                     debug!("Invalid packet during kex");
-                    error::SSHProto.fail()
+                    error::PacketWrong.fail()
                 },
             }
         } else {
-            // No KEX in progress, check for post-auth packets
+            // No KEX in progress (or only ours), check for post-auth packets
             match p.category() {
                 packets::Category::All => Ok(()),
                 packets::Category::Kex => Ok(()),
@@ -304,14 +423,14 @@ impl Conn {
                         | ConnState::PreAuth
                         | ConnState::Authed
                         => Ok(()),
-                        _ => error::SSHProto.fail(),
+                        _ => error::PacketWrong.fail(),
                     }
                 }
                 packets::Category::Sess => {
                     match self.state {
                         ConnState::Authed
                         => Ok(()),
-                        _ => error::SSHProto.fail(),
+                        _ => error::PacketWrong.fail(),
                     }
                 }
             }
@@ -325,6 +444,64 @@ impl Conn {
         r
     }
 
+    /// Records the first negotiated algorithms, or compares against
+    /// them for a rekey.
+    fn check_rekey_downgrade(&mut self) -> Result<()> {
+        let algos = self.kex.algos().trap()?;
+        let ranks = AlgoRanks::new(algos, &self.algo_conf);
+        match &self.first_algos {
+            None => self.first_algos = Some(ranks),
+            Some(first) if self.algo_conf.reject_rekey_downgrade => {
+                ranks.check_downgrade(first)?
+            }
+            Some(_) => (),
+        }
+        Ok(())
+    }
+
+    /// Returns the algorithms in use, after the first key exchange completes.
+    pub(crate) fn algo_names(&self) -> Option<&AlgoNames> {
+        self.algo_names.as_ref()
+    }
+
+    /// Fail if a rekey negotiates weaker algorithms than the first key exchange.
+    pub(crate) fn set_reject_rekey_downgrade(&mut self, reject: bool) {
+        self.algo_conf.reject_rekey_downgrade = reject
+    }
+
+    pub(crate) fn send_banner(&self, message: &str, s: &mut TrafSend) -> Result<()> {
+        let ClientServer::Server(serv) = &self.cliserv else {
+            return error::BadUsage.fail()
+        };
+        serv.auth.send_banner(message, s)
+    }
+
+    pub(crate) fn set_max_auth_tries(&mut self, tries: u32) -> Result<()> {
+        self.mut_server()?.auth.max_tries = tries;
+        Ok(())
+    }
+
+    /// Replaces the algorithm configuration, to be used from the next `KexInit`.
+    pub(crate) fn set_algo_config(&mut self, mut conf: AlgoConfig) -> Result<()> {
+        if conf.is_client() != self.is_client() || self.kex_in_progress() {
+            return error::BadUsage.fail()
+        }
+        conf.reject_rekey_downgrade = self.algo_conf.reject_rekey_downgrade;
+        self.algo_conf = conf;
+        Ok(())
+    }
+
+    /// Returns the session identifier, the first exchange hash.
+    pub(crate) fn sess_id(&self) -> Option<&SessId> {
+        self.sess_id.as_ref()
+    }
+
+    /// Marks the first key exchange as complete, for tests.
+    #[cfg(test)]
+    pub(crate) fn set_test_sess_id(&mut self) {
+        self.sess_id = Some(SessId::from_slice(&[7u8; 32]).unwrap());
+    }
+
     fn is_first_kex(&self) -> bool {
         self.sess_id.is_none()
     }
@@ -332,7 +509,6 @@ impl Conn {
     pub fn dispatch_packet(&mut self, packet: Packet, s: &mut TrafSend,
     ) -> Result<Dispatched, Error> {
         // TODO: perhaps could consolidate packet client vs server checks
-        trace!("Incoming {packet:#?}");
         let mut disp = Dispatched::default();
 
         self.check_packet(&packet)?;
@@ -341,12 +517,13 @@ impl Conn {
             Packet::KexInit(k) => {
                 self.kex.handle_kexinit(
                     k,
-                    self.cliserv.is_client(),
                     &self.algo_conf,
+                    self.our_version.as_bytes(),
                     &self.remote_version,
                     self.is_first_kex(),
                     s,
                 )?;
+                self.check_rekey_downgrade()?;
             }
             Packet::KexDHInit(_p) => {
                 if self.cliserv.is_client() {
@@ -364,10 +541,13 @@ impl Conn {
                     return error::SSHProto.fail();
                 }
 
-                disp.event = self.kex.handle_kexdhreply();
+                disp.event = self.kex.handle_kexdhreply()?;
             }
             Packet::NewKeys(_) => {
+                let names = self.kex.algos().map(AlgoNames::from);
                 self.kex.handle_newkeys(&mut self.sess_id, s)?;
+                self.algo_names = names;
+                self.channels.send_window_adjusts(s)?;
             }
             Packet::ExtInfo(p) => {
                 if let ClientServer::Client(cli) = &mut self.cliserv {
@@ -390,15 +570,18 @@ impl Conn {
             Packet::Ignore(_) => {
                 // nothing to do
             }
-            Packet::Unimplemented(_) => {
-                warn!("Received SSH unimplemented message");
+            Packet::Unimplemented(p) => {
+                warn!("Received SSH unimplemented message for seq {}", p.seq);
             }
             Packet::DebugPacket(p) => {
-                let level = match p.always_display {
-                    true => log::Level::Info,
-                    false => log::Level::Debug,
-                };
-                log!(level, "SSH debug message from remote host: {}", p.message);
+                debug!("SSH debug message from remote host: {}", p.message);
+                if p.always_display {
+                    disp.event = if self.is_client() {
+                        DispatchEvent::CliEvent(CliEventId::DebugMessage)
+                    } else {
+                        DispatchEvent::ServEvent(ServEventId::DebugMessage)
+                    };
+                }
             }
             Packet::Disconnect(_p) => {
                 // We ignore p.reason.
@@ -416,7 +599,7 @@ impl Conn {
             }
             Packet::UserauthFailure(p) => {
                 if let ClientServer::Client(cli) = &mut self.cliserv {
-                    disp.event = cli.auth.failure(&p, &mut self.parse_ctx)?;
+                    disp.event = cli.auth.failure(&p, &mut self.parse_ctx, s)?;
                 } else {
                     debug!("Received UserauthFailure as a server");
                     return error::SSHProto.fail()
@@ -426,6 +609,7 @@ impl Conn {
                 if let ClientServer::Client(cli) = &mut self.cliserv {
                     if matches!(self.state, ConnState::PreAuth) {
                         self.state = ConnState::Authed;
+                        s.enable_delayed_compression();
                         disp.event = cli.auth_success(&mut self.parse_ctx);
                     } else {
                         debug!("Received UserauthSuccess unrequested")
@@ -452,6 +636,12 @@ impl Conn {
                     return error::SSHProto.fail()
                 }
             }
+            Packet::UserauthInfoResponse(_) => {
+                // Keyboard-interactive isn't implemented for servers,
+                // we never send an info request.
+                debug!("Unexpected userauth info response");
+                return error::SSHProto.fail()
+            }
             | Packet::ChannelOpen(_)
             | Packet::ChannelOpenConfirmation(_)
             | Packet::ChannelOpenFailure(_)
@@ -469,7 +659,18 @@ impl Conn {
             }
             Packet::GlobalRequest(p) => {
                 trace!("Got global request {p:?}");
-                if p.want_reply {
+                let ev = match (&p.req, self.is_server()) {
+                    (packets::GlobalRequestMethod::TcpipForward(_), true)
+                    => Some(DispatchEvent::ServEvent(ServEventId::TcpipForward)),
+                    (packets::GlobalRequestMethod::CancelTcpipForward(_), true)
+                    => Some(DispatchEvent::ServEvent(ServEventId::CancelTcpipForward)),
+                    (packets::GlobalRequestMethod::HostKeys(_), false)
+                    => Some(DispatchEvent::CliEvent(CliEventId::HostkeysUpdate)),
+                    _ => None,
+                };
+                if let Some(ev) = ev {
+                    disp.event = ev;
+                } else if p.want_reply {
                     s.send(packets::RequestFailure {})?;
                 }
             }
@@ -521,7 +722,7 @@ impl Conn {
                 return error::BadUsage.fail()
             }
 
-            self.kex.resume_kexdhreply(&p, self.is_first_kex(), s)
+            self.kex.resume_kexdhreply(&p, s)
         } else {
             Err(Error::bug())
         }
@@ -553,6 +754,64 @@ impl Conn {
         }
     }
 
+    pub(crate) fn fetch_debug<'p>(&self, payload: &'p [u8]) -> Result<DebugMessage<'p>> {
+        if let Packet::DebugPacket(p) = self.packet(payload)? {
+            Ok(DebugMessage(p))
+        } else {
+            Err(Error::bug())
+        }
+    }
+
+    pub(crate) fn fetch_cli_hostkeys<'p>(&self, payload: &'p [u8]) -> Result<HostkeysUpdate<'p>> {
+        self.client()?;
+        if let Packet::GlobalRequest(packets::GlobalRequest {
+            req: packets::GlobalRequestMethod::HostKeys(k), ..
+        }) = self.packet(payload)? {
+            Ok(HostkeysUpdate(k))
+        } else {
+            Err(Error::bug())
+        }
+    }
+
+    /// Sends a server's host keys in a `hostkeys-00@openssh.com` request.
+    pub(crate) fn send_hostkeys(&self, keys: &[&SignKey], s: &mut TrafSend) -> Result<()> {
+        if !self.is_server() || !matches!(self.state, ConnState::Authed) {
+            return error::BadUsage.fail()
+        }
+        let mut pubkeys = Vec::<_, { config::MAX_HOSTKEYS }>::new();
+        for k in keys {
+            pubkeys.push(k.pubkey()).map_err(|_| error::BadUsage.build())?;
+        }
+        s.send(packets::GlobalRequest {
+            want_reply: false,
+            req: packets::GlobalRequestMethod::HostKeys(
+                packets::HostKeyList::Local(&pubkeys)),
+        })
+    }
+
+    pub(crate) fn fetch_cli_kbdint<'p>(&self, payload: &'p [u8]) -> Result<packets::UserauthInfoRequest<'p>> {
+        self.client()?;
+        if let Packet::Userauth60(packets::Userauth60::InfoRequest(r)) = self.packet(payload)? {
+            Ok(r)
+        } else {
+            Err(Error::bug())
+        }
+    }
+
+    pub(crate) fn resume_cli_kbdint(&mut self,
+        payload: &[u8],
+        s: &mut TrafSend,
+        responses: Option<&[&str]>) -> Result<()> {
+        let num_prompts = self.fetch_cli_kbdint(payload)?.prompts.as_slice().len();
+        let (cliauth, _) = self.mut_cliauth()?;
+        cliauth.resume_kbdint(s, responses, num_prompts)
+    }
+
+    pub(crate) fn fetch_servhostkey_type(&self) -> Result<KeyType> {
+        self.server()?;
+        self.kex.hostkey_type()
+    }
+
     pub(crate) fn resume_servhostkeys(&mut self,
         payload: &[u8], s: &mut TrafSend, keys: &[&SignKey]) -> Result<()> {
         self.server()?;
@@ -607,5 +866,298 @@ mod tests {
     use crate::sunsetlog::*;
     use crate::conn::*;
     use crate::error::Error;
-}
+    use crate::encrypt::{KeyState, TestSend};
+    use crate::traffic::TrafOut;
+    use crate::sshwire::BinString;
+
+    fn handle<'p>(conn: &mut Conn, p: impl Into<Packet<'p>>) -> Result<Dispatched> {
+        let mut outbuf = vec![0u8; 1000];
+        let mut out = TrafOut::new(&mut outbuf);
+        let mut keys = KeyState::new_cleartext();
+        handle_send(conn, p, &mut out.sender(&mut keys))
+    }
+
+    fn handle_send<'p>(conn: &mut Conn, p: impl Into<Packet<'p>>, s: &mut TrafSend)
+        -> Result<Dispatched> {
+        let mut buf = vec![0u8; 1000];
+        let l = sshwire::write_ssh(&mut buf, &p.into()).unwrap();
+        conn.handle_payload(&buf[..l], 0, s)
+    }
+
+    fn test_algos() -> kex::Algos {
+        kex::Algos {
+            kex: kex::SharedSecret::from_name(SSH_NAME_CURVE25519).unwrap(),
+            hostsig: sign::SigType::Ed25519,
+            cipher_enc: encrypt::Cipher::ChaPoly,
+            cipher_dec: encrypt::Cipher::ChaPoly,
+            integ_enc: encrypt::Integ::ChaPoly,
+            integ_dec: encrypt::Integ::ChaPoly,
+            comp_enc: compress::Compress::NoComp,
+            comp_dec: compress::Compress::NoComp,
+            discard_next: false,
+            is_client: false,
+            send_ext_info: false,
+            strict_kex: false,
+        }
+    }
+
+    #[test]
+    /// Packets are rejected outside their connection phase
+    fn category_phase() {
+        init_test_log();
+        let data = || packets::ChannelData { num: 0, data: BinString(&[1u8, 2]) };
+        let service = || packets::ServiceRequest { name: SSH_SERVICE_USERAUTH };
+
+        let mut conn = Conn::new(false).unwrap();
+        // before auth
+        assert!(handle(&mut conn, packets::Ignore { data: BinString(&[]) }).is_ok());
+        assert!(matches!(handle(&mut conn, data()), Err(Error::PacketWrong)));
+        assert!(matches!(handle(&mut conn, service()), Err(Error::PacketWrong)));
+        conn.state = ConnState::PreAuth;
+        assert!(matches!(handle(&mut conn, data()), Err(Error::PacketWrong)));
+
+        // during kex
+        conn.state = ConnState::Authed;
+        let mut outbuf = vec![0u8; 1000];
+        let mut out = TrafOut::new(&mut outbuf);
+        let mut keys = KeyState::new_cleartext();
+        conn.kex.send_kexinit(&conn.algo_conf, &mut out.sender(&mut keys)).unwrap();
+        // the peer hasn't replied with its KexInit, so may still send data
+        assert!(handle(&mut conn, data()).is_ok());
+    }
+
+    /// Once our NewKeys is sent only the peer's NewKeys may follow
+    #[test]
+    fn newkeys_only() {
+        init_test_log();
+        let data = || packets::ChannelData { num: 0, data: BinString(&[1u8, 2]) };
+        let dhinit = || packets::KexDHInit { q_c: BinString(&[1u8; 32]) };
+
+        let mut conn = Conn::new(false).unwrap();
+        conn.state = ConnState::Authed;
+        conn.sess_id = Some(SessId::from_slice(&[7u8; 32]).unwrap());
+        let algos = test_algos();
+        let output = kex::KexOutput::new_test(b"test", &algos, conn.sess_id.as_ref().unwrap());
+        conn.kex = Kex::NewKeys { output, algos };
+
+        assert!(matches!(handle(&mut conn, data()), Err(Error::PacketWrong)));
+        assert!(matches!(handle(&mut conn, dhinit()), Err(Error::PacketWrong)));
+        assert!(handle(&mut conn, packets::Ignore { data: BinString(&[]) }).is_ok());
+        assert!(matches!(conn.kex, Kex::NewKeys { .. }));
+        assert!(handle(&mut conn, packets::NewKeys {}).is_ok());
+        assert!(matches!(conn.kex, Kex::Idle));
+
+        // kexdhinit outside of kex
+        assert!(matches!(handle(&mut conn, dhinit()), Err(Error::PacketWrong)));
+    }
+
+    /// A rekey started by us doesn't interrupt channel traffic the peer
+    /// sends before its own KexInit.
+    #[test]
+    fn rekey_traffic() {
+        init_test_log();
+        let mut buf = [0u8; 3000];
+        let mut ts = TestSend::with_buf(&mut buf);
+        let mut o = vec![0u8; 3000];
+
+        let mut conn = Conn::new(false).unwrap();
+        conn.state = ConnState::Authed;
+        conn.sess_id = Some(SessId::from_slice(&[7u8; 32]).unwrap());
+
+        // the peer opens a session
+        let open = packets::ChannelOpen {
+            sender_num: 7,
+            initial_window: 1000,
+            max_packet: 500,
+            ty: packets::ChannelOpenType::Session,
+        };
+        let d = handle_send(&mut conn, open, &mut ts.sender()).unwrap();
+        let DispatchEvent::ServEvent(ServEventId::OpenSession { ch }) = d.event else {
+            panic!("unexpected {:?}", d.event)
+        };
+        conn.channels.resume_open(ch, None, &mut ts.sender()).unwrap();
+
+        conn.initiate_rekey(&mut ts.sender()).unwrap();
+        // our own channel data is held
+        assert!(conn.kex_in_progress());
+        ts.out.output(&mut o);
+        assert!(!ts.out.is_output_pending());
+
+        // data and window adjustments from the peer are still accepted.
+        // Enough is read out that a window adjustment would be sent.
+        let data = [3u8; 400];
+        let adjust = packets::ChannelWindowAdjust { num: ch.0, adjust: 2000 };
+        let _ = handle_send(&mut conn, adjust, &mut ts.sender()).unwrap();
+        assert_eq!(conn.channels.send_allowed(ch), Some(500));
+        for _ in 0..config::DEFAULT_WINDOW / data.len() {
+            let p = packets::ChannelData { num: ch.0, data: BinString(&data) };
+            let d = handle_send(&mut conn, p, &mut ts.sender()).unwrap();
+            assert!(matches!(d.event, DispatchEvent::Data(_)));
+            let hold = conn.kex_in_progress();
+            conn.channels.finished_input(ch, data.len(), hold, &mut ts.sender()).unwrap();
+        }
+        // but the adjustment is held until the kex completes
+        assert!(!ts.out.is_output_pending());
+
+        // the peer's KexInit has arrived, only kex packets are allowed now
+        let algos = test_algos();
+        let output = kex::KexOutput::new_test(b"test", &algos, conn.sess_id.as_ref().unwrap());
+        conn.kex = Kex::NewKeys { output, algos };
+        let p = packets::ChannelData { num: ch.0, data: BinString(&data) };
+        let r = handle_send(&mut conn, p, &mut ts.sender());
+        assert!(matches!(r, Err(Error::PacketWrong)));
+
+        let _ = handle_send(&mut conn, packets::NewKeys {}, &mut ts.sender()).unwrap();
+        assert!(!conn.kex_in_progress());
+        assert!(ts.out.is_output_pending());
+    }
+
+    /// A rekey negotiating a weaker cipher than the first key exchange
+    /// is rejected
+    #[test]
+    #[cfg(feature = "aes")]
+    fn rekey_downgrade() {
+        init_test_log();
+        let mut buf = [0u8; 3000];
+        let mut ts = TestSend::with_buf(&mut buf);
+        let mut o = [0u8; 3000];
+
+        let mut conn = Conn::new(true).unwrap();
+        conn.set_reject_rekey_downgrade(true);
+        conn.remote_version.consume(b"SSH-2.0-serv\r\n").unwrap();
+        // the first kex is also handled as a rekey, avoiding strict kex checks
+        conn.set_test_sess_id();
+
+        // a KexInit payload from a server
+        let kexinit = |conf: &AlgoConfig| {
+            let mut b = [0u8; 3000];
+            let mut ss = TestSend::with_buf(&mut b);
+            Kex::Idle.send_kexinit(conf, &mut ss.sender()).unwrap();
+            let mut o = [0u8; 3000];
+            ss.recv_payload(&mut o).to_vec()
+        };
+        let mut rekey = |conn: &mut Conn, p: &[u8]| {
+            conn.kex = Kex::Idle;
+            let r = conn.handle_payload(p, 0, &mut ts.sender());
+            ts.out.output(&mut o);
+            r
+        };
+
+        let serv_conf = AlgoConfig::new(false);
+        let p = kexinit(&serv_conf);
+        rekey(&mut conn, &p).unwrap();
+        assert!(matches!(conn.kex.algos().unwrap().cipher_enc, encrypt::Cipher::ChaPoly));
+        // same algorithms again
+        rekey(&mut conn, &p).unwrap();
+
+        let weak_conf = AlgoConfig::new(false)
+            .with_ciphers(&[SSH_NAME_AES256_CTR]).unwrap();
+        let p = kexinit(&weak_conf);
+        let r = rekey(&mut conn, &p);
+        assert!(matches!(r, Err(Error::RekeyDowngrade { algo: "encryption" })));
+
+        // allowed unless rejection is enabled
+        conn.set_reject_rekey_downgrade(false);
+        rekey(&mut conn, &p).unwrap();
+    }
+
+    /// Replies to the peer's requests aren't sent between our KexInit
+    /// and the end of key exchange.
+    #[test]
+    fn rekey_held_reply() {
+        init_test_log();
+        let mut buf = [0u8; 1000];
+        let mut ts = TestSend::with_buf(&mut buf);
+        let mut o = [0u8; 1000];
+
+        let mut conn = Conn::new(false).unwrap();
+        conn.state = ConnState::Authed;
+        conn.sess_id = Some(SessId::from_slice(&[7u8; 32]).unwrap());
+
+        conn.initiate_rekey(&mut ts.sender()).unwrap();
+        assert!(matches!(conn.kex, Kex::KexInit { .. }));
+        let p = ts.recv_payload(&mut o);
+        let p = sshwire::packet_from_bytes(p, &Default::default()).unwrap();
+        assert!(matches!(p, Packet::KexInit(_)));
+        assert!(!ts.out.is_output_pending());
+
+        let req = packets::GlobalRequest {
+            want_reply: true,
+            req: packets::GlobalRequestMethod::KeepAlive,
+        };
+        let _ = handle_send(&mut conn, req, &mut ts.sender()).unwrap();
+        assert!(!ts.out.is_output_pending());
+
+        // sent once the kex completes
+        ts.sender().finish_kex().unwrap();
+        let p = ts.recv_payload(&mut o);
+        let p = sshwire::packet_from_bytes(p, &Default::default()).unwrap();
+        assert!(matches!(p, Packet::RequestFailure(_)));
+    }
 
+    #[test]
+    fn keepalive() {
+        init_test_log();
+        let mut buf = [0u8; 1000];
+        let mut ts = TestSend::with_buf(&mut buf);
+
+        let mut conn = Conn::new(true).unwrap();
+        conn.set_keepalive(10, 2);
+        // not sent before auth
+        conn.keepalive(100, &mut ts.sender()).unwrap();
+        assert!(conn.keepalive.last.is_none());
+
+        conn.state = ConnState::Authed;
+        let mut ka = |conn: &mut Conn, now| {
+            conn.keepalive(now, &mut ts.sender())
+        };
+        ka(&mut conn, 100).unwrap();
+        ka(&mut conn, 109).unwrap();
+        assert_eq!(conn.keepalive.missed, 0);
+        ka(&mut conn, 110).unwrap();
+        assert_eq!(conn.keepalive.missed, 1);
+
+        // a received packet resets the count
+        let _ = handle(&mut conn, packets::RequestFailure {}).unwrap();
+        ka(&mut conn, 125).unwrap();
+        assert_eq!(conn.keepalive.missed, 0);
+        ka(&mut conn, 135).unwrap();
+        ka(&mut conn, 145).unwrap();
+        assert_eq!(conn.keepalive.missed, 2);
+        assert!(matches!(ka(&mut conn, 155), Err(Error::Timeout)));
+    }
+
+    /// Only always_display debug messages are returned as events
+    #[test]
+    fn debug_message() {
+        init_test_log();
+        let debug = |always_display| packets::DebugPacket {
+            always_display,
+            message: "hi".into(),
+            lang: "",
+        };
+
+        let mut conn = Conn::new(true).unwrap();
+        let d = handle(&mut conn, debug(false)).unwrap();
+        assert!(d.event.is_none());
+        let d = handle(&mut conn, debug(true)).unwrap();
+        assert!(matches!(d.event, DispatchEvent::CliEvent(CliEventId::DebugMessage)));
+
+        let mut conn = Conn::new(false).unwrap();
+        let d = handle(&mut conn, debug(true)).unwrap();
+        assert!(matches!(d.event, DispatchEvent::ServEvent(ServEventId::DebugMessage)));
+
+        let mut buf = [0u8; 100];
+        let l = sshwire::write_ssh(&mut buf, &Packet::from(debug(true))).unwrap();
+        let m = conn.fetch_debug(&buf[..l]).unwrap();
+        assert_eq!(m.message().unwrap(), "hi");
+    }
+
+    #[test]
+    fn session_id() {
+        let mut conn = Conn::new(true).unwrap();
+        assert!(conn.sess_id().is_none());
+        conn.sess_id = Some(SessId::from_slice(&[7u8; 32]).unwrap());
+        assert_eq!(conn.sess_id().unwrap().as_slice(), &[7u8; 32]);
+    }
+}
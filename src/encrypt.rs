@@ -10,6 +10,7 @@ use core::fmt;
 use core::fmt::Debug;
 use core::num::Wrapping;
 
+#[cfg(feature = "aes")]
 use aes::{
     cipher::{BlockSizeUser, KeyIvInit, KeySizeUser, StreamCipher},
     Aes256,
@@ -20,13 +21,16 @@ use sha2::Digest as Sha2DigestForTrait;
 use zeroize::ZeroizeOnDrop;
 
 use crate::*;
+use compress::{Compress, Compressor, Decompressor};
 use kex::{self, SessId};
 use ssh_chapoly::SSHChaPoly;
 use sshnames::*;
 use sshwire::hash_mpint;
 
-// TODO: check that Ctr32 is sufficient. Should be OK with SSH rekeying.
-type Aes256Ctr32BE = ctr::Ctr32BE<aes::Aes256>;
+// RFC4344 increments the whole IV as a 128 bit counter. A 32 bit counter
+// would wrap without carrying, a random IV can be close to wrapping.
+#[cfg(feature = "aes")]
+type Aes256Ctr128BE = ctr::Ctr128BE<aes::Aes256>;
 type HmacSha256 = hmac::Hmac<sha2::Sha256>;
 
 const SSH_MIN_PADLEN: usize = 4;
@@ -51,6 +55,20 @@ pub(crate) struct KeyState {
     pub seq_decrypt: Wrapping<u32>,
     strict_kex: bool,
     done_first_kex: bool,
+    // Set once authentication succeeds, for delayed compression
+    authed: bool,
+    // Compression streams are reset on each rekey
+    comp_enc: Compressor,
+    comp_dec: Decompressor,
+
+    // Traffic since the last kex, for triggering rekeying
+    bytes_since_kex: u64,
+    packets_since_kex: u32,
+    rekey_bytes: u64,
+    rekey_secs: u64,
+    // Times are provided by the application, with an arbitrary epoch.
+    now: Option<u64>,
+    kex_time: Option<u64>,
 }
 
 impl KeyState {
@@ -61,10 +79,65 @@ impl KeyState {
             seq_encrypt: Wrapping(0),
             seq_decrypt: Wrapping(0),
             strict_kex: false,
-            done_first_kex: false
+            done_first_kex: false,
+            authed: false,
+            comp_enc: Compressor::NoComp,
+            comp_dec: Decompressor::NoComp,
+            bytes_since_kex: 0,
+            packets_since_kex: 0,
+            rekey_bytes: config::DEFAULT_REKEY_BYTES,
+            rekey_secs: config::DEFAULT_REKEY_SECS,
+            now: None,
+            kex_time: None,
         }
     }
 
+    /// A `KeyState` with arbitrary fixed chacha20-poly1305 keys, so that
+    /// tests can send session packets.
+    #[cfg(test)]
+    pub fn new_test_keys() -> Self {
+        Self::test_keys(false)
+    }
+
+    /// The other side of [`new_test_keys()`](Self::new_test_keys), able to
+    /// decrypt its output.
+    #[cfg(test)]
+    pub fn new_test_peer_keys() -> Self {
+        Self::test_keys(true)
+    }
+
+    /// Decrypts the first packet of `buf`, returning its payload.
+    #[cfg(test)]
+    pub fn test_recv_payload<'b>(&mut self, buf: &'b mut [u8]) -> &'b [u8] {
+        let l = self.decrypt_first_block(buf).unwrap();
+        let buf = &mut buf[..l];
+        let payload_len = self.decrypt(buf).unwrap();
+        &buf[SSH_PAYLOAD_START..SSH_PAYLOAD_START + payload_len]
+    }
+
+    #[cfg(test)]
+    fn test_keys(is_client: bool) -> Self {
+        let algos = kex::Algos {
+            kex: kex::SharedSecret::from_name(SSH_NAME_CURVE25519).unwrap(),
+            hostsig: sign::SigType::Ed25519,
+            cipher_enc: Cipher::ChaPoly,
+            cipher_dec: Cipher::ChaPoly,
+            integ_enc: Integ::ChaPoly,
+            integ_dec: Integ::ChaPoly,
+            comp_enc: Compress::NoComp,
+            comp_dec: Compress::NoComp,
+            discard_next: false,
+            is_client,
+            send_ext_info: false,
+            strict_kex: false,
+        };
+        let h = SessId::from_slice(&sha2::Sha256::digest(b"test hash")).unwrap();
+        let ko = kex::KexOutput::new_test(b"test", &algos, &h);
+        let mut ks = Self::new_cleartext();
+        ks.rekey(Keys::derive(ko, &h, &algos).unwrap());
+        ks
+    }
+
     pub fn is_cleartext(&self) -> bool {
         matches!(self.keys.enc, EncKey::NoCipher)
             || matches!(self.keys.dec, DecKey::NoCipher)
@@ -74,7 +147,12 @@ impl KeyState {
     pub fn rekey(&mut self, keys: Keys) {
         trace!("rekey");
         self.keys = keys;
+        self.comp_enc = Compressor::new(&self.keys.comp_enc, self.authed);
+        self.comp_dec = Decompressor::new(&self.keys.comp_dec, self.authed);
         self.done_first_kex = true;
+        self.bytes_since_kex = 0;
+        self.packets_since_kex = 0;
+        self.kex_time = self.now;
         if self.strict_kex {
             self.seq_decrypt = Wrapping(0);
             self.seq_encrypt = Wrapping(0);
@@ -87,6 +165,54 @@ impl KeyState {
         }
     }
 
+    /// Called after authentication succeeds, starts any delayed compression
+    /// (`zlib@openssh.com`).
+    pub fn enable_delayed_compression(&mut self) {
+        self.authed = true;
+        if !self.comp_enc.is_active() {
+            self.comp_enc = Compressor::new(&self.keys.comp_enc, true);
+        }
+        if !self.comp_dec.is_active() {
+            self.comp_dec = Decompressor::new(&self.keys.comp_dec, true);
+        }
+    }
+
+    pub fn set_rekey_limits(&mut self, bytes: u64, secs: u64) {
+        self.rekey_bytes = bytes;
+        self.rekey_secs = secs;
+    }
+
+    /// Records the current time in seconds, for time based rekeying.
+    pub fn set_time(&mut self, now: u64) {
+        self.now = Some(now);
+        if self.kex_time.is_none() && self.done_first_kex {
+            // First call after kex, start counting from now.
+            self.kex_time = Some(now);
+        }
+    }
+
+    /// Returns `true` when a traffic or time limit has been reached
+    /// and a new key exchange should be started.
+    pub fn rekey_due(&self) -> bool {
+        if !self.done_first_kex {
+            return false
+        }
+
+        let elapsed = match (self.now, self.kex_time) {
+            (Some(now), Some(t)) => now.saturating_sub(t),
+            _ => 0,
+        };
+
+        self.bytes_since_kex >= self.rekey_bytes
+            || self.packets_since_kex >= config::REKEY_PACKETS
+            || elapsed >= self.rekey_secs
+    }
+
+    fn account(&mut self, len: usize) {
+        self.bytes_since_kex = self.bytes_since_kex.saturating_add(len as u64);
+        self.packets_since_kex = self.packets_since_kex.saturating_add(1);
+    }
+
     pub fn recv_seq(&self) -> u32 {
         self.seq_decrypt.0
     }
@@ -101,19 +227,40 @@ impl KeyState {
     pub fn decrypt(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
         let e = self.keys.decrypt(buf, self.seq_decrypt.0);
         self.seq_decrypt += 1;
+        self.account(buf.len());
         e
     }
 
+    /// Decompresses a decrypted payload in place, returning the new length.
+    /// `buf` is the payload buffer, with the remainder available for output.
+    /// `zeroize` clears the decompression scratch space afterwards.
+    pub fn decompress(&mut self, buf: &mut [u8], payload_len: usize, zeroize: bool)
+        -> Result<usize, Error> {
+        self.comp_dec.decompress(buf, payload_len, zeroize)
+    }
+
     /// [`buf`] is the entire output buffer to encrypt in place.
-    /// payload_len is the length of the payload portion
+    /// payload_len is the length of the payload portion, which
+    /// will be compressed first if enabled.
     /// This is stateful, updating the sequence number.
     pub fn encrypt(
         &mut self,
         payload_len: usize,
         buf: &mut [u8],
     ) -> Result<usize, Error> {
+        let payload_len = if self.comp_enc.is_active() {
+            let Some(p) = buf.get_mut(SSH_PAYLOAD_START..) else {
+                return error::NoRoom.fail()
+            };
+            self.comp_enc.compress(p, payload_len)?
+        } else {
+            payload_len
+        };
         let e = self.keys.encrypt(payload_len, buf, self.seq_encrypt.0);
         self.seq_encrypt += 1;
+        if let Ok(l) = e {
+            self.account(l);
+        }
         e
     }
 
@@ -124,8 +271,10 @@ impl KeyState {
     /// Returns the maximum payload that can fit in an available buffer
     /// after header, encryption, padding, mac
     pub fn max_enc_payload(&self, total_avail: usize) -> usize {
-        // mac is independent of the rest
-        let total_avail = total_avail.saturating_sub(self.keys.integ_enc.size_out());
+        // mac and compression are independent of the rest
+        let total_avail = total_avail
+            .saturating_sub(self.keys.integ_enc.size_out())
+            .saturating_sub(self.comp_enc.overhead());
 
         let overhead = SSH_LENGTH_SIZE + 1 + SSH_MIN_PADLEN;
         let mut space = total_avail;
@@ -147,6 +296,37 @@ impl KeyState {
     }
 }
 
+/// A test fixture holding a [`TrafOut`](traffic::TrafOut) with
+/// [`KeyState::new_test_keys()`], for sending session packets.
+#[cfg(test)]
+pub(crate) struct TestSend<'a> {
+    pub out: traffic::TrafOut<'a>,
+    pub keys: KeyState,
+    peer: KeyState,
+}
+
+#[cfg(test)]
+impl<'a> TestSend<'a> {
+    pub fn with_buf(buf: &'a mut [u8]) -> Self {
+        Self {
+            out: traffic::TrafOut::new(buf),
+            keys: KeyState::new_test_keys(),
+            peer: KeyState::new_test_peer_keys(),
+        }
+    }
+
+    pub fn sender(&mut self) -> traffic::TrafSend<'_, 'a> {
+        self.out.sender(&mut self.keys)
+    }
+
+    /// Takes the pending output and returns the decrypted payload of its
+    /// first packet.
+    pub fn recv_payload<'b>(&mut self, buf: &'b mut [u8]) -> &'b [u8] {
+        let l = self.out.output(buf);
+        self.peer.test_recv_payload(&mut buf[..l])
+    }
+}
+
 // Clone is required so we can clone() then drop the original in place,
 // avoiding issues with Option::take(). This could be revisited.
 #[derive(Debug, Clone, ZeroizeOnDrop)]
@@ -158,6 +338,11 @@ pub(crate) struct Keys {
     pub(crate) integ_enc: IntegKey,
     #[zeroize(skip)]
     pub(crate) integ_dec: IntegKey,
+
+    #[zeroize(skip)]
+    pub(crate) comp_enc: Compress,
+    #[zeroize(skip)]
+    pub(crate) comp_dec: Compress,
 }
 
 impl Keys {
@@ -167,6 +352,8 @@ impl Keys {
             dec: DecKey::NoCipher,
             integ_enc: IntegKey::NoInteg,
             integ_dec: IntegKey::NoInteg,
+            comp_enc: Compress::NoComp,
+            comp_dec: Compress::NoComp,
         }
     }
 
@@ -236,7 +423,14 @@ impl Keys {
             IntegKey::from_integ(&algos.integ_dec, ck)?
         };
 
-        Ok(Keys { enc, dec, integ_enc, integ_dec })
+        Ok(Keys {
+            enc,
+            dec,
+            integ_enc,
+            integ_dec,
+            comp_enc: algos.comp_enc.clone(),
+            comp_dec: algos.comp_dec.clone(),
+        })
     }
 
     /// Decrypts the first block in the buffer, returning the length of the
@@ -255,6 +449,7 @@ impl Keys {
 
         let len = match &mut self.dec {
             DecKey::ChaPoly(k) => k.packet_length(seq, buf).trap()?,
+            #[cfg(feature = "aes")]
             DecKey::Aes256Ctr(a) => {
                 a.apply_keystream(&mut buf[..16]);
                 u32::from_be_bytes(buf[..SSH_LENGTH_SIZE].try_into().unwrap())
@@ -305,6 +500,7 @@ impl Keys {
             DecKey::ChaPoly(k) => {
                 k.decrypt(seq, data, mac).map_err(|_| Error::BadDecrypt)?;
             }
+            #[cfg(feature = "aes")]
             DecKey::Aes256Ctr(a) => {
                 // safe index, checked data.len()
                 a.apply_keystream(&mut data[16..]);
@@ -413,6 +609,7 @@ impl Keys {
 
         match &mut self.enc {
             EncKey::ChaPoly(k) => k.encrypt(seq, enc, mac).trap()?,
+            #[cfg(feature = "aes")]
             EncKey::Aes256Ctr(a) => {
                 a.apply_keystream(enc);
             }
@@ -430,25 +627,31 @@ impl Keys {
 #[derive(Debug, Clone)]
 pub(crate) enum Cipher {
     ChaPoly,
+    #[cfg(feature = "aes")]
     Aes256Ctr,
     // TODO AesGcm etc
 }
 
 impl fmt::Display for Cipher {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let n = match self {
-            Self::ChaPoly => SSH_NAME_CHAPOLY,
-            Self::Aes256Ctr => SSH_NAME_AES256_CTR,
-        };
-        write!(f, "{n}")
+        write!(f, "{}", self.name())
     }
 }
 
 impl Cipher {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::ChaPoly => SSH_NAME_CHAPOLY,
+            #[cfg(feature = "aes")]
+            Self::Aes256Ctr => SSH_NAME_AES256_CTR,
+        }
+    }
+
     /// Creates a cipher key by algorithm name. Must be passed a known name.
     pub fn from_name(name: &'static str) -> Result<Self, Error> {
         match name {
             SSH_NAME_CHAPOLY => Ok(Cipher::ChaPoly),
+            #[cfg(feature = "aes")]
             SSH_NAME_AES256_CTR => Ok(Cipher::Aes256Ctr),
             _ => Err(Error::bug()),
         }
@@ -458,6 +661,7 @@ impl Cipher {
     pub fn key_len(&self) -> usize {
         match self {
             Cipher::ChaPoly => SSHChaPoly::KEY_LEN,
+            #[cfg(feature = "aes")]
             Cipher::Aes256Ctr => aes::Aes256::key_size(),
         }
     }
@@ -466,6 +670,7 @@ impl Cipher {
     pub fn iv_len(&self) -> usize {
         match self {
             Cipher::ChaPoly => 0,
+            #[cfg(feature = "aes")]
             Cipher::Aes256Ctr => aes::Aes256::block_size(),
         }
     }
@@ -474,6 +679,7 @@ impl Cipher {
     pub fn integ(&self) -> Option<Integ> {
         match self {
             Cipher::ChaPoly => Some(Integ::ChaPoly),
+            #[cfg(feature = "aes")]
             Cipher::Aes256Ctr => None,
         }
     }
@@ -482,7 +688,8 @@ impl Cipher {
 #[derive(Clone, ZeroizeOnDrop)]
 pub(crate) enum EncKey {
     ChaPoly(SSHChaPoly),
-    Aes256Ctr(Aes256Ctr32BE),
+    #[cfg(feature = "aes")]
+    Aes256Ctr(Aes256Ctr128BE),
     // AesGcm(Todo?)
     NoCipher,
 }
@@ -491,6 +698,7 @@ impl Debug for EncKey {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let n = match self {
             Self::ChaPoly(_) => "ChaPoly",
+            #[cfg(feature = "aes")]
             Self::Aes256Ctr(_) => "Aes256Ctr",
             Self::NoCipher => "NoCipher",
         };
@@ -504,6 +712,7 @@ impl Debug for EncKey {
 
 impl EncKey {
     /// Construct a key
+    #[cfg_attr(not(feature = "aes"), allow(unused_variables))]
     pub fn from_cipher<'a>(
         cipher: &Cipher,
         key: &'a [u8],
@@ -513,14 +722,16 @@ impl EncKey {
             Cipher::ChaPoly => {
                 Ok(EncKey::ChaPoly(SSHChaPoly::new_from_slice(key).trap()?))
             }
+            #[cfg(feature = "aes")]
             Cipher::Aes256Ctr => Ok(EncKey::Aes256Ctr(
-                Aes256Ctr32BE::new_from_slices(key, iv).trap()?,
+                Aes256Ctr128BE::new_from_slices(key, iv).trap()?,
             )),
         }
     }
     pub fn is_aead(&self) -> bool {
         match self {
             EncKey::ChaPoly(_) => true,
+            #[cfg(feature = "aes")]
             EncKey::Aes256Ctr(_a) => false,
             EncKey::NoCipher => false,
         }
@@ -528,6 +739,7 @@ impl EncKey {
     pub fn size_block(&self) -> usize {
         match self {
             EncKey::ChaPoly(_) => SSH_MIN_BLOCK,
+            #[cfg(feature = "aes")]
             EncKey::Aes256Ctr(_) => aes::Aes256::block_size(),
             EncKey::NoCipher => SSH_MIN_BLOCK,
         }
@@ -537,7 +749,8 @@ impl EncKey {
 #[derive(Clone, ZeroizeOnDrop)]
 pub(crate) enum DecKey {
     ChaPoly(SSHChaPoly),
-    Aes256Ctr(Aes256Ctr32BE),
+    #[cfg(feature = "aes")]
+    Aes256Ctr(Aes256Ctr128BE),
     // AesGcm256
     // AesCtr256
     NoCipher,
@@ -547,6 +760,7 @@ impl Debug for DecKey {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let n = match self {
             Self::ChaPoly(_) => "ChaPoly",
+            #[cfg(feature = "aes")]
             Self::Aes256Ctr(_) => "Aes256Ctr",
             Self::NoCipher => "NoCipher",
         };
@@ -556,6 +770,7 @@ impl Debug for DecKey {
 
 impl DecKey {
     /// Construct a key
+    #[cfg_attr(not(feature = "aes"), allow(unused_variables))]
     pub fn from_cipher<'a>(
         cipher: &Cipher,
         key: &'a [u8],
@@ -565,14 +780,16 @@ impl DecKey {
             Cipher::ChaPoly => {
                 Ok(DecKey::ChaPoly(SSHChaPoly::new_from_slice(key).trap()?))
             }
+            #[cfg(feature = "aes")]
             Cipher::Aes256Ctr => Ok(DecKey::Aes256Ctr(
-                Aes256Ctr32BE::new_from_slices(key, iv).trap()?,
+                Aes256Ctr128BE::new_from_slices(key, iv).trap()?,
             )),
         }
     }
     pub fn is_aead(&self) -> bool {
         match self {
             DecKey::ChaPoly(_) => true,
+            #[cfg(feature = "aes")]
             DecKey::Aes256Ctr(_a) => false,
             DecKey::NoCipher => false,
         }
@@ -580,6 +797,7 @@ impl DecKey {
     pub fn size_block(&self) -> usize {
         match self {
             DecKey::ChaPoly(_) => SSH_MIN_BLOCK,
+            #[cfg(feature = "aes")]
             DecKey::Aes256Ctr(_) => aes::Aes256::block_size(),
             DecKey::NoCipher => SSH_MIN_BLOCK,
         }
@@ -602,6 +820,18 @@ impl Integ {
             _ => Err(Error::bug()),
         }
     }
+
+    /// Algorithm name. AEAD ciphers return the cipher name.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::ChaPoly => SSH_NAME_CHAPOLY,
+            Self::HmacSha256 => SSH_NAME_HMAC_SHA256,
+        }
+    }
+
+    pub fn is_aead(&self) -> bool {
+        matches!(self, Self::ChaPoly)
+    }
     /// length in bytes
     fn key_len(&self) -> usize {
         match self {
@@ -613,11 +843,7 @@ impl Integ {
 
 impl fmt::Display for Integ {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let n = match self {
-            Self::ChaPoly => SSH_NAME_CHAPOLY,
-            Self::HmacSha256 => SSH_NAME_HMAC_SHA256,
-        };
-        write!(f, "{n}")
+        write!(f, "{}", self.name())
     }
 }
 
@@ -667,6 +893,33 @@ mod tests {
     use pretty_hex::PrettyHex;
     use sha2::Sha256;
 
+    #[test]
+    #[cfg(feature = "aes")]
+    /// The AES-CTR counter carries past 32 bits
+    fn aes_ctr_carry() {
+        use aes::cipher::{BlockEncrypt, KeyInit};
+
+        let key = [3u8; 32];
+        let mut iv = [0u8; 16];
+        iv[11] = 0x44;
+        iv[12..].copy_from_slice(&[0xff; 4]);
+        let mut c = EncKey::from_cipher(&Cipher::Aes256Ctr, &key, &iv).unwrap();
+        let EncKey::Aes256Ctr(ctr) = &mut c else { panic!() };
+        let mut buf = [0u8; 32];
+        ctr.apply_keystream(&mut buf);
+
+        let mut next = iv;
+        next[11] = 0x45;
+        next[12..].fill(0);
+        let aes = aes::Aes256::new(&key.into());
+        let mut b1 = iv.into();
+        let mut b2 = next.into();
+        aes.encrypt_block(&mut b1);
+        aes.encrypt_block(&mut b2);
+        assert_eq!(&buf[..16], b1.as_slice());
+        assert_eq!(&buf[16..], b2.as_slice());
+    }
+
     // setting `corrupt` tests that incorrect mac is detected
     fn do_roundtrips(
         keys_enc: &mut KeyState,
@@ -724,7 +977,8 @@ mod tests {
     fn algo_combos() -> impl Iterator<Item = Option<kex::Algos>> {
         // TODO make this combinatorial
         // order is enc, dec
-        const COMBOS: [(Cipher, Integ, Cipher, Integ); 4] = [
+        const COMBOS: &[(Cipher, Integ, Cipher, Integ)] = &[
+            #[cfg(feature = "aes")]
             (
                 Cipher::Aes256Ctr,
                 Integ::HmacSha256,
@@ -732,7 +986,9 @@ mod tests {
                 Integ::HmacSha256,
             ),
             (Cipher::ChaPoly, Integ::ChaPoly, Cipher::ChaPoly, Integ::ChaPoly),
+            #[cfg(feature = "aes")]
             (Cipher::Aes256Ctr, Integ::HmacSha256, Cipher::ChaPoly, Integ::ChaPoly),
+            #[cfg(feature = "aes")]
             (Cipher::ChaPoly, Integ::ChaPoly, Cipher::Aes256Ctr, Integ::HmacSha256),
         ];
         COMBOS
@@ -745,6 +1001,8 @@ mod tests {
                     cipher_dec: cd.clone(),
                     integ_enc: ie.clone(),
                     integ_dec: id.clone(),
+                    comp_enc: Compress::NoComp,
+                    comp_dec: Compress::NoComp,
                     discard_next: false,
                     is_client: false,
                     send_ext_info: true,
@@ -839,4 +1097,67 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn rekey_limits() {
+        init_test_log();
+        let mut keys = KeyState::new_cleartext();
+        keys.set_rekey_limits(1000, 60);
+        let mut buf = [0u8; 200];
+
+        // not before the first kex
+        for _ in 0..20 {
+            keys.encrypt(100, &mut buf).unwrap();
+        }
+        assert!(!keys.rekey_due());
+
+        // byte limit
+        keys.rekey(Keys::new_cleartext());
+        assert!(!keys.rekey_due());
+        while !keys.rekey_due() {
+            keys.encrypt(100, &mut buf).unwrap();
+        }
+        assert!(keys.bytes_since_kex >= 1000);
+
+        // time limit, counting from the first set_time() after kex
+        keys.rekey(Keys::new_cleartext());
+        keys.set_time(5000);
+        keys.set_time(5059);
+        assert!(!keys.rekey_due());
+        keys.set_time(5060);
+        assert!(keys.rekey_due());
+        keys.rekey(Keys::new_cleartext());
+        assert!(!keys.rekey_due());
+        keys.set_time(5120);
+        assert!(keys.rekey_due());
+    }
+
+    /// Sequence numbers reset on each rekey with strict kex,
+    /// which can only be enabled during the first kex.
+    #[test]
+    fn strict_kex_seq() {
+        init_test_log();
+        let mut buf = [0u8; 200];
+
+        let mut keys = KeyState::new_cleartext();
+        keys.encrypt(20, &mut buf).unwrap();
+        keys.seq_decrypt += 3;
+        keys.rekey(Keys::new_cleartext());
+        assert_eq!((keys.seq_encrypt.0, keys.seq_decrypt.0), (1, 3));
+        // too late to enable
+        keys.enable_strict_kex();
+        keys.rekey(Keys::new_cleartext());
+        assert_eq!((keys.seq_encrypt.0, keys.seq_decrypt.0), (1, 3));
+
+        let mut keys = KeyState::new_cleartext();
+        keys.encrypt(20, &mut buf).unwrap();
+        keys.seq_decrypt += 3;
+        keys.enable_strict_kex();
+        keys.rekey(Keys::new_cleartext());
+        assert_eq!((keys.seq_encrypt.0, keys.seq_decrypt.0), (0, 0));
+        // and for later rekeys
+        keys.encrypt(20, &mut buf).unwrap();
+        keys.rekey(Keys::new_cleartext());
+        assert_eq!(keys.seq_encrypt.0, 0);
+    }
 }
@@ -10,6 +10,7 @@ use snafu::{prelude::*, Backtrace, Location};
 use heapless::String;
 
 use crate::channel::ChanNum;
+use crate::sshnames::DisconnectReason;
 
 // TODO: can we make Snafu not require Debug?
 
@@ -70,6 +71,13 @@ pub enum Error {
     /// Bad key format
     BadKey,
 
+    /// A certificate isn't valid, or wasn't signed by a trusted CA
+    #[snafu(display("Bad certificate: {reason}"))]
+    BadCert { reason: &'static str },
+
+    /// Incorrect passphrase for an encrypted key
+    BadPassphrase,
+
     /// Ran out of channels
     NoChannels,
 
@@ -99,6 +107,14 @@ pub enum Error {
     /// SSH packet contents doesn't match length
     WrongPacketLength,
 
+    /// The peer refused to open a channel.
+    ///
+    /// `reason` is a [`ChanFail`](crate::ChanFail) code, though peers may send
+    /// other values. The peer's description is available from
+    /// [`Runner::channel_open_failure()`](crate::Runner::channel_open_failure).
+    #[snafu(display("Channel open failed, reason {reason}"))]
+    ChannelOpenFailed { reason: u32 },
+
     /// Channel EOF
     ///
     /// This is an expected error when a SSH channel completes. Can be returned
@@ -122,12 +138,36 @@ pub enum Error {
     #[snafu(display("No matching {algo} algorithm"))]
     AlgoNoMatch { algo: &'static str },
 
+    /// A rekey negotiated a less preferred algorithm than the first key exchange,
+    /// with `reject_rekey_downgrade` set.
+    #[snafu(display("Rekey downgraded {algo} algorithm"))]
+    RekeyDowngrade { algo: &'static str },
+
+    /// A negotiated algorithm was rejected by
+    /// [`AlgoConfig::with_policy()`](crate::AlgoConfig::with_policy).
+    #[snafu(display("Algorithm {algo} rejected by policy"))]
+    AlgoRejected { algo: &'static str },
+
+    /// The server has no host key for the negotiated signature type.
+    ///
+    /// [`AlgoConfig::with_hostkeys()`](crate::AlgoConfig::with_hostkeys) can
+    /// be used to only advertise usable algorithms.
+    #[snafu(display("No host key for {sig} signatures"))]
+    NoHostKey { sig: &'static str },
+
     #[snafu(display("Packet size {size} too large (or bad decrypt)"))]
     BigPacket { size: usize },
 
+    /// Peer didn't respond to keepalive requests
+    Timeout,
+
     /// Ran out of authentication methods to try (as a client)
     NoAuthMethods,
 
+    /// Client exceeded the limit of failed authentication attempts (as a server)
+    #[snafu(display("Too many authentication failures"))]
+    AuthTriesExceeded,
+
     /// An unknown SSH name is provided, for a key type, signature type,
     /// channel name etc.
     #[snafu(display("Unknown {kind} method"))]
@@ -157,14 +197,15 @@ impl Error {
 
     #[cold]
     #[track_caller]
-    /// Panics in debug builds, returns [`Error::Bug`] in release.
+    /// Returns [`Error::Bug`], or panics with the `strict-panic` feature.
     // TODO: this should return a Result since it's always used as Err(Error::bug())
     pub fn bug() -> Error {
         // Easier to track the source of errors in development,
-        // but release builds shouldn't panic.
-        if cfg!(debug_assertions) {
+        // but applications shouldn't panic unless they opt in.
+        if cfg!(any(test, feature = "strict-panic")) {
             panic!("Hit a bug");
         } else {
+            debug!("Hit a bug");
             // let caller = core::panic::Location::caller();
             Error::Bug
             // {
@@ -183,11 +224,11 @@ impl Error {
     #[cold]
     pub fn bug_fmt(args: Arguments) -> Error {
         // Easier to track the source of errors in development,
-        // but release builds shouldn't panic.
-        if cfg!(debug_assertions) {
+        // but applications shouldn't panic unless they opt in.
+        if cfg!(any(test, feature = "strict-panic")) {
             panic!("Hit a bug: {args}");
         } else {
-            trace!("Hit a bug: {args}");
+            debug!("Hit a bug: {args}");
             // TODO: this bloats binaries with full paths
             // https://github.com/rust-lang/rust/issues/95529 is having function
             // let caller = core::panic::Location::caller();
@@ -202,6 +243,31 @@ impl Error {
         }
     }
 
+    /// Returns the reason to send in a `SSH_MSG_DISCONNECT` when this
+    /// error terminates a connection.
+    ///
+    /// Returns `None` for errors that aren't reported to the peer, such as
+    /// local usage errors, or decryption failures.
+    pub fn disconnect_reason(&self) -> Option<DisconnectReason> {
+        use DisconnectReason::*;
+        let r = match self {
+            | Error::SSHProto { .. }
+            | Error::PacketWrong
+            | Error::BadKex
+            => SSH_DISCONNECT_PROTOCOL_ERROR,
+            | Error::AlgoNoMatch { .. }
+            | Error::RekeyDowngrade { .. }
+            | Error::AlgoRejected { .. }
+            | Error::NoHostKey { .. }
+            => SSH_DISCONNECT_KEY_EXCHANGE_FAILED,
+            | Error::NoAuthMethods
+            | Error::AuthTriesExceeded
+            => SSH_DISCONNECT_NO_MORE_AUTH_METHODS_AVAILABLE,
+            _ => return None,
+        };
+        Some(r)
+    }
+
     #[cold]
     /// TODO: is the generic `T` going to make it bloat?
     pub fn bug_msg<T>(msg: &str) -> Result<T, Error> {
@@ -228,7 +294,7 @@ pub type Result<T, E = Error> = core::result::Result<T, E>;
 pub trait TrapBug<T> {
     /// `.trap()` should be used like `.unwrap()`, in situations
     /// never expected to fail. Instead it calls [`Error::bug()`].
-    /// (or debug builds may panic)
+    /// (or may panic with the `strict-panic` feature)
     fn trap(self) -> Result<T, Error>;
 
     /// Like `trap()` but with a message, calls [`Error::bug_msg()`]
@@ -15,7 +15,7 @@ use core::fmt::Debug;
 
 use crate::*;
 use sshwire::TextString;
-use packets::Packet;
+use packets::{InfoPrompts, Packet};
 use channel::{CliSessionOpener, CliSessionExit};
 
 #[derive(Debug)]
@@ -56,10 +56,29 @@ pub enum CliEvent<'g, 'a>
     Password(RequestPassword<'g, 'a>),
     Pubkey(RequestPubkey<'g, 'a>),
     AgentSign(RequestSign<'g, 'a>),
+    KbdInteractive(RequestKbdInteractive<'g, 'a>),
     Authenticated,
     SessionOpened(CliSessionOpener<'g, 'a>),
     /// Remote process exited
     SessionExit(CliSessionExit<'g>),
+    /// The server replied to a channel request
+    ///
+    /// Currently only for [`Runner::open_subsystem()`] and [`Runner::open_shell()`].
+    ChanRequestResult { ch: ChanNum, success: bool },
+    /// The server opened a forwarded agent channel
+    ///
+    /// Only occurs after [`CliSessionOpener::agent_forward()`].
+    OpenAgent(CliOpenAgent<'g, 'a>),
+
+    /// The server listed all its host keys, after authentication.
+    ///
+    /// Applications may add these to known hosts, to allow for rotated keys.
+    HostkeysUpdate(HostkeysUpdate<'g>),
+
+    /// The server sent a debug message that should be displayed
+    ///
+    /// Only messages with `always_display` set are returned, others are logged.
+    DebugMessage(DebugMessage<'g>),
 
     /// The SSH connection is no longer running
     #[allow(unused)]
@@ -79,8 +98,13 @@ impl Debug for CliEvent<'_, '_> {
             Self::Authenticated => "Authenticated",
             Self::SessionOpened(_) => "SessionOpened",
             Self::SessionExit(_) => "SessionExit",
+            Self::ChanRequestResult { .. } => "ChanRequestResult",
+            Self::OpenAgent(_) => "OpenAgent",
             Self::AgentSign(_) => "AgentSign",
+            Self::KbdInteractive(_) => "KbdInteractive",
             Self::Banner(_) => "Banner",
+            Self::HostkeysUpdate(_) => "HostkeysUpdate",
+            Self::DebugMessage(_) => "DebugMessage",
             Self::Defunct => "Defunct",
         };
         write!(f, "CliEvent({e})")
@@ -122,14 +146,24 @@ pub struct RequestPubkey<'g, 'a> {
 impl<'g, 'a> RequestPubkey<'g, 'a> {
     /// Provide a public key to try
     pub fn pubkey(self, signkey: SignKey) -> Result<()> {
-        self.runner.resume_clipubkey(Some(signkey))
+        self.runner.resume_clipubkey(Some(signkey), None)
+    }
+
+    /// Provide a key to try along with an OpenSSH certificate for it
+    ///
+    /// `cert` is the encoded `ssh-ed25519-cert-v01@openssh.com` certificate,
+    /// the base64-decoded part of an `id_ed25519-cert.pub` file.
+    /// Returns [`Error::BadUsage`] if it isn't a certificate for `signkey`,
+    /// or if sunset was built without the `user-cert` feature.
+    pub fn pubkey_cert(self, signkey: SignKey, cert: &[u8]) -> Result<()> {
+        self.runner.resume_clipubkey(Some(signkey), Some(cert))
     }
 
     /// Don't provide a public key
     ///
     /// `RequestPubkey` will not be returned again.
     pub fn skip(self) -> Result<()> {
-        self.runner.resume_clipubkey(None)
+        self.runner.resume_clipubkey(None, None)
     }
 }
 
@@ -152,6 +186,47 @@ impl RequestSign<'_, '_> {
     }
 }
 
+/// A keyboard-interactive authentication request from the server
+///
+/// This may be a password prompt, a one-time code, or other
+/// challenges. A server may send several requests in turn.
+pub struct RequestKbdInteractive<'g, 'a> {
+    runner: &'g mut Runner<'a>,
+}
+
+impl RequestKbdInteractive<'_, '_> {
+    /// The name of the request, may be empty.
+    pub fn name(&self) -> Result<TextString<'_>> {
+        Ok(self.runner.fetch_cli_kbdint()?.name)
+    }
+
+    /// Instructions to display to the user, may be empty.
+    pub fn instruction(&self) -> Result<TextString<'_>> {
+        Ok(self.runner.fetch_cli_kbdint()?.instruction)
+    }
+
+    /// Prompts to present to the user. May be empty.
+    pub fn prompts(&self) -> Result<InfoPrompts<'_>> {
+        Ok(self.runner.fetch_cli_kbdint()?.prompts)
+    }
+
+    /// Provide responses to the prompts
+    ///
+    /// There must be a response for each of the [`prompts()`](Self::prompts),
+    /// in the same order.
+    pub fn responses(self, responses: &[&str]) -> Result<()> {
+        self.runner.resume_cli_kbdint(Some(responses))
+    }
+
+    /// Don't answer the prompts
+    ///
+    /// Empty responses will be sent, and keyboard-interactive
+    /// won't be attempted again.
+    pub fn skip(self) -> Result<()> {
+        self.runner.resume_cli_kbdint(None)
+    }
+}
+
 pub struct CheckHostkey<'g, 'a> {
     runner: &'g mut Runner<'a>,
 }
@@ -161,6 +236,26 @@ impl CheckHostkey<'_, '_> {
         self.runner.fetch_checkhostkey()
     }
 
+    /// Checks a host certificate against a trusted CA key.
+    ///
+    /// Returns `Ok(true)` if the host key is a certificate for `host`
+    /// signed by `ca` and valid at `now` (seconds since the Unix epoch).
+    /// Host certificates are only presented if the client enables them with
+    /// [`AlgoConfig::with_hostsig()`](crate::AlgoConfig::with_hostsig).
+    pub fn valid_host_cert(&self, ca: &PubKey, host: &str, now: u64) -> Result<bool> {
+        let PubKey::Ed25519Cert(c) = self.hostkey()? else {
+            return Ok(false)
+        };
+        match c.check(ca, sshnames::SSH_CERT_TYPE_HOST, host, now) {
+            Ok(()) => Ok(true),
+            Err(Error::BadCert { reason }) => {
+                debug!("Host certificate rejected: {reason}");
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
     pub fn accept(self) -> Result<()> {
         self.runner.resume_checkhostkey(true)
     }
@@ -170,6 +265,41 @@ impl CheckHostkey<'_, '_> {
     }
 }
 
+/// An agent channel opened by the server.
+///
+/// The accepted channel should be connected to a local agent.
+pub struct CliOpenAgent<'g, 'a> {
+    runner: &'g mut Runner<'a>,
+    done: bool,
+    ch: ChanNum,
+}
+
+impl CliOpenAgent<'_, '_> {
+    pub fn accept(mut self) -> Result<ChanHandle> {
+        self.done = true;
+        self.runner.resume_cli_chanopen(self.ch, None)?;
+        Ok(ChanHandle(self.ch))
+    }
+
+    /// Does not need to be called explicitly, also occurs on drop without `accept()`
+    pub fn reject(mut self, reason: ChanFail) -> Result<()> {
+        self.done = true;
+        self.runner.resume_cli_chanopen(self.ch, Some(reason))
+    }
+}
+
+// implement Drop to be the same as .reject()
+impl Drop for CliOpenAgent<'_, '_> {
+    fn drop(&mut self) {
+        if !self.done {
+            if let Err(e) = self.runner.resume_cli_chanopen(self.ch,
+                Some(ChanFail::SSH_OPEN_ADMINISTRATIVELY_PROHIBITED)) {
+                trace!("Error for chanopen: {e}")
+            }
+        }
+    }
+}
+
 pub struct Banner<'a>(pub(crate) packets::UserauthBanner<'a>);
 
 impl Banner<'_> {
@@ -182,6 +312,32 @@ impl Banner<'_> {
     }
 }
 
+/// A `SSH_MSG_DEBUG` message from the peer, with `always_display` set
+pub struct DebugMessage<'a>(pub(crate) packets::DebugPacket<'a>);
+
+impl DebugMessage<'_> {
+    pub fn message(&self) -> Result<&str> {
+        self.0.message.as_str()
+    }
+
+    pub fn raw_message(&self) -> TextString<'_> {
+        self.0.message
+    }
+}
+
+/// Host keys from a server's `hostkeys-00@openssh.com` request
+pub struct HostkeysUpdate<'a>(pub(crate) packets::HostKeyList<'a>);
+
+impl<'a> HostkeysUpdate<'a> {
+    /// Returns each host key.
+    ///
+    /// These have not been verified to belong to the server, other than
+    /// being sent over the authenticated connection. Keys of unsupported
+    /// types are returned as `PubKey::Unknown`.
+    pub fn hostkeys(&self) -> impl Iterator<Item = Result<PubKey<'a>>> + '_ {
+        self.0.iter()
+    }
+}
 
 // impl CliExit<''_, '_> {
 //     pub fn 
@@ -195,10 +351,15 @@ pub(crate) enum CliEventId {
     Password,
     Pubkey,
     AgentSign,
+    KbdInteractive,
     Authenticated,
     SessionOpened(ChanNum),
     SessionExit,
+    OpenAgent { ch: ChanNum },
+    ChanRequestResult { ch: ChanNum, success: bool },
     Banner,
+    HostkeysUpdate,
+    DebugMessage,
     #[allow(unused)]
     Defunct
 
@@ -225,6 +386,10 @@ impl CliEventId {
             Self::AgentSign => {
                 Ok(CliEvent::AgentSign(RequestSign { runner }))
             }
+            Self::KbdInteractive => {
+                debug_assert!(matches!(pk, Some(Packet::Userauth60(_))));
+                Ok(CliEvent::KbdInteractive(RequestKbdInteractive { runner }))
+            }
             Self::Hostkey => {
                 debug_assert!(matches!(pk, Some(Packet::KexDHReply(_))));
                 Ok(CliEvent::Hostkey(CheckHostkey { runner }))
@@ -239,9 +404,22 @@ impl CliEventId {
             Self::SessionExit => {
                 Ok(CliEvent::SessionExit(runner.fetch_cli_session_exit()?))
             }
+            Self::ChanRequestResult { ch, success } => {
+                Ok(CliEvent::ChanRequestResult { ch, success })
+            }
+            Self::OpenAgent { ch } => {
+                debug_assert!(matches!(pk, Some(Packet::ChannelOpen(_))));
+                Ok(CliEvent::OpenAgent(CliOpenAgent { runner, done: false, ch }))
+            }
             Self::Banner => {
                 Ok(CliEvent::Banner(runner.fetch_cli_banner()?))
             }
+            Self::HostkeysUpdate => {
+                Ok(CliEvent::HostkeysUpdate(runner.fetch_cli_hostkeys()?))
+            }
+            Self::DebugMessage => {
+                Ok(CliEvent::DebugMessage(runner.fetch_debug()?))
+            }
             Self::Defunct => error::BadUsage.fail()
         }
     }
@@ -253,7 +431,10 @@ impl CliEventId {
             | Self::Authenticated
             | Self::SessionOpened(_)
             | Self::SessionExit
+            | Self::ChanRequestResult { .. }
             | Self::Banner
+            | Self::HostkeysUpdate
+            | Self::DebugMessage
             | Self::Defunct
             => false,
             | Self::Hostkey
@@ -261,6 +442,8 @@ impl CliEventId {
             | Self::Password
             | Self::Pubkey
             | Self::AgentSign
+            | Self::KbdInteractive
+            | Self::OpenAgent { .. }
             => true,
         }
     }
@@ -273,8 +456,40 @@ pub enum ServEvent<'g, 'a> {
     FirstAuth(ServFirstAuth<'g, 'a>),
     OpenSession(ServOpenSession<'g, 'a>),
     SessionShell(ChanRequest<'g, 'a>),
+    /// The client requests execution of a command.
+    ///
+    /// The command is available from [`ChanRequest::command()`].
     SessionExec(ChanRequest<'g, 'a>),
     SessionPty(ChanRequest<'g, 'a>),
+    /// The client's terminal has been resized.
+    ///
+    /// The new size is available from [`ChanRequest::win_change()`].
+    SessionWinch(ChanRequest<'g, 'a>),
+    /// The client requests a subsystem such as "sftp".
+    ///
+    /// The name is available from [`ChanRequest::subsystem()`].
+    SessionSubsystem(ChanRequest<'g, 'a>),
+    /// The client requests agent forwarding.
+    ///
+    /// After succeeding, agent channels can be opened with
+    /// [`Runner::open_agent_forward()`](crate::Runner::open_agent_forward).
+    SessionAgentReq(ChanRequest<'g, 'a>),
+    /// The client requests an environment variable be set.
+    ///
+    /// The variable is available from [`ChanRequest::env()`].
+    SessionEnv(ChanRequest<'g, 'a>),
+    /// The client sends a break, for example to a serial console.
+    ///
+    /// The length is available from [`ChanRequest::break_length()`].
+    SessionBreak(ChanRequest<'g, 'a>),
+    /// Remote port forwarding request, `ssh -R`
+    TcpipForward(ServTcpipForward<'g, 'a>),
+    /// Cancel a previous [`TcpipForward`](Self::TcpipForward)
+    CancelTcpipForward(ServTcpipForward<'g, 'a>),
+    /// The client sent a debug message that should be displayed
+    ///
+    /// Only messages with `always_display` set are returned, others are logged.
+    DebugMessage(DebugMessage<'g>),
     /// The SSH session is no longer running
     #[allow(unused)]
     Defunct,
@@ -291,6 +506,14 @@ impl Debug for ServEvent<'_, '_> {
             Self::SessionShell(_) => "SessionShell",
             Self::SessionExec(_) => "SessionExec",
             Self::SessionPty(_) => "SessionPty",
+            Self::SessionWinch(_) => "SessionWinch",
+            Self::SessionSubsystem(_) => "SessionSubsystem",
+            Self::SessionAgentReq(_) => "SessionAgentReq",
+            Self::SessionEnv(_) => "SessionEnv",
+            Self::SessionBreak(_) => "SessionBreak",
+            Self::TcpipForward(_) => "TcpipForward",
+            Self::CancelTcpipForward(_) => "CancelTcpipForward",
+            Self::DebugMessage(_) => "DebugMessage",
             Self::Defunct => "Defunct",
         };
         write!(f, "ServEvent({e})")
@@ -302,6 +525,14 @@ pub struct ServHostkeys<'g, 'a> {
 }
 
 impl<'g, 'a> ServHostkeys<'g, 'a> {
+    /// Returns the host key type negotiated with the client.
+    ///
+    /// Only a key of this type needs to be passed to
+    /// [`hostkeys()`](Self::hostkeys), so other keys need not be loaded.
+    pub fn key_type(&self) -> Result<KeyType> {
+        self.runner.fetch_servhostkey_type()
+    }
+
     pub fn hostkeys(self, keys: &[&SignKey]) -> Result<()> {
         self.runner.resume_servhostkeys(keys)
     }
@@ -384,6 +615,26 @@ impl<'g, 'a> ServPubkeyAuth<'g, 'a> {
         self.runner.fetch_servpubkey()
     }
 
+    /// Checks a user certificate against a trusted CA key.
+    ///
+    /// Returns `Ok(true)` if the public key is a certificate for the
+    /// requested username, signed by `ca` and valid at `now` (seconds since
+    /// the Unix epoch). The application should still decide whether
+    /// the username may log in.
+    pub fn valid_user_cert(&self, ca: &PubKey, now: u64) -> Result<bool> {
+        let PubKey::Ed25519Cert(c) = self.pubkey()? else {
+            return Ok(false)
+        };
+        match c.check(ca, sshnames::SSH_CERT_TYPE_USER, self.username()?, now) {
+            Ok(()) => Ok(true),
+            Err(Error::BadCert { reason }) => {
+                debug!("User certificate rejected: {reason}");
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
     pub fn allow(mut self) -> Result<()> {
         self.done = true;
         if self.real_sig {
@@ -431,6 +682,13 @@ impl<'g, 'a> ServFirstAuth<'g, 'a> {
         self.raw_username()?.as_str()
     }
 
+    /// Sends a banner to the client before the authentication response.
+    ///
+    /// See [`Runner::send_banner()`].
+    pub fn banner(&mut self, message: &str) -> Result<()> {
+        self.runner.send_banner(message)
+    }
+
     pub fn allow(mut self) -> Result<()> {
         self.done = true;
         self.runner.resume_servauth(true)
@@ -497,6 +755,64 @@ impl Drop for ServOpenSession<'_, '_> {
         }
     }
 }
+/// A `tcpip-forward` or `cancel-tcpip-forward` global request
+pub struct ServTcpipForward<'g, 'a> {
+    runner: &'g mut Runner<'a>,
+    done: bool,
+}
+
+impl<'g, 'a> ServTcpipForward<'g, 'a> {
+    fn new(runner: &'g mut Runner<'a>) -> Self {
+        Self {
+            runner,
+            done: false,
+        }
+    }
+
+    /// The address to listen on.
+    ///
+    /// May be `""` for all addresses, or `"localhost"` etc.
+    pub fn address(&self) -> Result<&str> {
+        self.raw_address()?.as_str()
+    }
+
+    /// The port to listen on. 0 requests the server choose a port.
+    pub fn port(&self) -> Result<u32> {
+        Ok(self.runner.fetch_tcpip_forward()?.port)
+    }
+
+    /// Accept the request.
+    ///
+    /// `bound_port` is the port that was listened on, which is reported
+    /// to the client if it requested port 0. It is ignored for
+    /// a cancel request.
+    pub fn accept(mut self, bound_port: u32) -> Result<()> {
+        self.done = true;
+        self.runner.resume_tcpip_forward(Some(bound_port))
+    }
+
+    /// Does not need to be called explicitly, also occurs on drop without `accept()`
+    pub fn reject(mut self) -> Result<()> {
+        self.done = true;
+        self.runner.resume_tcpip_forward(None)
+    }
+
+    pub fn raw_address(&self) -> Result<TextString<'_>> {
+        Ok(self.runner.fetch_tcpip_forward()?.address)
+    }
+}
+
+// implement Drop to be the same as .reject()
+impl Drop for ServTcpipForward<'_, '_> {
+    fn drop(&mut self) {
+        if !self.done {
+            if let Err(e) = self.runner.resume_tcpip_forward(None) {
+                trace!("Error for tcpip-forward: {e}")
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub(crate) enum ServEventId {
     Hostkeys,
@@ -507,6 +823,14 @@ pub(crate) enum ServEventId {
     SessionShell,
     SessionExec,
     SessionPty,
+    SessionWinch,
+    SessionSubsystem,
+    SessionAgentReq,
+    SessionEnv,
+    SessionBreak,
+    TcpipForward,
+    CancelTcpipForward,
+    DebugMessage,
     #[allow(unused)]
     Defunct,
 
@@ -554,6 +878,38 @@ impl ServEventId {
                 debug_assert!(matches!(p, Some(Packet::ChannelRequest(_))));
                 Ok(ServEvent::SessionPty(ChanRequest::new(runner)))
             }
+            Self::SessionWinch => {
+                debug_assert!(matches!(p, Some(Packet::ChannelRequest(_))));
+                Ok(ServEvent::SessionWinch(ChanRequest::new(runner)))
+            }
+            Self::SessionSubsystem => {
+                debug_assert!(matches!(p, Some(Packet::ChannelRequest(_))));
+                Ok(ServEvent::SessionSubsystem(ChanRequest::new(runner)))
+            }
+            Self::SessionAgentReq => {
+                debug_assert!(matches!(p, Some(Packet::ChannelRequest(_))));
+                Ok(ServEvent::SessionAgentReq(ChanRequest::new(runner)))
+            }
+            Self::SessionEnv => {
+                debug_assert!(matches!(p, Some(Packet::ChannelRequest(_))));
+                Ok(ServEvent::SessionEnv(ChanRequest::new(runner)))
+            }
+            Self::SessionBreak => {
+                debug_assert!(matches!(p, Some(Packet::ChannelRequest(_))));
+                Ok(ServEvent::SessionBreak(ChanRequest::new(runner)))
+            }
+            Self::TcpipForward => {
+                debug_assert!(matches!(p, Some(Packet::GlobalRequest(_))));
+                Ok(ServEvent::TcpipForward(ServTcpipForward::new(runner)))
+            }
+            Self::CancelTcpipForward => {
+                debug_assert!(matches!(p, Some(Packet::GlobalRequest(_))));
+                Ok(ServEvent::CancelTcpipForward(ServTcpipForward::new(runner)))
+            }
+            Self::DebugMessage => {
+                debug_assert!(matches!(p, Some(Packet::DebugPacket(_))));
+                Ok(ServEvent::DebugMessage(runner.fetch_debug()?))
+            }
             Self::Defunct => Ok(ServEvent::Defunct),
         }
     }
@@ -562,6 +918,7 @@ impl ServEventId {
     // Used for internal correctness checks.
     pub(crate) fn needs_resume(&self) -> bool {
         match self {
+            | Self::DebugMessage
             | Self::Defunct
             => false,
             | Self::Hostkeys
@@ -572,6 +929,13 @@ impl ServEventId {
             | Self::SessionShell
             | Self::SessionExec
             | Self::SessionPty
+            | Self::SessionWinch
+            | Self::SessionSubsystem
+            | Self::SessionAgentReq
+            | Self::SessionEnv
+            | Self::SessionBreak
+            | Self::TcpipForward
+            | Self::CancelTcpipForward
             => true,
         }
     }
@@ -612,6 +976,48 @@ impl<'g, 'a> ChanRequest<'g, 'a> {
         self.runner.fetch_reqchannel()
     }
 
+    /// The new terminal size for a [`ServEvent::SessionWinch`] request.
+    pub fn win_change(&self) -> Result<packets::WinChange> {
+        match self.runner.fetch_chanreq()? {
+            packets::ChannelReqType::WinChange(w) => Ok(w),
+            _ => error::BadUsage.fail(),
+        }
+    }
+
+    /// The command for a [`ServEvent::SessionExec`] request.
+    pub fn command(&self) -> Result<TextString<'_>> {
+        match self.runner.fetch_chanreq()? {
+            packets::ChannelReqType::Exec(e) => Ok(e.command),
+            _ => error::BadUsage.fail(),
+        }
+    }
+
+    /// The name for a [`ServEvent::SessionSubsystem`] request.
+    pub fn subsystem(&self) -> Result<TextString<'_>> {
+        match self.runner.fetch_chanreq()? {
+            packets::ChannelReqType::Subsystem(s) => Ok(s.subsystem),
+            _ => error::BadUsage.fail(),
+        }
+    }
+
+    /// The variable for a [`ServEvent::SessionEnv`] request.
+    pub fn env(&self) -> Result<packets::Env<'_>> {
+        match self.runner.fetch_chanreq()? {
+            packets::ChannelReqType::Env(e) => Ok(e),
+            _ => error::BadUsage.fail(),
+        }
+    }
+
+    /// The length in milliseconds for a [`ServEvent::SessionBreak`] request.
+    ///
+    /// 0 requests a default length.
+    pub fn break_length(&self) -> Result<u32> {
+        match self.runner.fetch_chanreq()? {
+            packets::ChannelReqType::Break(b) => Ok(b.length),
+            _ => error::BadUsage.fail(),
+        }
+    }
+
     // TODO: does the app care about wantreply?
 }
 
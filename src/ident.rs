@@ -8,19 +8,59 @@ pub(crate) const SSH_PREFIX: &[u8] = b"SSH-2.0-";
 // TODO find what's in the wild
 const MAX_REMOTE_VERSION_LEN: usize = 253;
 const MAX_LINES: usize = 50;
+// Total length of discarded lines
+const MAX_PREAMBLE: usize = 8192;
 
 pub const CR: u8 = 0x0d;
 pub const LF: u8 = 0x0a;
 
-pub(crate) fn write_version(buf: &mut [u8]) -> Result<usize> {
+// RFC4253 4.2, 255 including CR LF
+const MAX_OUR_VERSION_LEN: usize = 253;
 
-    let total_len = OUR_VERSION.len() + 2;
+/// Our identification string, `OUR_VERSION` by default.
+#[derive(Debug, Clone)]
+pub(crate) struct OurVersion(&'static [u8]);
+
+impl Default for OurVersion {
+    fn default() -> Self {
+        Self(OUR_VERSION)
+    }
+}
+
+impl OurVersion {
+    /// Validates a custom identification string.
+    ///
+    /// It must start with `SSH-2.0-`, followed by printable ASCII
+    /// (spaces are allowed before comments). The softwareversion part
+    /// can't contain `-`. CR LF is added when sending.
+    pub fn new(v: &'static str) -> Result<Self> {
+        let b = v.as_bytes();
+        let Some(rest) = b.strip_prefix(SSH_PREFIX) else {
+            return Err(Error::BadName)
+        };
+        let software = rest.split(|c| *c == b' ').next().unwrap_or_default();
+        if software.is_empty() || software.contains(&b'-')
+            || b.len() > MAX_OUR_VERSION_LEN
+            || !b.iter().all(|c| (b' '..=b'~').contains(c)) {
+            return Err(Error::BadName)
+        }
+        Ok(Self(b))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0
+    }
+}
+
+pub(crate) fn write_version(buf: &mut [u8], version: &[u8]) -> Result<usize> {
+
+    let total_len = version.len() + 2;
     if total_len > buf.len() {
         return error::NoRoom.fail();
     }
 
-    let (d, b) = buf.split_at_mut(OUR_VERSION.len());
-    d.copy_from_slice(OUR_VERSION);
+    let (d, b) = buf.split_at_mut(version.len());
+    d.copy_from_slice(version);
     b[0] = CR;
     b[1] = LF;
 
@@ -33,6 +73,7 @@ pub struct RemoteVersion {
     /// Parse state
     st: VersPars,
     num_lines: usize,
+    preamble_len: usize,
     is_client: bool,
 }
 
@@ -65,6 +106,7 @@ impl RemoteVersion {
             storage: [0; MAX_REMOTE_VERSION_LEN],
             st: VersPars::Start(0),
             num_lines: 0,
+            preamble_len: 0,
             is_client,
         }
     }
@@ -112,6 +154,10 @@ impl RemoteVersion {
                 }
 
                 VersPars::Discarding => {
+                    self.preamble_len += 1;
+                    if self.preamble_len > MAX_PREAMBLE {
+                        return Err(Error::NotSSH);
+                    }
                     if b == LF {
                         self.st = VersPars::Start(0);
                         self.num_lines += 1;
@@ -134,7 +180,10 @@ impl RemoteVersion {
                         return Err(Error::msg("bad remote version"));
                     }
                     _ => {
-                        let w = self.storage.get_mut(*pos).ok_or(error::NoRoom.build())?;
+                        // Too long
+                        let Some(w) = self.storage.get_mut(*pos) else {
+                            return error::SSHProto.fail();
+                        };
                         *w = b;
                         *pos += 1;
                     }
@@ -188,6 +237,28 @@ mod tests {
         Ok(taken1 + taken2)
     }
 
+    #[test]
+    fn our_version() {
+        let v = ident::OurVersion::new("SSH-2.0-App_1.0 extra").unwrap();
+        let mut buf = [0u8; 300];
+        let l = ident::write_version(&mut buf, v.as_bytes()).unwrap();
+        assert_eq!(&buf[..l], b"SSH-2.0-App_1.0 extra\r\n");
+        assert_eq!(ident::OurVersion::default().as_bytes(), ident::OUR_VERSION);
+
+        for bad in ["SSH-2.0-", "SSH-2.0- x", "SSH-2.0-x\ny", "SSH-2.0-\u{e9}", "ssh-2.0-x",
+            "SSH-2.0-App-1.0", "SSH-2.0--"] {
+            assert!(matches!(ident::OurVersion::new(bad), Err(Error::BadName)), "{bad:?}");
+        }
+        // a '-' is allowed in comments
+        assert!(ident::OurVersion::new("SSH-2.0-App_1.0 built 2024-01-01").is_ok());
+        let long = "SSH-2.0-".to_string() + &"x".repeat(245);
+        let long: &'static str = long.leak();
+        assert!(ident::OurVersion::new(long).is_ok());
+        let long = long.to_string() + "x";
+        let long: &'static str = long.leak();
+        assert!(ident::OurVersion::new(long).is_err());
+    }
+
     #[test]
     /// check round trip of packet enums is right
     fn version() -> Result<()> {
@@ -217,6 +288,41 @@ mod tests {
     }
 
 
+    #[test]
+    fn version_long() {
+        init_test_log();
+        // 255 including CR LF
+        let v = "SSH-2.0-".to_string() + &"x".repeat(245);
+        test_version(&format!("{v}\r\n"), 0, &v).unwrap();
+        let v = v + "x";
+        assert!(matches!(test_version(&format!("{v}\r\n"), 0, ""),
+            Err(Error::SSHProto { .. })));
+        // no line ending
+        let v = v + &"x".repeat(10000);
+        assert!(matches!(test_version(&v, 0, ""), Err(Error::SSHProto { .. })));
+    }
+
+    #[test]
+    /// a client skips a server's banner lines before the version
+    fn version_preamble() {
+        init_test_log();
+        let banner = "Authorized use only\r\n\r\nSSH-1 is not a version\r\n";
+        let v = format!("{banner}SSH-2.0-srv\r\n");
+        for i in [0, 5, 20, banner.len(), v.len()] {
+            test_version(&v, i, "SSH-2.0-srv").unwrap();
+        }
+
+        // too many lines
+        let v = format!("{}SSH-2.0-srv\r\n", "x\r\n".repeat(51));
+        assert!(matches!(test_version(&v, 0, ""), Err(Error::NotSSH)));
+
+        // too long in total, even without line endings
+        let v = format!("{}SSH-2.0-srv\r\n", "x".repeat(9000));
+        assert!(matches!(test_version(&v, 0, ""), Err(Error::NotSSH)));
+        let v = format!("{}SSH-2.0-srv\r\n", format!("{}\r\n", "x".repeat(200)).repeat(45));
+        assert!(matches!(test_version(&v, 0, ""), Err(Error::NotSSH)));
+    }
+
     #[test]
     /// check server doesn't allow leading lines
     fn version_server_lines() -> Result<()> {
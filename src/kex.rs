@@ -11,9 +11,9 @@ use core::fmt;
 use sha2::Sha256;
 use digest::Digest;
 use zeroize::{Zeroize, ZeroizeOnDrop};
-use rand_core::{RngCore, CryptoRng, OsRng};
 
 use crate::*;
+use compress::Compress;
 use encrypt::{Cipher, Integ, Keys};
 use ident::RemoteVersion;
 use traffic::TrafSend;
@@ -31,7 +31,7 @@ pub type SessId = heapless::Vec<u8, MAX_SESSID>;
 
 use pretty_hex::PrettyHex;
 
-// TODO this will be configurable.
+/// Default kex preference, may be replaced with [`AlgoConfig::with_kexs()`]
 const fixed_options_kex: &[&str] =
     &[SSH_NAME_CURVE25519, SSH_NAME_CURVE25519_LIBSSH];
 
@@ -46,33 +46,74 @@ const fixed_options_hostsig: &[&str] = &[
     SSH_NAME_RSA_SHA256,
 ];
 
-const fixed_options_cipher: &[&str] =
-    &[SSH_NAME_CHAPOLY, SSH_NAME_AES256_CTR];
+/// Host key algorithms that a client may enable with [`AlgoConfig::with_hostsig()`]
+const client_options_hostsig: &[&str] = &[
+    SSH_NAME_ED25519_CERT,
+    SSH_NAME_ED25519,
+    #[cfg(feature = "rsa")]
+    SSH_NAME_RSA_SHA256,
+];
+
+const fixed_options_cipher: &[&str] = &[
+    SSH_NAME_CHAPOLY,
+    #[cfg(feature = "aes")]
+    SSH_NAME_AES256_CTR,
+];
 const fixed_options_mac: &[&str] = &[SSH_NAME_HMAC_SHA256];
-const fixed_options_comp: &[&str] = &[SSH_NAME_NONE];
+const fixed_options_comp: &[&str] = &[
+    #[cfg(feature = "zlib")]
+    SSH_NAME_ZLIB_OPENSSH,
+    #[cfg(feature = "zlib")]
+    SSH_NAME_ZLIB,
+    SSH_NAME_NONE,
+];
 
-pub(crate) struct AlgoConfig {
+/// Local algorithm preferences, sent in our `KexInit`.
+///
+/// The default from [`AlgoConfig::new()`] enables all supported algorithms.
+/// Each `with_...()` method replaces a list, earlier names are preferred.
+/// Unknown names fail with [`Error::BadName`].
+///
+/// Apply with [`Runner::set_algo_config()`](crate::Runner::set_algo_config).
+#[derive(Debug, Clone)]
+pub struct AlgoConfig {
+    is_client: bool,
     kexs: LocalNames,
     hostsig: LocalNames,
     ciphers: LocalNames,
     macs: LocalNames,
     comps: LocalNames,
+
+    /// Fail if a rekey negotiates algorithms less preferred than
+    /// the first key exchange.
+    pub(crate) reject_rekey_downgrade: bool,
+
+    /// Client sends a guessed `KexDHInit` immediately after `KexInit`
+    first_follows: bool,
+
+    /// Application check of negotiated algorithms, see [`AlgoConfig::with_policy()`]
+    policy: Option<AlgoPolicy>,
 }
 
+/// Checks negotiated algorithms, returning `Err` with a rejected algorithm name.
+///
+/// Set with [`AlgoConfig::with_policy()`].
+pub type AlgoPolicy = fn(&AlgoNames) -> core::result::Result<(), &'static str>;
+
 impl AlgoConfig {
     /// Creates the standard algorithm configuration
-    /// TODO: ext-info-s and ext-info-c
     pub fn new(is_client: bool) -> Self {
         // OK unwrap: static arrays are < MAX_LOCAL_NAMES
         let mut kexs: LocalNames = fixed_options_kex.try_into().unwrap();
 
-        // Only clients are interested in ext-info
-        // TODO perhaps it could go behind cfg rsa?
+        // OK unwrap: static arrays are <= MAX_LOCAL_NAMES
         if is_client {
-            // OK unwrap: static arrays are <= MAX_LOCAL_NAMES
             kexs.0.push(SSH_NAME_EXT_INFO_C).unwrap();
             kexs.0.push(SSH_NAME_STRICT_KEX_C).unwrap();
         } else {
+            // We don't handle MSG_EXT_INFO from clients, but advertise
+            // for clients that want to see it
+            kexs.0.push(SSH_NAME_EXT_INFO_S).unwrap();
             kexs.0.push(SSH_NAME_STRICT_KEX_S).unwrap();
         }
 
@@ -80,12 +121,142 @@ impl AlgoConfig {
         kexs.0.push(SSH_NAME_KEXGUESS2).unwrap();
 
         AlgoConfig {
+            is_client,
             kexs,
             hostsig: fixed_options_hostsig.try_into().unwrap(),
             ciphers: fixed_options_cipher.try_into().unwrap(),
             macs: fixed_options_mac.try_into().unwrap(),
             comps: fixed_options_comp.try_into().unwrap(),
+            reject_rekey_downgrade: false,
+            first_follows: false,
+            policy: None,
+        }
+    }
+
+    /// Sets key exchange methods.
+    ///
+    /// Marker names such as `ext-info-c` are retained.
+    pub fn with_kexs(mut self, names: &[&'static str]) -> Result<Self> {
+        let mut kexs = Self::check_names(names, fixed_options_kex)?;
+        for m in self.kexs.0.iter().filter(|k| marker_only_kexs.contains(k)) {
+            kexs.0.push(m).map_err(|_| error::BadUsage.build())?;
         }
+        self.kexs = kexs;
+        Ok(self)
+    }
+
+    /// Sets host key signature algorithms.
+    ///
+    /// Clients may also enable `ssh-ed25519-cert-v01@openssh.com` host
+    /// certificates, which should then be checked with
+    /// [`Ed25519Cert::check()`](crate::packets::Ed25519Cert::check).
+    pub fn with_hostsig(mut self, names: &[&'static str]) -> Result<Self> {
+        let supported = if self.is_client {
+            client_options_hostsig
+        } else {
+            fixed_options_hostsig
+        };
+        self.hostsig = Self::check_names(names, supported)?;
+        Ok(self)
+    }
+
+    /// Restricts host key algorithms to those that `keys` can sign, keeping
+    /// the existing preference order.
+    ///
+    /// Servers should use this so that only usable algorithms are advertised,
+    /// otherwise key exchange may fail with [`Error::NoHostKey`].
+    /// Returns [`Error::BadUsage`] if no algorithms remain.
+    pub fn with_hostkeys(mut self, keys: &[&SignKey]) -> Result<Self> {
+        let mut hostsig = LocalNames::new();
+        for n in self.hostsig.0.iter() {
+            let t = SigType::from_name(n)?;
+            if keys.iter().any(|k| k.can_sign(t)) {
+                hostsig.0.push(n).trap()?;
+            }
+        }
+        if hostsig.0.is_empty() {
+            return error::BadUsage.fail()
+        }
+        self.hostsig = hostsig;
+        Ok(self)
+    }
+
+    /// Sets ciphers, used for both directions.
+    pub fn with_ciphers(mut self, names: &[&'static str]) -> Result<Self> {
+        self.ciphers = Self::check_names(names, fixed_options_cipher)?;
+        Ok(self)
+    }
+
+    /// Sets MAC algorithms, used for both directions.
+    ///
+    /// These are only used with non-AEAD ciphers.
+    pub fn with_macs(mut self, names: &[&'static str]) -> Result<Self> {
+        self.macs = Self::check_names(names, fixed_options_mac)?;
+        Ok(self)
+    }
+
+    /// Sets compression methods, used for both directions.
+    pub fn with_comps(mut self, names: &[&'static str]) -> Result<Self> {
+        self.comps = Self::check_names(names, fixed_options_comp)?;
+        Ok(self)
+    }
+
+    /// Sends a client's `KexDHInit` immediately after `KexInit`, guessing the
+    /// first kex and hostkey algorithms will match the server's.
+    ///
+    /// This saves a round trip when the guess is correct. Otherwise the
+    /// server discards the packet and another is sent.
+    /// Returns [`Error::BadUsage`] for a server.
+    pub fn with_first_kex_follows(mut self, enable: bool) -> Result<Self> {
+        if !self.is_client {
+            return error::BadUsage.fail()
+        }
+        self.first_follows = enable;
+        Ok(self)
+    }
+
+    /// Sets a policy to veto algorithms after negotiation.
+    ///
+    /// `policy` is called for each key exchange before keys are derived.
+    /// An `Err` fails the connection with [`Error::AlgoRejected`], for example
+    /// to forbid `aes256-ctr` even when both sides support it.
+    pub fn with_policy(mut self, policy: AlgoPolicy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    pub(crate) fn is_client(&self) -> bool {
+        self.is_client
+    }
+
+    /// Applies the policy from [`with_policy()`](Self::with_policy), if any.
+    fn check_policy(&self, algos: &Algos) -> Result<()> {
+        let Some(policy) = self.policy else {
+            return Ok(())
+        };
+        policy(&algos.into()).map_err(|algo| {
+            info!("Negotiated {algo} rejected by policy");
+            Error::AlgoRejected { algo }
+        })
+    }
+
+    /// Returns the kex method to guess for `first_kex_packet_follows`, if enabled.
+    fn kex_guess(&self) -> Option<&'static str> {
+        let k = self.kexs.0.first()?;
+        (self.first_follows && self.is_client && !marker_only_kexs.contains(k)).then_some(*k)
+    }
+
+    /// Checks `names` against the supported list. These are the
+    /// names accepted by the respective `from_name()` functions.
+    fn check_names(names: &[&'static str], supported: &[&str]) -> Result<LocalNames> {
+        if names.is_empty() {
+            return error::BadUsage.fail()
+        }
+        if let Some(n) = names.iter().find(|n| !supported.contains(n)) {
+            debug!("Unsupported algorithm {n}");
+            return Err(Error::BadName)
+        }
+        names.try_into().map_err(|_| error::BadUsage.build())
     }
 }
 
@@ -101,6 +272,8 @@ pub(crate) enum Kex {
         // Cookie sent in our KexInit packet. Kept so that we can reproduce the
         // KexInit packet when calculating the exchange hash.
         our_cookie: KexCookie,
+        /// Client's guessed kex, for which a `KexDHInit` was already sent.
+        guess: Option<SharedSecret>,
     },
     /// Waiting for KexDHInit (server) or KexDHReply (client)
     KexDH {
@@ -134,7 +307,8 @@ pub(crate) struct KexHash {
 impl KexHash {
     fn new(
         algos: &Algos, algo_conf: &AlgoConfig, our_cookie: &KexCookie,
-        remote_version: &RemoteVersion, remote_kexinit: &packets::Packet,
+        our_version: &[u8], remote_version: &RemoteVersion,
+        remote_kexinit: &packets::Packet,
     ) -> Result<Self> {
         // RFC4253 section 8:
         // The hash H is computed as the HASH hash of the concatenation of the
@@ -155,13 +329,13 @@ impl KexHash {
         // Recreate our own kexinit packet to hash.
         let own_kexinit = Kex::make_kexinit(our_cookie, algo_conf);
         if algos.is_client {
-            kh.hash_slice(ident::OUR_VERSION);
+            kh.hash_slice(our_version);
             kh.hash_slice(remote_version);
             hash_ser_length(&mut kh.hash_ctx, &own_kexinit)?;
             hash_ser_length(&mut kh.hash_ctx, remote_kexinit)?;
         } else {
             kh.hash_slice(remote_version);
-            kh.hash_slice(ident::OUR_VERSION);
+            kh.hash_slice(our_version);
             hash_ser_length(&mut kh.hash_ctx, remote_kexinit)?;
             hash_ser_length(&mut kh.hash_ctx, &own_kexinit)?
         }
@@ -210,6 +384,8 @@ pub(crate) struct Algos {
     pub cipher_dec: Cipher,
     pub integ_enc: Integ,
     pub integ_dec: Integ,
+    pub comp_enc: Compress,
+    pub comp_dec: Compress,
 
     // If first_kex_packet_follows was set in SSH_MSG_KEXINIT but the
     // guessed algorithms don't match, we discard the next message (RFC4253 Sec 7).
@@ -231,6 +407,97 @@ pub(crate) struct Algos {
     pub strict_kex: bool,
 }
 
+/// Algorithms negotiated by key exchange.
+///
+/// Returned by [`Runner::negotiated_algorithms()`](crate::Runner::negotiated_algorithms).
+/// Directions are client-to-server (`c2s`) and server-to-client (`s2c`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AlgoNames {
+    pub kex: &'static str,
+    pub hostsig: &'static str,
+    pub cipher_c2s: &'static str,
+    pub cipher_s2c: &'static str,
+    /// `None` for AEAD ciphers which have integrated MACs
+    pub mac_c2s: Option<&'static str>,
+    /// `None` for AEAD ciphers which have integrated MACs
+    pub mac_s2c: Option<&'static str>,
+    pub comp_c2s: &'static str,
+    pub comp_s2c: &'static str,
+}
+
+impl From<&Algos> for AlgoNames {
+    fn from(a: &Algos) -> Self {
+        let mac = |i: &Integ| if i.is_aead() { None } else { Some(i.name()) };
+        let (cc, cs, mc, ms, zc, zs) = if a.is_client {
+            (&a.cipher_enc, &a.cipher_dec, &a.integ_enc, &a.integ_dec, &a.comp_enc, &a.comp_dec)
+        } else {
+            (&a.cipher_dec, &a.cipher_enc, &a.integ_dec, &a.integ_enc, &a.comp_dec, &a.comp_enc)
+        };
+        Self {
+            kex: a.kex.name(),
+            hostsig: a.hostsig.algorithm_name(),
+            cipher_c2s: cc.name(),
+            cipher_s2c: cs.name(),
+            mac_c2s: mac(mc),
+            mac_s2c: mac(ms),
+            comp_c2s: zc.name(),
+            comp_s2c: zs.name(),
+        }
+    }
+}
+
+/// Preference ranking of negotiated algorithms, used to detect
+/// downgrades on rekey.
+///
+/// Each is the index in our [`AlgoConfig`] list, lower is preferred.
+#[derive(Debug, Clone)]
+pub(crate) struct AlgoRanks {
+    hostsig: Option<usize>,
+    cipher_enc: Option<usize>,
+    cipher_dec: Option<usize>,
+    integ_enc: Option<usize>,
+    integ_dec: Option<usize>,
+}
+
+impl AlgoRanks {
+    pub fn new(algos: &Algos, conf: &AlgoConfig) -> Self {
+        let pos = |names: &LocalNames, n: &str| names.0.iter().position(|x| *x == n);
+        // AEAD ciphers have no separate MAC, they rank above any MAC
+        let integ = |i: &Integ| if i.is_aead() {
+            Some(0)
+        } else {
+            pos(&conf.macs, i.name()).map(|p| p + 1)
+        };
+        Self {
+            hostsig: pos(&conf.hostsig, algos.hostsig.algorithm_name()),
+            cipher_enc: pos(&conf.ciphers, algos.cipher_enc.name()),
+            cipher_dec: pos(&conf.ciphers, algos.cipher_dec.name()),
+            integ_enc: integ(&algos.integ_enc),
+            integ_dec: integ(&algos.integ_dec),
+        }
+    }
+
+    /// Fails with [`Error::RekeyDowngrade`] if any algorithm is less preferred
+    /// than in `first`.
+    pub fn check_downgrade(&self, first: &AlgoRanks) -> Result<()> {
+        let checks = [
+            ("hostkey", self.hostsig, first.hostsig),
+            ("encryption", self.cipher_enc, first.cipher_enc),
+            ("encryption", self.cipher_dec, first.cipher_dec),
+            ("mac", self.integ_enc, first.integ_enc),
+            ("mac", self.integ_dec, first.integ_dec),
+        ];
+        for (algo, new, first) in checks {
+            // None (not in our config) shouldn't occur, treat it as weakest
+            if new.unwrap_or(usize::MAX) > first.unwrap_or(usize::MAX) {
+                debug!("Rekey downgraded {algo}");
+                return error::RekeyDowngrade { algo }.fail()
+            }
+        }
+        Ok(())
+    }
+}
+
 impl fmt::Display for Algos {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let (cc, cs, mc, ms) = if self.is_client {
@@ -238,12 +505,24 @@ impl fmt::Display for Algos {
         } else {
             (&self.cipher_dec, &self.cipher_enc, &self.integ_dec, &self.integ_enc)
         };
+        let (zc, zs) = if self.is_client {
+            (&self.comp_enc, &self.comp_dec)
+        } else {
+            (&self.comp_dec, &self.comp_enc)
+        };
 
-        write!(f, "Negotiated algorithms {{\nkex {}\nhostkey {}\ncipher c->s {}\ncipher s->c {}\nmac c->s {}\nmac s->c {}\n}}",
-            self.kex, self.hostsig.algorithm_name(), cc, cs, mc, ms)
+        write!(f, "Negotiated algorithms {{\nkex {}\nhostkey {}\ncipher c->s {}\ncipher s->c {}\nmac c->s {}\nmac s->c {}\ncomp c->s {}\ncomp s->c {}\n}}",
+            self.kex, self.hostsig.algorithm_name(), cc, cs, mc, ms, zc, zs)
     }
 }
 
+/// Logs both name lists for a failed negotiation.
+fn no_match(algo: &'static str, remote: &NameList, local: &LocalNames) -> Error {
+    info!("No matching {algo} algorithm. Peer offered \"{remote}\", we allow \"{}\"",
+        NameList::Local(local));
+    Error::AlgoNoMatch { algo }
+}
+
 impl Kex {
     pub fn new() -> Self {
         Kex::Idle
@@ -262,13 +541,23 @@ impl Kex {
         let mut our_cookie = [0u8; 16];
         random::fill_random(our_cookie.as_mut_slice())?;
         s.send(Kex::make_kexinit(&our_cookie, conf))?;
-        *self = Kex::KexInit { our_cookie };
+        s.start_kex();
+
+        let guess = if let Some(k) = conf.kex_guess() {
+            let guess = SharedSecret::from_name(k)?;
+            trace!("Sending guessed {k} kexdhinit");
+            s.send(guess.make_kexdhinit()?)?;
+            Some(guess)
+        } else {
+            None
+        };
+        *self = Kex::KexInit { our_cookie, guess };
         Ok(())
     }
 
     pub fn handle_kexinit(
-        &mut self, remote_kexinit: packets::KexInit, is_client: bool, algo_conf: &AlgoConfig,
-        remote_version: &RemoteVersion,
+        &mut self, remote_kexinit: packets::KexInit, algo_conf: &AlgoConfig,
+        our_version: &[u8], remote_version: &RemoteVersion,
         first_kex: bool,
         s: &mut TrafSend,
     ) -> Result<()> {
@@ -277,25 +566,35 @@ impl Kex {
             self.send_kexinit(algo_conf, s)?;
         }
 
-        let our_cookie = if let Kex::KexInit { ref our_cookie } = self {
-            our_cookie
-        } else {
+        let Kex::KexInit { our_cookie, guess } = self else {
             // already received a KexInit
             return error::PacketWrong.fail();
         };
 
-        let algos = Self::algo_negotiation(is_client, &remote_kexinit, algo_conf)?;
+        let is_client = algo_conf.is_client();
+        let mut algos = Self::algo_negotiation(is_client, &remote_kexinit, algo_conf)?;
         debug!("{algos}");
+        algo_conf.check_policy(&algos)?;
 
         if first_kex && algos.strict_kex && s.recv_seq() != 1 {
             debug!("kexinit has strict kex but wasn't first packet");
             return error::PacketWrong.fail();
         }
         if is_client {
-            let p = algos.kex.make_kexdhinit()?;
-            s.send(p)?;
+            match guess.take() {
+                Some(g) if Self::guess_good(&remote_kexinit, algo_conf, &algos) => {
+                    // The server will use our guessed KexDHInit
+                    debug!("Kex guess was correct");
+                    algos.kex = g;
+                }
+                _ => {
+                    let p = algos.kex.make_kexdhinit()?;
+                    s.send(p)?;
+                }
+            }
         }
-        let kex_hash = KexHash::new(&algos, algo_conf, our_cookie, remote_version, &remote_kexinit.into())?;
+        let kex_hash = KexHash::new(&algos, algo_conf, our_cookie, our_version,
+            remote_version, &remote_kexinit.into())?;
         *self = Kex::KexDH {
             algos,
             kex_hash
@@ -316,23 +615,36 @@ impl Kex {
             comp_s2c: (&conf.comps).into(),
             lang_c2s: NameList::empty(),
             lang_s2c: NameList::empty(),
-            first_follows: false,
+            first_follows: conf.kex_guess().is_some(),
             reserved: 0,
         }.into()
     }
 
+    /// Returns the negotiated host key type, for a server
+    /// [`ServEventId::Hostkeys`] event.
+    pub fn hostkey_type(&self) -> Result<KeyType> {
+        if let Kex::KexDH { algos, .. } = self {
+            Ok(algos.hostsig.key_type())
+        } else {
+            Err(Error::bug())
+        }
+    }
+
     pub fn handle_kexdhinit(&mut self) 
     -> Result<DispatchEvent> {
-        if let Kex::KexDH { algos, ..} = self {
-            if algos.is_client {
-                return Err(Error::bug());
-            }
+        let Kex::KexDH { algos, ..} = self else {
+            debug!("Unexpected kexdhinit");
+            return error::PacketWrong.fail()
+        };
 
-            if algos.discard_next {
-                algos.discard_next = false;
-                // Ignore this packet
-                return Ok(DispatchEvent::None)
-            }
+        if algos.is_client {
+            return Err(Error::bug());
+        }
+
+        if algos.discard_next {
+            algos.discard_next = false;
+            // Ignore this packet
+            return Ok(DispatchEvent::None)
         }
 
         Ok(DispatchEvent::ServEvent(ServEventId::Hostkeys))
@@ -352,13 +664,16 @@ impl Kex {
     }
 
     // client only
-    pub fn handle_kexdhreply(&self) -> DispatchEvent {
-        DispatchEvent::CliEvent(event::CliEventId::Hostkey)
+    pub fn handle_kexdhreply(&self) -> Result<DispatchEvent> {
+        if !matches!(self, Kex::KexDH { .. }) {
+            debug!("Unexpected kexdhreply");
+            return error::PacketWrong.fail()
+        }
+        Ok(DispatchEvent::CliEvent(event::CliEventId::Hostkey))
     }
 
     pub fn resume_kexdhreply(
         &mut self, p: &packets::KexDHReply,
-        first_kex: bool,
         s: &mut TrafSend,
     ) -> Result<()> {
         trace!("resume");
@@ -377,11 +692,6 @@ impl Kex {
         if let Kex::KexDH { mut algos, kex_hash } = self.take() {
             let output = SharedSecret::handle_kexdhreply(&mut algos, kex_hash, p)?;
             s.send(packets::NewKeys {})?;
-
-            if first_kex && algos.send_ext_info {
-                self.send_ext_info(s)?;
-            }
-
             *self = Kex::NewKeys { output, algos };
             Ok(())
         } else {
@@ -389,16 +699,15 @@ impl Kex {
         }
     }
 
-    pub fn send_ext_info(&self, s: &mut TrafSend) -> Result<()> {
-        if cfg!(feature = "rsa") {
-            // OK unwrap: namelist has capacity
-            let algs = ([SSH_NAME_RSA_SHA256, SSH_NAME_ED25519].as_slice()).try_into().unwrap();
-            let ext = packets::ExtInfo {
-                server_sig_algs: Some(NameList::Local(&algs)),
-            };
-            s.send(ext)?;
-        }
-        Ok(())
+    /// Sends `server-sig-algs` to a client. Must be the first packet
+    /// after our first `NewKeys`.
+    fn send_ext_info(s: &mut TrafSend) -> Result<()> {
+        // OK unwrap: namelist has capacity
+        let algs = fixed_options_hostsig.try_into().unwrap();
+        let ext = packets::ExtInfo {
+            server_sig_algs: Some(NameList::Local(&algs)),
+        };
+        s.send(ext)
     }
 
     pub fn handle_newkeys(&mut self, sess_id: &mut Option<SessId>, s: &mut TrafSend) -> Result<()> {
@@ -406,6 +715,7 @@ impl Kex {
             // We will have already sent our own NewKeys message if we reach thi
             // state.
 
+            let first_kex = sess_id.is_none();
             // The first KEX's H becomes the persistent sess_id
             let sess_id = sess_id.get_or_insert(output.h.clone());
             let keys = Keys::derive(output, sess_id, &algos)?;
@@ -413,6 +723,13 @@ impl Kex {
                 s.enable_strict_kex()
             }
             s.rekey(keys);
+
+            // Our NewKeys was already sent, but outgoing keys only
+            // change here, so this is the next packet with new keys.
+            if first_kex && algos.send_ext_info {
+                Self::send_ext_info(s)?;
+            }
+            s.finish_kex()?;
             *self = Kex::Idle;
             Ok(())
         } else {
@@ -421,6 +738,20 @@ impl Kex {
     }
 
 
+    /// Returns whether the peer will accept our `first_kex_packet_follows` guess.
+    ///
+    /// This matches the peer's `discard_next` logic in `algo_negotiation()`.
+    fn guess_good(p: &packets::KexInit, conf: &AlgoConfig, algos: &Algos) -> bool {
+        // OK unwrap: p.kex is a remote list
+        if p.kex.has_algo(SSH_NAME_KEXGUESS2).unwrap() {
+            conf.kexs.first() == algos.kex.name()
+                && conf.hostsig.first() == algos.hostsig.algorithm_name()
+        } else {
+            conf.kexs.first() == p.kex.first()
+                && conf.hostsig.first() == p.hostsig.first()
+        }
+    }
+
     /// Perform SSH algorithm negotiation
     fn algo_negotiation(
         is_client: bool, p: &packets::KexInit, conf: &AlgoConfig,
@@ -432,12 +763,12 @@ impl Kex {
         let kex_method = p
             .kex
             .first_match(is_client, &conf.kexs)?
-            .ok_or(Error::AlgoNoMatch { algo: "kex" })?;
+            .ok_or_else(|| no_match("kex", &p.kex, &conf.kexs))?;
 
         // Certain kex method names aren't actual algorithms, just markers.
         // If they are negotiated it means no valid method matched
         if marker_only_kexs.contains(&kex_method) {
-            return Err(Error::AlgoNoMatch { algo: "kex" });
+            return Err(no_match("kex", &p.kex, &conf.kexs));
         }
 
         let kex = SharedSecret::from_name(kex_method)?;
@@ -468,7 +799,7 @@ impl Kex {
         let hostsig_method = p
             .hostsig
             .first_match(is_client, &conf.hostsig)?
-            .ok_or(Error::AlgoNoMatch { algo: "hostkey" })?;
+            .ok_or_else(|| no_match("hostkey", &p.hostsig, &conf.hostsig))?;
         let hostsig = SigType::from_name(hostsig_method)?;
         let goodguess_hostkey = if kexguess2 {
             p.hostsig.first() == hostsig_method
@@ -484,11 +815,11 @@ impl Kex {
 
         let n = cipher_tx
             .first_match(is_client, &conf.ciphers)?
-            .ok_or(Error::AlgoNoMatch { algo: "encryption" })?;
+            .ok_or_else(|| no_match("encryption", cipher_tx, &conf.ciphers))?;
         let cipher_enc = Cipher::from_name(n)?;
         let n = cipher_rx
             .first_match(is_client, &conf.ciphers)?
-            .ok_or(Error::AlgoNoMatch { algo: "encryption" })?;
+            .ok_or_else(|| no_match("encryption", cipher_rx, &conf.ciphers))?;
         let cipher_dec = Cipher::from_name(n)?;
 
         // We ignore mac algorithms for AEAD ciphers
@@ -497,7 +828,7 @@ impl Kex {
         } else {
             let n = mac_tx
                 .first_match(is_client, &conf.macs)?
-                .ok_or(Error::AlgoNoMatch { algo: "mac" })?;
+                .ok_or_else(|| no_match("mac", mac_tx, &conf.macs))?;
             Integ::from_name(n)?
         };
         let integ_dec = if let Some(integ) = cipher_dec.integ() {
@@ -505,18 +836,18 @@ impl Kex {
         } else {
             let n = mac_rx
                 .first_match(is_client, &conf.macs)?
-                .ok_or(Error::AlgoNoMatch { algo: "mac" })?;
+                .ok_or_else(|| no_match("mac", mac_rx, &conf.macs))?;
             Integ::from_name(n)?
         };
 
-        // Compression only matches "none", we don't need further handling
-        // at the moment.
-        comp_tx
+        let n = comp_tx
             .first_match(is_client, &conf.comps)?
-            .ok_or(Error::AlgoNoMatch { algo: "compression" })?;
-        comp_rx
+            .ok_or_else(|| no_match("compression", comp_tx, &conf.comps))?;
+        let comp_enc = Compress::from_name(n)?;
+        let n = comp_rx
             .first_match(is_client, &conf.comps)?
-            .ok_or(Error::AlgoNoMatch { algo: "compression" })?;
+            .ok_or_else(|| no_match("compression", comp_rx, &conf.comps))?;
+        let comp_dec = Compress::from_name(n)?;
 
         // Ignore language fields at present. Unsure which implementations
         // use it, possibly SunSSH
@@ -530,6 +861,8 @@ impl Kex {
             cipher_dec,
             integ_enc,
             integ_dec,
+            comp_enc,
+            comp_dec,
             discard_next,
             is_client,
             send_ext_info,
@@ -537,6 +870,14 @@ impl Kex {
         })
     }
 
+    /// Returns the negotiated algorithms, once a KexInit has been handled
+    pub fn algos(&self) -> Option<&Algos> {
+        match self {
+            Kex::KexDH { algos, .. } | Kex::NewKeys { algos, .. } => Some(algos),
+            _ => None,
+        }
+    }
+
     pub fn is_strict(&self) -> bool {
         match self {
             Kex::KexDH { algos: Algos { strict_kex: true, ..}, .. } => true,
@@ -554,18 +895,21 @@ pub(crate) enum SharedSecret {
 
 impl fmt::Display for SharedSecret {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let n = match self {
-            Self::KexCurve25519(_) => SSH_NAME_CURVE25519
-        };
-        write!(f, "{n}")
+        write!(f, "{}", self.name())
     }
 }
 
 impl SharedSecret {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::KexCurve25519(k) => k.name,
+        }
+    }
+
     pub fn from_name(name: &'static str) -> Result<Self> {
         match name {
             SSH_NAME_CURVE25519 | SSH_NAME_CURVE25519_LIBSSH => {
-                Ok(SharedSecret::KexCurve25519(KexCurve25519::new()?))
+                Ok(SharedSecret::KexCurve25519(KexCurve25519::new(name)?))
             }
             _ => Err(Error::bug()),
         }
@@ -613,11 +957,10 @@ impl SharedSecret {
         
         let hostkey = keys.iter().find(|k| k.can_sign(algos.hostsig));
         let hostkey = hostkey.ok_or_else(|| {
-            // TODO: hostkeys should be requested
-            // earlier and used for kexinit algorithm negotiation too,
-            // so then this shouldn't fail here.
-            debug!("No suitable hostkey provided");
-            error::BadUsage.build()
+            // Servers can avoid this with AlgoConfig::with_hostkeys()
+            let sig = algos.hostsig.algorithm_name();
+            info!("No hostkey provided for negotiated {sig}");
+            Error::NoHostKey { sig }
         })?;
 
         kex_hash.prefinish(&hostkey.pubkey(), p.q_c.0, algos.kex.pubkey())?;
@@ -739,6 +1082,9 @@ pub(crate) struct KexCurve25519 {
     ours: Option<x25519_dalek::EphemeralSecret>,
     // pubkey is relatively expensive to compute from the secret key
     pubkey: [u8; 32],
+    /// The negotiated name, `curve25519-sha256` or the `@libssh.org` alias.
+    #[zeroize(skip)]
+    name: &'static str,
 }
 
 impl core::fmt::Debug for KexCurve25519 {
@@ -746,19 +1092,17 @@ impl core::fmt::Debug for KexCurve25519 {
         f.debug_struct("KexCurve25519")
             .field("ours", &if self.ours.is_some() { "Some" } else { "None" })
             .field("pubkey", &self.pubkey)
+            .field("name", &self.name)
             .finish()
     }
 }
 
 impl KexCurve25519 {
-    fn new() -> Result<Self> {
-        let mut s = [0u8; 32];
-        random::fill_random(s.as_mut_slice())?;
-        // TODO: check that pure random bytes are OK
-        let ours = x25519_dalek::EphemeralSecret::random_from_rng(OsRng);
+    fn new(name: &'static str) -> Result<Self> {
+        let ours = x25519_dalek::EphemeralSecret::random_from_rng(random::SunsetRng);
         let pubkey = x25519_dalek::PublicKey::from(&ours);
         let pubkey = pubkey.to_bytes();
-        Ok(KexCurve25519 { ours: Some(ours), pubkey })
+        Ok(KexCurve25519 { ours: Some(ours), pubkey, name })
     }
 
     fn pubkey(&self) -> &[u8] {
@@ -804,7 +1148,7 @@ mod tests {
         for k in kex::fixed_options_kex.iter() {
             kex::SharedSecret::from_name(k).unwrap();
         }
-        for k in kex::fixed_options_hostsig.iter() {
+        for k in kex::client_options_hostsig.iter() {
             sign::SigType::from_name(k).unwrap();
         }
         for k in kex::fixed_options_cipher.iter() {
@@ -813,12 +1157,15 @@ mod tests {
         for k in kex::fixed_options_mac.iter() {
             encrypt::Integ::from_name(k).unwrap();
         }
+        for k in kex::fixed_options_comp.iter() {
+            compress::Compress::from_name(k).unwrap();
+        }
     }
 
     // Unknown names fail. This is easy to hit if the names of from_name()
     // match statements are mistyped or aren't imported.
     // These are separate tests because they trigger `Error::bug()` which
-    // is an explicit panic in debug builds.
+    // is an explicit panic in tests.
     #[test]
     #[should_panic]
     fn test_unknown_kex() {
@@ -903,27 +1250,210 @@ mod tests {
         }
     }
 
-    // other things to test:
-    // - first_follows, and kexguess2
-    // - kex rejection. is in conn though.
+    #[test]
+    #[cfg(feature = "aes")]
+    fn rekey_downgrade() {
+        init_test_log();
+        let mut cli_conf = kex::AlgoConfig::new(true);
+        cli_conf.reject_rekey_downgrade = true;
+        let mut serv_conf = kex::AlgoConfig::new(false);
+        let cookie = [1u8; 16];
+
+        let negotiate = |serv_conf: &AlgoConfig| {
+            // encode and parse so the negotiation sees a remote list
+            let p = Kex::make_kexinit(&cookie, serv_conf);
+            let mut buf = vec![0u8; 1000];
+            let l = sshwire::write_ssh(&mut buf, &p).unwrap();
+            let p = sshwire::packet_from_bytes(&buf[..l], &Default::default()).unwrap();
+            let Packet::KexInit(k) = p else { panic!() };
+            Kex::algo_negotiation(true, &k, &cli_conf).unwrap()
+        };
+
+        let first = negotiate(&serv_conf);
+        assert!(matches!(first.cipher_enc, Cipher::ChaPoly));
+        let names = AlgoNames::from(&first);
+        assert_eq!(names.cipher_c2s, SSH_NAME_CHAPOLY);
+        assert_eq!(names.mac_c2s, None);
+        assert_eq!(names.hostsig, SSH_NAME_ED25519);
+        let first = AlgoRanks::new(&first, &cli_conf);
+
+        // same algorithms are fine
+        let again = negotiate(&serv_conf);
+        AlgoRanks::new(&again, &cli_conf).check_downgrade(&first).unwrap();
+
+        // server now only offers a weaker cipher
+        serv_conf.ciphers = [SSH_NAME_AES256_CTR].as_slice().try_into().unwrap();
+        let weak = negotiate(&serv_conf);
+        assert!(matches!(weak.cipher_enc, Cipher::Aes256Ctr));
+        let names = AlgoNames::from(&weak);
+        assert_eq!(names.cipher_s2c, SSH_NAME_AES256_CTR);
+        assert_eq!(names.mac_s2c, Some(SSH_NAME_HMAC_SHA256));
+        let weak = AlgoRanks::new(&weak, &cli_conf);
+        let r = weak.check_downgrade(&first);
+        assert!(matches!(r, Err(Error::RekeyDowngrade { algo: "encryption" })));
+
+        // an upgrade is allowed
+        AlgoRanks::new(&again, &cli_conf).check_downgrade(&weak).unwrap();
+    }
 
     #[test]
-    fn test_agree_kex_allow_key() {
-        #![allow(unused)]
+    #[cfg(feature = "aes")]
+    fn algo_config_builder() {
         init_test_log();
-        let cli_conf = kex::AlgoConfig::new(true);
+        let cli_conf = kex::AlgoConfig::new(true)
+            .with_ciphers(&[SSH_NAME_AES256_CTR, SSH_NAME_CHAPOLY]).unwrap()
+            .with_kexs(&[SSH_NAME_CURVE25519]).unwrap();
+        assert_eq!(cli_conf.kexs.0.as_slice(), &[SSH_NAME_CURVE25519,
+            SSH_NAME_EXT_INFO_C, SSH_NAME_STRICT_KEX_C, SSH_NAME_KEXGUESS2]);
         let serv_conf = kex::AlgoConfig::new(false);
 
+        let p = Kex::make_kexinit(&[1u8; 16], &serv_conf);
+        let mut buf = vec![0u8; 1000];
+        let l = sshwire::write_ssh(&mut buf, &p).unwrap();
+        let p = sshwire::packet_from_bytes(&buf[..l], &Default::default()).unwrap();
+        let Packet::KexInit(k) = p else { panic!() };
+        // client preference wins
+        let algos = Kex::algo_negotiation(true, &k, &cli_conf).unwrap();
+        assert!(matches!(algos.cipher_enc, Cipher::Aes256Ctr));
+
+        let c = kex::AlgoConfig::new(true);
+        assert!(matches!(c.clone().with_ciphers(&["aes128-cbc"]), Err(Error::BadName)));
+        assert!(matches!(c.clone().with_macs(&[]), Err(Error::BadUsage { .. })));
+        // kex marker names can't be set directly
+        assert!(matches!(c.clone().with_kexs(&[SSH_NAME_KEXGUESS2]), Err(Error::BadName)));
+
+        let k = SignKey::generate(KeyType::Ed25519, None).unwrap();
+        let c = c.with_hostkeys(&[&k]).unwrap();
+        assert_eq!(c.hostsig.0.as_slice(), &[SSH_NAME_ED25519]);
+        assert!(matches!(c.clone().with_hostkeys(&[]), Err(Error::BadUsage { .. })));
+
+        // host certificates are only for clients
+        let c = c.with_hostsig(&[SSH_NAME_ED25519_CERT, SSH_NAME_ED25519]).unwrap();
+        assert_eq!(c.hostsig.0.as_slice(), &[SSH_NAME_ED25519_CERT, SSH_NAME_ED25519]);
+        let s = kex::AlgoConfig::new(false);
+        assert!(matches!(s.with_hostsig(&[SSH_NAME_ED25519_CERT]), Err(Error::BadName)));
+    }
+
+    #[test]
+    #[cfg(feature = "aes")]
+    fn algo_policy() {
+        init_test_log();
+        let serv_conf = kex::AlgoConfig::new(false)
+            .with_ciphers(&[SSH_NAME_AES256_CTR, SSH_NAME_CHAPOLY]).unwrap();
+        let p = Kex::make_kexinit(&[1u8; 16], &serv_conf);
+        let mut buf = vec![0u8; 1000];
+        let l = sshwire::write_ssh(&mut buf, &p).unwrap();
+        let p = sshwire::packet_from_bytes(&buf[..l], &Default::default()).unwrap();
+        let Packet::KexInit(k) = p else { panic!() };
+
+        let no_ctr = |a: &AlgoNames| {
+            for c in [a.cipher_c2s, a.cipher_s2c] {
+                if c == SSH_NAME_AES256_CTR {
+                    return Err(c)
+                }
+            }
+            Ok(())
+        };
+        let cli_conf = kex::AlgoConfig::new(true).with_policy(no_ctr);
+        let algos = Kex::algo_negotiation(true, &k, &cli_conf).unwrap();
+        cli_conf.check_policy(&algos).unwrap();
+
+        let cli_conf = cli_conf.with_ciphers(&[SSH_NAME_AES256_CTR]).unwrap();
+        let algos = Kex::algo_negotiation(true, &k, &cli_conf).unwrap();
+        let r = cli_conf.check_policy(&algos);
+        assert!(matches!(r, Err(Error::AlgoRejected { algo: SSH_NAME_AES256_CTR })));
+    }
+
+    #[test]
+    #[cfg(not(feature = "aes"))]
+    fn no_aes() {
+        let c = kex::AlgoConfig::new(true);
+        assert!(matches!(c.clone().with_ciphers(&[SSH_NAME_AES256_CTR]), Err(Error::BadName)));
+        assert_eq!(c.ciphers.0.as_slice(), &[SSH_NAME_CHAPOLY]);
+    }
+
+    #[test]
+    fn curve25519_name() {
+        for n in [SSH_NAME_CURVE25519, SSH_NAME_CURVE25519_LIBSSH] {
+            let k = SharedSecret::from_name(n).unwrap();
+            assert_eq!(k.to_string(), n);
+        }
+    }
+
+    #[test]
+    fn first_follows() {
+        init_test_log();
+        let mut version = RemoteVersion::new(true);
+        let mut v = Vec::from(crate::ident::OUR_VERSION);
+        v.extend_from_slice(b"\r\n");
+        version.consume(v.as_slice()).unwrap();
+        let key = crate::SignKey::generate(crate::KeyType::Ed25519, None).unwrap();
+
+        let cli_conf = kex::AlgoConfig::new(true).with_first_kex_follows(true).unwrap();
+        assert!(kex::AlgoConfig::new(false).with_first_kex_follows(true).is_err());
+        let good = kex::AlgoConfig::new(false);
+        // server doesn't have the client's first kex, so the guess is wrong
+        let bad = kex::AlgoConfig::new(false)
+            .with_kexs(&[SSH_NAME_CURVE25519_LIBSSH]).unwrap();
+
+        for (serv_conf, guess_good) in [(good, true), (bad, false)] {
+            let mut ts = TrafCatcher::new();
+            let mut tc = TrafCatcher::new();
+            let mut cli = kex::Kex::new();
+            let mut serv = kex::Kex::new();
+
+            serv.send_kexinit(&serv_conf, &mut ts.sender()).unwrap();
+            cli.send_kexinit(&cli_conf, &mut tc.sender()).unwrap();
+            let Packet::KexInit(cli_init) = tc.next().unwrap() else { panic!() };
+            assert!(cli_init.first_follows);
+            let Packet::KexDHInit(guess) = tc.next().unwrap() else { panic!() };
+            let Packet::KexInit(serv_init) = ts.next().unwrap() else { panic!() };
+
+            serv.handle_kexinit(cli_init, &serv_conf, ident::OUR_VERSION, &version, true, &mut ts.sender()).unwrap();
+            // not first_kex, tc has seen our own guess so the strict kex seq check would fail
+            cli.handle_kexinit(serv_init, &cli_conf, ident::OUR_VERSION, &version, false, &mut tc.sender()).unwrap();
+
+            let ev = serv.handle_kexdhinit().unwrap();
+            let dhinit = if guess_good {
+                assert!(matches!(ev, DispatchEvent::ServEvent(ServEventId::Hostkeys)));
+                assert!(tc.next().is_none());
+                guess
+            } else {
+                // discarded, the client sends another
+                assert!(ev.is_none());
+                let Packet::KexDHInit(p) = tc.next().unwrap() else { panic!() };
+                let ev = serv.handle_kexdhinit().unwrap();
+                assert!(matches!(ev, DispatchEvent::ServEvent(ServEventId::Hostkeys)));
+                p
+            };
+
+            serv.resume_kexdhinit(&dhinit, &[&key], &mut ts.sender()).unwrap();
+            let Packet::KexDHReply(rep) = ts.next().unwrap() else { panic!() };
+            cli.resume_kexdhreply(&rep, &mut tc.sender()).unwrap();
+
+            let (Kex::NewKeys { output: cout, .. }, Kex::NewKeys { output: sout, .. })
+                = (cli, serv) else { panic!() };
+            assert_eq!(cout.h, sout.h);
+        }
+    }
+
+    // other things to test:
+    // - kex rejection. is in conn though.
+
+    /// Runs a key exchange between a client and server with default
+    /// configuration and `key` as the host key.
+    ///
+    /// Returns the client then server outputs.
+    fn agree_kex(key: &SignKey) -> ((KexOutput, Algos), (KexOutput, Algos)) {
+        let cli_conf = kex::AlgoConfig::new(true);
+        let serv_conf = kex::AlgoConfig::new(false).with_hostkeys(&[key]).unwrap();
+
         // needs to be hardcoded because that's what we send.
         let mut s = Vec::from(crate::ident::OUR_VERSION);
         s.extend_from_slice(b"\r\n");
         let mut version = RemoteVersion::new(true);
         version.consume(s.as_slice()).unwrap();
 
-        let mut keys = vec![];
-        keys.push(crate::SignKey::generate(crate::KeyType::Ed25519, None).unwrap());
-        let keys: Vec<&SignKey> = keys.iter().collect();
-
         let mut ts = TrafCatcher::new();
         let mut tc = TrafCatcher::new();
 
@@ -940,8 +1470,8 @@ mod tests {
         let serv_init = if let Packet::KexInit(k) = serv_init { k } else { panic!() };
         assert!(ts.next().is_none());
 
-        serv.handle_kexinit(cli_init, false, &serv_conf, &version, true, &mut ts.sender()).unwrap();
-        cli.handle_kexinit(serv_init, true, &cli_conf, &version, true, &mut tc.sender()).unwrap();
+        serv.handle_kexinit(cli_init, &serv_conf, ident::OUR_VERSION, &version, true, &mut ts.sender()).unwrap();
+        cli.handle_kexinit(serv_init, &cli_conf, ident::OUR_VERSION, &version, true, &mut tc.sender()).unwrap();
 
         let cli_dhinit = tc.next().unwrap();
         let cli_dhinit = if let Packet::KexDHInit(k) = cli_dhinit { k } else { panic!() };
@@ -951,34 +1481,54 @@ mod tests {
 
         let ev = serv.handle_kexdhinit().unwrap();
         assert!(matches!(ev, DispatchEvent::ServEvent(ServEventId::Hostkeys)));
-        let e = serv.resume_kexdhinit(&cli_dhinit, keys.as_slice(), &mut ts.sender()).unwrap();
+        assert_eq!(serv.hostkey_type().unwrap(), key.sig_type().key_type());
+        serv.resume_kexdhinit(&cli_dhinit, &[key], &mut ts.sender()).unwrap();
         let serv_dhrep = ts.next().unwrap();
         let serv_dhrep = if let Packet::KexDHReply(k) = serv_dhrep { k } else { panic!() };
         assert!(matches!(ts.next().unwrap(), Packet::NewKeys(_)));
 
         let s = &mut tc.sender();
-        let ev = cli.handle_kexdhreply();
+        let ev = cli.handle_kexdhreply().unwrap();
         assert!(matches!(ev, DispatchEvent::CliEvent(CliEventId::Hostkey)));
-        let f = cli.resume_kexdhreply(&serv_dhrep, true, s);
+        cli.resume_kexdhreply(&serv_dhrep, s).unwrap();
         assert!(matches!(tc.next().unwrap(), Packet::NewKeys(_)));
         assert!(matches!(tc.next(), None));
 
-        let (cout, calgos) = if let Kex::NewKeys { output, algos } = cli {
-            (output, algos)
-        } else {
-            panic!();
-        };
-        let (sout, salgos) = if let Kex::NewKeys { output, algos } = serv {
-            (output, algos)
-        } else {
-            panic!();
-        };
+        let Kex::NewKeys { output: cout, algos: calgos } = cli else { panic!() };
+        let Kex::NewKeys { output: sout, algos: salgos } = serv else { panic!() };
+        ((cout, calgos), (sout, salgos))
+    }
+
+    #[test]
+    fn test_agree_kex_allow_key() {
+        init_test_log();
+        let key = crate::SignKey::generate(crate::KeyType::Ed25519, None).unwrap();
+        check_agree_kex(&key);
+    }
+
+    #[cfg(feature = "rsa")]
+    #[test]
+    fn test_agree_kex_rsa() {
+        init_test_log();
+        let key = crate::SignKey::generate(crate::KeyType::RSA, Some(2048)).unwrap();
+        check_agree_kex(&key);
+    }
+
+    /// Both sides derive the same session ID and keys
+    fn check_agree_kex(key: &SignKey) {
+        let ((cout, calgos), (sout, salgos)) = agree_kex(key);
 
         // output hash matches
         assert_eq!(cout.h, sout.h);
+        assert_eq!(calgos.hostsig.algorithm_name(), key.sig_type().algorithm_name());
+
+        // only the server sends MSG_EXT_INFO
+        assert!(salgos.send_ext_info);
+        assert!(!calgos.send_ext_info);
 
+        // the first exchange hash is the session ID.
         // roundtrip with the derived keys
-        let sess_id = SessId::from_slice(&Sha256::digest(b"some sessid")).unwrap();
+        let sess_id = cout.h.clone();
 
         let mut skeys = crate::encrypt::KeyState::new_cleartext();
         skeys.rekey(Keys::derive(sout, &sess_id, &salgos).unwrap());
@@ -990,6 +1540,25 @@ mod tests {
 
     }
 
+    #[test]
+    /// A seeded RNG gives the same exchange hash each time
+    fn deterministic_kex() {
+        init_test_log();
+        let key = SignKey::Ed25519(ed25519_dalek::SigningKey::from_bytes(&[9; 32]));
+
+        let mut hashes = vec![];
+        for seed in [[1u8; 32], [1; 32], [2; 32]] {
+            random::set_test_seed(Some(seed));
+            let ((cout, _), (sout, _)) = agree_kex(&key);
+            assert_eq!(cout.h, sout.h);
+            hashes.push(cout.h);
+        }
+        random::set_test_seed(None);
+
+        assert_eq!(hashes[0], hashes[1]);
+        assert_ne!(hashes[0], hashes[2]);
+    }
+
     fn roundtrip(payload: &[u8], enc: &mut KeyState, dec: &mut KeyState) {
         let mut b = vec![];
         b.resize(SSH_PAYLOAD_START, 0);
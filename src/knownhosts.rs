@@ -0,0 +1,125 @@
+//! Host key pinning without `alloc`.
+//!
+//! Clients can check a [`CliHostkey`](crate::event::CliHostkey) event's key
+//! against a small fixed list of trusted host keys. Hostnames are
+//! compared case-insensitively, non-standard ports should be given in
+//! OpenSSH `[host]:port` form.
+
+#[allow(unused_imports)]
+use {
+    crate::error::{Error, Result, TrapBug},
+    log::{debug, error, info, log, trace, warn},
+};
+
+use crate::*;
+use packets::PubKey;
+
+/// Result of a host key lookup
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KnownHost {
+    /// An entry for the host has the same key.
+    Match,
+    /// The host has an entry with the same key type but a different key.
+    ///
+    /// This should be treated as a failure.
+    Mismatch,
+    /// No entry for the host with this key type. An application may
+    /// choose to add the key (trust on first use).
+    Unknown,
+}
+
+/// Checks `key` for `host` against a list of `(hostname, key)` entries.
+///
+/// Entries with a different key type are ignored. Unknown key types never match.
+pub fn check_known_host(host: &str, key: &PubKey, entries: &[(&str, PubKey)]) -> KnownHost {
+    let Ok(algo) = key.algorithm_name() else {
+        return KnownHost::Unknown
+    };
+
+    let mut res = KnownHost::Unknown;
+    for (h, k) in entries.iter().filter(|(h, _)| h.eq_ignore_ascii_case(host)) {
+        if k.algorithm_name() != Ok(algo) {
+            continue;
+        }
+        if k == key {
+            debug!("Matched known host key for {host}");
+            return KnownHost::Match
+        }
+        debug!("Host key mismatch for {h}");
+        res = KnownHost::Mismatch;
+    }
+    res
+}
+
+/// Checks `key` for `host` against `(hostname, key)` entries where the key
+/// is in OpenSSH `ssh-ed25519 AAAA...` format.
+///
+/// Unparsed entries are skipped.
+#[cfg(feature = "openssh-key")]
+pub fn check_known_host_openssh(host: &str, key: &PubKey, entries: &[(&str, &str)]) -> KnownHost {
+    let Ok(algo) = key.algorithm_name() else {
+        return KnownHost::Unknown
+    };
+
+    let mut res = KnownHost::Unknown;
+    for (h, k) in entries.iter().filter(|(h, _)| h.eq_ignore_ascii_case(host)) {
+        if k.split_once(' ').map(|(a, _)| a) != Some(algo) {
+            continue;
+        }
+        match key.matches_openssh(k) {
+            Ok(true) => {
+                debug!("Matched known host key for {host}");
+                return KnownHost::Match
+            }
+            Ok(false) => {
+                debug!("Host key mismatch for {h}");
+                res = KnownHost::Mismatch;
+            }
+            Err(_) => {
+                debug!("Unparsed known host key for {h}");
+            }
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::knownhosts::*;
+    use crate::sunsetlog::*;
+    use crate::{KeyType, SignKey};
+
+    #[test]
+    fn known_host() {
+        init_test_log();
+        let s1 = SignKey::generate(KeyType::Ed25519, None).unwrap();
+        let s2 = SignKey::generate(KeyType::Ed25519, None).unwrap();
+        let (k1, k2) = (s1.pubkey(), s2.pubkey());
+
+        let entries = [("other", k2.clone()), ("Host1", k1.clone())];
+        assert_eq!(check_known_host("host1", &k1, &entries), KnownHost::Match);
+        assert_eq!(check_known_host("host1", &k2, &entries), KnownHost::Mismatch);
+        assert_eq!(check_known_host("[host1]:2222", &k1, &entries), KnownHost::Unknown);
+
+        // a second entry for the same host
+        let entries = [("host1", k2.clone()), ("host1", k1.clone())];
+        assert_eq!(check_known_host("host1", &k1, &entries), KnownHost::Match);
+    }
+
+    #[cfg(feature = "openssh-key")]
+    #[test]
+    fn known_host_openssh() {
+        init_test_log();
+        let k = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIAzk7dRpQCwWiPh0JYXUUNmN0yn0GcNAFnaiVrJpvjJa";
+        let pk = PubKey::Ed25519(packets::Ed25519PubKey { key: sshwire::Blob([
+            12, 228, 237, 212, 105, 64, 44, 22, 136, 248, 116, 37, 133, 212, 80, 217,
+            141, 211, 41, 244, 25, 195, 64, 22, 118, 162, 86, 178, 105, 190, 50, 90,
+        ])});
+        let other = SignKey::generate(KeyType::Ed25519, None).unwrap();
+        let other = other.pubkey();
+        let entries = [("host1", "ssh-ed25519 junk"), ("host1", k)];
+        assert_eq!(check_known_host_openssh("host1", &pk, &entries), KnownHost::Match);
+        assert_eq!(check_known_host_openssh("host1", &other, &entries), KnownHost::Mismatch);
+        assert_eq!(check_known_host_openssh("host2", &pk, &entries), KnownHost::Unknown);
+    }
+}
@@ -13,6 +13,9 @@
 // XXX unused_imports only during dev churn
 #![allow(unused_imports)]
 
+#[cfg(feature = "zlib")]
+extern crate alloc;
+
 pub mod sshwire;
 pub mod packets;
 pub mod sshnames;
@@ -23,14 +26,18 @@ pub mod error;
 pub mod random;
 
 pub mod event;
+pub mod knownhosts;
+pub mod sshsig;
 
 mod conn;
 mod encrypt;
+mod compress;
 mod ident;
 mod kex;
 mod test;
 mod namelist;
 mod sign;
+mod cert;
 
 mod client;
 mod cliauth;
@@ -42,7 +49,7 @@ mod sunsetlog;
 mod auth;
 mod channel;
 mod runner;
-mod termmodes;
+pub mod termmodes;
 mod ssh_chapoly;
 mod traffic;
 
@@ -52,14 +59,18 @@ use event::{CliEventId, ServEventId};
 // Application API
 pub use sshwire::TextString;
 
-pub use sign::{SignKey, KeyType, OwnedSig};
+pub use sign::{SignKey, KeyType, OwnedSig, SigType};
 pub use packets::{PubKey, Signature};
 pub use error::{Error,Result};
-pub use channel::{Pty, ChanOpened, SessionCommand};
-pub use sshnames::ChanFail;
-pub use channel::{ChanData, ChanNum, CliSessionExit};
+pub use channel::{Pty, ModePair, ChanOpened, SessionCommand};
+pub use sshnames::{ChanFail, DisconnectReason};
+pub use channel::{ChanData, ChanNum, CliSessionExit, MaxPacket};
 pub use auth::AuthSigMsg;
 
 pub use runner::Runner;
+pub use kex::{AlgoNames, AlgoConfig, AlgoPolicy};
 pub use runner::ChanHandle;
 pub use event::{Event, CliEvent, ServEvent};
+
+#[cfg(feature = "fuzzing")]
+pub use sshwire::fuzz_decode_packet;
@@ -36,7 +36,7 @@ pub struct StringNames<'a>(pub &'a AsciiStr);
 ///
 /// Deliberately `'static` since it should only come from hardcoded local strings
 /// `SSH_NAME_*` in [`crate::sshnames`]. We don't validate string contents.
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct LocalNames(pub Vec<&'static str, MAX_LOCAL_NAMES>);
 
 /// The general form that can store either representation
@@ -143,12 +143,39 @@ impl NameList<'_> {
         }
     }
 
+    /// Returns an iterator over the names in the list.
+    ///
+    /// An empty list has no names.
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        let (s, l) = match self {
+            NameList::String(s) => {
+                let s = (!s.0.is_empty()).then(|| s.0.split(Comma).map(|n| n.as_str()));
+                (s, None)
+            }
+            NameList::Local(l) => (None, Some(l.0.iter().copied())),
+        };
+        s.into_iter().flatten().chain(l.into_iter().flatten())
+    }
+
     /// Returns an empty `Local` variant
     pub fn empty() -> Self {
         Self::Local(&EMPTY_LOCALNAMES)
     }
 }
 
+/// Comma separated, as sent on the wire
+impl core::fmt::Display for NameList<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for (i, n) in self.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            f.write_str(n)?;
+        }
+        Ok(())
+    }
+}
+
 impl StringNames<'_> {
     /// Returns the first name in this namelist that matches one of the provided options
     fn first_string_match(&self, options: &LocalNames) -> Option<&'static str> {
@@ -286,6 +313,26 @@ mod tests {
         assert_eq!(n("zzz,boo", "urp"), false);
     }
 
+    #[test]
+    fn test_iter() {
+        let tests: Vec<&[&str]> = vec![
+            &["foo", "quux", "boo"],
+            &[],
+            &["one"],
+        ];
+
+        for t in tests.iter() {
+            let l = LocalNames::try_from(*t).unwrap();
+            let l = NameList::Local(&l);
+            let x = t.join(",");
+            let s: NameList = x.as_str().try_into().unwrap();
+            assert_eq!(l.iter().collect::<Vec<_>>(), *t);
+            assert_eq!(s.iter().collect::<Vec<_>>(), *t);
+            assert_eq!(format!("{l}"), x);
+            assert_eq!(format!("{s}"), x);
+        }
+    }
+
     #[test]
     fn localnames_max_size() {
         let s = vec!["one"; MAX_LOCAL_NAMES + 1];
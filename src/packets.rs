@@ -160,6 +160,8 @@ pub struct UserauthRequest<'a> {
 }
 
 /// The method-specific part of a [`UserauthRequest`].
+// A PubKey may be a large certificate
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, SSHEncode, SSHDecode, Clone)]
 #[sshwire(variant_prefix)]
 pub enum AuthMethod<'a> {
@@ -167,6 +169,8 @@ pub enum AuthMethod<'a> {
     Password(MethodPassword<'a>),
     #[sshwire(variant = SSH_AUTHMETHOD_PUBLICKEY)]
     PubKey(MethodPubKey<'a>),
+    #[sshwire(variant = SSH_AUTHMETHOD_INTERACTIVE)]
+    KeyboardInteractive(MethodKeyboardInteractive<'a>),
     #[sshwire(variant = SSH_NAME_NONE)]
     None,
     #[sshwire(unknown)]
@@ -178,7 +182,7 @@ pub enum AuthMethod<'a> {
 pub enum Userauth60<'a> {
     PkOk(UserauthPkOk<'a>),
     PwChangeReq(UserauthPwChangeReq<'a>),
-    // TODO keyboard interactive
+    InfoRequest(UserauthInfoRequest<'a>),
 }
 
 impl<'de: 'a, 'a> SSHDecode<'de> for Userauth60<'a> {
@@ -187,6 +191,9 @@ impl<'de: 'a, 'a> SSHDecode<'de> for Userauth60<'a> {
         match s.ctx().cli_auth_type {
             Some(auth::AuthType::Password) => Ok(Self::PwChangeReq(SSHDecode::dec(s)?)),
             Some(auth::AuthType::PubKey) => Ok(Self::PkOk(SSHDecode::dec(s)?)),
+            Some(auth::AuthType::KeyboardInteractive) => {
+                Ok(Self::InfoRequest(SSHDecode::dec(s)?))
+            }
             _ => {
                 trace!("Wrong packet state for userauth60");
                 Err(WireError::PacketWrong)
@@ -207,6 +214,132 @@ pub struct UserauthPwChangeReq<'a> {
     pub lang: TextString<'a>,
 }
 
+/// `SSH_MSG_USERAUTH_INFO_REQUEST`, [RFC4256](https://tools.ietf.org/html/rfc4256)
+#[derive(Debug, SSHEncode, SSHDecode)]
+pub struct UserauthInfoRequest<'a> {
+    pub name: TextString<'a>,
+    pub instruction: TextString<'a>,
+    pub lang: TextString<'a>,
+    pub prompts: InfoPrompts<'a>,
+}
+
+/// A prompt in a [`UserauthInfoRequest`]
+#[derive(Debug, SSHEncode, SSHDecode, Clone, Copy, Default)]
+pub struct InfoPrompt<'a> {
+    pub prompt: TextString<'a>,
+    /// Whether the user's response should be echoed when typed
+    pub echo: bool,
+}
+
+/// A list of up to [`MAX_KBDINT_PROMPTS`](config::MAX_KBDINT_PROMPTS) prompts.
+///
+/// Packets avoid `heapless::Vec` since it would give `Packet` a `Drop` impl.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InfoPrompts<'a> {
+    num: usize,
+    prompts: [InfoPrompt<'a>; config::MAX_KBDINT_PROMPTS],
+}
+
+impl<'a> InfoPrompts<'a> {
+    pub fn new(prompts: &[InfoPrompt<'a>]) -> Result<Self> {
+        let mut p = Self::default();
+        p.prompts.get_mut(..prompts.len())
+            .ok_or(error::NoRoom.build())?
+            .copy_from_slice(prompts);
+        p.num = prompts.len();
+        Ok(p)
+    }
+
+    pub fn as_slice(&self) -> &[InfoPrompt<'a>] {
+        &self.prompts[..self.num]
+    }
+}
+
+impl SSHEncode for InfoPrompts<'_> {
+    fn enc(&self, s: &mut dyn SSHSink) -> WireResult<()> {
+        (self.num as u32).enc(s)?;
+        for p in self.as_slice() {
+            p.enc(s)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'de: 'a, 'a> SSHDecode<'de> for InfoPrompts<'a> {
+    fn dec<S>(s: &mut S) -> WireResult<Self> where S: SSHSource<'de> {
+        let num = u32::dec(s)? as usize;
+        let mut p = Self::default();
+        let prompts = p.prompts.get_mut(..num).ok_or_else(|| {
+            debug!("Too many keyboard-interactive prompts, {num}");
+            WireError::NoRoom
+        })?;
+        for x in prompts.iter_mut() {
+            *x = SSHDecode::dec(s)?;
+        }
+        p.num = num;
+        Ok(p)
+    }
+}
+
+/// `SSH_MSG_USERAUTH_INFO_RESPONSE`, [RFC4256](https://tools.ietf.org/html/rfc4256)
+#[derive(SSHEncode, SSHDecode)]
+pub struct UserauthInfoResponse<'a> {
+    pub responses: InfoResponses<'a>,
+}
+
+// Don't print responses, they may be passwords
+impl Debug for UserauthInfoResponse<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UserauthInfoResponse")
+            .field("num", &self.responses.num)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A list of up to [`MAX_KBDINT_PROMPTS`](config::MAX_KBDINT_PROMPTS) responses.
+#[derive(Clone, Copy, Default)]
+pub struct InfoResponses<'a> {
+    num: usize,
+    responses: [TextString<'a>; config::MAX_KBDINT_PROMPTS],
+}
+
+impl<'a> InfoResponses<'a> {
+    pub fn as_slice(&self) -> &[TextString<'a>] {
+        &self.responses[..self.num]
+    }
+
+    /// Appends a response, fails if full.
+    pub fn push(&mut self, r: TextString<'a>) -> Result<()> {
+        let x = self.responses.get_mut(self.num).ok_or(error::NoRoom.build())?;
+        *x = r;
+        self.num += 1;
+        Ok(())
+    }
+}
+
+impl SSHEncode for InfoResponses<'_> {
+    fn enc(&self, s: &mut dyn SSHSink) -> WireResult<()> {
+        (self.num as u32).enc(s)?;
+        for r in self.as_slice() {
+            r.enc(s)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'de: 'a, 'a> SSHDecode<'de> for InfoResponses<'a> {
+    fn dec<S>(s: &mut S) -> WireResult<Self> where S: SSHSource<'de> {
+        let num = u32::dec(s)? as usize;
+        let mut r = Self::default();
+        let responses = r.responses.get_mut(..num).ok_or(WireError::NoRoom)?;
+        for x in responses.iter_mut() {
+            *x = SSHDecode::dec(s)?;
+        }
+        r.num = num;
+        Ok(r)
+    }
+}
+
 #[derive(SSHEncode, SSHDecode, Clone)]
 pub struct MethodPassword<'a> {
     pub change: bool,
@@ -222,15 +355,24 @@ impl Debug for MethodPassword<'_>{
     }
 }
 
-#[derive(Debug, Clone)]
+/// Keyboard-interactive auth request, [RFC4256](https://tools.ietf.org/html/rfc4256)
+#[derive(Debug, SSHEncode, SSHDecode, Clone)]
+pub struct MethodKeyboardInteractive<'a> {
+    /// Deprecated, should be empty
+    pub lang: TextString<'a>,
+    pub submethods: TextString<'a>,
+}
+
+#[derive(Debug, Clone, SSHEncode, SSHDecode)]
 pub struct MethodPubKey<'a> {
+    /// Set when a signature is present. Also set when serializing to create
+    /// a signature, without a signature (the signature is appended later).
+    pub sig_present: bool,
     /// A signature algorithm name (not key algorithm name).
     pub sig_algo: &'a str,
     pub pubkey: Blob<PubKey<'a>>,
+    #[sshwire(present_if = sig_present)]
     pub sig: Option<Blob<Signature<'a>>>,
-    // Set when serializing to create a signature. Will set the "signature present"
-    // boolean to TRUE even without a signature (signature is appended later).
-    pub force_sig: bool,
 }
 
 impl<'a> MethodPubKey<'a> {
@@ -239,51 +381,15 @@ impl<'a> MethodPubKey<'a> {
             Signature::sig_name_for_pubkey(&pubkey).trap()?;
         let sig = sig.map(|s| Blob((s).into()));
         Ok(MethodPubKey {
+            sig_present: sig.is_some(),
             sig_algo,
             pubkey: Blob(pubkey),
             sig,
-            force_sig: false,
         })
 
     }
 }
 
-impl SSHEncode for MethodPubKey<'_> {
-    fn enc(&self, s: &mut dyn SSHSink) -> WireResult<()> {
-        // byte      SSH_MSG_USERAUTH_REQUEST
-        // string    user name
-        // string    service name
-        // string    "publickey"
-        // boolean   TRUE
-        // string    public key algorithm name
-        // string    public key to be used for authentication
-        // string    signature
-
-        // Signature bool will be set when signing
-        let sig = self.sig.is_some() || self.force_sig;
-        sig.enc(s)?;
-        self.sig_algo.enc(s)?;
-        self.pubkey.enc(s)?;
-        self.sig.enc(s)?;
-        Ok(())
-    }
-}
-
-impl<'de: 'a, 'a> SSHDecode<'de> for MethodPubKey<'a> {
-    fn dec<S>(s: &mut S) -> WireResult<Self>
-    where S: sshwire::SSHSource<'de> {
-        let sig = bool::dec(s)?;
-        let sig_algo = SSHDecode::dec(s)?;
-        let pubkey = SSHDecode::dec(s)?;
-        let sig = if sig {
-            Some(SSHDecode::dec(s)?)
-        } else {
-            None
-        };
-        Ok(Self { sig_algo, pubkey, sig, force_sig: false })
-    }
-}
-
 #[derive(Debug, SSHEncode, SSHDecode)]
 pub struct UserauthFailure<'a> {
     pub methods: NameList<'a>,
@@ -299,6 +405,11 @@ pub struct UserauthBanner<'a> {
     pub lang: TextString<'a>,
 }
 
+/// Length of a `SHA256:` fingerprint string, from [`PubKey::fingerprint_sha256()`]
+pub const FINGERPRINT_LEN: usize = 50;
+
+// Certificates are larger than other keys, but Box isn't available without alloc.
+#[allow(clippy::large_enum_variant)]
 #[derive(SSHEncode, SSHDecode, Debug, Clone, PartialEq)]
 #[sshwire(variant_prefix)]
 pub enum PubKey<'a> {
@@ -309,6 +420,12 @@ pub enum PubKey<'a> {
     #[sshwire(variant = SSH_NAME_RSA)]
     RSA(RSAPubKey),
 
+    /// An OpenSSH certificate for an ed25519 key.
+    ///
+    /// Check with [`Ed25519Cert::check()`].
+    #[sshwire(variant = SSH_NAME_ED25519_CERT)]
+    Ed25519Cert(Ed25519Cert<'a>),
+
     #[sshwire(unknown)]
     Unknown(Unknown<'a>),
 }
@@ -320,10 +437,31 @@ impl PubKey<'_> {
             PubKey::Ed25519(_) => Ok(SSH_NAME_ED25519),
             #[cfg(feature = "rsa")]
             PubKey::RSA(_) => Ok(SSH_NAME_RSA),
+            PubKey::Ed25519Cert(_) => Ok(SSH_NAME_ED25519_CERT),
             PubKey::Unknown(u) => Err(u),
         }
     }
 
+    /// Returns the key fingerprint in OpenSSH format, `SHA256:` followed by
+    /// unpadded base64.
+    ///
+    /// Fails for unknown keys.
+    pub fn fingerprint_sha256(&self) -> Result<String<FINGERPRINT_LEN>> {
+        use base64ct::{Base64Unpadded, Encoding};
+        use sha2::Digest;
+
+        let mut h = sha2::Sha256::new();
+        sshwire::hash_ser(&mut h, self)?;
+        let h = h.finalize();
+
+        let mut b = [0u8; FINGERPRINT_LEN];
+        let b = Base64Unpadded::encode(&h, &mut b).trap()?;
+        let mut s = String::new();
+        s.push_str("SHA256:").trap()?;
+        s.push_str(b).trap()?;
+        Ok(s)
+    }
+
     #[cfg(feature = "openssh-key")]
     pub fn matches_openssh(&self, k: &str) -> Result<bool> {
         let k = ssh_key::PublicKey::from_openssh(k)
@@ -340,6 +478,71 @@ impl PubKey<'_> {
         };
         Ok(m)
     }
+
+    /// Serializes to OpenSSH `ssh-ed25519 AAAA...` format, without a comment.
+    ///
+    /// Certificates and unknown keys can't be serialized.
+    #[cfg(all(feature = "openssh-key", feature = "std"))]
+    pub fn to_openssh(&self) -> Result<std::string::String> {
+        let k = ssh_key::PublicKey::try_from(self)?;
+        k.to_openssh().map_err(|_| Error::msg("Failed serializing key"))
+    }
+
+    /// Parses a single OpenSSH `authorized_keys` line.
+    ///
+    /// Returns the key and any leading options, for example `no-pty` or
+    /// `command="uptime"`. Comments and blank lines are an error, as are
+    /// quoted option values containing spaces.
+    #[cfg(all(feature = "openssh-key", feature = "std"))]
+    pub fn from_authorized_key_line(line: &str)
+        -> Result<(PubKey<'static>, std::vec::Vec<std::string::String>)> {
+        let e: ssh_key::authorized_keys::Entry = line.parse()
+            .map_err(|_| Error::msg("Bad authorized_keys line"))?;
+        let k = e.public_key().try_into()?;
+        let opts = e.config_opts().iter().map(|o| o.into()).collect();
+        Ok((k, opts))
+    }
+
+    /// Looks for this key in the contents of an `authorized_keys` file.
+    ///
+    /// Returns the options of the first matching entry, or `None` if the key
+    /// isn't listed. Unparsed lines are skipped.
+    #[cfg(all(feature = "openssh-key", feature = "std"))]
+    pub fn find_authorized_key(&self, authorized_keys: &str)
+        -> Option<std::vec::Vec<std::string::String>> {
+        for line in authorized_keys.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match Self::from_authorized_key_line(line) {
+                Ok((k, opts)) if &k == self => return Some(opts),
+                Ok(_) => (),
+                Err(_) => debug!("Skipping unparsed authorized_keys line"),
+            }
+        }
+        None
+    }
+}
+
+#[cfg(feature = "openssh-key")]
+impl TryFrom<&ssh_key::PublicKey> for PubKey<'static> {
+    type Error = Error;
+    fn try_from(k: &ssh_key::PublicKey) -> Result<Self> {
+        match k.key_data() {
+            ssh_key::public::KeyData::Ed25519(k) => {
+                Ok(PubKey::Ed25519(Ed25519PubKey { key: Blob(k.0) }))
+            }
+
+            #[cfg(feature = "rsa")]
+            ssh_key::public::KeyData::Rsa(k) => {
+                let key = k.try_into().map_err(|_| Error::BadKey)?;
+                Ok(PubKey::RSA(RSAPubKey { key }))
+            }
+
+            _ => Err(Error::msg("Unsupported OpenSSH key")),
+        }
+    }
 }
 
 // ssh_key::PublicKey is used for known_hosts comparisons
@@ -361,6 +564,10 @@ impl TryFrom<&PubKey<'_>> for ssh_key::PublicKey {
                 Ok(k.into())
             }
 
+            PubKey::Ed25519Cert(_) => {
+                Err(Error::msg("Unsupported OpenSSH key"))
+            }
+
             PubKey::Unknown(u) => {
                 trace!("unsupported {u}");
                 Err(Error::msg("Unsupported OpenSSH key"))
@@ -374,6 +581,34 @@ pub struct Ed25519PubKey {
     pub key: Blob<[u8; 32]>,
 }
 
+/// An `ssh-ed25519-cert-v01@openssh.com` certificate.
+///
+/// OpenSSH [PROTOCOL.certkeys](https://cvsweb.openbsd.org/src/usr.bin/ssh/PROTOCOL.certkeys?annotate=HEAD).
+/// The CA key and signature are left encoded, see [`Ed25519Cert::check()`].
+#[derive(Debug, Clone, PartialEq, SSHEncode, SSHDecode)]
+pub struct Ed25519Cert<'a> {
+    pub nonce: BinString<'a>,
+    /// The certified key
+    pub key: Ed25519PubKey,
+    pub serial: u64,
+    /// [`SSH_CERT_TYPE_USER`] or [`SSH_CERT_TYPE_HOST`]
+    pub cert_type: u32,
+    pub key_id: TextString<'a>,
+    /// Packed list of strings
+    pub principals: BinString<'a>,
+    /// Seconds since the Unix epoch
+    pub valid_after: u64,
+    /// Seconds since the Unix epoch
+    pub valid_before: u64,
+    pub critical_options: BinString<'a>,
+    pub extensions: BinString<'a>,
+    pub reserved: BinString<'a>,
+    /// Encoded CA public key
+    pub signature_key: BinString<'a>,
+    /// Encoded CA signature
+    pub signature: BinString<'a>,
+}
+
 #[cfg(feature = "rsa")]
 #[derive(Clone, PartialEq)]
 pub struct RSAPubKey {
@@ -450,6 +685,7 @@ impl<'a> Signature<'a> {
             PubKey::Ed25519(_) => Ok(SSH_NAME_ED25519),
             #[cfg(feature = "rsa")]
             PubKey::RSA(_) => Ok(SSH_NAME_RSA_SHA256),
+            PubKey::Ed25519Cert(_) => Ok(SSH_NAME_ED25519_CERT),
             PubKey::Unknown(u) => {
                 warn!("Unknown key type \"{}\"", u);
                 Err(Error::UnknownMethod {kind: "key"})
@@ -502,35 +738,104 @@ pub struct GlobalRequest<'a> {
 
 #[derive(Debug, SSHEncode, SSHDecode)]
 pub enum GlobalRequestMethod<'a> {
-    // #[sshwire(variant = "tcpip-forward")]
-    // TcpipForward(TcipForward<'a>),
-    // #[sshwire(variant = "cancel-tcpip-forward")]
-    // CancelTcpipForward(CancelTcpipForward<'a>),
+    #[sshwire(variant = "tcpip-forward")]
+    TcpipForward(TcpipForward<'a>),
+    #[sshwire(variant = "cancel-tcpip-forward")]
+    CancelTcpipForward(TcpipForward<'a>),
+    /// Sent by OpenSSH (and Sunset) to check the peer is alive.
+    /// Expects a failure reply.
+    #[sshwire(variant = "keepalive@openssh.com")]
+    KeepAlive,
+    /// Sent by a server after authentication, listing all its host keys
+    /// so that clients can learn of rotated keys.
+    #[sshwire(variant = "hostkeys-00@openssh.com")]
+    HostKeys(HostKeyList<'a>),
     #[sshwire(unknown)]
     Unknown(Unknown<'a>),
 }
 
-// pub struct TcpipForward<'a> {
-//     pub address: TextString<'a>,
-//     pub port: u32,
-// }
+/// A remote forwarding request, used by both `tcpip-forward` and
+/// `cancel-tcpip-forward`.
+#[derive(Debug, SSHEncode, SSHDecode)]
+pub struct TcpipForward<'a> {
+    pub address: TextString<'a>,
+    pub port: u32,
+}
+
+/// Host keys in a `hostkeys-00@openssh.com` request.
+///
+/// Each key is a length-prefixed public key blob, filling the rest of the packet.
+#[derive(Debug)]
+pub enum HostKeyList<'a> {
+    /// Received encoded keys
+    Remote(&'a [u8]),
+    Local(&'a [PubKey<'a>]),
+}
+
+impl<'a> HostKeyList<'a> {
+    /// Returns each key in a received list.
+    ///
+    /// Keys of unsupported types are returned as `PubKey::Unknown`.
+    pub fn iter(&self) -> impl Iterator<Item = Result<PubKey<'a>>> + '_ {
+        let mut rest = match self {
+            Self::Remote(r) => *r,
+            Self::Local(_) => &[],
+        };
+        core::iter::from_fn(move || {
+            if rest.is_empty() {
+                return None
+            }
+            let r = sshwire::read_ssh::<BinString>(rest, None)
+                .inspect(|b| rest = &rest[4 + b.0.len()..])
+                .and_then(|b| sshwire::read_ssh::<PubKey>(b.0, None));
+            if r.is_err() {
+                rest = &[];
+            }
+            Some(r)
+        })
+    }
+}
+
+impl SSHEncode for HostKeyList<'_> {
+    fn enc(&self, s: &mut dyn SSHSink) -> WireResult<()> {
+        match self {
+            Self::Remote(r) => r.enc(s),
+            Self::Local(keys) => {
+                for k in keys.iter() {
+                    Blob(k).enc(s)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
 
-// pub struct CancelTcpipForward<'a> {
-//     pub address: TextString<'a>,
-//     pub port: u32,
-// }
+impl<'de: 'a, 'a> SSHDecode<'de> for HostKeyList<'a> {
+    fn dec<S>(s: &mut S) -> WireResult<Self> where S: SSHSource<'de> {
+        let r = s.take(s.remaining())?;
+        // Check the framing, key contents are decoded by iter()
+        let mut check = r;
+        while !check.is_empty() {
+            let b = sshwire::read_ssh::<BinString>(check, None)
+                .map_err(|_| WireError::RanOut)?;
+            check = &check[4 + b.0.len()..];
+        }
+        Ok(Self::Remote(r))
+    }
+}
 
 #[derive(Debug, SSHEncode)]
 #[sshwire(no_variant_names)]
 pub enum RequestSuccess {
     SuccessEmpty,
-    // TcpPort(TcpPort),
+    /// Response to a `tcpip-forward` request for port 0
+    TcpPort(u32),
 }
 
 impl<'de> SSHDecode<'de> for RequestSuccess {
     fn dec<S>(_s: &mut S) -> WireResult<Self> where S: SSHSource<'de> {
         // if s.ctx().last_req_port {
-        //     Ok(Self::TcpPort(TcpPort::dec(s)?))
+        //     Ok(Self::TcpPort(u32::dec(s)?))
         // } else {
         //     Ok(Self::SuccessEmpty)
         // }
@@ -538,11 +843,6 @@ impl<'de> SSHDecode<'de> for RequestSuccess {
     }
 }
 
-// #[derive(Debug, SSHEncode, SSHDecode)]
-// pub struct TcpPort {
-//     pub port: u32,
-// }
-
 #[derive(Debug, SSHEncode, SSHDecode)]
 pub struct RequestFailure {}
 
@@ -566,8 +866,9 @@ pub enum ChannelOpenType<'a> {
     DirectTcpip(DirectTcpip<'a>),
     // #[sshwire(variant = "x11")]
     // Session(X11<'a>),
-    // #[sshwire(variant = "auth-agent@openssh.com")]
-    // Session(Agent<'a>),
+    /// A forwarded agent connection, opened by the server
+    #[sshwire(variant = "auth-agent@openssh.com")]
+    AuthAgent,
     #[sshwire(unknown)]
     Unknown(Unknown<'a>),
 }
@@ -674,10 +975,13 @@ pub enum ChannelReqType<'a> {
     /// [RFC4335](https://datatracker.ietf.org/doc/html/rfc4335)
     #[sshwire(variant = "break")]
     Break(Break),
+    #[sshwire(variant = "env")]
+    Env(Env<'a>),
+    /// Request forwarding of the client's agent
+    #[sshwire(variant = "auth-agent-req@openssh.com")]
+    AuthAgentReq,
     // Other requests that aren't implemented at present:
-    // auth-agent-req@openssh.com
     // x11-req
-    // env
     // xon-xoff
     #[sshwire(unknown)]
     Unknown(Unknown<'a>),
@@ -688,6 +992,13 @@ pub struct Exec<'a> {
     pub command: TextString<'a>,
 }
 
+/// An environment variable to set for a session
+#[derive(Debug, SSHEncode, SSHDecode)]
+pub struct Env<'a> {
+    pub name: TextString<'a>,
+    pub value: TextString<'a>,
+}
+
 #[derive(Debug, SSHEncode, SSHDecode)]
 pub struct Subsystem<'a> {
     pub subsystem: TextString<'a>,
@@ -747,6 +1058,15 @@ pub struct ForwardedTcpip<'a> {
     pub origin_port: u32,
 }
 
+impl<'a> ForwardedTcpip<'a> {
+    /// Returns the validated connected address and port.
+    ///
+    /// See [`DirectTcpip::parse_target()`].
+    pub fn parse_target(&self) -> Result<(Host<'a>, u16)> {
+        parse_host_port(&self.address, self.port)
+    }
+}
+
 #[derive(Debug, SSHEncode, SSHDecode)]
 pub struct DirectTcpip<'a> {
     pub address: TextString<'a>,
@@ -755,6 +1075,51 @@ pub struct DirectTcpip<'a> {
     pub origin_port: u32,
 }
 
+impl<'a> DirectTcpip<'a> {
+    /// Returns the validated destination address and port.
+    ///
+    /// The address must be an IPv4 or IPv6 literal or a hostname, failing
+    /// with [`Error::BadName`]. Ports outside `1..=65535` fail with
+    /// [`Error::BadNumber`].
+    pub fn parse_target(&self) -> Result<(Host<'a>, u16)> {
+        parse_host_port(&self.address, self.port)
+    }
+}
+
+/// A validated address from a TCP forwarding request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Host<'a> {
+    Ip(core::net::IpAddr),
+    /// A hostname, letters, digits and `-` separated by `.`
+    Name(&'a str),
+}
+
+fn parse_host_port<'a>(address: &TextString<'a>, port: u32) -> Result<(Host<'a>, u16)> {
+    let port = match u16::try_from(port) {
+        Ok(p) if p != 0 => p,
+        _ => return Err(Error::BadNumber),
+    };
+
+    let a = address.as_str().map_err(|_| Error::BadName)?;
+    if let Ok(ip) = a.parse() {
+        return Ok((Host::Ip(ip), port))
+    }
+
+    let valid_label = |l: &str| {
+        (1..=63).contains(&l.len())
+            && !l.starts_with('-')
+            && !l.ends_with('-')
+            && l.bytes().all(|c| c.is_ascii_alphanumeric() || c == b'-')
+    };
+    // a trailing '.' is allowed for a fully qualified name
+    let name = a.strip_suffix('.').unwrap_or(a);
+    if name.len() > 253 || !name.split('.').all(valid_label) {
+        debug!("Bad forwarding address {a:?}");
+        return Err(Error::BadName)
+    }
+    Ok((Host::Name(a), port))
+}
+
 
 /// Placeholder for unknown method names.
 ///
@@ -766,7 +1131,7 @@ pub struct DirectTcpip<'a> {
 pub struct Unknown<'a>(pub &'a [u8]);
 
 impl<'a> Unknown<'a> {
-    fn new(u: &'a [u8]) -> Self {
+    pub(crate) fn new(u: &'a [u8]) -> Self {
         let u = Unknown(u);
         trace!("saw unknown variant \"{u}\"");
         u
@@ -795,6 +1160,12 @@ impl Debug for Unknown<'_> {
 pub struct ParseContext {
     pub cli_auth_type: Option<auth::AuthType>,
 
+    /// Accept trailing bytes after a decoded packet.
+    ///
+    /// By default [`packet_from_bytes`](sshwire::packet_from_bytes) fails with
+    /// [`Error::WrongPacketLength`] if bytes remain.
+    pub allow_trailing: bool,
+
     // Set to true if an unknown variant is encountered.
     // Packet length checks should be omitted in that case.
     pub(crate) seen_unknown: bool,
@@ -804,6 +1175,7 @@ impl ParseContext {
     pub fn new() -> Self {
         ParseContext {
             cli_auth_type: None,
+            allow_trailing: false,
             seen_unknown: false,
         }
     }
@@ -913,6 +1285,19 @@ impl<'a> Packet<'a> {
         }
     }
 
+    /// Returns the message name, eg `"SSH_MSG_CHANNEL_DATA"`.
+    ///
+    /// Useful for compact logging.
+    pub fn message_name(&self) -> &'static str {
+        match self {
+            // eg
+            // Packet::KexInit() => "SSH_MSG_KEXINIT",
+            $(
+            Packet::$SpecificPacketVariant(_) => stringify!($SSH_MESSAGE_NAME),
+            )*
+        }
+    }
+
     pub fn category(&self) -> Category {
         match self {
             // eg
@@ -970,7 +1355,7 @@ messagetypes![
 // SSH_MSG_USERAUTH_PK_OK
 // SSH_MSG_USERAUTH_INFO_REQUEST
 (60, Userauth60, Userauth60<'a>, SSH_MSG_USERAUTH_60, Auth),
-// (61, SSH_MSG_USERAUTH_INFO_RESPONSE),
+(61, UserauthInfoResponse, UserauthInfoResponse<'a>, SSH_MSG_USERAUTH_INFO_RESPONSE, Auth),
 
 (80, GlobalRequest, GlobalRequest<'a>, SSH_MSG_GLOBAL_REQUEST, Sess),
 (81, RequestSuccess, RequestSuccess, SSH_MSG_REQUEST_SUCCESS, Sess),
@@ -994,7 +1379,7 @@ mod tests {
     use crate::sunsetlog::init_test_log;
     use crate::packets::*;
     use crate::sshnames::*;
-    use crate::sshwire::tests::{assert_serialize_equal, test_roundtrip};
+    use crate::sshwire::tests::{assert_serialize_equal, test_roundtrip, test_roundtrip_context};
     use crate::sshwire::{packet_from_bytes, write_ssh};
     use crate::{packets, sshwire};
     use pretty_hex::PrettyHex;
@@ -1010,6 +1395,85 @@ mod tests {
         }
     }
 
+    #[test]
+    fn message_name() {
+        let p: Packet = NewKeys {}.into();
+        assert_eq!(p.message_name(), "SSH_MSG_NEWKEYS");
+        let p: Packet = ChannelEof { num: 1 }.into();
+        assert_eq!(p.message_name(), "SSH_MSG_CHANNEL_EOF");
+    }
+
+    #[test]
+    fn pubkey_fingerprint() {
+        let k = PubKey::Ed25519(Ed25519PubKey {
+            key: Blob([
+                0x8b, 0xf0, 0x3b, 0xa1, 0xed, 0xdc, 0x71, 0x2a,
+                0x10, 0x10, 0xa3, 0x7b, 0x60, 0x32, 0x74, 0xee,
+                0xe3, 0xaf, 0x34, 0x3a, 0x62, 0x80, 0x55, 0xc8,
+                0xd9, 0x1b, 0xcd, 0x8d, 0xe7, 0xc9, 0x74, 0x51,
+            ]),
+        });
+        // from ssh-keygen -l
+        assert_eq!(k.fingerprint_sha256().unwrap(),
+            "SHA256:wgg+Lz11r4mVRYXWzjg2wE3ooeiSG7lDYXZamJErAp4");
+    }
+
+    #[cfg(all(feature = "openssh-key", feature = "std"))]
+    #[test]
+    fn authorized_keys() {
+        init_test_log();
+        let k = "AAAAC3NzaC1lZDI1NTE5AAAAIAzk7dRpQCwWiPh0JYXUUNmN0yn0GcNAFnaiVrJpvjJa";
+        let pk = PubKey::Ed25519(Ed25519PubKey { key: Blob([
+            12, 228, 237, 212, 105, 64, 44, 22, 136, 248, 116, 37, 133, 212, 80, 217,
+            141, 211, 41, 244, 25, 195, 64, 22, 118, 162, 86, 178, 105, 190, 50, 90,
+        ])});
+
+        let line = format!("no-pty,command=\"uptime,x\" ssh-ed25519 {k} me@host");
+        let (k2, opts) = PubKey::from_authorized_key_line(&line).unwrap();
+        assert_eq!(k2, pk);
+        assert_eq!(opts, ["no-pty", "command=\"uptime,x\""]);
+        assert!(PubKey::from_authorized_key_line("ssh-ed25519 junk").is_err());
+
+        assert_eq!(pk.to_openssh().unwrap(), format!("ssh-ed25519 {k}"));
+        #[cfg(feature = "rsa")]
+        {
+            let rk = crate::SignKey::generate(crate::KeyType::RSA, Some(2048)).unwrap();
+            let rk = rk.pubkey();
+            let (rk2, _) = PubKey::from_authorized_key_line(&rk.to_openssh().unwrap()).unwrap();
+            assert_eq!(rk2, rk);
+        }
+
+        let other = PubKey::Ed25519(Ed25519PubKey { key: Blob([1; 32]) });
+        let file = format!("# comment\n\nssh-ed25519 junk\nssh-ed25519 {k}\n");
+        assert_eq!(pk.find_authorized_key(&file), Some(vec![]));
+        assert_eq!(other.find_authorized_key(&file), None);
+    }
+
+    #[test]
+    fn tcpip_target() {
+        let t = |address, port| DirectTcpip {
+            address: TextString(address),
+            port,
+            origin: "".into(),
+            origin_port: 0,
+        }.parse_target();
+
+        assert_eq!(t(b"127.0.0.1", 22).unwrap(),
+            (Host::Ip(core::net::Ipv4Addr::LOCALHOST.into()), 22));
+        assert_eq!(t(b"::1", 65535).unwrap(),
+            (Host::Ip(core::net::Ipv6Addr::LOCALHOST.into()), 65535));
+        assert_eq!(t(b"localhost", 80).unwrap(), (Host::Name("localhost"), 80));
+        assert_eq!(t(b"a-1.example.com.", 80).unwrap(),
+            (Host::Name("a-1.example.com."), 80));
+
+        assert!(matches!(t(b"localhost", 0), Err(Error::BadNumber)));
+        assert!(matches!(t(b"localhost", 65536), Err(Error::BadNumber)));
+        for bad in [&b""[..], b"a..b", b"-a.com", b"a b", b"[::1]", b"host:22", b"\xff"] {
+            assert!(matches!(t(bad, 22), Err(Error::BadName)), "{bad:?}");
+        }
+        assert!(matches!(t(&[b'a'; 64], 22), Err(Error::BadName)));
+    }
+
     #[test]
     /// Tests MethodPubKey custom serde
     fn roundtrip_authpubkey() {
@@ -1030,10 +1494,10 @@ mod tests {
         let sig_algo = sig.algorithm_name().unwrap();
         let sig = Some(Blob(sig));
         let method = AuthMethod::PubKey(MethodPubKey {
+            sig_present: true,
             sig_algo,
             pubkey: Blob(k.pubkey()),
             sig,
-            force_sig: false,
         });
         let p = UserauthRequest {
             username: "matt".into(),
@@ -1043,6 +1507,115 @@ mod tests {
         test_roundtrip(&p);
     }
 
+    #[test]
+    /// Malformed packets fail to decode rather than panicking
+    fn decode_mutated() {
+        init_test_log();
+        let k = SignKey::generate(KeyType::Ed25519, None).unwrap();
+        let owned_sig = k.sign(&"hello").unwrap();
+        let sig: Signature = (&owned_sig).into();
+        let corpus: [Packet; 3] = [
+            UserauthRequest {
+                username: "matt".into(),
+                service: "ssh-connection",
+                method: AuthMethod::PubKey(MethodPubKey {
+                    sig_present: true,
+                    sig_algo: sig.algorithm_name().unwrap(),
+                    pubkey: Blob(k.pubkey()),
+                    sig: Some(Blob(sig)),
+                }),
+            }.into(),
+            ChannelOpen {
+                sender_num: 1,
+                initial_window: 1000,
+                max_packet: 1000,
+                ty: ChannelOpenType::DirectTcpip(DirectTcpip {
+                    address: "localhost".into(),
+                    port: 22,
+                    origin: "::1".into(),
+                    origin_port: 4444,
+                }),
+            }.into(),
+            GlobalRequest {
+                want_reply: true,
+                req: GlobalRequestMethod::TcpipForward(TcpipForward {
+                    address: "".into(),
+                    port: 80,
+                }),
+            }.into(),
+        ];
+
+        let mut pk_ctx = ParseContext::new();
+        pk_ctx.cli_auth_type = Some(auth::AuthType::PubKey);
+        for p in corpus.iter() {
+            let mut buf = vec![0u8; 500];
+            let l = write_ssh(&mut buf, p).unwrap();
+            buf.truncate(l);
+            for i in 0..l {
+                packet_from_bytes(&buf[..i], &pk_ctx).unwrap_err();
+                for v in [0, 1, 0x7f, 0xff, buf[i] ^ 0x80] {
+                    let mut b = buf.clone();
+                    b[i] = v;
+                    let _ = packet_from_bytes(&b, &ParseContext::default());
+                    let _ = packet_from_bytes(&b, &pk_ctx);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn roundtrip_tcpip_forward() {
+        init_test_log();
+        let p = Packet::GlobalRequest(GlobalRequest {
+            want_reply: true,
+            req: GlobalRequestMethod::TcpipForward(TcpipForward {
+                address: "localhost".into(),
+                port: 0,
+            }),
+        });
+        test_roundtrip(&p);
+
+        let p = Packet::GlobalRequest(GlobalRequest {
+            want_reply: false,
+            req: GlobalRequestMethod::CancelTcpipForward(TcpipForward {
+                address: "".into(),
+                port: 2222,
+            }),
+        });
+        test_roundtrip(&p);
+
+        // bound port follows the message number
+        let p: Packet = RequestSuccess::TcpPort(0x1234).into();
+        let mut buf = [0u8; 20];
+        let l = write_ssh(&mut buf, &p).unwrap();
+        assert_eq!(&buf[..l], &[MessageNumber::SSH_MSG_REQUEST_SUCCESS as u8, 0, 0, 0x12, 0x34]);
+    }
+
+    #[test]
+    fn hostkeys_request() {
+        init_test_log();
+        let k1 = SignKey::generate(KeyType::Ed25519, None).unwrap();
+        let k2 = SignKey::generate(KeyType::Ed25519, None).unwrap();
+        let keys = [k1.pubkey(), k2.pubkey()];
+        let p = Packet::GlobalRequest(GlobalRequest {
+            want_reply: false,
+            req: GlobalRequestMethod::HostKeys(HostKeyList::Local(&keys)),
+        });
+        let mut buf = vec![0u8; 500];
+        let l = write_ssh(&mut buf, &p).unwrap();
+
+        let p = sshwire::packet_from_bytes(&buf[..l], &Default::default()).unwrap();
+        let Packet::GlobalRequest(GlobalRequest {
+            req: GlobalRequestMethod::HostKeys(h), ..
+        }) = p else { panic!() };
+        let got = h.iter().collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(got.as_slice(), &keys);
+
+        // truncated key list
+        let r = sshwire::packet_from_bytes(&buf[..l - 1], &Default::default());
+        assert!(r.is_err());
+    }
+
     #[test]
     fn roundtrip_channel_open() {
         init_test_log();
@@ -1098,6 +1671,7 @@ mod tests {
         let p: Packet = UserauthRequest {
             username: "matt".into(), service: "connection",
             method: AuthMethod::PubKey(MethodPubKey {
+                sig_present: true,
                 sig_algo: "something",
                 pubkey: Blob(PubKey::Ed25519(
                     Ed25519PubKey { key: Blob([3u8; 32]) }
@@ -1105,7 +1679,6 @@ mod tests {
                 sig: Some(Blob(Signature::Ed25519(Ed25519Sig {
                     sig: BinString(b"sighere")
                 }))),
-                force_sig: false,
             })}.into();
 
         let mut buf1 = vec![88; 1000];
@@ -1142,4 +1715,38 @@ mod tests {
         let mut buf1 = vec![88; 1000];
         write_ssh(&mut buf1, &p).unwrap();
     }
+
+    #[test]
+    fn roundtrip_kbdint() {
+        init_test_log();
+        let p = UserauthRequest {
+            username: "matt".into(),
+            service: SSH_SERVICE_CONNECTION,
+            method: AuthMethod::KeyboardInteractive(MethodKeyboardInteractive {
+                lang: "".into(),
+                submethods: "".into(),
+            }),
+        }.into();
+        test_roundtrip(&p);
+
+        let prompts = InfoPrompts::new(&[
+            InfoPrompt { prompt: "Password: ".into(), echo: false },
+            InfoPrompt { prompt: "Code: ".into(), echo: true },
+        ]).unwrap();
+        let p = Userauth60::InfoRequest(UserauthInfoRequest {
+            name: "".into(),
+            instruction: "Enter your details".into(),
+            lang: "".into(),
+            prompts,
+        }).into();
+        let mut ctx = ParseContext::new();
+        ctx.cli_auth_type = Some(auth::AuthType::KeyboardInteractive);
+        test_roundtrip_context(&p, &ctx);
+
+        let mut responses = InfoResponses::default();
+        responses.push("hunter2".into()).unwrap();
+        responses.push("".into()).unwrap();
+        let p = UserauthInfoResponse { responses }.into();
+        test_roundtrip(&p);
+    }
 }
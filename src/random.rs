@@ -4,9 +4,72 @@ use {
     log::{debug, error, info, log, trace, warn},
 };
 
+use rand_core::{CryptoRng, RngCore};
+
+#[cfg(test)]
+use {
+    chacha20::ChaCha20,
+    chacha20::cipher::{KeyIvInit, StreamCipher},
+    core::cell::RefCell,
+};
+
+#[cfg(test)]
+std::thread_local! {
+    static TEST_RNG: RefCell<Option<ChaCha20>> = const { RefCell::new(None) };
+}
+
 pub fn fill_random(buf: &mut [u8]) -> Result<(), Error> {
+    #[cfg(test)]
+    if TEST_RNG.with_borrow_mut(|r| {
+        r.as_mut().map(|r| {
+            buf.fill(0);
+            r.apply_keystream(buf)
+        })
+    }).is_some() {
+        return Ok(())
+    }
+
     getrandom::getrandom(buf)
     .map_err(|_| {
         Error::msg("RNG failed")
     })
 }
+
+/// Makes [`fill_random()`] deterministic for the current thread.
+///
+/// Tests can use this to compare key exchanges. `None` restores
+/// the system RNG.
+#[cfg(test)]
+pub(crate) fn set_test_seed(seed: Option<[u8; 32]>) {
+    let rng = seed.map(|s| ChaCha20::new(&s.into(), &[0u8; 12].into()));
+    TEST_RNG.set(rng);
+}
+
+/// A [`RngCore`] for crates that take one, using [`fill_random()`].
+pub(crate) struct SunsetRng;
+
+impl RngCore for SunsetRng {
+    fn next_u32(&mut self) -> u32 {
+        rand_core::impls::next_u32_via_fill(self)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        rand_core::impls::next_u64_via_fill(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        // Same as OsRng
+        if let Err(e) = self.try_fill_bytes(dest) {
+            panic!("Error: {}", e);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        fill_random(dest).map_err(|_| {
+            let code = core::num::NonZeroU32::new(rand_core::Error::CUSTOM_START).unwrap();
+            rand_core::Error::from(code)
+        })
+    }
+}
+
+impl CryptoRng for SunsetRng {}
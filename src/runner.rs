@@ -11,7 +11,7 @@ use pretty_hex::PrettyHex;
 
 use crate::{event::ChanRequest, packets::{Packet, Subsystem}, *};
 use packets::{ChannelDataExt, ChannelData};
-use channel::{ChanNum, ChanData};
+use channel::{ChanNum, ChanData, MaxPacket};
 use channel::{CliSessionExit, CliSessionOpener};
 use encrypt::KeyState;
 use traffic::{TrafIn, TrafOut};
@@ -27,7 +27,8 @@ use conn::{Conn, Dispatched, DispatchEvent};
 /// A SSH session instance
 ///
 /// An application provides network or channel data to `Runner` method calls,
-/// and provides customisation callbacks via `CliBehaviour` or `ServBehaviour`.
+/// and handles the [`Event`]s returned from [`progress()`](Self::progress)
+/// for decisions such as hostkey checking or authentication.
 pub struct Runner<'a> {
     conn: Conn,
 
@@ -45,6 +46,8 @@ pub struct Runner<'a> {
     input_waker: Option<Waker>,
 
     closed_input: bool,
+    /// Set by [`disconnect()`](Self::disconnect)
+    disconnected: bool,
 
     resume_event: DispatchEvent,
     // Some incoming packets will produce multiple Events from a single packet.
@@ -71,6 +74,8 @@ impl core::fmt::Debug for Runner<'_> {
 
 impl<'a> Runner<'a> {
     /// `inbuf` and `outbuf` must be sized to fit the largest SSH packet allowed.
+    ///
+    /// See [`new()`](Self::new) for buffer sizes.
     pub fn new_client(
         inbuf: &'a mut [u8],
         outbuf: &'a mut [u8],
@@ -79,6 +84,8 @@ impl<'a> Runner<'a> {
     }
 
     /// `inbuf` and `outbuf` must be sized to fit the largest SSH packet allowed.
+    ///
+    /// See [`new()`](Self::new) for buffer sizes.
     pub fn new_server(
         inbuf: &'a mut [u8],
         outbuf: &'a mut [u8],
@@ -86,12 +93,34 @@ impl<'a> Runner<'a> {
         Self::new(inbuf, outbuf, false)
     }
 
+    /// Creates a new SSH session.
+    ///
+    /// `inbuf` and `outbuf` must be sized to fit the largest SSH packet allowed.
+    /// The sizes are chosen by the application, an `inbuf` of
+    /// [`RFC_MIN_BUFFER`](config::RFC_MIN_BUFFER) is required for RFC compliance.
+    /// The channel `max_packet` advertised to the peer is derived from the
+    /// `inbuf` size. Incoming packets that don't fit fail with
+    /// [`Error::BigPacket`]. Outgoing packets are limited to `outbuf` size.
+    ///
+    /// Returns [`Error::NoRoom`] if `inbuf` or `outbuf` is too small for any
+    /// channel data.
     pub fn new(
         inbuf: &'a mut [u8],
         outbuf: &'a mut [u8],
         is_client: bool,
     ) -> Result<Runner<'a>, Error> {
-        let conn = Conn::new(is_client)?;
+        let max_packet = inbuf.len().saturating_sub(config::RECV_PACKET_OVERHEAD);
+        if max_packet == 0 {
+            return error::NoRoom.fail()
+        }
+        let mut conn = Conn::new(is_client)?;
+        conn.channels.set_recv_max_packet(max_packet);
+        // Outgoing packets have the same overhead
+        let send_max_packet = outbuf.len().saturating_sub(config::RECV_PACKET_OVERHEAD);
+        if send_max_packet == 0 {
+            return error::NoRoom.fail()
+        }
+        conn.channels.set_send_max_packet(send_max_packet);
         let runner = Runner {
             conn,
             traf_in: TrafIn::new(inbuf),
@@ -100,6 +129,7 @@ impl<'a> Runner<'a> {
             output_waker: None,
             input_waker: None,
             closed_input: false,
+            disconnected: false,
             resume_event: DispatchEvent::None,
             extra_resume_event: DispatchEvent::None,
         };
@@ -113,10 +143,23 @@ impl<'a> Runner<'a> {
 
     /// Drives connection progress, handling received payload and queueing
     /// packets to send as required.
+    ///
+    /// Fatal protocol errors queue a `SSH_MSG_DISCONNECT` with a reason
+    /// code before returning the error, output should still be flushed.
     pub fn progress(&mut self) -> Result<Event<'_, 'a>> {
         // Any previous Event must have been dropped to be able to call progress()
         // again, since it borrows from Runner. We can check if it was dropped
         // without a required response, or complete the payload handling otherwise.
+        if self.disconnected {
+            self.resume_event = DispatchEvent::None;
+            self.extra_resume_event = DispatchEvent::None;
+            if self.conn.is_client() {
+                return Ok(Event::Cli(CliEvent::Defunct))
+            } else {
+                return Ok(Event::Serv(ServEvent::Defunct))
+            }
+        }
+
         let prev = self.resume_event.take();
         if prev.needs_resume() {
             // Events that need a response would have cleared runner.resume_event in their
@@ -132,8 +175,9 @@ impl<'a> Runner<'a> {
             return Event::from_dispatch(&ex, self);
         }
 
-        // Previous event payload is complete
-        if prev.is_some() {
+        // Previous event payload is complete. Progressed doesn't hold a payload,
+        // one may have arrived since.
+        if prev.is_some() && !matches!(prev, DispatchEvent::Progressed) {
             self.traf_in.done_payload();
         }
 
@@ -142,7 +186,8 @@ impl<'a> Runner<'a> {
 
         // Handle incoming packets
         if let Some((payload, seq)) = self.traf_in.payload() {
-            disp = self.conn.handle_payload(payload, seq, &mut s)?;
+            disp = self.conn.handle_payload(payload, seq, &mut s)
+                .inspect_err(|e| s.send_disconnect(e))?;
 
             match disp.event {
                 DispatchEvent::Data(data_in) => {
@@ -175,7 +220,8 @@ impl<'a> Runner<'a> {
         // If there isn't any pending event for the application, run conn.progress()
         // (which may return other events).
         if disp.event.is_none() {
-            disp = self.conn.progress(&mut s)?;
+            disp = self.conn.progress(&mut s)
+                .inspect_err(|e| s.send_disconnect(e))?;
             trace!("prog disp {disp:?}");
             match disp.event {
                 | DispatchEvent::CliEvent(_) 
@@ -197,6 +243,39 @@ impl<'a> Runner<'a> {
         Event::from_dispatch(&disp.event, self)
     }
 
+    /// Runs [`progress()`](Self::progress) once, passing the event to `f`.
+    ///
+    /// This is for synchronous main loops without an async executor.
+    /// Returns `true` if `progress_sync()` should be called again,
+    /// or `false` if the `Runner` is idle until more [`input()`](Self::input)
+    /// arrives or the application sends channel data. After a `Defunct`
+    /// event it returns `false`, the connection has finished. A loop could be:
+    ///
+    /// ```ignore
+    /// loop {
+    ///     let n = runner.input(&rx[..rx_len])?;
+    ///     while runner.progress_sync(|ev| handle_event(ev))? {}
+    ///     while runner.is_output_pending() {
+    ///         let n = runner.output(&mut tx)?;
+    ///         socket.write(&tx[..n])?;
+    ///     }
+    ///     // ... read socket, channel IO
+    /// }
+    /// ```
+    ///
+    /// The same event handling rules apply as for `progress()`.
+    pub fn progress_sync<F>(&mut self, f: F) -> Result<bool>
+    where F: FnOnce(Event<'_, 'a>) -> Result<()>
+    {
+        let ev = self.progress()?;
+        let more = !matches!(ev,
+            Event::None
+            | Event::Cli(CliEvent::Defunct)
+            | Event::Serv(ServEvent::Defunct));
+        f(ev)?;
+        Ok(more)
+    }
+
     pub(crate) fn packet(&self) -> Result<Option<packets::Packet>> {
         if let Some((payload, _seq)) = self.traf_in.payload() {
             self.conn.packet(payload).map(|p| Some(p))
@@ -215,6 +294,12 @@ impl<'a> Runner<'a> {
             &mut self.conn.remote_version,
             buf,
         )
+        .inspect_err(|e| {
+            // The peer can't parse a binary packet until version exchange completes
+            if self.conn.version_exchanged() {
+                self.traf_out.sender(&mut self.keys).send_disconnect(e)
+            }
+        })
     }
 
     // Whether [`input()`](input) is ready
@@ -250,7 +335,8 @@ impl<'a> Runner<'a> {
         Ok(r)
     }
 
-    // Whether [`output()`](output) is ready
+    /// Whether [`output()`](Self::output) has data to write
+    #[doc(alias = "wants_write")]
     pub fn is_output_pending(&self) -> bool {
         self.traf_out.is_output_pending()
     }
@@ -267,6 +353,25 @@ impl<'a> Runner<'a> {
         }
     }
 
+    /// Sends `SSH_MSG_DISCONNECT` and closes the connection.
+    ///
+    /// Further received packets are ignored, [`progress()`](Self::progress)
+    /// will return a `Defunct` event. Output should still be flushed
+    /// so the peer receives the disconnect message.
+    pub fn disconnect(&mut self, reason: DisconnectReason, desc: &str) -> Result<()> {
+        if self.disconnected {
+            return Ok(())
+        }
+        // Can't send a binary packet before our version string
+        if self.conn.initial_sent() {
+            self.traf_out.sender(&mut self.keys).send_disconnect_reason(reason, desc)?;
+        }
+        self.disconnected = true;
+        self.closed_input = true;
+        self.wake();
+        Ok(())
+    }
+
     /// Indicate that the output SSH tcp socket has closed
     pub fn close_output(&mut self) {
         trace!("close_input");
@@ -283,9 +388,74 @@ impl<'a> Runner<'a> {
         Ok(ChanHandle(chan))
     }
 
+    /// Opens a client session channel running a shell, with a PTY if `pty` is given.
+    ///
+    /// Once the server confirms the channel the PTY and shell requests are
+    /// sent, without a [`CliEvent::SessionOpened`](event::CliEvent::SessionOpened).
+    /// The server's reply to the shell request is returned as a
+    /// [`CliEvent::ChanRequestResult`](event::CliEvent::ChanRequestResult),
+    /// after which the session is ready.
+    ///
+    /// Only one `open_shell()` may be awaiting confirmation at a time,
+    /// otherwise returns [`Error::BadUsage`].
+    pub fn open_shell(&mut self, pty: Option<Pty>) -> Result<ChanHandle> {
+        trace!("open_shell");
+        if !self.is_client() {
+            return error::BadUsage.fail()
+        }
+
+        let (chan, p) = self.conn.channels.open_shell(pty)?;
+        self.traf_out.send_packet(p, &mut self.keys)?;
+        self.wake();
+        Ok(ChanHandle(chan))
+    }
+
+    /// Opens a `direct-tcpip` channel, for local port forwarding.
+    ///
+    /// The peer will connect to `host:port`, `orig_host:orig_port` is the
+    /// originator of the connection. Data may be sent once
+    /// [`ready_channel_send()`](Self::ready_channel_send) is non-zero.
+    /// If the peer refuses the channel, channel operations return
+    /// [`Error::ChannelOpenFailed`], and the description is available from
+    /// [`channel_open_failure()`](Self::channel_open_failure).
+    pub fn open_direct_tcpip(&mut self, host: &str, port: u16,
+        orig_host: &str, orig_port: u16) -> Result<ChanHandle> {
+        trace!("open_direct_tcpip {host}:{port}");
+
+        let ty = packets::ChannelOpenType::DirectTcpip(packets::DirectTcpip {
+            address: host.into(),
+            port: port as u32,
+            origin: orig_host.into(),
+            origin_port: orig_port as u32,
+        });
+        let (chan, p) = self.conn.channels.open(ty)?;
+        self.traf_out.send_packet(p, &mut self.keys)?;
+        self.wake();
+        Ok(ChanHandle(chan))
+    }
+
+    /// Opens a forwarded agent channel to the client.
+    ///
+    /// Only call on a server, after the client has requested forwarding
+    /// with [`ServEvent::SessionAgentReq`](event::ServEvent::SessionAgentReq)
+    /// and it succeeded. Returns [`Error::BadUsage`] otherwise.
+    pub fn open_agent_forward(&mut self) -> Result<ChanHandle> {
+        if self.is_client() || !self.conn.channels.agent_forwarding() {
+            return error::BadUsage.fail()
+        }
+        let (chan, p) = self.conn.channels.open(packets::ChannelOpenType::AuthAgent)?;
+        self.traf_out.send_packet(p, &mut self.keys)?;
+        self.wake();
+        Ok(ChanHandle(chan))
+    }
+
     /// Send data from this application out the wire.
     ///
+    /// Data larger than the peer's maximum packet size is split into
+    /// several packets, limited by the channel window and output buffer space.
+    ///
     /// Returns `Ok(len)` consumed, `Err(Error::ChannelEof)` on EOF,
+    /// `Err(Error::ChannelOpenFailed)` if the peer refused the channel,
     /// or other errors.
     pub fn channel_send(
         &mut self,
@@ -302,26 +472,49 @@ impl<'a> Runner<'a> {
             return Ok(0)
         }
 
-        let len = self.ready_channel_send(chan, dt)?;
-        let len = match len {
-            Some(l) if l == 0 => return Ok(0),
-            Some(l) => l,
-            None => return Err(Error::ChannelEOF),
-        };
-
-        let len = len.min(buf.len());
+        let mut sent = 0;
+        let r = self.channel_send_packets(chan, dt, buf, &mut sent);
+        if sent > 0 {
+            self.wake();
+            // Errors (including EOF) are only reported when nothing was sent,
+            // a subsequent call will return them.
+            return Ok(sent)
+        }
+        r.map(|()| 0)
+    }
 
-        let p = self.conn.channels.send_data(chan.0, dt, &buf[..len])?;
-        self.traf_out.send_packet(p, &mut self.keys)?;
-        self.wake();
-        Ok(len)
+    /// Splits `buf` into packets of at most the peer's max_packet,
+    /// updating `sent` as each is queued.
+    fn channel_send_packets(
+        &mut self,
+        chan: &ChanHandle,
+        dt: ChanData,
+        buf: &[u8],
+        sent: &mut usize,
+    ) -> Result<()> {
+        while *sent < buf.len() {
+            let len = match self.ready_channel_send(chan, dt)? {
+                Some(0) => break,
+                Some(l) => l,
+                None => return Err(self.channel_eof_error(chan)),
+            };
+
+            let len = len.min(buf.len() - *sent);
+
+            let num = self.conn.channels.send_data(chan.0, len)?;
+            self.traf_out.send_channel_data(num, dt, &buf[*sent..*sent + len], &mut self.keys)?;
+            *sent += len;
+        }
+        Ok(())
     }
 
     /// Receive data coming from the wire into this application.
     ///
     /// Returns `Ok(len)` received, `Err(Error::ChannelEof)` on EOF,
+    /// `Err(Error::ChannelOpenFailed)` if the peer refused the channel,
     /// or other errors. Ok(0) indicates no data available, ie pending.
-    /// TODO: EOF is unimplemented
+    ///
+    /// Window adjustments are sent to the peer as data is consumed.
     pub fn channel_input(
         &mut self,
         chan: &ChanHandle,
@@ -334,17 +527,26 @@ impl<'a> Runner<'a> {
 
         dt.validate_receive(self.conn.is_client())?;
 
-        if self.is_channel_eof(chan) {
-            return error::ChannelEOF.fail()
-        }
-
+        // Data received prior to EOF is returned first
         let (len, complete) = self.traf_in.channel_input(chan.0, dt, buf);
         if let Some(x) = complete {
             self.finished_input(chan, x)?;
         }
+        if len == 0 && self.is_channel_eof(chan) {
+            return Err(self.channel_eof_error(chan))
+        }
         Ok(len)
     }
 
+    /// Returns the error for a channel that has ended, either
+    /// [`Error::ChannelOpenFailed`] or [`Error::ChannelEOF`].
+    fn channel_eof_error(&self, chan: &ChanHandle) -> Error {
+        match self.conn.channels.open_failure(chan.0) {
+            Some((reason, _)) => Error::ChannelOpenFailed { reason },
+            None => Error::ChannelEOF,
+        }
+    }
+
     /// Receives input data, either normal or extended.
     pub fn channel_input_either(
         &mut self,
@@ -368,8 +570,10 @@ impl<'a> Runner<'a> {
     }
 
     fn finished_input(&mut self, chan: &ChanHandle, len: usize) -> Result<()> {
+        // Window adjustments can't be sent during key exchange
+        let hold = self.conn.kex_in_progress();
         let mut s = self.traf_out.sender(&mut self.keys);
-        self.conn.channels.finished_input(chan.0, len, &mut s)?;
+        self.conn.channels.finished_input(chan.0, len, hold, &mut s)?;
         self.wake();
         Ok(())
     }
@@ -387,6 +591,15 @@ impl<'a> Runner<'a> {
         self.traf_in.ready_channel_input()
     }
 
+    /// Returns whether `num` refers to an open channel.
+    ///
+    /// This is `false` once the channel's [`ChanHandle`] has been released
+    /// by [`channel_done()`](Self::channel_done), since the number may
+    /// then be reused for a different channel.
+    pub fn is_channel_valid(&self, num: ChanNum) -> bool {
+        self.conn.channels.is_valid(num)
+    }
+
     pub fn is_channel_eof(&self, chan: &ChanHandle) -> bool {
         self.conn.channels.have_recv_eof(chan.0) || self.closed_input
     }
@@ -395,6 +608,26 @@ impl<'a> Runner<'a> {
         self.conn.channels.is_closed(chan.0) || self.closed_input
     }
 
+    /// The exit status sent by the server for a client session.
+    ///
+    /// Returns `None` if no exit status has been received, or
+    /// the command exited with a signal (see [`channel_exit_signal()`](Self::channel_exit_signal)).
+    pub fn channel_exit_status(&self, chan: &ChanHandle) -> Option<u32> {
+        self.conn.channels.exit_status(chan.0)
+    }
+
+    /// The signal name (such as "TERM") that terminated a client session's command.
+    pub fn channel_exit_signal(&self, chan: &ChanHandle) -> Option<&str> {
+        self.conn.channels.exit_signal(chan.0)
+    }
+
+    /// The reason code and description if the peer refused to open a channel.
+    ///
+    /// The description is truncated to [`MAX_OPEN_FAIL_DESC`](config::MAX_OPEN_FAIL_DESC).
+    pub fn channel_open_failure(&self, chan: &ChanHandle) -> Option<(u32, &str)> {
+        self.conn.channels.open_failure(chan.0)
+    }
+
     /// Returns the maximum data that may be sent to a channel
     ///
     /// Returns `Ok(None)` on channel closed.
@@ -404,7 +637,10 @@ impl<'a> Runner<'a> {
         if self.traf_out.closed() {
             return Ok(None)
         }
-        // TODO: return 0 if InKex means we can't transmit packets.
+        // Channel data can't be sent during key exchange
+        if self.conn.kex_in_progress() {
+            return Ok(Some(0))
+        }
 
         // Avoid apps polling forever on a packet type that won't come
         dt.validate_send(self.conn.is_client())?;
@@ -418,6 +654,207 @@ impl<'a> Runner<'a> {
         r
     }
 
+    /// Starts a new key exchange.
+    ///
+    /// Does nothing if a key exchange is already in progress. Key exchange
+    /// also starts automatically once rekey limits are reached, see
+    /// [`set_rekey_limits()`](Self::set_rekey_limits).
+    pub fn initiate_rekey(&mut self) -> Result<()> {
+        let mut s = self.traf_out.sender(&mut self.keys);
+        self.conn.initiate_rekey(&mut s)?;
+        self.wake();
+        Ok(())
+    }
+
+    /// Returns the session identifier once the first key exchange has completed.
+    ///
+    /// This is the exchange hash `H` of the first key exchange, and stays
+    /// the same after rekeying. It can be used to bind higher level
+    /// protocols to the SSH session.
+    pub fn session_id(&self) -> Option<&[u8]> {
+        self.conn.sess_id().map(|s| s.as_slice())
+    }
+
+    /// Returns the algorithms negotiated by the most recent key exchange.
+    ///
+    /// Returns `None` before the first key exchange has completed.
+    pub fn negotiated_algorithms(&self) -> Option<AlgoNames> {
+        self.conn.algo_names().cloned()
+    }
+
+    /// Fail the connection if a rekey negotiates algorithms less preferred
+    /// than those of the first key exchange.
+    ///
+    /// This is a defense-in-depth measure against a peer (or MITM with
+    /// a compromised session) weakening a session. Disabled by default.
+    /// A downgrade fails with [`Error::RekeyDowngrade`].
+    pub fn set_reject_rekey_downgrade(&mut self, reject: bool) {
+        self.conn.set_reject_rekey_downgrade(reject)
+    }
+
+    /// Sends an `SSH_MSG_DEBUG` message to the peer.
+    ///
+    /// If `always_display` is set the peer should show the message to its user.
+    /// Received debug messages are logged, at `Info` level if `always_display`
+    /// is set. Returns [`Error::BadUsage`] before the first key exchange
+    /// has completed (strict kex forbids it) or after disconnecting.
+    pub fn send_debug(&mut self, message: &str, always_display: bool) -> Result<()> {
+        if self.disconnected || self.conn.sess_id().is_none() {
+            return error::BadUsage.fail()
+        }
+        self.traf_out.send_packet(packets::DebugPacket {
+            always_display,
+            message: message.into(),
+            lang: "",
+        }.into(), &mut self.keys)?;
+        self.wake();
+        Ok(())
+    }
+
+    /// Sends a banner message to the client, such as a legal notice.
+    ///
+    /// Only valid for a server, while handling an authentication event
+    /// ([`ServEvent::FirstAuth`] etc) before authentication has succeeded.
+    /// The banner is sent before the response to that request.
+    /// Returns [`Error::BadUsage`] otherwise.
+    pub fn send_banner(&mut self, message: &str) -> Result<()> {
+        let mut s = self.traf_out.sender(&mut self.keys);
+        self.conn.send_banner(message, &mut s)
+    }
+
+    /// Sends all of a server's host keys to the client.
+    ///
+    /// This uses the OpenSSH `hostkeys-00@openssh.com` extension, so clients
+    /// can learn of new keys prior to rotation. Clients receive a
+    /// [`CliEvent::HostkeysUpdate`].
+    ///
+    /// Unlike OpenSSH this isn't sent automatically after authentication,
+    /// since sunset doesn't retain host keys past the
+    /// [`ServEvent::Hostkeys`](event::ServEvent::Hostkeys) event. A server
+    /// should call this once it has accepted authentication, passing
+    /// all of its host keys.
+    ///
+    /// Only valid for a server after authentication, with at most
+    /// [`config::MAX_HOSTKEYS`] keys. Returns [`Error::BadUsage`] otherwise.
+    pub fn send_hostkeys(&mut self, keys: &[&SignKey]) -> Result<()> {
+        let mut s = self.traf_out.sender(&mut self.keys);
+        self.conn.send_hostkeys(keys, &mut s)?;
+        self.wake();
+        Ok(())
+    }
+
+    /// Sets the number of failed authentication attempts after which a server
+    /// disconnects.
+    ///
+    /// The default is [`config::DEFAULT_MAX_AUTH_TRIES`]. The initial `none`
+    /// method query isn't counted. Returns [`Error::BadUsage`] for a client.
+    pub fn set_max_auth_tries(&mut self, tries: u32) -> Result<()> {
+        if self.is_client() {
+            return error::BadUsage.fail()
+        }
+        self.conn.set_max_auth_tries(tries)
+    }
+
+    /// Sets our SSH identification string, instead of the default `SSH-2.0-Sunset-1`.
+    ///
+    /// It must start with `SSH-2.0-` and be printable ASCII, at most 253 bytes.
+    /// The softwareversion part (before any space) can't contain `-`.
+    /// Invalid strings fail with [`Error::BadName`]. Must be called before
+    /// the first [`progress()`](Self::progress), otherwise
+    /// returns [`Error::BadUsage`].
+    pub fn set_ident(&mut self, ident: &'static str) -> Result<()> {
+        self.conn.set_ident(ident)
+    }
+
+    /// Sets the algorithm preferences for key exchange.
+    ///
+    /// Should be called before the first [`progress()`](Self::progress) to apply
+    /// to the initial key exchange, otherwise it applies from the next rekey.
+    /// `conf` must be created with a matching `is_client`.
+    /// Returns [`Error::BadUsage`] during a key exchange.
+    pub fn set_algo_config(&mut self, conf: AlgoConfig) -> Result<()> {
+        self.conn.set_algo_config(conf)
+    }
+
+    /// Sets the traffic volume (in bytes, both directions) and time (in seconds)
+    /// after which a new key exchange will be started.
+    ///
+    /// Defaults are [`config::DEFAULT_REKEY_BYTES`] and [`config::DEFAULT_REKEY_SECS`].
+    pub fn set_rekey_limits(&mut self, bytes: u64, secs: u64) {
+        self.keys.set_rekey_limits(bytes, secs)
+    }
+
+    /// Sends an already serialized SSH payload, for relaying packets
+    /// without a decode/encode round trip.
+    ///
+    /// Only messages that don't affect connection or channel state are allowed:
+    /// `SSH_MSG_IGNORE`, `SSH_MSG_UNIMPLEMENTED` and `SSH_MSG_DEBUG`.
+    /// Other payload contents aren't checked.
+    /// Returns [`Error::BadUsage`] for other message numbers, before the
+    /// first key exchange completes, or while a key exchange is in progress.
+    pub fn send_raw(&mut self, payload: &[u8]) -> Result<()> {
+        use packets::MessageNumber::*;
+        let allowed = payload.first()
+            .and_then(|&n| packets::MessageNumber::try_from(n).ok())
+            .is_some_and(|n| matches!(n,
+                SSH_MSG_IGNORE | SSH_MSG_UNIMPLEMENTED | SSH_MSG_DEBUG));
+        if !allowed || self.keys.is_cleartext() || self.conn.kex_in_progress() {
+            return error::BadUsage.fail()
+        }
+        let mut s = self.traf_out.sender(&mut self.keys);
+        s.send_raw(payload)?;
+        self.wake();
+        Ok(())
+    }
+
+    /// Zeroize all received packets in the input buffer once they have
+    /// been handled.
+    ///
+    /// By default only authentication requests (which may contain passwords)
+    /// are zeroized. Enabling this also clears decrypted channel data after
+    /// it has been read, at a small performance cost.
+    pub fn set_zeroize_input(&mut self, zeroize: bool) {
+        self.traf_in.set_zeroize(zeroize)
+    }
+
+    /// Informs the `Runner` of the current time, in seconds.
+    ///
+    /// The time may have any epoch but must be monotonic.
+    /// This is optional, time-based rekeying and keepalives only occur if
+    /// applications call `set_time()` periodically.
+    ///
+    /// Returns [`Error::Timeout`] if the peer hasn't responded to keepalives.
+    pub fn set_time(&mut self, now: u64) -> Result<()> {
+        self.keys.set_time(now);
+        if self.keys.rekey_due() {
+            self.initiate_rekey()?;
+        }
+        let mut s = self.traf_out.sender(&mut self.keys);
+        self.conn.keepalive(now, &mut s)?;
+        self.wake();
+        Ok(())
+    }
+
+    /// Sends a `keepalive@openssh.com` request after `interval` seconds
+    /// without receiving any packets.
+    ///
+    /// Once `max_missed` keepalives have been sent without a response,
+    /// [`set_time()`](Self::set_time) returns [`Error::Timeout`] and the
+    /// connection should be closed. An `interval` of 0 disables keepalives
+    /// (the default). Keepalives are only sent after authentication.
+    pub fn set_keepalive(&mut self, interval: u64, max_missed: u32) {
+        self.conn.set_keepalive(interval, max_missed)
+    }
+
+    /// Returns the maximum channel data packet sizes negotiated for a channel.
+    ///
+    /// [`MaxPacket::local`] is the most data the peer may send in a single
+    /// packet, useful for sizing read buffers. Sends are already limited to
+    /// [`MaxPacket::remote`] by [`ready_channel_send()`](Self::ready_channel_send).
+    pub fn channel_max_packet(&self, chan: &ChanHandle) -> Result<MaxPacket> {
+        self.conn.channels.max_packet(chan.0)
+    }
+
     /// Returns `true` if the channel and `dt` are currently valid for writing.
     ///
     /// Note that they may not be ready to send output.
@@ -439,7 +876,44 @@ impl<'a> Runner<'a> {
     pub fn term_window_change(&mut self, chan: &ChanHandle, winch: packets::WinChange) -> Result<()> {
         if self.is_client() {
             let mut s = self.traf_out.sender(&mut self.keys);
-            self.conn.channels.term_window_change(chan.0, winch, &mut s)
+            self.conn.channels.term_window_change(chan.0, winch, &mut s)?;
+            self.wake();
+            Ok(())
+        } else {
+            error::BadChannelData.fail()
+        }
+    }
+
+    /// Request a subsystem such as "sftp" on an opened session
+    ///
+    /// The server's reply is returned as a
+    /// [`CliEvent::ChanRequestResult`](event::CliEvent::ChanRequestResult).
+    /// This is an alternative to requesting a subsystem
+    /// from [`CliEvent::SessionOpened`](event::CliEvent::SessionOpened),
+    /// which doesn't report the result.
+    /// Only call on a client session.
+    pub fn open_subsystem(&mut self, chan: &ChanHandle, name: &str) -> Result<()> {
+        if !self.is_client() {
+            return error::BadChannelData.fail()
+        }
+        let mut s = self.traf_out.sender(&mut self.keys);
+        self.conn.channels.send_subsystem(chan.0, name, &mut s)?;
+        self.wake();
+        Ok(())
+    }
+
+    /// Set an environment variable for a session
+    ///
+    /// Should be sent prior to the shell or exec request. Servers
+    /// commonly ignore variables that aren't in an allowlist, no
+    /// response is indicated.
+    /// Only call on a client session.
+    pub fn send_env(&mut self, chan: &ChanHandle, name: &str, value: &str) -> Result<()> {
+        if self.is_client() {
+            let mut s = self.traf_out.sender(&mut self.keys);
+            self.conn.channels.send_env(chan.0, name, value, &mut s)?;
+            self.wake();
+            Ok(())
         } else {
             error::BadChannelData.fail()
         }
@@ -454,14 +928,82 @@ impl<'a> Runner<'a> {
     pub fn term_break(&mut self, chan: &ChanHandle, length: u32) -> Result<()> {
         if self.is_client() {
             let mut s = self.traf_out.sender(&mut self.keys);
-            self.conn.channels.term_break(chan.0, length, &mut s)
+            self.conn.channels.term_break(chan.0, length, &mut s)?;
+            self.wake();
+            Ok(())
         } else {
             error::BadChannelData.fail()
         }
     }
 
+    /// Send a signal to a remote command or shell
+    ///
+    /// `signal` is the name without a "SIG" prefix, for example "INT".
+    /// Names not listed in RFC4254 fail with [`Error::BadName`].
+    /// Only call on a client session.
+    pub fn send_signal(&mut self, chan: &ChanHandle, signal: &str) -> Result<()> {
+        if !self.is_client() {
+            return error::BadChannelData.fail()
+        }
+        let mut s = self.traf_out.sender(&mut self.keys);
+        self.conn.channels.send_signal(chan.0, signal, &mut s)?;
+        self.wake();
+        Ok(())
+    }
+
+    /// Send the exit status of a command or shell
+    ///
+    /// Only call on a server session channel. The channel can then be
+    /// closed with [`channel_eof()`](Self::channel_eof) and
+    /// [`channel_close()`](Self::channel_close).
+    pub fn send_exit_status(&mut self, chan: &ChanHandle, status: u32) -> Result<()> {
+        if self.is_client() {
+            return error::BadChannelData.fail()
+        }
+        let mut s = self.traf_out.sender(&mut self.keys);
+        self.conn.channels.send_exit_status(chan.0, status, &mut s)?;
+        self.wake();
+        Ok(())
+    }
+
+    /// Send the signal that terminated a command or shell
+    ///
+    /// `signal` is the name without a "SIG" prefix, for example "TERM".
+    /// Only call on a server session channel.
+    pub fn send_exit_signal(&mut self, chan: &ChanHandle, signal: &str,
+        core_dumped: bool, msg: &str) -> Result<()> {
+        if self.is_client() {
+            return error::BadChannelData.fail()
+        }
+        let mut s = self.traf_out.sender(&mut self.keys);
+        self.conn.channels.send_exit_signal(chan.0, signal, core_dumped, msg, &mut s)?;
+        self.wake();
+        Ok(())
+    }
+
+    /// Send EOF on a channel
+    ///
+    /// No further data can be sent, though data may still be received.
+    pub fn channel_eof(&mut self, chan: &ChanHandle) -> Result<()> {
+        let mut s = self.traf_out.sender(&mut self.keys);
+        self.conn.channels.send_eof(chan.0, &mut s)?;
+        self.wake();
+        Ok(())
+    }
+
+    /// Close a channel
+    ///
+    /// [`channel_done()`](Self::channel_done) must still be called
+    /// once the application has finished with the channel.
+    pub fn channel_close(&mut self, chan: &ChanHandle) -> Result<()> {
+        let mut s = self.traf_out.sender(&mut self.keys);
+        self.conn.channels.send_close(chan.0, &mut s)?;
+        self.wake();
+        Ok(())
+    }
+
     pub(crate) fn cli_session_opener(&mut self, ch: ChanNum) -> Result<CliSessionOpener<'_, 'a>> {
-        let ch = self.conn.channels.get(ch)?;
+        let ch = self.conn.channels.get_mut(ch)?;
         let s = self.traf_out.sender(&mut self.keys);
 
         Ok(CliSessionOpener {
@@ -480,6 +1022,16 @@ impl<'a> Runner<'a> {
         self.conn.fetch_cli_banner(payload)
     }
 
+    pub(crate) fn fetch_debug(&mut self) -> Result<event::DebugMessage<'_>> {
+        let (payload, _seq) = self.traf_in.payload().trap()?;
+        self.conn.fetch_debug(payload)
+    }
+
+    pub(crate) fn fetch_cli_hostkeys(&mut self) -> Result<event::HostkeysUpdate<'_>> {
+        let (payload, _seq) = self.traf_in.payload().trap()?;
+        self.conn.fetch_cli_hostkeys(payload)
+    }
+
     fn wake(&mut self) {
         if self.is_input_ready() {
             trace!("wake ready_input, waker {:?}", self.input_waker);
@@ -536,18 +1088,36 @@ impl<'a> Runner<'a> {
         self.traf_in.done_payload();
         let mut s = self.traf_out.sender(&mut self.keys);
         let (cliauth, ctx) = self.conn.mut_cliauth()?;
-        cliauth.resume_password(&mut s, password, ctx)?;
-        // assert that resume_password() returns error with none password.
-        // otherwise we might need to handle other events like with clipubkey
-        debug_assert!(password.is_some(), "no password");
-        Ok(())
+        // A None password may fall back to keyboard-interactive, which
+        // doesn't need any further events until the server responds.
+        cliauth.resume_password(&mut s, password, ctx)
+            .inspect_err(|e| s.send_disconnect(e))
+
+    }
+
+    pub(crate) fn fetch_cli_kbdint(&self) -> Result<packets::UserauthInfoRequest<'_>> {
+        self.check_resume(&DispatchEvent::CliEvent(CliEventId::KbdInteractive));
+        let (payload, _seq) = self.traf_in.payload().trap()?;
+        self.conn.fetch_cli_kbdint(payload)
+    }
+
+    pub(crate) fn resume_cli_kbdint(&mut self, responses: Option<&[&str]>) -> Result<()> {
+        self.resume(&DispatchEvent::CliEvent(CliEventId::KbdInteractive));
+        let (payload, _seq) = self.traf_in.payload().trap()?;
+        let mut s = self.traf_out.sender(&mut self.keys);
+        let r = self.conn.resume_cli_kbdint(payload, &mut s, responses)
+            .inspect_err(|e| s.send_disconnect(e));
+        self.traf_in.done_payload();
+        r
     }
 
-    pub(crate) fn resume_clipubkey(&mut self, key: Option<SignKey>) -> Result<()> {
+    pub(crate) fn resume_clipubkey(&mut self, key: Option<SignKey>, cert: Option<&[u8]>)
+        -> Result<()> {
         self.resume(&DispatchEvent::CliEvent(CliEventId::Pubkey));
         let mut s = self.traf_out.sender(&mut self.keys);
         let (cliauth, ctx) = self.conn.mut_cliauth()?;
-        self.extra_resume_event = cliauth.resume_pubkey(&mut s, key, ctx)?;
+        self.extra_resume_event = cliauth.resume_pubkey(&mut s, key, cert, ctx)
+            .inspect_err(|e| s.send_disconnect(e))?;
         if self.extra_resume_event.is_none() {
             self.traf_in.done_payload();
         }
@@ -595,6 +1165,11 @@ impl<'a> Runner<'a> {
         self.conn.fetch_checkhostkey(payload)
     }
 
+    pub(crate) fn fetch_servhostkey_type(&self) -> Result<KeyType> {
+        self.check_resume(&DispatchEvent::ServEvent(ServEventId::Hostkeys));
+        self.conn.fetch_servhostkey_type()
+    }
+
     pub(crate) fn resume_servhostkeys(&mut self, keys: &[&SignKey]) -> Result<()> {
         self.resume(&DispatchEvent::ServEvent(ServEventId::Hostkeys));
         let (payload, _seq) = self.traf_in.payload().trap()?;
@@ -631,7 +1206,14 @@ impl<'a> Runner<'a> {
             || matches!(prev_event, DispatchEvent::ServEvent(ServEventId::PubkeyAuth {..})));
 
         let mut s = self.traf_out.sender(&mut self.keys);
-        self.conn.resume_servauth(allow, &mut s)
+        let r = self.conn.resume_servauth(allow, &mut s);
+        if let Err(e) = &r {
+            if e.disconnect_reason().is_some() {
+                s.send_disconnect(e);
+                self.disconnected = true;
+            }
+        }
+        r
     }
 
     pub(crate) fn resume_servauth_pkok(&mut self) -> Result<()> {
@@ -651,11 +1233,23 @@ impl<'a> Runner<'a> {
         self.conn.channels.resume_open(ch, failure, &mut s)
     }
 
+    pub(crate) fn resume_cli_chanopen(&mut self, ch: ChanNum, failure: Option<ChanFail>) -> Result<()> {
+        self.resume(&DispatchEvent::CliEvent(CliEventId::OpenAgent { ch }));
+        self.traf_in.done_payload();
+        let mut s = self.traf_out.sender(&mut self.keys);
+        self.conn.channels.resume_open(ch, failure, &mut s)
+    }
+
     fn check_chanreq(prev_event: &DispatchEvent) {
         debug_assert!(
             matches!(prev_event, DispatchEvent::ServEvent(ServEventId::SessionShell))
             || matches!(prev_event, DispatchEvent::ServEvent(ServEventId::SessionExec))
             || matches!(prev_event, DispatchEvent::ServEvent(ServEventId::SessionPty))
+            || matches!(prev_event, DispatchEvent::ServEvent(ServEventId::SessionWinch))
+            || matches!(prev_event, DispatchEvent::ServEvent(ServEventId::SessionSubsystem))
+            || matches!(prev_event, DispatchEvent::ServEvent(ServEventId::SessionAgentReq))
+            || matches!(prev_event, DispatchEvent::ServEvent(ServEventId::SessionEnv))
+            || matches!(prev_event, DispatchEvent::ServEvent(ServEventId::SessionBreak))
             );
     }
 
@@ -672,6 +1266,67 @@ impl<'a> Runner<'a> {
         r
     }
 
+    fn check_tcpip_forward(prev_event: &DispatchEvent) {
+        debug_assert!(
+            matches!(prev_event, DispatchEvent::ServEvent(ServEventId::TcpipForward))
+            || matches!(prev_event,
+                DispatchEvent::ServEvent(ServEventId::CancelTcpipForward))
+            );
+    }
+
+    /// `bound_port` is `Some` on success
+    pub(crate) fn resume_tcpip_forward(&mut self, bound_port: Option<u32>) -> Result<()> {
+        let prev_event = self.resume_event.take();
+        trace!("resume tcpip_forward {prev_event:?} {bound_port:?}");
+        Self::check_tcpip_forward(&prev_event);
+
+        let mut s = self.traf_out.sender(&mut self.keys);
+        let (payload, _seq) = self.traf_in.payload().trap()?;
+        let Packet::GlobalRequest(p) = self.conn.packet(payload)? else {
+            return Err(Error::bug())
+        };
+        let r = if p.want_reply {
+            match (bound_port, p.req) {
+                (None, _) => s.send(packets::RequestFailure {}),
+                // Port is only reported when the client asked for 0
+                (Some(port), packets::GlobalRequestMethod::TcpipForward(f))
+                    if f.port == 0 => {
+                    s.send(packets::RequestSuccess::TcpPort(port))
+                }
+                (Some(_), _) => s.send(packets::RequestSuccess::SuccessEmpty),
+            }
+        } else {
+            Ok(())
+        };
+        self.traf_in.done_payload();
+        r
+    }
+
+    pub(crate) fn fetch_tcpip_forward(&self) -> Result<packets::TcpipForward<'_>> {
+        Self::check_tcpip_forward(&self.resume_event);
+        let (payload, _seq) = self.traf_in.payload().trap()?;
+
+        match self.conn.packet(payload)? {
+            Packet::GlobalRequest(packets::GlobalRequest {
+                req: packets::GlobalRequestMethod::TcpipForward(f)
+                    | packets::GlobalRequestMethod::CancelTcpipForward(f),
+                ..
+            }) => Ok(f),
+            _ => Err(Error::bug()),
+        }
+    }
+
+    // Returns the contents of a currently pending request
+    pub(crate) fn fetch_chanreq(&self) -> Result<packets::ChannelReqType<'_>> {
+        Self::check_chanreq(&self.resume_event);
+        let (payload, _seq) = self.traf_in.payload().trap()?;
+
+        match self.conn.packet(payload)? {
+            Packet::ChannelRequest(r) => Ok(r.req),
+            _ => Err(Error::bug()),
+        }
+    }
+
     // Returns the channel of a currently pending request
     pub(crate) fn fetch_reqchannel(&self) -> Result<ChanNum> {
         Self::check_chanreq(&self.resume_event);
@@ -701,7 +1356,11 @@ impl ChanHandle {
     ///
     /// An index may be reused after a call to [`Runner::channel_done()`],
     /// applications must take care not to keep using this `num()` index after
-    /// that.
+    /// that. [`Runner::is_channel_valid()`] can check a `ChanNum`.
+    ///
+    /// Events such as [`ChanRequest::channel()`](crate::event::ChanRequest::channel)
+    /// and [`Runner::ready_channel_input()`] give a `ChanNum`, which can be
+    /// compared with `num()` to find the application's `ChanHandle`.
     pub fn num(&self) -> ChanNum {
         self.0
     }
@@ -715,5 +1374,290 @@ impl core::fmt::Debug for ChanHandle {
 
 #[cfg(test)]
 mod tests {
+    use crate::*;
+    use crate::sunsetlog::*;
+
     // TODO: test send_allowed() limits
+
+    #[test]
+    /// Channel max_packet is derived from the input buffer
+    fn buffer_max_packet() {
+        init_test_log();
+        let mut inbuf = vec![0u8; config::RFC_MIN_BUFFER];
+        let mut outbuf = vec![0u8; 1000];
+        let mut r = Runner::new_client(&mut inbuf, &mut outbuf).unwrap();
+        let (_, p) = r.conn.channels.open(packets::ChannelOpenType::Session).unwrap();
+        let packets::Packet::ChannelOpen(open) = p else { panic!() };
+        assert_eq!(open.max_packet as usize,
+            config::RFC_MIN_BUFFER - config::RECV_PACKET_OVERHEAD);
+        assert!(open.initial_window >= open.max_packet);
+
+        let mut inbuf = vec![0u8; 100];
+        let mut outbuf = vec![0u8; 1000];
+        assert!(matches!(Runner::new_server(&mut inbuf, &mut outbuf),
+            Err(Error::NoRoom { .. })));
+
+        let mut inbuf = vec![0u8; config::RFC_MIN_BUFFER];
+        let mut outbuf = vec![0u8; 100];
+        assert!(matches!(Runner::new_server(&mut inbuf, &mut outbuf),
+            Err(Error::NoRoom { .. })));
+    }
+
+    #[test]
+    fn progress_sync() {
+        init_test_log();
+        let mut inbuf = vec![0u8; config::RFC_MIN_BUFFER];
+        let mut outbuf = vec![0u8; 1000];
+        let mut r = Runner::new_client(&mut inbuf, &mut outbuf).unwrap();
+        let mut n = 0;
+        while r.progress_sync(|_ev| Ok(())).unwrap() {
+            n += 1;
+            assert!(n < 10);
+        }
+        // version and kexinit
+        assert!(r.is_output_pending());
+
+        // doesn't spin once disconnected
+        r.disconnect(DisconnectReason::SSH_DISCONNECT_BY_APPLICATION, "bye").unwrap();
+        let mut defunct = false;
+        assert!(!r.progress_sync(|ev| {
+            defunct = matches!(ev, Event::Cli(CliEvent::Defunct));
+            Ok(())
+        }).unwrap());
+        assert!(defunct);
+    }
+
+    #[test]
+    fn set_ident() {
+        init_test_log();
+        let mut inbuf = vec![0u8; config::RFC_MIN_BUFFER];
+        let mut outbuf = vec![0u8; 1000];
+        let mut r = Runner::new_server(&mut inbuf, &mut outbuf).unwrap();
+        assert!(matches!(r.set_ident("SSH-1.99-x"), Err(Error::BadName)));
+        assert!(matches!(r.set_ident("SSH-2.0-x\r\n"), Err(Error::BadName)));
+        assert!(matches!(r.set_ident("SSH-2.0-My-App"), Err(Error::BadName)));
+        r.set_ident("SSH-2.0-MyApp_1.2 comment").unwrap();
+        r.progress().unwrap();
+        assert!(matches!(r.set_ident("SSH-2.0-later"), Err(Error::BadUsage { .. })));
+
+        let mut b = vec![0u8; 1000];
+        let l = r.output(&mut b).unwrap();
+        assert!(b[..l].starts_with(b"SSH-2.0-MyApp_1.2 comment\r\n"));
+    }
+
+    #[test]
+    fn send_debug() {
+        init_test_log();
+        let mut inbuf = vec![0u8; config::RFC_MIN_BUFFER];
+        let mut outbuf = vec![0u8; 1000];
+        let mut r = Runner::new_client(&mut inbuf, &mut outbuf).unwrap();
+        assert!(matches!(r.send_debug("early", false), Err(Error::BadUsage { .. })));
+        // sends version and kexinit
+        r.progress().unwrap();
+        let mut b = vec![0u8; 1000];
+        let l1 = r.output(&mut b).unwrap();
+        // not allowed during the first (strict) kex
+        assert!(matches!(r.send_debug("kex", false), Err(Error::BadUsage { .. })));
+        assert!(!r.is_output_pending());
+
+        // after kex
+        r.keys = encrypt::KeyState::new_test_keys();
+        r.conn.set_test_sess_id();
+        r.send_debug("hello", true).unwrap();
+        assert_eq!(r.keys.seq_encrypt.0, 1);
+        let l2 = r.output(&mut b[l1..]).unwrap();
+        let mut peer = encrypt::KeyState::new_test_peer_keys();
+        let payload = peer.test_recv_payload(&mut b[l1..l1 + l2]);
+        let p = sshwire::packet_from_bytes(payload, &Default::default()).unwrap();
+        let packets::Packet::DebugPacket(d) = p else {
+            panic!("unexpected {p:?}")
+        };
+        assert_eq!(d.message.as_str().unwrap(), "hello");
+        assert!(d.always_display);
+
+        r.disconnect(DisconnectReason::SSH_DISCONNECT_BY_APPLICATION, "bye").unwrap();
+        assert!(matches!(r.send_debug("late", false), Err(Error::BadUsage { .. })));
+    }
+
+    #[test]
+    /// Raw payloads are limited to stateless messages
+    fn send_raw() {
+        init_test_log();
+        let mut inbuf = vec![0u8; config::RFC_MIN_BUFFER];
+        let mut outbuf = vec![0u8; 1000];
+        let mut r = Runner::new_client(&mut inbuf, &mut outbuf).unwrap();
+        r.keys = encrypt::KeyState::new_test_keys();
+        r.conn.set_test_sess_id();
+
+        let mut payload = [0u8; 100];
+        let ign = packets::Ignore { data: sshwire::BinString(b"relayed") };
+        let l = sshwire::write_ssh(&mut payload, &packets::Packet::from(ign)).unwrap();
+        r.send_raw(&payload[..l]).unwrap();
+        assert_eq!(r.keys.seq_encrypt.0, 1);
+
+        let close = packets::ChannelClose { num: 0 };
+        let l = sshwire::write_ssh(&mut payload, &packets::Packet::from(close)).unwrap();
+        assert!(matches!(r.send_raw(&payload[..l]), Err(Error::BadUsage { .. })));
+        assert!(matches!(r.send_raw(&[packets::MessageNumber::SSH_MSG_NEWKEYS as u8]),
+            Err(Error::BadUsage { .. })));
+        assert!(matches!(r.send_raw(&[]), Err(Error::BadUsage { .. })));
+        assert_eq!(r.keys.seq_encrypt.0, 1);
+    }
+
+    #[test]
+    /// An unknown message number is answered with SSH_MSG_UNIMPLEMENTED
+    fn unknown_packet() {
+        init_test_log();
+        let mut inbuf = vec![0u8; config::RFC_MIN_BUFFER];
+        let mut outbuf = vec![0u8; 1000];
+        let mut r = Runner::new_client(&mut inbuf, &mut outbuf).unwrap();
+        // sends version and kexinit
+        r.progress().unwrap();
+        let mut b = vec![0u8; 1000];
+        let l1 = r.output(&mut b).unwrap();
+
+        // version then two cleartext packets with message number 200,
+        // length, padding length, message number, padding
+        let mut wire = b"SSH-2.0-x\r\n".to_vec();
+        for _ in 0..2 {
+            wire.extend_from_slice(&[0, 0, 0, 12, 10, 200]);
+            wire.extend_from_slice(&[0; 10]);
+        }
+        let mut w = wire.as_slice();
+        while !w.is_empty() {
+            let n = r.input(w).unwrap();
+            w = &w[n..];
+            while !matches!(r.progress().unwrap(), Event::None) {}
+        }
+
+        let mut seqs = vec![];
+        let mut off = l1;
+        while let Ok(l) = r.output(&mut b[off..]) {
+            if l == 0 {
+                break
+            }
+            off += l;
+        }
+        let mut o = &b[l1..off];
+        while !o.is_empty() {
+            let plen = u32::from_be_bytes(o[..4].try_into().unwrap()) as usize;
+            let p: packets::Packet = sshwire::read_ssh(&o[5..4 + plen], None).unwrap();
+            let packets::Packet::Unimplemented(u) = p else { panic!() };
+            seqs.push(u.seq);
+            o = &o[4 + plen..];
+        }
+        assert_eq!(seqs, [0, 1]);
+    }
+
+    #[test]
+    /// input() accepts part of a buffer when a payload is pending
+    fn input_backpressure() {
+        init_test_log();
+        let mut inbuf = vec![0u8; config::RFC_MIN_BUFFER];
+        let mut outbuf = vec![0u8; 1000];
+        let mut r = Runner::new_client(&mut inbuf, &mut outbuf).unwrap();
+        r.progress().unwrap();
+
+        // version then two cleartext Ignore packets
+        let mut wire = b"SSH-2.0-x\r\n".to_vec();
+        let mut pbuf = vec![0u8; 500];
+        let mut out = traffic::TrafOut::new(&mut pbuf);
+        let mut keys = encrypt::KeyState::new_cleartext();
+        for _ in 0..2 {
+            out.send_packet(packets::Ignore { data: sshwire::BinString(&[1, 2, 3]) }.into(),
+                &mut keys).unwrap();
+        }
+        let mut b = vec![0u8; 500];
+        let l = out.output(&mut b);
+        wire.extend_from_slice(&b[..l]);
+
+        let mut w = wire.as_slice();
+        let mut rounds = 0;
+        while !w.is_empty() {
+            assert!(r.is_input_ready());
+            let n = r.input(w).unwrap();
+            assert!(n > 0 && n <= w.len());
+            w = &w[n..];
+            while !matches!(r.progress().unwrap(), Event::None) {}
+            rounds += 1;
+            assert!(rounds < 10);
+        }
+        // the second packet waits for the first to be handled
+        assert!(rounds >= 2);
+        assert!(r.is_input_ready());
+    }
+
+    #[test]
+    /// A large channel_send() is split into max_packet sized packets
+    fn send_split() {
+        init_test_log();
+        let mut inbuf = vec![0u8; config::RFC_MIN_BUFFER];
+        let mut outbuf = vec![0u8; 1000];
+        let mut r = Runner::new_client(&mut inbuf, &mut outbuf).unwrap();
+        r.keys = encrypt::KeyState::new_test_keys();
+
+        let (num, _) = r.conn.channels.open(packets::ChannelOpenType::Session).unwrap();
+        let conf = packets::ChannelOpenConfirmation {
+            num: num.0,
+            sender_num: 9,
+            initial_window: 250,
+            max_packet: 100,
+        };
+        let mut s = r.traf_out.sender(&mut r.keys);
+        let _ = r.conn.channels.dispatch(conf.into(), &mut s).unwrap();
+        let ch = ChanHandle(num);
+
+        // limited by the window
+        let data = [5u8; 400];
+        assert_eq!(r.keys.seq_encrypt.0, 0);
+        assert_eq!(r.channel_send(&ch, ChanData::Normal, &data).unwrap(), 250);
+        assert_eq!(r.channel_send(&ch, ChanData::Normal, &data).unwrap(), 0);
+
+        // one packet for each max_packet
+        assert_eq!(r.keys.seq_encrypt.0, 3);
+        let mut b = vec![0u8; 1000];
+        let l = r.output(&mut b).unwrap();
+        assert!(l > 250 + 3 * 4);
+    }
+
+    #[test]
+    fn disconnect() {
+        init_test_log();
+        let mut inbuf = vec![0u8; config::RFC_MIN_BUFFER];
+        let mut outbuf = vec![0u8; 1000];
+        let mut r = Runner::new_client(&mut inbuf, &mut outbuf).unwrap();
+        // sends version and kexinit
+        r.progress().unwrap();
+        let mut b = vec![0u8; 1000];
+        let l1 = r.output(&mut b).unwrap();
+
+        r.disconnect(DisconnectReason::SSH_DISCONNECT_BY_APPLICATION, "bye").unwrap();
+        let l2 = r.output(&mut b[l1..]).unwrap();
+        assert!(l2 > 0);
+        // cleartext, the payload follows the length and padding length
+        assert_eq!(b[l1 + 5], packets::MessageNumber::SSH_MSG_DISCONNECT as u8);
+        assert_eq!(&b[l1 + 6..l1 + 10], &11u32.to_be_bytes());
+
+        assert!(matches!(r.progress().unwrap(), Event::Cli(CliEvent::Defunct)));
+        assert!(matches!(r.input(b"SSH-2.0-x\r\n"), Err(Error::ChannelEOF)));
+    }
+
+    /// A bad version line fails without a binary disconnect packet
+    #[test]
+    fn bad_version_no_disconnect() {
+        init_test_log();
+        let mut inbuf = vec![0u8; config::RFC_MIN_BUFFER];
+        let mut outbuf = vec![0u8; 1000];
+        let mut r = Runner::new_client(&mut inbuf, &mut outbuf).unwrap();
+        // sends version and kexinit
+        r.progress().unwrap();
+        let mut b = vec![0u8; 1000];
+        r.output(&mut b).unwrap();
+
+        // too long
+        let mut v = b"SSH-2.0-".to_vec();
+        v.resize(400, b'a');
+        assert!(matches!(r.input(&v), Err(Error::SSHProto { .. })));
+        assert!(!r.is_output_pending());
+    }
 }
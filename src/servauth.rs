@@ -9,6 +9,7 @@ use crate::*;
 use packets::{AuthMethod, Userauth60, UserauthPkOk, Packet, UserauthRequest};
 use sshwire::{BinString, Blob};
 use traffic::TrafSend;
+use sign::SigType;
 use kex::SessId;
 use event::{CliEvent, ServEventId};
 
@@ -37,6 +38,13 @@ pub(crate) struct ServAuth {
     ///
     /// Enabled by default
     pub method_pubkey: bool,
+
+    /// Failed attempts so far
+    failures: u32,
+    /// Whether a denial of the current request counts as a failure
+    pending_count: bool,
+    /// Disconnect after this many failed attempts
+    pub max_tries: u32,
 }
 
 impl ServAuth {
@@ -47,6 +55,9 @@ impl ServAuth {
             username: None,
             method_password: true,
             method_pubkey: true,
+            failures: 0,
+            pending_count: true,
+            max_tries: config::DEFAULT_MAX_AUTH_TRIES,
         }
     }
 
@@ -79,6 +90,7 @@ impl ServAuth {
         }
         debug_assert!(self.username.is_some());
 
+        let none = matches!(p.method, AuthMethod::None);
         let ev = match p.method {
             AuthMethod::Password(_) if self.method_password => {
                 DispatchEvent::ServEvent(ServEventId::PasswordAuth)
@@ -93,22 +105,40 @@ impl ServAuth {
             }
         };
 
+        // The initial "none" request is a query for methods, it doesn't
+        // count as a failed attempt.
+        let count = !none || self.tried_first;
+
         // FirstAuth would have been returned by now.
         self.tried_first = true;
 
         // Auth method isn't supported, send failure straight away.
         // No concerns about timing leaks since it is independent of the username.
         if ev.is_none() {
-            self.send_failure(s)?;
+            self.send_failure(count, s)?;
         }
+        self.pending_count = count;
 
         Ok(ev)
     }
 
-    fn send_failure(&self, s: &mut TrafSend) -> Result<()> {
+    /// Sends a failure response.
+    ///
+    /// Returns [`Error::AuthTriesExceeded`] once `max_tries` counted failures
+    /// have been sent.
+    fn send_failure(&mut self, count: bool, s: &mut TrafSend) -> Result<()> {
         let methods = self.avail_methods();
         let methods = (&methods).into();
-        s.send(packets::UserauthFailure { methods, partial: false })
+        s.send(packets::UserauthFailure { methods, partial: false })?;
+
+        if count {
+            self.failures += 1;
+            if self.failures >= self.max_tries {
+                info!("Too many auth failures ({})", self.failures);
+                return Err(Error::AuthTriesExceeded)
+            }
+        }
+        Ok(())
     }
 
     fn request_pubkey(&mut self, mut p: packets::UserauthRequest,
@@ -138,12 +168,27 @@ impl ServAuth {
     pub fn resume_request(&mut self, allow: bool, s: &mut TrafSend) -> Result<()> {
         if allow {
             self.authed = true;
-            s.send(packets::UserauthSuccess {})
+            self.failures = 0;
+            s.send(packets::UserauthSuccess {})?;
+            s.enable_delayed_compression();
+            Ok(())
         } else {
-            self.send_failure(s)
+            self.send_failure(self.pending_count, s)
         }
     }
 
+    /// Sends a `UserauthBanner`, only valid after a request has been
+    /// received and before auth succeeds.
+    pub fn send_banner(&self, message: &str, s: &mut TrafSend) -> Result<()> {
+        if self.authed || self.username.is_none() {
+            return error::BadUsage.fail()
+        }
+        s.send(packets::UserauthBanner {
+            message: message.into(),
+            lang: "".into(),
+        })
+    }
+
     pub fn resume_pkok(&self, p: Packet, s: &mut TrafSend)
     -> Result<()> {
         if let Packet::UserauthRequest(
@@ -162,11 +207,6 @@ impl ServAuth {
         // Remove the signature from the packet - the signature message includes
         // packet without that signature part.
 
-        let sig_type = match sig.sig_type() {
-            Ok(t) => t,
-            Err(_) => return false,
-        };
-
         let key = match &p.method {
             AuthMethod::PubKey(m) => &m.pubkey.0,
             _ => {
@@ -175,6 +215,14 @@ impl ServAuth {
             }
         };
 
+        // The algorithm is determined by the key, certificates
+        // use a plain signature type.
+        let sig_type = match Signature::sig_name_for_pubkey(key)
+            .and_then(SigType::from_name) {
+            Ok(t) => t,
+            Err(_) => return false,
+        };
+
         let msg = auth::AuthSigMsg::new(p.clone(), sess_id);
         match sig_type.verify(key, &msg, &sig) {
             Ok(()) => true,
@@ -197,3 +245,115 @@ impl ServAuth {
         l
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::servauth::*;
+    use crate::encrypt::TestSend;
+    use crate::sunsetlog::*;
+
+    #[test]
+    fn max_tries() {
+        init_test_log();
+        let mut buf = [0u8; 3000];
+        let mut ts = TestSend::with_buf(&mut buf);
+        let mut s = ts.sender();
+        let sess_id = SessId::from_slice(&[1u8; 32]).unwrap();
+        let req = |method| UserauthRequest {
+            username: "matt".into(),
+            service: SSH_SERVICE_CONNECTION,
+            method,
+        };
+        let none = || req(AuthMethod::None);
+        let password = || req(AuthMethod::Password(packets::MethodPassword {
+            change: false,
+            password: "pw".into(),
+        }));
+
+        let mut auth = ServAuth::new();
+        auth.max_tries = 3;
+        // the initial query isn't counted
+        let ev = auth.request(&sess_id, &mut s, none()).unwrap();
+        assert!(matches!(ev, DispatchEvent::ServEvent(ServEventId::FirstAuth)));
+        auth.resume_request(false, &mut s).unwrap();
+
+        for _ in 0..2 {
+            let _ = auth.request(&sess_id, &mut s, password()).unwrap();
+            auth.resume_request(false, &mut s).unwrap();
+        }
+        // later none requests are counted
+        let r = auth.request(&sess_id, &mut s, none());
+        assert!(matches!(r, Err(Error::AuthTriesExceeded)));
+    }
+
+    #[test]
+    fn banner() {
+        init_test_log();
+        let mut buf = [0u8; 3000];
+        let mut ts = TestSend::with_buf(&mut buf);
+        let mut s = ts.sender();
+        let sess_id = SessId::from_slice(&[1u8; 32]).unwrap();
+        let none = UserauthRequest {
+            username: "matt".into(),
+            service: SSH_SERVICE_CONNECTION,
+            method: AuthMethod::None,
+        };
+
+        let mut auth = ServAuth::new();
+        // before userauth has started
+        assert!(auth.send_banner("hello", &mut s).is_err());
+        let _ = auth.request(&sess_id, &mut s, none).unwrap();
+        auth.send_banner("hello", &mut s).unwrap();
+        auth.resume_request(true, &mut s).unwrap();
+        // after success
+        assert!(auth.send_banner("hello", &mut s).is_err());
+    }
+
+    /// A request with a user certificate is verified with the certified key
+    #[test]
+    #[cfg(feature = "user-cert")]
+    fn pubkey_cert() {
+        init_test_log();
+        let mut buf = [0u8; 3000];
+        let mut ts = TestSend::with_buf(&mut buf);
+        let mut s = ts.sender();
+        let sess_id = SessId::from_slice(&[1u8; 32]).unwrap();
+
+        let key = SignKey::generate(KeyType::Ed25519, None).unwrap();
+        let ca = SignKey::generate(KeyType::Ed25519, None).unwrap();
+        let mut certbuf = vec![0u8; 1000];
+        let cert = crate::cert::tests::make_cert(&mut certbuf, &key, &ca,
+            SSH_CERT_TYPE_USER, &["matt"], (0, u64::MAX));
+        let cert: PubKey = sshwire::read_ssh(cert, None).unwrap();
+
+        let req = |sig| {
+            let mut m = packets::MethodPubKey::new(cert.clone(), sig).unwrap();
+            m.sig_present = true;
+            UserauthRequest {
+                username: "matt".into(),
+                service: SSH_SERVICE_CONNECTION,
+                method: AuthMethod::PubKey(m),
+            }
+        };
+        let msg = auth::AuthSigMsg::new(req(None), &sess_id);
+        let sig = key.sign(&msg).unwrap();
+        let other_sig = ca.sign(&msg).unwrap();
+
+        let mut auth = ServAuth::new();
+        let ev = auth.request(&sess_id, &mut s, req(Some(&sig))).unwrap();
+        assert!(matches!(ev, DispatchEvent::ServEvent(ServEventId::PubkeyAuth { real_sig: true })));
+        auth.resume_request(false, &mut s).unwrap();
+
+        // as received from the wire
+        let mut reqbuf = vec![0u8; 1000];
+        let l = sshwire::write_ssh(&mut reqbuf, &req(Some(&sig))).unwrap();
+        let wire_req: UserauthRequest = sshwire::read_ssh(&reqbuf[..l], None).unwrap();
+        let ev = auth.request(&sess_id, &mut s, wire_req).unwrap();
+        assert!(matches!(ev, DispatchEvent::ServEvent(ServEventId::PubkeyAuth { real_sig: true })));
+        auth.resume_request(false, &mut s).unwrap();
+
+        // signed by the CA rather than the certified key
+        let ev = auth.request(&sess_id, &mut s, req(Some(&other_sig))).unwrap();
+        assert!(ev.is_none());
+    }
+}
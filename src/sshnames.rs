@@ -30,6 +30,8 @@ pub const SSH_NAME_ED25519: &str = "ssh-ed25519";
 pub const SSH_NAME_RSA_SHA256: &str = "rsa-sha2-256";
 /// [RFC4253](https://tools.ietf.org/html/rfc4253). Deprecated for signatures but is a valid key type.
 pub const SSH_NAME_RSA: &str = "ssh-rsa";
+/// OpenSSH [PROTOCOL.certkeys](https://cvsweb.openbsd.org/src/usr.bin/ssh/PROTOCOL.certkeys?annotate=HEAD)
+pub const SSH_NAME_ED25519_CERT: &str = "ssh-ed25519-cert-v01@openssh.com";
 
 /// [RFC4344](https://tools.ietf.org/html/rfc4344)
 pub const SSH_NAME_AES256_CTR: &str = "aes256-ctr";
@@ -46,6 +48,12 @@ pub const SSH_NAME_HMAC_SHA256: &str = "hmac-sha2-256";
 /// [RFC4253](https://tools.ietf.org/html/rfc4253)
 pub const SSH_NAME_NONE: &str = "none";
 
+/// [RFC4253](https://tools.ietf.org/html/rfc4253)
+pub const SSH_NAME_ZLIB: &str = "zlib";
+/// OpenSSH [PROTOCOL](https://cvsweb.openbsd.org/src/usr.bin/ssh/PROTOCOL?annotate=HEAD).
+/// Compression is delayed until after authentication.
+pub const SSH_NAME_ZLIB_OPENSSH: &str = "zlib@openssh.com";
+
 /// [RFC4252](https://tools.ietf.org/html/rfc4252)
 pub const SSH_SERVICE_USERAUTH: &str = "ssh-userauth";
 /// [RFC4254](https://tools.ietf.org/html/rfc4254)
@@ -67,9 +75,32 @@ pub const SSH_EXTENDED_DATA_STDERR: u32 = 1;
 /// [RFC8308](https://tools.ietf.org/html/rfc8308) Extension Negotiation
 pub const SSH_EXT_SERVER_SIG_ALGS: &str = "server-sig-algs";
 
-/// [RFC4254](https://tools.ietf.org/html/rfc4254)
+/// [RFC4253](https://tools.ietf.org/html/rfc4253) Section 11.1
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisconnectReason {
+    SSH_DISCONNECT_HOST_NOT_ALLOWED_TO_CONNECT = 1,
+    SSH_DISCONNECT_PROTOCOL_ERROR = 2,
+    SSH_DISCONNECT_KEY_EXCHANGE_FAILED = 3,
+    SSH_DISCONNECT_RESERVED = 4,
+    SSH_DISCONNECT_MAC_ERROR = 5,
+    SSH_DISCONNECT_COMPRESSION_ERROR = 6,
+    SSH_DISCONNECT_SERVICE_NOT_AVAILABLE = 7,
+    SSH_DISCONNECT_PROTOCOL_VERSION_NOT_SUPPORTED = 8,
+    SSH_DISCONNECT_HOST_KEY_NOT_VERIFIABLE = 9,
+    SSH_DISCONNECT_CONNECTION_LOST = 10,
+    SSH_DISCONNECT_BY_APPLICATION = 11,
+    SSH_DISCONNECT_TOO_MANY_CONNECTIONS = 12,
+    SSH_DISCONNECT_AUTH_CANCELLED_BY_USER = 13,
+    SSH_DISCONNECT_NO_MORE_AUTH_METHODS_AVAILABLE = 14,
+    SSH_DISCONNECT_ILLEGAL_USER_NAME = 15,
+}
+
+/// Channel open failure reason codes
+///
+/// [RFC4254](https://tools.ietf.org/html/rfc4254#section-5.1)
 #[allow(non_camel_case_types)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ChanFail {
     SSH_OPEN_ADMINISTRATIVELY_PROHIBITED = 1,
     SSH_OPEN_CONNECT_FAILED = 2,
@@ -77,6 +108,44 @@ pub enum ChanFail {
     SSH_OPEN_RESOURCE_SHORTAGE = 4,
 }
 
+impl ChanFail {
+    /// Returns the reason code for a received `SSH_MSG_CHANNEL_OPEN_FAILURE`
+    pub fn from_u32(reason: u32) -> Option<Self> {
+        match reason {
+            1 => Some(Self::SSH_OPEN_ADMINISTRATIVELY_PROHIBITED),
+            2 => Some(Self::SSH_OPEN_CONNECT_FAILED),
+            3 => Some(Self::SSH_OPEN_UNKNOWN_CHANNEL_TYPE),
+            4 => Some(Self::SSH_OPEN_RESOURCE_SHORTAGE),
+            _ => None,
+        }
+    }
+
+    /// A description sent in `SSH_MSG_CHANNEL_OPEN_FAILURE`
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::SSH_OPEN_ADMINISTRATIVELY_PROHIBITED => "Administratively prohibited",
+            Self::SSH_OPEN_CONNECT_FAILED => "Connect failed",
+            Self::SSH_OPEN_UNKNOWN_CHANNEL_TYPE => "Unknown channel type",
+            Self::SSH_OPEN_RESOURCE_SHORTAGE => "Resource shortage",
+        }
+    }
+}
+
+/// Certificate type for a user key.
+///
+/// OpenSSH [PROTOCOL.certkeys](https://cvsweb.openbsd.org/src/usr.bin/ssh/PROTOCOL.certkeys?annotate=HEAD)
+pub const SSH_CERT_TYPE_USER: u32 = 1;
+/// Certificate type for a host key
+pub const SSH_CERT_TYPE_HOST: u32 = 2;
+
+/// Signal names for `signal` and `exit-signal` requests, without a "SIG" prefix.
+///
+/// [RFC4254 Section 6.10](https://tools.ietf.org/html/rfc4254#section-6.10)
+pub const SSH_SIGNALS: &[&str] = &[
+    "ABRT", "ALRM", "FPE", "HUP", "ILL", "INT", "KILL",
+    "PIPE", "QUIT", "SEGV", "TERM", "USR1", "USR2",
+];
+
 /// SSH agent message numbers
 ///
 /// [draft-miller-ssh-agent](https://datatracker.ietf.org/doc/html/draft-miller-ssh-agent-14#section-5.1)
@@ -0,0 +1,296 @@
+//! OpenSSH `SSHSIG` signatures over arbitrary data.
+//!
+//! These are the signatures made by `ssh-keygen -Y sign`, independent of
+//! an SSH connection. The message is hashed and framed with a namespace,
+//! so that a signature for one purpose (such as `"file"` or `"git"`) can't
+//! be used for another. See [PROTOCOL.sshsig](https://cvsweb.openbsd.org/src/usr.bin/ssh/PROTOCOL.sshsig?annotate=HEAD).
+
+#[allow(unused_imports)]
+use {
+    crate::error::{Error, Result, TrapBug},
+    log::{debug, error, info, log, trace, warn},
+};
+
+use digest::Digest;
+
+use crate::*;
+use packets::{PubKey, Signature};
+use sshwire::{BinString, Blob, SSHDecode, SSHEncode, SSHSink, SSHSource, TextString};
+use sshwire::{WireError, WireResult};
+
+/// Preamble for signed data and signature blobs
+pub const SSHSIG_MAGIC: &[u8; 6] = b"SSHSIG";
+
+const SIG_VERSION: u32 = 1;
+
+const ARMOR_BEGIN: &str = "-----BEGIN SSH SIGNATURE-----";
+const ARMOR_END: &str = "-----END SSH SIGNATURE-----";
+const ARMOR_WIDTH: usize = 70;
+
+/// Hash algorithm applied to the message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlg {
+    Sha256,
+    /// Used by default by `ssh-keygen`
+    Sha512,
+}
+
+impl HashAlg {
+    /// Returns the algorithm name, `"sha256"` or `"sha512"`
+    pub fn name(&self) -> &'static str {
+        match self {
+            HashAlg::Sha256 => "sha256",
+            HashAlg::Sha512 => "sha512",
+        }
+    }
+
+    /// Returns [`Error::BadName`] for unknown names
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "sha256" => Ok(HashAlg::Sha256),
+            "sha512" => Ok(HashAlg::Sha512),
+            _ => Err(Error::BadName),
+        }
+    }
+}
+
+/// The data signed for a `SSHSIG` signature.
+///
+/// Pass this to [`PubKey::verify()`] to check a signature over `message`.
+pub struct SignedData<'a> {
+    /// Must not be empty
+    pub namespace: &'a str,
+    pub hash_alg: HashAlg,
+    pub message: &'a [u8],
+}
+
+impl SSHEncode for SignedData<'_> {
+    fn enc(&self, s: &mut dyn SSHSink) -> WireResult<()> {
+        SSHSIG_MAGIC.enc(s)?;
+        self.namespace.enc(s)?;
+        // reserved
+        BinString(&[]).enc(s)?;
+        self.hash_alg.name().enc(s)?;
+        match self.hash_alg {
+            HashAlg::Sha256 => {
+                BinString(&sha2::Sha256::digest(self.message)).enc(s)
+            }
+            HashAlg::Sha512 => {
+                BinString(&sha2::Sha512::digest(self.message)).enc(s)
+            }
+        }
+    }
+}
+
+/// A `SSHSIG` signature blob, as written by `ssh-keygen -Y sign` inside
+/// the armoring.
+#[derive(Debug)]
+pub struct SshSig<'a> {
+    pub pubkey: Blob<PubKey<'a>>,
+    pub namespace: TextString<'a>,
+    pub reserved: BinString<'a>,
+    pub hash_alg: &'a str,
+    pub signature: Blob<Signature<'a>>,
+}
+
+impl SSHEncode for SshSig<'_> {
+    fn enc(&self, s: &mut dyn SSHSink) -> WireResult<()> {
+        SSHSIG_MAGIC.enc(s)?;
+        SIG_VERSION.enc(s)?;
+        self.pubkey.enc(s)?;
+        self.namespace.enc(s)?;
+        self.reserved.enc(s)?;
+        self.hash_alg.enc(s)?;
+        self.signature.enc(s)
+    }
+}
+
+impl<'de> SSHDecode<'de> for SshSig<'de> {
+    fn dec<S>(s: &mut S) -> WireResult<Self>
+    where S: SSHSource<'de> {
+        let magic: [u8; 6] = SSHDecode::dec(s)?;
+        let version = u32::dec(s)?;
+        if &magic != SSHSIG_MAGIC || version != SIG_VERSION {
+            return Err(WireError::PacketWrong)
+        }
+        Ok(Self {
+            pubkey: SSHDecode::dec(s)?,
+            namespace: SSHDecode::dec(s)?,
+            reserved: SSHDecode::dec(s)?,
+            hash_alg: SSHDecode::dec(s)?,
+            signature: SSHDecode::dec(s)?,
+        })
+    }
+}
+
+impl SignKey {
+    /// Creates a `SSHSIG` signature over `message`, the same as
+    /// `ssh-keygen -Y sign -n namespace`.
+    ///
+    /// The binary signature blob is written to `buf`, it can be
+    /// converted to the usual text form with [`armor()`].
+    /// Returns [`Error::BadUsage`] for an empty namespace or agent keys.
+    pub fn sign_sshsig<'b>(&self, namespace: &str, hash_alg: HashAlg,
+        message: &[u8], buf: &'b mut [u8]) -> Result<&'b [u8]> {
+        if namespace.is_empty() || self.is_agent() {
+            return error::BadUsage.fail()
+        }
+
+        let sig = self.sign(&SignedData { namespace, hash_alg, message })?;
+        let sig = SshSig {
+            pubkey: Blob(self.pubkey()),
+            namespace: namespace.into(),
+            reserved: BinString(&[]),
+            hash_alg: hash_alg.name(),
+            signature: Blob((&sig).into()),
+        };
+        let l = sshwire::write_ssh(buf, &sig)?;
+        Ok(&buf[..l])
+    }
+}
+
+/// Verifies a binary `SSHSIG` signature blob over `message`.
+///
+/// Returns the signing key on success. The caller must check that it is
+/// an allowed signer, as `ssh-keygen -Y verify` does with an
+/// allowed signers file. Fails with [`Error::BadSig`].
+pub fn verify<'s>(sig: &'s [u8], namespace: &str, message: &[u8]) -> Result<PubKey<'s>> {
+    let sig: SshSig = sshwire::read_ssh(sig, None).map_err(|_| Error::BadSig)?;
+    if sig.namespace.as_str()? != namespace {
+        debug!("SSHSIG namespace differs, {:?}", sig.namespace);
+        return Err(Error::BadSig)
+    }
+    let hash_alg = HashAlg::from_name(sig.hash_alg).map_err(|_| Error::BadSig)?;
+
+    let data = SignedData { namespace, hash_alg, message };
+    sig.pubkey.0.verify(&data, &sig.signature.0)?;
+    Ok(sig.pubkey.0)
+}
+
+/// Converts a binary signature blob to the `-----BEGIN SSH SIGNATURE-----`
+/// text form, written to `buf`.
+pub fn armor<'b>(sig: &[u8], buf: &'b mut [u8]) -> Result<&'b str> {
+    use base64ct::{Base64, Encoder, LineEnding};
+
+    let (head, rest) = buf.split_at_mut_checked(ARMOR_BEGIN.len() + 1)
+        .ok_or(error::NoRoom.build())?;
+    head[..ARMOR_BEGIN.len()].copy_from_slice(ARMOR_BEGIN.as_bytes());
+    head[ARMOR_BEGIN.len()] = b'\n';
+
+    let mut enc = Encoder::<Base64>::new_wrapped(rest, ARMOR_WIDTH, LineEnding::LF)
+        .map_err(|_| error::NoRoom.build())?;
+    enc.encode(sig).map_err(|_| error::NoRoom.build())?;
+    let (b64, rest) = enc.finish_with_remaining().map_err(|_| error::NoRoom.build())?;
+    let b64_len = b64.len();
+
+    let tail = rest.get_mut(..ARMOR_END.len() + 2).ok_or(error::NoRoom.build())?;
+    tail[0] = b'\n';
+    tail[1..ARMOR_END.len() + 1].copy_from_slice(ARMOR_END.as_bytes());
+    tail[ARMOR_END.len() + 1] = b'\n';
+
+    let l = head.len() + b64_len + tail.len();
+    core::str::from_utf8(&buf[..l]).trap()
+}
+
+/// Converts an armored `-----BEGIN SSH SIGNATURE-----` signature to
+/// a binary blob in `buf`, for [`verify()`].
+///
+/// Fails with [`Error::BadSig`] if malformed.
+pub fn dearmor<'b>(sig: &str, buf: &'b mut [u8]) -> Result<&'b [u8]> {
+    use base64ct::{Base64, Encoding};
+
+    let b64 = sig.trim()
+        .strip_prefix(ARMOR_BEGIN)
+        .and_then(|s| s.strip_suffix(ARMOR_END))
+        .ok_or(Error::BadSig)?;
+
+    // Remove line breaks
+    let mut l = 0;
+    for c in b64.bytes().filter(|c| !c.is_ascii_whitespace()) {
+        *buf.get_mut(l).ok_or(error::NoRoom.build())? = c;
+        l += 1;
+    }
+    let b = Base64::decode_in_place(&mut buf[..l]).map_err(|_| Error::BadSig)?;
+    Ok(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sshsig::*;
+    use crate::sunsetlog::*;
+    use crate::{KeyType, SignKey};
+    use packets::Signature;
+
+    #[test]
+    fn verify_signed_data() {
+        init_test_log();
+        let k = SignKey::generate(KeyType::Ed25519, None).unwrap();
+        let other = SignKey::generate(KeyType::Ed25519, None).unwrap();
+        // longer than a fixed size signing buffer
+        let message = vec![5u8; 5000];
+        let data = SignedData { namespace: "file", hash_alg: HashAlg::Sha512, message: &message };
+        let sig = k.sign(&data).unwrap();
+        let sig = Signature::from(&sig);
+
+        k.pubkey().verify(&data, &sig).unwrap();
+        assert!(other.pubkey().verify(&data, &sig).is_err());
+
+        let data2 = SignedData { namespace: "git", .. data };
+        assert!(k.pubkey().verify(&data2, &sig).is_err());
+        let data2 = SignedData { hash_alg: HashAlg::Sha256, .. data };
+        assert!(k.pubkey().verify(&data2, &sig).is_err());
+        let data2 = SignedData { message: &message[1..], .. data };
+        assert!(k.pubkey().verify(&data2, &sig).is_err());
+
+        // raw messages without framing
+        let sig = k.sign(&b"raw".as_slice()).unwrap();
+        let sig = Signature::from(&sig);
+        k.pubkey().verify(&b"raw".as_slice(), &sig).unwrap();
+        assert!(k.pubkey().verify(&b"raw2".as_slice(), &sig).is_err());
+    }
+
+    #[test]
+    fn sshsig() {
+        init_test_log();
+        let k = SignKey::generate(KeyType::Ed25519, None).unwrap();
+        let message = b"some data";
+        let mut buf = [0u8; 300];
+        let sig = k.sign_sshsig("file", HashAlg::Sha256, message, &mut buf).unwrap();
+        assert_eq!(verify(sig, "file", message).unwrap(), k.pubkey());
+        assert!(matches!(verify(sig, "git", message), Err(Error::BadSig)));
+        assert!(matches!(verify(sig, "file", b"other data"), Err(Error::BadSig)));
+        assert!(matches!(verify(&sig[1..], "file", message), Err(Error::BadSig)));
+        assert!(matches!(k.sign_sshsig("", HashAlg::Sha256, message, &mut [0u8; 300]),
+            Err(Error::BadUsage { .. })));
+
+        let mut abuf = [0u8; 600];
+        let a = armor(sig, &mut abuf).unwrap();
+        assert!(a.starts_with("-----BEGIN SSH SIGNATURE-----\n"));
+        assert!(a.ends_with("\n-----END SSH SIGNATURE-----\n"));
+        assert!(a.lines().all(|l| l.len() <= 70));
+        let mut dbuf = [0u8; 600];
+        assert_eq!(dearmor(a, &mut dbuf).unwrap(), sig);
+    }
+
+    #[test]
+    /// Signature from `ssh-keygen -Y sign -n file`
+    fn sshsig_openssh() {
+        init_test_log();
+        let sig = "-----BEGIN SSH SIGNATURE-----
+U1NIU0lHAAAAAQAAADMAAAALc3NoLWVkMjU1MTkAAAAgDH1rc0StvyfgvbbpWLwK0kdLuu
+Ssb9HvjD09Vz+RkeEAAAAEZmlsZQAAAAAAAAAGc2hhNTEyAAAAUwAAAAtzc2gtZWQyNTUx
+OQAAAEBQZaqziNqYKHYtqWmzjwvSxZ/BVLjJUgbN9+OOdjlE65ywQjp8+toizP0qX00O5X
+zYr7ZP52KomB3j2y9nUi8D
+-----END SSH SIGNATURE-----
+";
+        let mut buf = [0u8; 300];
+        let b = dearmor(sig, &mut buf).unwrap();
+        let k = verify(b, "file", b"hello sshsig\n").unwrap();
+        assert_eq!(k.fingerprint_sha256().unwrap(),
+            "SHA256:SFcD92wTLa91Rp7Gt+UX1fLLK5pYc0Sal13LbSgVrE4");
+        assert!(verify(b, "file", b"hello sshsig").is_err());
+        // armoring gives the same text
+        let mut abuf = [0u8; 600];
+        assert_eq!(armor(b, &mut abuf).unwrap(), sig);
+    }
+}
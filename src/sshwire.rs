@@ -119,7 +119,7 @@ pub fn packet_from_bytes<'a>(b: &'a [u8], ctx: &ParseContext) -> Result<Packet<'
     let mut s = DecodeBytes { input: b, parse_ctx: ctx };
     let p = Packet::dec(&mut s)?;
 
-    if s.input.len() != 0 && !s.ctx().seen_unknown {
+    if s.input.len() != 0 && !s.ctx().seen_unknown && !s.ctx().allow_trailing {
         // No length check if the packet had an unknown variant
         // - it skipped parsing the remainder of the packet.
         Err(Error::WrongPacketLength)
@@ -128,6 +128,14 @@ pub fn packet_from_bytes<'a>(b: &'a [u8], ctx: &ParseContext) -> Result<Packet<'
     }
 }
 
+/// Decodes a [`Packet`] from arbitrary input, for fuzzing.
+///
+/// Malformed input returns an error, it should never panic.
+#[cfg(feature = "fuzzing")]
+pub fn fuzz_decode_packet(b: &[u8]) {
+    let _ = packet_from_bytes(b, &ParseContext::default());
+}
+
 pub fn read_ssh<'a, T: SSHDecode<'a>>(b: &'a [u8], ctx: Option<ParseContext>) -> Result<T> {
     let mut s = DecodeBytes { input: b, parse_ctx: ctx.unwrap_or_default() };
     Ok(T::dec(&mut s)?)
@@ -171,6 +179,39 @@ pub fn hash_ser(hash_ctx: &mut impl SSHWireDigestUpdate,
     Ok(())
 }
 
+/// Encodes `value` inside a SSH `string`, with a `u32` length prefix.
+///
+/// Used by `#[sshwire(length_prefixed)]`, and [`Blob`].
+pub fn enc_length_prefixed(value: &dyn SSHEncode, s: &mut dyn SSHSink) -> WireResult<()> {
+    let len: u32 = length_enc(value)?;
+    len.enc(s)?;
+    value.enc(s)
+}
+
+/// Decodes a value from inside a SSH `string`.
+///
+/// Parsing is bounded by the `u32` length prefix, and the contents must be fully
+/// consumed unless an unknown variant was seen.
+/// Used by `#[sshwire(length_prefixed)]`.
+pub fn dec_length_prefixed<'de, S, T>(s: &mut S) -> WireResult<T>
+where
+    S: SSHSource<'de>,
+    T: SSHDecode<'de>,
+{
+    let len = u32::dec(s)? as usize;
+    let input = s.take(len)?;
+    let mut sub = DecodeBytes { input, parse_ctx: s.ctx().clone() };
+    let inner = T::dec(&mut sub)?;
+
+    let seen_unknown = sub.parse_ctx.seen_unknown;
+    s.ctx().seen_unknown |= seen_unknown;
+    if sub.remaining() != 0 && !seen_unknown {
+        trace!("SSH length prefixed field has {} extra bytes", sub.remaining());
+        return Err(WireError::SSHProto)
+    }
+    Ok(inner)
+}
+
 /// Returns `WireError::NoRoom` if larger than `u32`
 fn length_enc(value: &dyn SSHEncode) -> WireResult<u32>
 {
@@ -321,6 +362,26 @@ impl<'a> TextString<'a> {
         core::str::from_utf8(self.0).map_err(|_| Error::BadString)
     }
 
+    /// Returns the UTF-8 decoded string, with the specific decoding error.
+    ///
+    /// Authentication code should reject invalid strings rather than
+    /// substituting a default.
+    pub fn as_str_checked(&self) -> Result<&'a str, core::str::Utf8Error> {
+        core::str::from_utf8(self.0)
+    }
+
+    /// Returns the leading valid UTF-8 portion of the string.
+    ///
+    /// Any invalid sequence and following bytes are omitted. This is
+    /// suitable for display or logging, not for comparisons.
+    pub fn as_str_lossy(&self) -> &'a str {
+        match core::str::from_utf8(self.0) {
+            Ok(s) => s,
+            // OK unwrap: valid_up_to() is a valid boundary
+            Err(e) => core::str::from_utf8(&self.0[..e.valid_up_to()]).unwrap(),
+        }
+    }
+
     pub fn as_ascii(&self) -> Result<&'a str> {
         self.0.as_ascii_str().map_err(|_| Error::BadString).map(|s| s.as_str())
     }
@@ -403,9 +464,7 @@ impl<B: SSHEncode + Debug> Debug for Blob<B> {
 
 impl<B: SSHEncode> SSHEncode for Blob<B> {
     fn enc(&self, s: &mut dyn SSHSink) -> WireResult<()> {
-        let len: u32 = sshwire::length_enc(&self.0)?;
-        len.enc(s)?;
-        self.0.enc(s)
+        enc_length_prefixed(&self.0, s)
     }
 }
 
@@ -456,6 +515,12 @@ impl SSHEncode for u32 {
     }
 }
 
+impl SSHEncode for u64 {
+    fn enc(&self, s: &mut dyn SSHSink) -> WireResult<()> {
+        s.push(&self.to_be_bytes())
+    }
+}
+
 // no length prefix
 impl SSHEncode for &[u8] {
     fn enc(&self, s: &mut dyn SSHSink) -> WireResult<()> {
@@ -519,6 +584,14 @@ impl<'de> SSHDecode<'de> for u32 {
     }
 }
 
+impl<'de> SSHDecode<'de> for u64 {
+    fn dec<S>(s: &mut S) -> WireResult<Self>
+    where S: SSHSource<'de> {
+        let t = s.take(core::mem::size_of::<u64>())?;
+        Ok(u64::from_be_bytes(t.try_into().unwrap()))
+    }
+}
+
 /// Decodes a SSH name string. Must be ASCII
 /// without control characters. RFC4251 section 6.
 pub fn try_as_ascii(t: &[u8]) -> WireResult<&AsciiStr> {
@@ -670,6 +743,7 @@ pub(crate) mod tests {
     use packets::*;
     use sshwire::*;
     use pretty_hex::PrettyHex;
+    use sunset_sshwire_derive::{SSHEncode, SSHDecode};
 
     /// Checks that two items serialize the same
     pub fn assert_serialize_equal<'de, T: SSHEncode>(p1: &T, p2: &T) {
@@ -682,6 +756,18 @@ pub(crate) mod tests {
         assert_eq!(buf1, buf2);
     }
 
+    #[test]
+    fn textstring_utf8() {
+        let t = TextString(b"user");
+        assert_eq!(t.as_str_checked(), Ok("user"));
+        assert_eq!(t.as_str_lossy(), "user");
+
+        let t = TextString(b"us\xffer");
+        assert!(t.as_str_checked().is_err());
+        assert!(matches!(t.as_str(), Err(Error::BadString)));
+        assert_eq!(t.as_str_lossy(), "us");
+    }
+
     #[test]
     /// check that hash_ser_length() matches hashing a serialized message
     fn test_hash_packet() {
@@ -792,6 +878,10 @@ pub(crate) mod tests {
         let r = packet_from_bytes(&buf1, &ctx);
         assert!(matches!(r.unwrap_err(), Error::WrongPacketLength));
 
+        // too long, allowed
+        let lax = ParseContext { allow_trailing: true, ..ParseContext::new() };
+        packet_from_bytes(&buf1, &lax).unwrap();
+
         // success
         buf1.truncate(l);
         packet_from_bytes(&buf1, &ctx).unwrap();
@@ -813,4 +903,154 @@ pub(crate) mod tests {
         assert_eq!(write_ssh(&mut buf1, &"aaa").unwrap(), 7);
         assert!(matches!(write_ssh(&mut buf1, &"aaaa").unwrap_err(), Error::NoRoom { .. }));
     }
+
+    #[derive(Debug, SSHEncode, SSHDecode)]
+    #[sshwire(variant_prefix)]
+    enum TestEnum<'a> {
+        #[sshwire(variant = "one")]
+        One(u32),
+        #[sshwire(variant = "pair")]
+        Pair(u32, BinString<'a>),
+        #[sshwire(variant = "named")]
+        Named { a: u32, b: BinString<'a> },
+        #[sshwire(unknown)]
+        Unknown(Unknown<'a>),
+    }
+
+    #[test]
+    /// Multiple fields of a tuple variant are encoded in order
+    fn derive_tuple_variant() {
+        let p = TestEnum::Pair(7, BinString(b"xy"));
+        let mut buf = vec![0; 100];
+        let l = write_ssh(&mut buf, &p).unwrap();
+        buf.truncate(l);
+        assert_eq!(buf, b"\0\0\0\x04pair\0\0\0\x07\0\0\0\x02xy");
+
+        let r: TestEnum = read_ssh(&buf, None).unwrap();
+        assert!(matches!(r, TestEnum::Pair(7, BinString(b"xy"))));
+
+        let r: TestEnum = read_ssh(b"\0\0\0\x03one\0\0\0\x01", None).unwrap();
+        assert!(matches!(r, TestEnum::One(1)));
+        let r: TestEnum = read_ssh(b"\0\0\0\x03two", None).unwrap();
+        assert!(matches!(r, TestEnum::Unknown(_)));
+    }
+
+    #[derive(Debug, SSHEncode, SSHDecode)]
+    enum TestReq<'a> {
+        #[sshwire(variant = "one")]
+        One(u32),
+        #[sshwire(unknown)]
+        Unknown(Unknown<'a>),
+    }
+
+    #[derive(Debug, SSHEncode, SSHDecode)]
+    struct TestTuple<'a>(
+        #[sshwire(variant_name = 1)]
+        bool,
+        TestReq<'a>,
+    );
+
+    #[test]
+    /// variant_name can refer to a tuple struct field
+    fn derive_tuple_struct_variant_name() {
+        let p = TestTuple(true, TestReq::One(9));
+        let mut buf = vec![0; 100];
+        let l = write_ssh(&mut buf, &p).unwrap();
+        buf.truncate(l);
+        assert_eq!(buf, b"\0\0\0\x03one\x01\0\0\0\x09");
+
+        let r: TestTuple = read_ssh(&buf, None).unwrap();
+        assert!(matches!(r, TestTuple(true, TestReq::One(9))));
+    }
+
+    #[derive(SSHEncode)]
+    struct TestNested<'a> {
+        a: u32,
+        b: BinString<'a>,
+    }
+
+    #[derive(Debug, SSHEncode, SSHDecode)]
+    struct TestPrefixed<'a> {
+        #[sshwire(length_prefixed)]
+        inner: TestTuple<'a>,
+        after: u32,
+    }
+
+    #[test]
+    fn derive_length_prefixed() {
+        let p = TestPrefixed { inner: TestTuple(false, TestReq::One(2)), after: 5 };
+        let mut buf = vec![0; 100];
+        let l = write_ssh(&mut buf, &p).unwrap();
+        buf.truncate(l);
+
+        // same as a Blob
+        let mut buf2 = vec![0; 100];
+        let l = write_ssh(&mut buf2, &Blob(TestTuple(false, TestReq::One(2)))).unwrap();
+        let l2 = write_ssh(&mut buf2[l..], &5u32).unwrap();
+        buf2.truncate(l + l2);
+        assert_eq!(buf, buf2);
+
+        let r: TestPrefixed = read_ssh(&buf, None).unwrap();
+        assert!(matches!(r.inner, TestTuple(false, TestReq::One(2))));
+        assert_eq!(r.after, 5);
+
+        // extra bytes within the length
+        let mut buf2 = buf.clone();
+        buf2[3] += 1;
+        buf2.insert(4 + 12, 0);
+        assert!(matches!(read_ssh::<TestPrefixed>(&buf2, None),
+            Err(Error::SSHProto { .. })));
+
+        // too short for the contents
+        let mut buf2 = buf.clone();
+        buf2[3] -= 1;
+        assert!(read_ssh::<TestPrefixed>(&buf2, None).is_err());
+    }
+
+    #[test]
+    /// Struct variants match the wire format of a nested struct
+    fn derive_struct_variant() {
+        let p = TestEnum::Named { a: 3, b: BinString(b"abc") };
+        let mut buf = vec![0; 100];
+        let l = write_ssh(&mut buf, &p).unwrap();
+        buf.truncate(l);
+
+        let mut buf2 = vec![0; 100];
+        let l = write_ssh(&mut buf2, &"named").unwrap();
+        let l2 = write_ssh(&mut buf2[l..], &TestNested { a: 3, b: BinString(b"abc") })
+            .unwrap();
+        buf2.truncate(l + l2);
+        assert_eq!(buf, buf2);
+
+        let r: TestEnum = read_ssh(&buf, None).unwrap();
+        assert!(matches!(r, TestEnum::Named { a: 3, b: BinString(b"abc") }));
+    }
+
+    #[derive(Debug, SSHEncode, SSHDecode)]
+    struct TestPresent {
+        present: bool,
+        a: u32,
+        #[sshwire(present_if = present)]
+        opt: Option<u32>,
+    }
+
+    #[test]
+    fn derive_present_if() {
+        let p = TestPresent { present: true, a: 1, opt: Some(2) };
+        let mut buf = vec![0; 100];
+        let l = write_ssh(&mut buf, &p).unwrap();
+        assert_eq!(&buf[..l], [1, 0, 0, 0, 1, 0, 0, 0, 2]);
+        let r: TestPresent = read_ssh(&buf[..l], None).unwrap();
+        assert!(matches!(r, TestPresent { present: true, a: 1, opt: Some(2) }));
+
+        let p = TestPresent { present: false, a: 1, opt: None };
+        let l = write_ssh(&mut buf, &p).unwrap();
+        assert_eq!(l, 5);
+        let r: TestPresent = read_ssh(&buf[..l], None).unwrap();
+        assert!(matches!(r, TestPresent { present: false, a: 1, opt: None }));
+
+        // the flag is set but the field is missing
+        buf[0] = 1;
+        assert!(read_ssh::<TestPresent>(&buf[..l], None).is_err());
+    }
 }
@@ -1,6 +1,15 @@
+//! Terminal mode opcodes for [`Pty`](crate::Pty) requests, from RFC4254 section 8.
+
 // TODO
 pub const NUM_MODES: usize = 57;
 
+/// Terminates the encoded modes list
+pub const TTY_OP_END: u8 = 0;
+/// Opcodes from this value are undefined and stop parsing
+pub const TTY_OP_UNDEFINED: u8 = 160;
+/// Encoded length of all modes, including `TTY_OP_END`
+pub const MAX_MODES_LEN: usize = NUM_MODES * 5 + 1;
+
 // Codes from rfc4254. IUTF8 from rfc8160
 /*
           0     TTY_OP_END  Indicates end of options.
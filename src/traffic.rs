@@ -4,6 +4,8 @@ use {
     log::{debug, error, info, log, trace, warn},
 };
 
+use core::fmt::Write;
+
 use zeroize::Zeroize;
 
 use crate::encrypt::KeyState;
@@ -20,8 +22,6 @@ use pretty_hex::PrettyHex;
 
 pub(crate) struct TrafOut<'a> {
 
-    // TODO: decompression will need another buffer
-
     /// Accumulated output buffer.
     ///
     /// Should be sized to fit the largest
@@ -31,13 +31,19 @@ pub(crate) struct TrafOut<'a> {
     /// in-place as they are written to `buf`.
     buf: &'a mut [u8],
     state: TxState,
+
+    /// Set from our `KexInit` until key exchange completes.
+    /// Session packets sent meanwhile are held in `held`.
+    kex: bool,
+
+    /// Serialized session packets to send after key exchange,
+    /// each prefixed by a `u16` length.
+    held: heapless::Vec<u8, {config::MAX_KEX_HELD}>,
 }
 
 // TODO only pub for testing
 // pub(crate) struct TrafIn<'a> {
 pub struct TrafIn<'a> {
-    // TODO: decompression will need another buffer
-
     /// Accumulated input buffer.
     ///
     /// Should be sized to fit the largest packet allowed for input.
@@ -45,6 +51,11 @@ pub struct TrafIn<'a> {
     /// Only contains a single SSH packet at a time.
     buf: &'a mut [u8],
     state: RxState,
+    /// Zeroize all payloads once consumed
+    zeroize: bool,
+    /// Length of the current packet in `buf`, for zeroizing.
+    /// Includes any decompressed payload.
+    used: usize,
 }
 
 /// State machine for writes
@@ -96,7 +107,23 @@ enum RxState {
 
 impl<'a> TrafIn<'a> {
     pub fn new(buf: &'a mut [u8]) -> Self {
-        Self { buf, state: RxState::Idle }
+        Self { buf, state: RxState::Idle, zeroize: false, used: 0 }
+    }
+
+    /// Zeroize every decrypted payload once it has been consumed, including
+    /// channel data.
+    ///
+    /// Otherwise only authentication requests are zeroized.
+    pub(crate) fn set_zeroize(&mut self, zeroize: bool) {
+        self.zeroize = zeroize
+    }
+
+    /// Returns to `Idle` after consuming a packet, zeroizing if enabled.
+    fn finish(&mut self) {
+        if self.zeroize {
+            self.buf[..self.used].zeroize();
+        }
+        self.state = RxState::Idle
     }
 
     pub fn is_input_ready(&self) -> bool {
@@ -132,7 +159,7 @@ impl<'a> TrafIn<'a> {
     /// Called when `payload()` is complete.
     pub(crate) fn done_payload(&mut self) {
         if let RxState::InPayload { .. } = self.state {
-            self.state = RxState::Idle
+            self.finish()
         }
     }
 
@@ -206,7 +233,8 @@ impl<'a> TrafIn<'a> {
             w.copy_from_slice(x);
             *idx += need;
             if *idx == expect {
-                self.state = RxState::ReadComplete { len: expect }
+                self.state = RxState::ReadComplete { len: expect };
+                self.used = expect;
             }
         }
 
@@ -214,6 +242,10 @@ impl<'a> TrafIn<'a> {
             let w = &mut self.buf[..len];
             let seq = keys.recv_seq();
             let payload_len = keys.decrypt(w)?;
+            let payload_len = keys.decompress(&mut self.buf[SSH_PAYLOAD_START..],
+                payload_len, self.zeroize)?;
+            // A decompressed payload can extend past the wire length
+            self.used = self.used.max(SSH_PAYLOAD_START + payload_len);
             self.state = RxState::InPayload { len: payload_len, seq }
         }
 
@@ -262,7 +294,7 @@ impl<'a> TrafIn<'a> {
 
                 if *idx == len {
                     // all done.
-                    self.state = RxState::Idle;
+                    self.finish();
                     (wlen, Some(len))
                 } else {
                     (wlen, None)
@@ -290,7 +322,7 @@ impl<'a> TrafIn<'a> {
 
                 if *idx == len {
                     // all done.
-                    self.state = RxState::Idle;
+                    self.finish();
                     (wlen, Some(len), dt)
                 } else {
                     (wlen, None, dt)
@@ -305,7 +337,7 @@ impl<'a> TrafIn<'a> {
         match self.state {
             RxState::InChannelData { chan: c, len, .. }
             if c == chan => {
-                self.state = RxState::Idle;
+                self.finish();
                 len
             }
             _ => 0
@@ -315,7 +347,7 @@ impl<'a> TrafIn<'a> {
 
 impl<'a> TrafOut<'a> {
     pub fn new(buf: &'a mut [u8]) -> Self {
-        Self { buf, state: TxState::Idle }
+        Self { buf, state: TxState::Idle, kex: false, held: heapless::Vec::new() }
     }
 
     /// Serializes and and encrypts a packet to send
@@ -327,17 +359,117 @@ impl<'a> TrafOut<'a> {
                 if keys.is_cleartext() {
                     return Error::bug_msg("send cleartext")
                 }
+                if self.kex {
+                    // RFC4253 section 7.1, only transport packets until NewKeys
+                    return self.hold_packet(&p)
+                }
+            }
+        }
+
+        let Some(wbuf) = self.payload_buf()? else {
+            trace!("Dropped {} after close", p.message_name());
+            return Ok(());
+        };
+        let plen = sshwire::write_ssh(wbuf, &p)?;
+        trace!("Sending {} len {plen}", p.message_name());
+
+        self.encrypt_payload(plen, keys)
+    }
+
+    /// Sends channel data without going through `Packet` serialization.
+    ///
+    /// This is equivalent to `send_packet()` with a `ChannelData` or `ChannelDataExt`,
+    /// but writes the header directly then copies the data.
+    /// `num` is the remote channel number.
+    pub(crate) fn send_channel_data(&mut self, num: u32, dt: ChanData, data: &[u8],
+        keys: &mut KeyState) -> Result<()> {
+        if keys.is_cleartext() {
+            return Error::bug_msg("send cleartext")
+        }
+
+        let Some(wbuf) = self.payload_buf()? else {
+            trace!("Dropped channel data after close");
+            return Ok(());
+        };
+        let plen = write_channel_data(wbuf, num, dt, data)?;
+        trace!("Sending {dt:?} data, chan {num} len {}", data.len());
+
+        self.encrypt_payload(plen, keys)
+    }
+
+    /// Sends an already serialized SSH payload, avoiding a decode/encode
+    /// round trip when relaying packets.
+    ///
+    /// `payload` must begin with a message number. Only allowed once keys
+    /// are established.
+    pub(crate) fn send_raw(&mut self, payload: &[u8], keys: &mut KeyState) -> Result<()> {
+        debug_assert!(payload.first()
+            .is_some_and(|&n| packets::MessageNumber::try_from(n).is_ok()),
+            "raw payload has no valid message number");
+        if keys.is_cleartext() {
+            return Error::bug_msg("send cleartext")
+        }
+
+        let Some(wbuf) = self.payload_buf()? else {
+            trace!("Dropped raw output after close");
+            return Ok(());
+        };
+        wbuf.get_mut(..payload.len()).ok_or(error::NoRoom.build())?
+            .copy_from_slice(payload);
+        trace!("Sending raw payload len {}", payload.len());
+
+        self.encrypt_payload(payload.len(), keys)
+    }
+
+    /// Serializes a session packet to send once key exchange completes.
+    fn hold_packet(&mut self, p: &packets::Packet) -> Result<()> {
+        let start = self.held.len();
+        // OK unwrap: resize within capacity
+        self.held.resize(self.held.capacity(), 0).unwrap();
+        let r = self.held.get_mut(start+2..)
+            .ok_or(error::NoRoom.build())
+            .and_then(|b| sshwire::write_ssh(b, p));
+        let plen = match r {
+            Ok(plen) => plen,
+            Err(e) => {
+                self.held.truncate(start);
+                debug!("No space to hold {} during kex", p.message_name());
+                return Err(e)
             }
+        };
+        self.held[start..start+2].copy_from_slice(&(plen as u16).to_be_bytes());
+        self.held.truncate(start + 2 + plen);
+        trace!("Holding {} len {plen} until kex completes", p.message_name());
+        Ok(())
+    }
+
+    /// Holds session packets from now until [`finish_kex()`](Self::finish_kex).
+    pub(crate) fn start_kex(&mut self) {
+        self.kex = true;
+    }
+
+    /// Sends any session packets held during key exchange.
+    pub(crate) fn finish_kex(&mut self, keys: &mut KeyState) -> Result<()> {
+        self.kex = false;
+        let held = core::mem::take(&mut self.held);
+        let mut h = held.as_slice();
+        while let [a, b, rest @ ..] = h {
+            let plen = u16::from_be_bytes([*a, *b]) as usize;
+            let (p, rest) = rest.split_at(plen);
+            self.send_raw(p, keys)?;
+            h = rest;
         }
+        Ok(())
+    }
 
+    /// Returns the buffer space for a payload following any pending output,
+    /// or `None` if output is closed.
+    fn payload_buf(&mut self) -> Result<Option<&mut [u8]>> {
         // Either a fresh buffer or appending to write
-        let (idx, len) = match self.state {
-            TxState::Idle => (0, 0),
-            TxState::Write { idx, len } => (idx, len),
-            TxState::Closed => {
-                trace!("Dropped output after close {p:?}");
-                return Ok(());
-            },
+        let len = match self.state {
+            TxState::Idle => 0,
+            TxState::Write { len, .. } => len,
+            TxState::Closed => return Ok(None),
         };
 
         // Use the remainder of our buffer to write the packet. Payload starts
@@ -346,14 +478,22 @@ impl<'a> TrafOut<'a> {
         if wbuf.len() < SSH_PAYLOAD_START {
             return error::NoRoom.fail()
         }
-        let plen = sshwire::write_ssh(&mut wbuf[SSH_PAYLOAD_START..], &p)?;
-        trace!("Sending {p:?}");
+        Ok(Some(&mut wbuf[SSH_PAYLOAD_START..]))
+    }
+
+    /// Encrypts a payload of `plen` previously written to `payload_buf()`
+    fn encrypt_payload(&mut self, plen: usize, keys: &mut KeyState) -> Result<()> {
+        let (idx, len) = match self.state {
+            TxState::Idle => (0, 0),
+            TxState::Write { idx, len } => (idx, len),
+            TxState::Closed => return Err(Error::bug()),
+        };
 
         // Encrypt in place
+        let wbuf = &mut self.buf[len..];
         let elen = keys.encrypt(plen, wbuf)?;
         self.state = TxState::Write { idx, len: len+elen };
         Ok(())
-
     }
 
     pub fn is_output_pending(&self) -> bool {
@@ -393,12 +533,12 @@ impl<'a> TrafOut<'a> {
         matches!(self.state, TxState::Closed)
     }
 
-    pub fn send_version(&mut self) -> Result<(), Error> {
+    pub fn send_version(&mut self, version: &[u8]) -> Result<(), Error> {
         if !matches!(self.state, TxState::Idle) {
             return Err(Error::bug());
         }
 
-        let len = ident::write_version(self.buf)?;
+        let len = ident::write_version(self.buf, version)?;
         self.state = TxState::Write { idx: 0, len };
         Ok(())
     }
@@ -428,6 +568,36 @@ impl<'a> TrafOut<'a> {
 
 }
 
+/// Writes a channel data payload to `buf`, returning the length written.
+///
+/// The result is identical to serializing a [`packets::ChannelData`]
+/// (or [`packets::ChannelDataExt`] for stderr), avoiding the generic `SSHEncode` path.
+fn write_channel_data(buf: &mut [u8], num: u32, dt: ChanData, data: &[u8])
+    -> Result<usize> {
+    let off = dt.packet_offset();
+    let plen = off + data.len();
+    let Some(buf) = buf.get_mut(..plen) else {
+        return error::NoRoom.fail()
+    };
+    let (hdr, d) = buf.split_at_mut(off);
+
+    let dlen = (data.len() as u32).to_be_bytes();
+    hdr[1..5].copy_from_slice(&num.to_be_bytes());
+    match dt {
+        ChanData::Normal => {
+            hdr[0] = packets::MessageNumber::SSH_MSG_CHANNEL_DATA as u8;
+            hdr[5..9].copy_from_slice(&dlen);
+        }
+        ChanData::Stderr => {
+            hdr[0] = packets::MessageNumber::SSH_MSG_CHANNEL_EXTENDED_DATA as u8;
+            hdr[5..9].copy_from_slice(&sshnames::SSH_EXTENDED_DATA_STDERR.to_be_bytes());
+            hdr[9..13].copy_from_slice(&dlen);
+        }
+    }
+    d.copy_from_slice(data);
+    Ok(plen)
+}
+
 /// Convenience to pass TrafOut with keys
 pub(crate) struct TrafSend<'s, 'a> {
     out: &'s mut TrafOut<'a>,
@@ -446,6 +616,11 @@ impl<'s, 'a> TrafSend<'s, 'a> {
         self.out.send_packet(p.into(), self.keys)
     }
 
+    /// Sends a pre-serialized payload, see [`TrafOut::send_raw()`]
+    pub fn send_raw(&mut self, payload: &[u8]) -> Result<()> {
+        self.out.send_raw(payload, self.keys)
+    }
+
     pub fn rekey(&mut self, keys: encrypt::Keys) {
         self.keys.rekey(keys)
     }
@@ -454,17 +629,220 @@ impl<'s, 'a> TrafSend<'s, 'a> {
         self.keys.enable_strict_kex()
     }
 
-    pub fn send_version(&mut self) -> Result<(), Error> {
-        self.out.send_version()
+    /// Holds session packets until [`finish_kex()`](Self::finish_kex),
+    /// see [`TrafOut::start_kex()`].
+    pub fn start_kex(&mut self) {
+        self.out.start_kex()
+    }
+
+    /// Sends session packets held during key exchange.
+    pub fn finish_kex(&mut self) -> Result<()> {
+        self.out.finish_kex(self.keys)
+    }
+
+    pub fn enable_delayed_compression(&mut self) {
+        self.keys.enable_delayed_compression()
+    }
+
+    pub fn send_version(&mut self, version: &[u8]) -> Result<(), Error> {
+        self.out.send_version(version)
     }
 
     pub fn can_output(&self) -> bool {
         self.out.can_output()
     }
 
+    /// Sends a `SSH_MSG_DISCONNECT` for a fatal error, so that
+    /// the peer knows the reason for the connection closing.
+    ///
+    /// Does nothing for errors without a
+    /// [`disconnect_reason()`](Error::disconnect_reason).
+    pub fn send_disconnect(&mut self, e: &Error) {
+        let Some(reason) = e.disconnect_reason() else {
+            return
+        };
+        // Truncated messages are fine.
+        let mut desc = heapless::String::<80>::new();
+        let _ = write!(desc, "{e}");
+        if let Err(e) = self.send_disconnect_reason(reason, &desc) {
+            debug!("Failed sending disconnect: {e}");
+        }
+    }
+
+    /// Sends a `SSH_MSG_DISCONNECT` with a given reason and description.
+    pub fn send_disconnect_reason(&mut self, reason: DisconnectReason, desc: &str)
+        -> Result<()> {
+        debug!("Sending disconnect {reason:?}: {desc}");
+        self.send(packets::Disconnect {
+            reason: reason as u32,
+            desc: desc.into(),
+            lang: "".into(),
+        })
+    }
+
+    /// Returns `true` if a rekey limit has been reached
+    pub fn rekey_due(&self) -> bool {
+        self.keys.rekey_due()
+    }
+
     /// Returns the current receive sequence number
     pub fn recv_seq(&self) -> u32 {
         self.keys.seq_decrypt.0
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::sunsetlog::*;
+    use crate::traffic::*;
+    use crate::encrypt::TestSend;
+    use crate::sshwire::BinString;
+    use std::time::Instant;
+
+    fn generic_channel_data(buf: &mut [u8], num: u32, dt: ChanData, data: &[u8])
+        -> usize {
+        let data = BinString(data);
+        let p: Packet = match dt {
+            ChanData::Normal => packets::ChannelData { num, data }.into(),
+            ChanData::Stderr => packets::ChannelDataExt {
+                num, code: sshnames::SSH_EXTENDED_DATA_STDERR, data }.into(),
+        };
+        sshwire::write_ssh(buf, &p).unwrap()
+    }
+
+    #[test]
+    /// Check that the direct channel data path matches `Packet` serialization
+    fn channel_data_matches_packet() {
+        init_test_log();
+        let data: std::vec::Vec<u8> = (0..=255).collect();
+        for dt in [ChanData::Normal, ChanData::Stderr] {
+            for len in [0, 1, 9, 100, 256] {
+                let mut b1 = vec![0u8; 300];
+                let mut b2 = vec![1u8; 300];
+                let l1 = generic_channel_data(&mut b1, 0x1234567, dt, &data[..len]);
+                let l2 = write_channel_data(&mut b2, 0x1234567, dt, &data[..len]).unwrap();
+                assert_eq!(b1[..l1], b2[..l2]);
+            }
+        }
+
+        // too small
+        let mut b = vec![0u8; 20];
+        write_channel_data(&mut b, 1, ChanData::Normal, &data[..12]).unwrap_err();
+    }
+
+    /// Compares the direct channel data path with generic `Packet`
+    /// serialization. Run with
+    /// `cargo test --release -- --ignored --nocapture bench_channel_data`
+    #[test]
+    #[ignore]
+    fn bench_channel_data() {
+        const ITERS: usize = 1_000_000;
+        let data = [0x55u8; 1000];
+        let mut buf = vec![0u8; 2000];
+
+        let t = Instant::now();
+        for i in 0..ITERS {
+            let l = generic_channel_data(&mut buf, i as u32, ChanData::Normal, &data);
+            core::hint::black_box(&buf[..l]);
+        }
+        let generic = t.elapsed();
+
+        let t = Instant::now();
+        for i in 0..ITERS {
+            let l = write_channel_data(&mut buf, i as u32, ChanData::Normal, &data).unwrap();
+            core::hint::black_box(&buf[..l]);
+        }
+        let direct = t.elapsed();
+
+        println!("{ITERS} x {} byte ChannelData", data.len());
+        println!("generic Packet encode: {generic:?}");
+        println!("direct write:          {direct:?}");
+    }
+
+    /// Returns the reason from a single sent cleartext Disconnect packet
+    fn sent_disconnect(e: &Error) -> u32 {
+        let mut buf = vec![0u8; 500];
+        let mut out = TrafOut::new(&mut buf);
+        let mut keys = KeyState::new_cleartext();
+        out.sender(&mut keys).send_disconnect(e);
+
+        let mut b = vec![0u8; 500];
+        let l = out.output(&mut b);
+        let b = &b[..l];
+        let len = u32::from_be_bytes(b[..4].try_into().unwrap()) as usize;
+        let padlen = b[4] as usize;
+        let payload = &b[SSH_PAYLOAD_START..SSH_LENGTH_SIZE + len - padlen];
+        let p = sshwire::packet_from_bytes(payload, &Default::default()).unwrap();
+        let Packet::Disconnect(d) = p else { panic!() };
+        trace!("disconnect {d:?}");
+        d.reason
+    }
+
+    #[test]
+    fn disconnect_reason() {
+        init_test_log();
+        let r = sent_disconnect(&Error::AlgoNoMatch { algo: "encryption" });
+        assert_eq!(r, sshnames::DisconnectReason::SSH_DISCONNECT_KEY_EXCHANGE_FAILED as u32);
+        let r = sent_disconnect(&Error::NoAuthMethods);
+        assert_eq!(r, sshnames::DisconnectReason::SSH_DISCONNECT_NO_MORE_AUTH_METHODS_AVAILABLE as u32);
+
+        // Local errors aren't sent
+        let mut buf = vec![0u8; 500];
+        let mut out = TrafOut::new(&mut buf);
+        let mut keys = KeyState::new_cleartext();
+        out.sender(&mut keys).send_disconnect(&error::BadUsage.build());
+        assert!(!out.is_output_pending());
+    }
+
+    #[test]
+    fn zeroize_input() {
+        init_test_log();
+        let mut b = vec![0u8; 500];
+        let mut out = TrafOut::new(&mut b);
+        let mut keys = KeyState::new_cleartext();
+        out.sender(&mut keys).send(packets::Ignore { data: BinString(b"secret") }).unwrap();
+        let mut wire = b"SSH-2.0-test\r\n".to_vec();
+        let mut b = vec![0u8; 500];
+        let l = out.output(&mut b);
+        wire.extend_from_slice(&b[..l]);
+
+        let mut inbuf = vec![0u8; 500];
+        let mut tin = TrafIn::new(&mut inbuf);
+        tin.set_zeroize(true);
+        let mut rv = RemoteVersion::new(true);
+        let mut keys = KeyState::new_cleartext();
+        let mut w = &wire[..];
+        while tin.payload().is_none() {
+            let l = tin.input(&mut keys, &mut rv, w).unwrap();
+            w = &w[l..];
+        }
+        tin.done_payload();
+        assert!(tin.is_input_ready());
+        assert!(inbuf.iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn send_raw() {
+        init_test_log();
+        let p = packets::ChannelData { num: 3, data: BinString(b"relayed") };
+        let mut payload = [0u8; 100];
+        let l = sshwire::write_ssh(&mut payload, &Packet::from(p)).unwrap();
+
+        let mut buf = [0u8; 500];
+        let mut ts = TestSend::with_buf(&mut buf);
+        ts.sender().send_raw(&payload[..l]).unwrap();
+        let mut o1 = vec![0u8; 500];
+        let p1 = ts.recv_payload(&mut o1);
+        assert_eq!(p1, &payload[..l]);
+
+        let mut buf = [0u8; 500];
+        let mut ts = TestSend::with_buf(&mut buf);
+        let p = packets::ChannelData { num: 3, data: BinString(b"relayed") };
+        ts.sender().send(p).unwrap();
+        let mut o2 = vec![0u8; 500];
+        let p2 = ts.recv_payload(&mut o2);
+        assert_eq!(p1, p2);
+    }
+}
@@ -74,19 +74,29 @@ enum ContainerAtt {
 #[derive(Debug)]
 enum FieldAtt {
     /// A variant method name will be encoded/decoded before the next field.
-    /// eg `#[sshwire(variant_name = ch)]` for `ChannelRequest`
-    VariantName(Ident),
+    /// eg `#[sshwire(variant_name = ch)]` for `ChannelRequest`,
+    /// or `#[sshwire(variant_name = 1)]` for a tuple struct.
+    VariantName(String),
 
     /// Any unknown variant name should be recorded here.
     /// This variant can't be written out.
     /// `#[sshwire(unknown))]`
     CaptureUnknown,
 
+    /// The field is encoded inside a SSH `string`, with a `u32` length prefix.
+    /// `#[sshwire(length_prefixed)]`
+    LengthPrefixed,
+
     /// The name of a variant, used by the parent struct
     /// `#[sshwire(variant = "exit-signal"))]`
     /// or
     /// `#[sshwire(variant = SSH_NAME_IDENT))]`
     Variant(TokenTree),
+
+    /// An `Option` field is only decoded when an earlier `bool` field is set.
+    /// `#[sshwire(present_if = sig_present)]` for `MethodPubKey`.
+    /// When encoding the field is written if it is `Some`.
+    PresentIf(String),
 }
 
 fn take_cont_atts(atts: &[Attribute]) -> Result<Vec<ContainerAtt>> {
@@ -145,7 +155,13 @@ fn take_field_atts(atts: &[Attribute]) -> Result<Vec<FieldAtt>> {
                                 }
                                 match g.next() {
                                     Some(TokenTree::Ident(i)) => {
-                                        Ok(FieldAtt::VariantName(i))
+                                        Ok(FieldAtt::VariantName(i.to_string()))
+                                    }
+                                    // tuple struct field index
+                                    Some(TokenTree::Literal(l))
+                                        if l.to_string().parse::<usize>().is_ok() =>
+                                    {
+                                        Ok(FieldAtt::VariantName(l.to_string()))
                                     }
                                     _ => Err(Error::ExpectedIdent(a.tokens.span())),
                                 }
@@ -157,6 +173,33 @@ fn take_field_atts(atts: &[Attribute]) -> Result<Vec<FieldAtt>> {
                                 Ok(FieldAtt::CaptureUnknown)
                             }
 
+                            Some(TokenTree::Ident(l))
+                                if l.to_string() == "length_prefixed" =>
+                            {
+                                Ok(FieldAtt::LengthPrefixed)
+                            }
+
+                            Some(TokenTree::Ident(l))
+                                if l.to_string() == "present_if" =>
+                            {
+                                // check for '='
+                                match g.next() {
+                                    Some(TokenTree::Punct(p)) if p == '=' => (),
+                                    _ => {
+                                        return Some(Err(Error::Custom {
+                                            error: "Missing '='".into(),
+                                            span: Some(a.tokens.span()),
+                                        }))
+                                    }
+                                }
+                                match g.next() {
+                                    Some(TokenTree::Ident(i)) => {
+                                        Ok(FieldAtt::PresentIf(i.to_string()))
+                                    }
+                                    _ => Err(Error::ExpectedIdent(a.tokens.span())),
+                                }
+                            }
+
                             Some(TokenTree::Ident(l))
                                 if l.to_string() == "variant" =>
                             {
@@ -208,6 +251,20 @@ fn take_field_atts(atts: &[Attribute]) -> Result<Vec<FieldAtt>> {
         .collect()
 }
 
+/// Returns struct fields with their names, or indices for a tuple struct.
+fn struct_fields(body: &StructBody) -> Vec<(String, &UnnamedField)> {
+    match &body.fields {
+        Some(Fields::Tuple(v)) => {
+            v.iter().enumerate().map(|(i, f)| (i.to_string(), f)).collect()
+        }
+        Some(Fields::Struct(v)) => {
+            v.iter().map(|(i, f)| (i.to_string(), f)).collect()
+        }
+        // either an empty braced struct or a unit struct.
+        None => vec![],
+    }
+}
+
 fn encode_struct(gen: &mut Generator, body: StructBody) -> Result<()> {
     gen.impl_for("crate::sshwire::SSHEncode")
         .generate_fn("enc")
@@ -215,35 +272,24 @@ fn encode_struct(gen: &mut Generator, body: StructBody) -> Result<()> {
         .with_arg("s", "&mut dyn crate::sshwire::SSHSink")
         .with_return_type("crate::sshwire::WireResult<()>")
         .body(|fn_body| {
-            match &body.fields {
-                Some(Fields::Tuple(v)) => {
-                    for (fname, f) in v.iter().enumerate() {
-                        // we're only using single elements for newtype, don't bother with atts for now
-                        if !f.attributes.is_empty() {
-                            return Err(Error::Custom { error: "Attributes aren't allowed for tuple structs".into(), span: Some(f.span()) })
-                        }
-                        fn_body.push_parsed(format!("crate::sshwire::SSHEncode::enc(&self.{fname}, s)?;"))?;
-                    }
-                }
-                Some(Fields::Struct(v)) => {
-                    for f in v {
-                        let fname = &f.0;
-                        let atts = take_field_atts(&f.1.attributes)?;
-                        for a in atts {
-                            if let FieldAtt::VariantName(enum_field) = a {
-                                // encode an enum field's variant name before this field
-                                fn_body.push_parsed(format!("crate::sshwire::SSHEncode::enc(&self.{enum_field}.variant_name()?, s)?;"))?;
-                            }
+            for (fname, f) in struct_fields(&body) {
+                let atts = take_field_atts(&f.attributes)?;
+                let mut length_prefixed = false;
+                for a in atts {
+                    match a {
+                        FieldAtt::VariantName(enum_field) => {
+                            // encode an enum field's variant name before this field
+                            fn_body.push_parsed(format!("crate::sshwire::SSHEncode::enc(&self.{enum_field}.variant_name()?, s)?;"))?;
                         }
-                        fn_body.push_parsed(format!("crate::sshwire::SSHEncode::enc(&self.{fname}, s)?;"))?;
+                        FieldAtt::LengthPrefixed => length_prefixed = true,
+                        _ => (),
                     }
-
                 }
-                None => {
-                    // nothing to do.
-                    // either an empty braced struct or a unit struct.
+                if length_prefixed {
+                    fn_body.push_parsed(format!("crate::sshwire::enc_length_prefixed(&self.{fname}, s)?;"))?;
+                } else {
+                    fn_body.push_parsed(format!("crate::sshwire::SSHEncode::enc(&self.{fname}, s)?;"))?;
                 }
-
             }
             fn_body.push_parsed("Ok(())")?;
             Ok(())
@@ -298,7 +344,36 @@ fn encode_enum(
                             }
 
                         }
-                        _ => return Err(Error::Custom { error: "SSHEncode currently only implements Unit or single value enum variants.".into(), span: None})
+                        Some(Fields::Tuple(ref f)) => {
+                            if atts.iter().any(|a| matches!(a, FieldAtt::CaptureUnknown)) {
+                                return Err(Error::Custom { error: "#[sshwire(unknown)] variant must have a single field".into(), span: Some(var.name.span())})
+                            }
+                            // bind and encode each field in order
+                            match_arm.group(Delimiter::Parenthesis, |item| {
+                                for n in 0..f.len() {
+                                    item.push_parsed(format!("ref i{n},"))?;
+                                }
+                                Ok(())
+                            })?;
+                            for n in 0..f.len() {
+                                rhs.push_parsed(format!("crate::sshwire::SSHEncode::enc(i{n}, s)?;"))?;
+                            }
+                        }
+                        Some(Fields::Struct(ref f)) => {
+                            if atts.iter().any(|a| matches!(a, FieldAtt::CaptureUnknown)) {
+                                return Err(Error::Custom { error: "#[sshwire(unknown)] variant must have a single field".into(), span: Some(var.name.span())})
+                            }
+                            // same wire format as a nested struct
+                            match_arm.group(Delimiter::Brace, |item| {
+                                for (fname, _) in f {
+                                    item.push_parsed(format!("ref {fname},"))?;
+                                }
+                                Ok(())
+                            })?;
+                            for (fname, _) in f {
+                                rhs.push_parsed(format!("crate::sshwire::SSHEncode::enc({fname}, s)?;"))?;
+                            }
+                        }
                     }
 
                     match_arm.puncts("=>");
@@ -363,14 +438,19 @@ fn encode_enum_names(
                         None => {
                             // nothing to do
                         }
-                        Some(Fields::Tuple(ref f)) if f.len() == 1 => {
+                        Some(Fields::Tuple(_)) => {
                             match_arm.group(Delimiter::Parenthesis, |item| {
-                                item.ident_str("_");
+                                item.puncts("..");
                                 Ok(())
                             })?;
 
                         }
-                        _ => return Err(Error::Custom { error: "SSHEncode currently only implements Unit or single value enum variants.".into(), span: None})
+                        Some(Fields::Struct(_)) => {
+                            match_arm.group(Delimiter::Brace, |item| {
+                                item.puncts("..");
+                                Ok(())
+                            })?;
+                        }
                     }
 
                     match_arm.puncts("=>");
@@ -406,33 +486,46 @@ fn decode_struct(gen: &mut Generator, body: StructBody) -> Result<()> {
         .with_return_type("crate::sshwire::WireResult<Self>")
         .body(|fn_body| {
             let mut named_enums = HashSet::new();
-            if let Some(Fields::Struct(v)) = &body.fields {
-                for f in v {
-                    let atts = take_field_atts(&f.1.attributes)?;
-                    for a in atts {
-                        if let FieldAtt::VariantName(enum_field) = a {
+            for (fname, f) in struct_fields(&body) {
+                let atts = take_field_atts(&f.attributes)?;
+                let mut length_prefixed = false;
+                let mut present_if = None;
+                for a in atts {
+                    match a {
+                        FieldAtt::VariantName(enum_field) => {
                             // Read the extra field on the wire that isn't directly included in the struct
-                            named_enums.insert(enum_field.to_string());
                             fn_body.push_parsed(format!("let enum_name_{enum_field}: BinString = crate::sshwire::SSHDecode::dec(s)?;"))?;
+                            named_enums.insert(enum_field);
                         }
+                        FieldAtt::LengthPrefixed => length_prefixed = true,
+                        FieldAtt::PresentIf(cond) => present_if = Some(cond),
+                        _ => (),
                     }
-                    let fname = &f.0;
-                    if named_enums.contains(&fname.to_string()) {
-                        fn_body.push_parsed(format!("let field_{fname} =  crate::sshwire::SSHDecodeEnum::dec_enum(s, enum_name_{fname}.0)?;"))?;
-                    } else {
-                        fn_body.push_parsed(format!("let field_{fname} = crate::sshwire::SSHDecode::dec(s)?;"))?;
+                }
+                if let Some(cond) = present_if {
+                    if named_enums.contains(&fname) || length_prefixed {
+                        return Err(Error::Custom { error: "present_if can't be combined with other attributes".into(), span: Some(f.span()) })
                     }
+                    fn_body.push_parsed(format!("let field_{fname} = if field_{cond} {{ Some(crate::sshwire::SSHDecode::dec(s)?) }} else {{ None }};"))?;
+                } else if named_enums.contains(&fname) {
+                    if length_prefixed {
+                        return Err(Error::Custom { error: "length_prefixed can't be used for a variant_name field".into(), span: Some(f.span()) })
+                    }
+                    fn_body.push_parsed(format!("let field_{fname} =  crate::sshwire::SSHDecodeEnum::dec_enum(s, enum_name_{fname}.0)?;"))?;
+                } else if length_prefixed {
+                    fn_body.push_parsed(format!("let field_{fname} = crate::sshwire::dec_length_prefixed(s)?;"))?;
+                } else {
+                    fn_body.push_parsed(format!("let field_{fname} = crate::sshwire::SSHDecode::dec(s)?;"))?;
                 }
             }
             fn_body.ident_str("Ok");
             fn_body.group(Delimiter::Parenthesis, |fn_body| {
                 match &body.fields {
                     Some(Fields::Tuple(f)) => {
-                        // we don't handle attributes for Tuple Structs - only use as newtype
                         fn_body.ident_str("Self");
                         fn_body.group(Delimiter::Parenthesis, |args| {
-                            for _ in f.iter() {
-                                args.push_parsed(format!("crate::sshwire::SSHDecode::dec(s)?,"))?;
+                            for fname in 0..f.len() {
+                                args.push_parsed(format!("field_{fname},"))?;
                             }
                             Ok(())
                         })?;
@@ -516,6 +609,21 @@ fn decode_enum_names(
     _atts: &[Attribute],
     body: &EnumBody,
 ) -> Result<()> {
+    // Without an unknown variant there is no arm for unrecognised names.
+    let has_unknown = body.variants.iter()
+        .map(|var| take_field_atts(&var.attributes))
+        .collect::<Result<Vec<_>>>()?
+        .iter()
+        .any(|atts| atts.iter().any(|a| matches!(a, FieldAtt::CaptureUnknown)));
+    if !has_unknown {
+        let name = gen.target_name();
+        return Err(Error::Custom {
+            error: format!("SSHDecode for enum {name} requires a #[sshwire(unknown)] variant \
+                to capture unrecognised names. Derive only SSHEncode if it isn't decoded."),
+            span: Some(name.span()),
+        })
+    }
+
     gen.impl_for_with_lifetimes("crate::sshwire::SSHDecodeEnum", ["de"])
         .modify_generic_constraints(|generics, where_constraints| {
             for lt in generics.iter_lifetimes() {
@@ -552,18 +660,26 @@ fn decode_enum_names(
                                 None => {
                                     var_body.push_parsed(format!("Self::{}", var.name))?;
                                 }
-                                Some(Fields::Tuple(ref f)) if f.len() == 1 => {
-                                    var_body.push_parsed(format!("Self::{}(crate::sshwire::SSHDecode::dec(s)?)", var.name))?;
+                                Some(Fields::Tuple(ref f)) => {
+                                    // fields are decoded in order
+                                    let args = "crate::sshwire::SSHDecode::dec(s)?,".repeat(f.len());
+                                    var_body.push_parsed(format!("Self::{}({args})", var.name))?;
+                                }
+                                Some(Fields::Struct(ref f)) => {
+                                    // fields are decoded in declaration order
+                                    let args: String = f.iter()
+                                        .map(|(fname, _)| format!("{fname}: crate::sshwire::SSHDecode::dec(s)?,"))
+                                        .collect();
+                                    var_body.push_parsed(format!("Self::{} {{ {args} }}", var.name))?;
                                 }
-                            _ => return Err(Error::Custom { error: "SSHDecode currently only implements Unit or single value enum variants. ".into(), span: None})
                             }
                             Ok(())
                         })?;
 
                     }
-                    if let Some(unk) = unknown_arm.take() {
-                        match_arm.append(unk);
-                    }
+                }
+                if let Some(unk) = unknown_arm.take() {
+                    match_arm.append(unk);
                 }
                 Ok(())
             })?;